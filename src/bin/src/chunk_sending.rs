@@ -1 +1,115 @@
+//! Loading, generating and encoding the chunks players need in the background.
+//!
+//! Players queue the chunks they need in their [`ChunkReceiver`], and each tick some of them are
+//! handed to the thread pool. Finished packets come back through [`ChunkStreamer`] and are sent on
+//! a later tick, as fast as the player's client asks for them, so the tick never waits on a chunk.
+//!
+//! [`ChunkReceiver`]: ferrumc_core::chunks::chunk_receiver::ChunkReceiver
 
+use bevy_ecs::prelude::{Entity, Resource};
+use crossbeam_channel::{Receiver, Sender};
+use ferrumc_core::chunks::chunk_receiver::{ChunkPacket, ChunkPos};
+use ferrumc_net::compression::compress_packet;
+use ferrumc_net::errors::NetError;
+use ferrumc_net::packets::outgoing::chunk_and_light_data::ChunkAndLightData;
+use ferrumc_net_codec::encode::NetEncode;
+use ferrumc_net_codec::encode::NetEncodeOpts::WithLength;
+use ferrumc_state::GlobalState;
+use tracing::{error, trace};
+
+/// How many chunks a player can have loading at once. Chunks a player moves away from can't be
+/// stopped once they've started loading, so this keeps players who move quickly from filling the
+/// thread pool with chunks they won't need.
+pub const MAX_LOADING_PER_PLAYER: usize = 32;
+/// How many loaded chunks a player can have waiting for their client to be ready for them. Slow
+/// clients would otherwise have every chunk in view held in memory.
+pub const MAX_READY_PER_PLAYER: usize = 128;
+
+/// A chunk packet that's finished loading, for a player.
+pub struct LoadedChunk {
+    pub player: Entity,
+    pub chunk: ChunkPos,
+    pub packet: Result<ChunkPacket, NetError>,
+}
+
+#[derive(Resource)]
+pub struct ChunkStreamer {
+    sender: Sender<LoadedChunk>,
+    pub receiver: Receiver<LoadedChunk>,
+}
+
+impl Default for ChunkStreamer {
+    fn default() -> Self {
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        Self { sender, receiver }
+    }
+}
+
+impl ChunkStreamer {
+    /// Starts loading a chunk for a player on the thread pool. This has to be called from the
+    /// tick thread, not the thread pool.
+    pub fn load(&self, state: &GlobalState, player: Entity, chunk: ChunkPos, compressed: bool) {
+        let task_state = state.clone();
+        let sender = self.sender.clone();
+        // The handle is dropped, the chunk comes back through the channel instead
+        let _ = state.thread_pool.oneshot(move || {
+            let packet = encode_chunk(&task_state, &chunk, compressed);
+            if let Err(e) = &packet {
+                error!("Failed to create chunk packet: {:?}", e);
+            }
+            // The receiver only goes away when the server is shutting down
+            let _ = sender.send(LoadedChunk {
+                player,
+                chunk,
+                packet,
+            });
+        });
+    }
+}
+
+/// Gets the packet for a chunk, generating the chunk if it doesn't exist yet. Every player sees
+/// the same chunk, so the packet is only encoded once for all of them.
+///
+/// The revision is read first, so if the chunk changes while it's encoded the packet is only
+/// ever marked older than it is, and gets made again before it's sent.
+fn encode_chunk(
+    state: &GlobalState,
+    (x, z, dim): &ChunkPos,
+    compressed: bool,
+) -> Result<ChunkPacket, NetError> {
+    let (x, z) = (*x, *z);
+    let revision = state.world.chunk_revision(x, z, dim);
+    let data = state.world.cached_chunk_packet(
+        x,
+        z,
+        dim,
+        compressed,
+        || -> Result<Vec<u8>, NetError> {
+            let packet = if state.world.chunk_exists(x, z, dim).unwrap_or(false) {
+                let chunk = state
+                    .world
+                    .load_chunk(x, z, dim)
+                    .map_err(|err| NetError::Misc(err.to_string()))?;
+                ChunkAndLightData::from_chunk_for_players(&state.world, &chunk)?
+            } else {
+                trace!("Generating chunk {}x{} in dimension {}", x, z, dim);
+                // Don't bother saving the chunk if it hasn't been edited yet
+                let chunk = state
+                    .terrain_generator
+                    .generate_chunk(x, z)
+                    .map_err(|err| NetError::Misc(err.to_string()))?;
+                ChunkAndLightData::from_chunk_for_players(&state.world, &chunk)?
+            };
+            if compressed {
+                compress_packet(&packet, true, &WithLength)
+            } else {
+                let mut buffer = Vec::new();
+                packet
+                    .encode(&mut buffer, &WithLength)
+                    .map_err(|e| NetError::Misc(e.to_string()))?;
+                Ok(buffer)
+            }
+        },
+    )?;
+    Ok(ChunkPacket { revision, data })
+}
@@ -20,6 +20,16 @@ pub enum Command {
     Import(ImportArgs),
     /// Start the server
     Run,
+    /// Rewrite every stored chunk with the compression settings from the config
+    Recompress(RecompressArgs),
+    /// Upgrade every stored chunk to the current chunk format
+    Migrate,
+    /// Show how many chunks are stored in each dimension
+    Stats,
+    /// Replace the world with a backup. The server must not be running
+    Restore(RestoreArgs),
+    /// Delete chunks that are far away or that players have barely spent any time in
+    Prune(PruneArgs),
 }
 
 #[derive(Debug, Clone, Parser)]
@@ -37,6 +47,54 @@ pub struct ImportArgs {
     pub max_concurrent_tasks: usize,
 }
 
+#[derive(Debug, Clone, Parser)]
+pub struct RecompressArgs {
+    /// Train a new zstd dictionary on the world before recompressing
+    ///
+    /// The dictionary is only used if `zstd_dictionary` is enabled in the database config.
+    #[clap(long)]
+    pub train_dictionary: bool,
+    /// Number of chunks to train the dictionary on
+    #[clap(long, default_value_t = 2000)]
+    pub dictionary_samples: usize,
+    /// Maximum size of the dictionary in bytes
+    #[clap(long, default_value_t = 112_640)]
+    pub dictionary_size: usize,
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct RestoreArgs {
+    /// The backup to restore, either a path or the name of a backup in the backups folder
+    ///
+    /// Defaults to the most recent backup.
+    pub backup: Option<String>,
+    /// List the available backups instead of restoring one
+    #[clap(long)]
+    pub list: bool,
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct PruneArgs {
+    /// Only prune this dimension. Defaults to every dimension
+    #[clap(long)]
+    pub dimension: Option<String>,
+    /// Prune chunks more than this many chunks away from the centre
+    #[clap(long)]
+    pub radius: Option<u32>,
+    /// The X coordinate of the chunk the radius is measured from
+    #[clap(long, default_value_t = 0, allow_hyphen_values = true)]
+    pub center_x: i32,
+    /// The Z coordinate of the chunk the radius is measured from
+    #[clap(long, default_value_t = 0, allow_hyphen_values = true)]
+    pub center_z: i32,
+    /// Prune chunks players have spent less than this many seconds near
+    #[clap(long)]
+    pub min_inhabited: Option<u64>,
+    /// Report what would be pruned without deleting anything
+    #[clap(long)]
+    pub dry_run: bool,
+}
+
 // Wrapper struct for the Level enum
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct LogLevel(Level);
@@ -163,12 +163,40 @@ fn build_timed_scheduler() -> Scheduler {
     // World sync
     let build_world_sync = |s: &mut Schedule| {
         s.add_systems(crate::systems::world_sync::sync_world);
+        s.add_systems(crate::systems::player_data::save_players);
     };
     timed.register(
         TimedSchedule::new("world_sync", Duration::from_secs(15), build_world_sync)
             .with_behavior(MissedTickBehavior::Skip),
     );
 
+    // Chunk activity, for pruning
+    let build_chunk_activity = |s: &mut Schedule| {
+        s.add_systems(crate::systems::chunk_activity::track_chunk_activity);
+    };
+    timed.register(
+        TimedSchedule::new(
+            "chunk_activity",
+            crate::systems::chunk_activity::ACTIVITY_PERIOD,
+            build_chunk_activity,
+        )
+        .with_behavior(MissedTickBehavior::Skip),
+    );
+
+    // Scheduled backups
+    let backups = &get_global_config().backups;
+    if backups.enabled && backups.interval > 0 {
+        let build_backups = |s: &mut Schedule| {
+            s.add_systems(crate::systems::backups::backup_world);
+        };
+        let backup_period = Duration::from_secs(backups.interval * 60);
+        timed.register(
+            TimedSchedule::new("backups", backup_period, build_backups)
+                .with_behavior(MissedTickBehavior::Skip)
+                .with_phase(backup_period),
+        );
+    }
+
     // Player count refresh
     let build_player_count = |s: &mut Schedule| {
         s.add_systems(crate::systems::player_count_update::player_count_updater);
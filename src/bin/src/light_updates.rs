@@ -0,0 +1,49 @@
+use crate::errors::BinaryError;
+use bevy_ecs::prelude::Entity;
+use ferrumc_core::chunks::chunk_receiver::ChunkReceiver;
+use ferrumc_net::connection::StreamWriter;
+use ferrumc_net::packets::outgoing::light_update::LightUpdatePacket;
+use ferrumc_state::GlobalState;
+use std::collections::BTreeMap;
+
+/// Relights the area around changed blocks and sends the new light to every connected player who
+/// has the chunk loaded.
+///
+/// The chunks containing the blocks need to have already been saved, since the light engine
+/// loads them from the world.
+pub fn relight_and_send<'a>(
+    state: &GlobalState,
+    dimension: &str,
+    positions: &[(i32, i32, i32)],
+    connections: impl IntoIterator<Item = (Entity, &'a ChunkReceiver, &'a StreamWriter)>,
+) -> Result<(), BinaryError> {
+    let changed_sections = state.world.relight(dimension, positions)?;
+    if changed_sections.is_empty() {
+        return Ok(());
+    }
+    let mut sections_by_chunk: BTreeMap<(i32, i32), Vec<i8>> = BTreeMap::new();
+    for (chunk_x, chunk_z, section_y) in changed_sections {
+        sections_by_chunk
+            .entry((chunk_x, chunk_z))
+            .or_default()
+            .push(section_y);
+    }
+    let mut packets = Vec::with_capacity(sections_by_chunk.len());
+    for ((chunk_x, chunk_z), sections) in sections_by_chunk {
+        let chunk = state.world.load_chunk(chunk_x, chunk_z, dimension)?;
+        let key = (chunk_x, chunk_z, dimension.to_string());
+        packets.push((key, LightUpdatePacket::from_sections(&chunk, &sections)));
+    }
+    for (eid, receiver, conn) in connections {
+        if !state.players.is_connected(eid) {
+            continue;
+        }
+        for (_, packet) in packets
+            .iter()
+            .filter(|(key, _)| receiver.seen.contains(key))
+        {
+            conn.send_packet_ref(packet)?;
+        }
+    }
+    Ok(())
+}
@@ -2,24 +2,33 @@
 
 use crate::errors::BinaryError;
 use clap::Parser;
-use ferrumc_config::server_config::get_global_config;
+use ferrumc_config::server_config::{get_global_config, DatabaseCompression};
 use ferrumc_config::whitelist::create_whitelist;
 use ferrumc_general_purpose::paths::get_root_path;
 use ferrumc_state::player_list::PlayerList;
 use ferrumc_state::{GlobalState, ServerState};
 use ferrumc_threadpool::ThreadPool;
+use ferrumc_world::activity::TICKS_PER_SECOND;
+use ferrumc_world::backups::{list_backups, restore_backup};
+use ferrumc_world::metadata::{parse_seed, WorldMetadata};
+use ferrumc_world::pruning::PruneOptions;
+use ferrumc_world::spatial::REGION_SIZE;
+use ferrumc_world::tickets::TicketType;
 use ferrumc_world::World;
 use ferrumc_world_gen::WorldGenerator;
-use std::sync::Arc;
+use std::collections::HashSet;
+use std::sync::{Arc, RwLock};
 use std::time::Instant;
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
 pub(crate) mod errors;
-use crate::cli::{CLIArgs, Command, ImportArgs};
+use crate::cli::{CLIArgs, Command, ImportArgs, PruneArgs, RecompressArgs, RestoreArgs};
 mod chunk_sending;
 mod cli;
 mod game_loop;
+mod light_updates;
 mod packet_handlers;
+mod protection;
 mod register_events;
 mod register_resources;
 mod systems;
@@ -55,6 +64,44 @@ fn main() {
                 info!("Import completed successfully.");
             }
         }
+        Some(Command::Recompress(recompress_args)) => {
+            info!("Starting recompression...");
+            if let Err(e) = handle_recompress(recompress_args) {
+                error!(
+                    "Recompression failed with the following error: {}",
+                    e.to_string()
+                );
+            } else {
+                info!("Recompression completed successfully.");
+            }
+        }
+        Some(Command::Migrate) => {
+            info!("Starting migration...");
+            if let Err(e) = handle_migrate() {
+                error!(
+                    "Migration failed with the following error: {}",
+                    e.to_string()
+                );
+            } else {
+                info!("Migration completed successfully.");
+            }
+        }
+        Some(Command::Stats) => {
+            if let Err(e) = handle_stats() {
+                error!("Could not read world stats: {}", e.to_string());
+            }
+        }
+        Some(Command::Restore(restore_args)) => {
+            if let Err(e) = handle_restore(restore_args) {
+                error!("Restore failed with the following error: {}", e.to_string());
+            }
+        }
+        Some(Command::Prune(prune_args)) => {
+            info!("Starting prune...");
+            if let Err(e) = handle_prune(prune_args) {
+                error!("Prune failed with the following error: {}", e.to_string());
+            }
+        }
         Some(Command::Run) | None => {
             info!("Starting server...");
             if let Err(e) = ferrumc_config::setup::setup() {
@@ -71,14 +118,14 @@ fn main() {
     }
 }
 
-fn generate_chunks(state: GlobalState) -> Result<(), BinaryError> {
+fn generate_chunks(state: GlobalState, spawn_chunk: (i32, i32)) -> Result<(), BinaryError> {
     info!("No overworld spawn chunk found, generating spawn chunks...");
-    // Generate a 12x12 chunk area around the spawn point
+    // Generate the area around the spawn point that players can see when they join
     let mut chunks = Vec::new();
     let start = Instant::now();
     let radius = get_global_config().chunk_render_distance as i32;
-    for x in -radius..=radius {
-        for z in -radius..=radius {
+    for x in spawn_chunk.0 - radius..=spawn_chunk.0 + radius {
+        for z in spawn_chunk.1 - radius..=spawn_chunk.1 + radius {
             chunks.push((x, z));
         }
     }
@@ -101,6 +148,7 @@ fn generate_chunks(state: GlobalState) -> Result<(), BinaryError> {
         });
     }
     batch.wait();
+    state.world.sync()?;
     info!("Finished generating spawn chunks in {:?}", start.elapsed());
     Ok(())
 }
@@ -109,9 +157,25 @@ fn entry(start_time: Instant) -> Result<(), BinaryError> {
     let state = create_state(start_time)?;
     let global_state = Arc::new(state);
     create_whitelist();
-    if !global_state.world.chunk_exists(0, 0, "overworld")? {
-        generate_chunks(global_state.clone())?;
+    let spawn = global_state.world_metadata.read().unwrap().spawn;
+    let spawn_chunk = (spawn.x >> 4, spawn.z >> 4);
+    if !global_state
+        .world
+        .chunk_exists(spawn_chunk.0, spawn_chunk.1, "overworld")?
+    {
+        generate_chunks(global_state.clone(), spawn_chunk)?;
     }
+    // Keep the area around spawn loaded, along with any chunks admins have force loaded
+    global_state.world.add_chunk_ticket(
+        TicketType::Spawn {
+            radius: get_global_config().spawn_chunk_radius,
+        },
+        spawn_chunk.0,
+        spawn_chunk.1,
+        "overworld",
+    )?;
+    let loaded = global_state.world.load_ticketed_chunks()?;
+    info!("Loaded {} spawn and force loaded chunks", loaded);
 
     ctrlc::set_handler({
         let global_state = global_state.clone();
@@ -121,9 +185,13 @@ fn entry(start_time: Instant) -> Result<(), BinaryError> {
                 .shut_down
                 .store(true, std::sync::atomic::Ordering::Relaxed);
             global_state
+                .save_world_metadata()
+                .expect("Failed to save world metadata before shutdown");
+            let written = global_state
                 .world
                 .sync()
-                .expect("Failed to sync world before shutdown")
+                .expect("Failed to sync world before shutdown");
+            info!("Saved {} chunks", written);
         }
     })
     .expect("Error setting Ctrl-C handler");
@@ -154,13 +222,187 @@ fn handle_import(import_args: ImportArgs) -> Result<(), BinaryError> {
     Ok(())
 }
 
+fn handle_recompress(recompress_args: RecompressArgs) -> Result<(), BinaryError> {
+    //! Rewrites the stored chunks with the current compression settings.
+    let config = &get_global_config().database;
+    let world = World::new(&config.db_path);
+
+    if recompress_args.train_dictionary {
+        if !config.zstd_dictionary || config.compression != DatabaseCompression::Zstd {
+            warn!(
+                "The dictionary will only be used once compression is set to zstd and \
+                 zstd_dictionary is enabled in the config."
+            );
+        }
+        let id = world.train_chunk_dictionary(
+            recompress_args.dictionary_samples,
+            recompress_args.dictionary_size,
+        )?;
+        info!("Trained zstd dictionary {}", id);
+    }
+
+    let recompressed = world.recompress()?;
+    info!(
+        "Recompressed {} chunks with {:?}",
+        recompressed, config.compression
+    );
+    Ok(())
+}
+
+fn handle_migrate() -> Result<(), BinaryError> {
+    //! Upgrades the stored chunks to the current chunk format.
+    let world = World::new(&get_global_config().database.db_path);
+    world.migrate()?;
+    Ok(())
+}
+
+fn handle_restore(restore_args: RestoreArgs) -> Result<(), BinaryError> {
+    //! Replaces the world database with a backup, or lists the available backups.
+    let config = get_global_config();
+    let root_path = get_root_path();
+    let backup_dir = root_path.join(&config.backups.path);
+    let backups = list_backups(&backup_dir)?;
+
+    if restore_args.list {
+        if backups.is_empty() {
+            info!("There are no backups in {}", backup_dir.display());
+        }
+        for backup in backups {
+            info!(
+                "{} (taken at {}, {:?})",
+                backup.path.display(),
+                backup.manifest.created,
+                backup.manifest.backend
+            );
+        }
+        return Ok(());
+    }
+
+    let backup_path = match restore_args.backup {
+        Some(backup) if backup_dir.join(&backup).is_dir() => backup_dir.join(backup),
+        Some(backup) => root_path.join(backup),
+        None => match backups.last() {
+            Some(latest) => latest.path.clone(),
+            None => {
+                return Err(BinaryError::Custom(format!(
+                    "There are no backups in {}",
+                    backup_dir.display()
+                )))
+            }
+        },
+    };
+
+    info!("Restoring world from {}...", backup_path.display());
+    restore_backup(&backup_path, &root_path.join(&config.database.db_path))?;
+    info!("World restored from {}", backup_path.display());
+    Ok(())
+}
+
+fn handle_prune(prune_args: PruneArgs) -> Result<(), BinaryError> {
+    //! Deletes chunks beyond a radius or below an inhabited time, or reports what would be.
+    if prune_args.radius.is_none() && prune_args.min_inhabited.is_none() {
+        return Err(BinaryError::Custom(
+            "Nothing to prune by, set --radius and/or --min-inhabited".to_string(),
+        ));
+    }
+    let options = PruneOptions {
+        radius: prune_args.radius,
+        center: (prune_args.center_x, prune_args.center_z),
+        min_inhabited_time: prune_args
+            .min_inhabited
+            .map(|seconds| seconds * TICKS_PER_SECOND),
+        keep: Vec::new(),
+        dry_run: prune_args.dry_run,
+    };
+
+    let world = World::new(&get_global_config().database.db_path);
+    let dimensions = match prune_args.dimension {
+        Some(dimension) => vec![dimension],
+        None => world.list_dimensions(),
+    };
+    let mut pruned = 0;
+    for dimension in dimensions {
+        pruned += world.prune(&dimension, &options)?.pruned.len();
+    }
+    if options.dry_run {
+        info!(
+            "Dry run: {} chunks would be pruned. Run again without --dry-run to delete them.",
+            pruned
+        );
+    } else {
+        info!("Pruned {} chunks", pruned);
+    }
+    Ok(())
+}
+
+fn handle_stats() -> Result<(), BinaryError> {
+    //! Logs how many chunks and regions are stored in each dimension.
+    let world = World::new(&get_global_config().database.db_path);
+    let dimensions = world.list_dimensions();
+    if dimensions.is_empty() {
+        info!("The world is empty.");
+    }
+    for dimension in dimensions {
+        let coords = world.chunk_coords(&dimension, None)?;
+        if coords.is_empty() {
+            info!("{}: no chunks", dimension);
+            continue;
+        }
+        let regions: HashSet<(i32, i32)> = coords
+            .iter()
+            .map(|(x, z)| (x.div_euclid(REGION_SIZE), z.div_euclid(REGION_SIZE)))
+            .collect();
+        let (min_x, max_x) = coords
+            .iter()
+            .map(|(x, _)| *x)
+            .fold((i32::MAX, i32::MIN), |(min, max), x| {
+                (min.min(x), max.max(x))
+            });
+        let (min_z, max_z) = coords
+            .iter()
+            .map(|(_, z)| *z)
+            .fold((i32::MAX, i32::MIN), |(min, max), z| {
+                (min.min(z), max.max(z))
+            });
+        info!(
+            "{}: {} chunks in {} regions, from chunk ({}, {}) to ({}, {})",
+            dimension,
+            coords.len(),
+            regions.len(),
+            min_x,
+            min_z,
+            max_x,
+            max_z
+        );
+    }
+    Ok(())
+}
+
 fn create_state(start_time: Instant) -> Result<ServerState, BinaryError> {
+    let world = World::new(&get_global_config().database.db_path);
+    let world_metadata = match world.load_metadata()? {
+        Some(metadata) => metadata,
+        None => {
+            // Worlds from before metadata was saved were all generated with a seed of 0, so they
+            // have to keep it for new chunks to line up with the old ones
+            let seed = if world.has_chunks() {
+                0
+            } else {
+                parse_seed(&get_global_config().world_seed).unwrap_or_else(rand::random)
+            };
+            info!("Creating world metadata with seed {}", seed as i64);
+            let metadata = WorldMetadata::new(seed);
+            world.save_metadata(&metadata)?;
+            metadata
+        }
+    };
     Ok(ServerState {
-        world: World::new(&get_global_config().database.db_path),
-        terrain_generator: WorldGenerator::new(0),
+        world,
+        terrain_generator: WorldGenerator::new(world_metadata.seed),
         shut_down: false.into(),
         players: PlayerList::default(),
         thread_pool: ThreadPool::new(),
         start_time,
+        world_metadata: RwLock::new(world_metadata),
     })
 }
@@ -24,6 +24,6 @@ pub fn handle(
             );
             continue;
         }
-        chunk_recv.chunks_per_tick = event.chunks_per_tick;
+        chunk_recv.batch_acknowledged(event.chunks_per_tick);
     }
 }
@@ -1,7 +1,9 @@
-use std::sync::Arc;
-
+use crate::light_updates::relight_and_send;
+use crate::protection::reject_block_change;
 use bevy_ecs::prelude::{Entity, Query, Res};
+use ferrumc_core::chunks::chunk_receiver::ChunkReceiver;
 use ferrumc_core::collisions::bounds::CollisionBounds;
+use ferrumc_core::identity::player_identity::PlayerIdentity;
 use ferrumc_core::transform::position::Position;
 use ferrumc_net::connection::StreamWriter;
 use ferrumc_net::packets::outgoing::block_change_ack::BlockChangeAck;
@@ -10,6 +12,9 @@ use ferrumc_net::PlaceBlockReceiver;
 use ferrumc_net_codec::net_types::network_position::NetworkPosition;
 use ferrumc_net_codec::net_types::var_int::VarInt;
 use ferrumc_state::GlobalStateResource;
+use ferrumc_world::audit::BlockAuditEntry;
+use ferrumc_world::block_id::BlockId;
+use ferrumc_world::protection::ProtectionAction;
 use tracing::{debug, error, trace};
 
 use ferrumc_inventories::hotbar::Hotbar;
@@ -32,11 +37,12 @@ static ITEM_TO_BLOCK_MAPPING: Lazy<HashMap<i32, i32>> = Lazy::new(|| {
 pub fn handle(
     events: Res<PlaceBlockReceiver>,
     state: Res<GlobalStateResource>,
-    query: Query<(Entity, &StreamWriter, &Inventory, &Hotbar)>,
+    query: Query<(Entity, &StreamWriter, &Inventory, &Hotbar, &PlayerIdentity)>,
     pos_q: Query<(&Position, &CollisionBounds)>,
+    conn_q: Query<(Entity, &ChunkReceiver, &StreamWriter)>,
 ) {
     'ev_loop: for (event, eid) in events.0.try_iter() {
-        let Ok((entity, conn, inventory, hotbar)) = query.get(eid) else {
+        let Ok((entity, conn, inventory, hotbar, identity)) = query.get(eid) else {
             debug!("Could not get connection for entity {:?}", eid);
             continue;
         };
@@ -64,7 +70,7 @@ pub fn handle(
                         "Placing block with item ID: {}, mapped to block ID: {}",
                         item_id.0, mapped_block_id
                     );
-                    let mut chunk = match state.0.world.load_chunk_owned(
+                    let chunk = match state.0.world.load_chunk(
                         event.position.x >> 4,
                         event.position.z >> 4,
                         "overworld",
@@ -120,6 +126,25 @@ pub fn handle(
                         trace!("Block placement collided with entity");
                         continue 'ev_loop;
                     }
+                    if !state.0.world.can_modify_block(
+                        identity.uuid.as_u128(),
+                        "overworld",
+                        (x, y as i32, z),
+                        ProtectionAction::Build,
+                    ) {
+                        trace!("Block placement at ({}, {}, {}) is protected", x, y, z);
+                        if let Err(err) = reject_block_change(
+                            &state.0,
+                            entity,
+                            conn,
+                            (x, y as i32, z),
+                            event.sequence,
+                            "You can't build here",
+                        ) {
+                            error!("Failed to reject block placement: {:?}", err);
+                        }
+                        continue 'ev_loop;
+                    }
                     let packet = BlockChangeAck {
                         sequence: event.sequence,
                     };
@@ -128,12 +153,27 @@ pub fn handle(
                         continue 'ev_loop;
                     }
 
-                    if let Err(err) =
-                        chunk.set_block(x & 0xF, y as i32, z & 0xF, VarInt::new(*mapped_block_id))
-                    {
-                        error!("Failed to set block: {:?}", err);
-                        continue 'ev_loop;
-                    }
+                    // The placed block can be in the chunk next to the one that was clicked
+                    let placed_block = BlockId::from(VarInt::new(*mapped_block_id));
+                    let old_block = match state.0.world.edit(x >> 4, z >> 4, "overworld", |chunk| {
+                        let old_block = chunk.get_block(x, y as i32, z)?;
+                        chunk.set_block(x, y as i32, z, placed_block)?;
+                        Ok(old_block)
+                    }) {
+                        Ok(old_block) => old_block,
+                        Err(err) => {
+                            error!("Failed to set block: {:?}", err);
+                            continue 'ev_loop;
+                        }
+                    };
+                    state.0.world.log_block_changes(&[BlockAuditEntry::now(
+                        identity.uuid.as_u128(),
+                        &identity.username,
+                        "overworld",
+                        (x, y as i32, z),
+                        old_block,
+                        placed_block,
+                    )]);
                     let ack_packet = BlockChangeAck {
                         sequence: event.sequence,
                     };
@@ -151,10 +191,11 @@ pub fn handle(
                         continue 'ev_loop;
                     }
 
-                    if let Err(err) = state.0.world.save_chunk(Arc::from(chunk)) {
-                        error!("Failed to save chunk after block placement: {:?}", err);
-                    } else {
-                        trace!("Block placed at ({}, {}, {})", x, y, z);
+                    trace!("Block placed at ({}, {}, {})", x, y, z);
+                    if let Err(err) =
+                        relight_and_send(&state.0, "overworld", &[(x, y as i32, z)], conn_q.iter())
+                    {
+                        error!("Failed to update light after block placement: {:?}", err);
                     }
                 }
             }
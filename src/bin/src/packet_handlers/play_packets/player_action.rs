@@ -1,14 +1,19 @@
-use std::sync::Arc;
-
 use crate::errors::BinaryError;
+use crate::light_updates::relight_and_send;
+use crate::protection::reject_block_change;
 use bevy_ecs::prelude::{Entity, Query, Res};
+use ferrumc_core::chunks::chunk_receiver::ChunkReceiver;
+use ferrumc_core::identity::player_identity::PlayerIdentity;
 use ferrumc_net::connection::StreamWriter;
 use ferrumc_net::packets::outgoing::block_change_ack::BlockChangeAck;
 use ferrumc_net::packets::outgoing::block_update::BlockUpdate;
 use ferrumc_net::PlayerActionReceiver;
 use ferrumc_net_codec::net_types::var_int::VarInt;
 use ferrumc_state::GlobalStateResource;
+use ferrumc_world::audit::BlockAuditEntry;
 use ferrumc_world::block_id::BlockId;
+use ferrumc_world::errors::WorldError;
+use ferrumc_world::protection::ProtectionAction;
 use ferrumc_world::vanilla_chunk_format::BlockData;
 use tracing::{debug, error, trace};
 
@@ -16,36 +21,67 @@ pub fn handle(
     events: Res<PlayerActionReceiver>,
     state: Res<GlobalStateResource>,
     query: Query<(Entity, &StreamWriter)>,
+    identity_query: Query<&PlayerIdentity>,
+    receivers: Query<(Entity, &ChunkReceiver, &StreamWriter)>,
 ) {
     // https://minecraft.wiki/w/Minecraft_Wiki:Projects/wiki.vg_merge/Protocol?oldid=2773393#Player_Action
     for (event, trigger_eid) in events.0.try_iter() {
         let res: Result<(), BinaryError> = try {
+            let position = (event.location.x, event.location.y as i32, event.location.z);
+            let identity = identity_query.get(trigger_eid).ok();
             match event.status.0 {
+                0 if identity.is_some_and(|identity| {
+                    !state.0.world.can_modify_block(
+                        identity.uuid.as_u128(),
+                        "overworld",
+                        position,
+                        ProtectionAction::Break,
+                    )
+                }) =>
+                {
+                    trace!("Block break at {:?} is protected", position);
+                    if let Ok((_, conn)) = query.get(trigger_eid) {
+                        reject_block_change(
+                            &state.0,
+                            trigger_eid,
+                            conn,
+                            position,
+                            event.sequence,
+                            "You can't break blocks here",
+                        )?;
+                    }
+                }
                 0 => {
-                    let mut chunk = match state.0.clone().world.load_chunk_owned(
-                        event.location.x >> 4,
-                        event.location.z >> 4,
+                    let (x, y, z) = position;
+                    let old_block = state.0.world.edit_or_create(
+                        x >> 4,
+                        z >> 4,
                         "overworld",
-                    ) {
-                        Ok(chunk) => chunk,
-                        Err(e) => {
-                            trace!("Chunk not found, generating new chunk: {:?}", e);
+                        || {
+                            trace!("Chunk not found, generating new chunk");
                             state
                                 .0
-                                .clone()
                                 .terrain_generator
-                                .generate_chunk(event.location.x >> 4, event.location.z >> 4)?
-                        }
-                    };
-                    let (relative_x, relative_y, relative_z) = (
-                        event.location.x.abs() % 16,
-                        event.location.y as i32,
-                        event.location.z.abs() % 16,
-                    );
-                    chunk.set_block(relative_x, relative_y, relative_z, BlockData::default())?;
-                    // Save the chunk to disk
-                    state.0.world.save_chunk(Arc::new(chunk))?;
-                    for (eid, conn) in query {
+                                .generate_chunk(x >> 4, z >> 4)
+                                .map_err(|e| WorldError::WorldGenerationError(e.to_string()))
+                        },
+                        |chunk| {
+                            let old_block = chunk.get_block(x, y, z)?;
+                            chunk.set_block(x, y, z, BlockData::default())?;
+                            Ok(old_block)
+                        },
+                    )?;
+                    if let Some(identity) = identity {
+                        state.0.world.log_block_changes(&[BlockAuditEntry::now(
+                            identity.uuid.as_u128(),
+                            &identity.username,
+                            "overworld",
+                            (x, y, z),
+                            old_block,
+                            BlockId::default(),
+                        )]);
+                    }
+                    for (eid, conn) in query.iter() {
                         if !state.0.players.is_connected(eid) {
                             continue;
                         }
@@ -62,6 +98,15 @@ pub fn handle(
                             conn.send_packet_ref(&ack_packet)?;
                         }
                     }
+                    relight_and_send(
+                        &state.0,
+                        "overworld",
+                        &[(event.location.x, event.location.y as i32, event.location.z)],
+                        receivers.iter(),
+                    )?;
+                    // Ores next to the broken block can be seen now, so stop hiding them
+                    let revealed = state.0.world.revealed_blocks("overworld", (x, y, z))?;
+                    state.0.world.notify_block_changes("overworld", revealed);
                 }
 
                 1 => {
@@ -48,8 +48,15 @@ pub fn handle(
                     player_pos.z,
                     head_block
                 );
-                // Teleport the player to the world center if their head block is not air
-                let packet = SynchronizePlayerPositionPacket::default();
+                // Teleport the player to the world spawn if their head block is not air
+                let spawn = state.0.world_metadata.read().unwrap().spawn;
+                let packet = SynchronizePlayerPositionPacket {
+                    x: spawn.x as f64 + 0.5,
+                    y: spawn.y as f64,
+                    z: spawn.z as f64 + 0.5,
+                    yaw: spawn.angle,
+                    ..Default::default()
+                };
                 if let Err(e) = conn.send_packet_ref(&packet) {
                     tracing::error!(
                         "Failed to send synchronize player position packet for player {}: {:?}",
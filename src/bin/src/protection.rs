@@ -0,0 +1,44 @@
+use crate::errors::BinaryError;
+use bevy_ecs::prelude::Entity;
+use ferrumc_core::mq;
+use ferrumc_net::connection::StreamWriter;
+use ferrumc_net::packets::outgoing::block_change_ack::BlockChangeAck;
+use ferrumc_net::packets::outgoing::block_update::BlockUpdate;
+use ferrumc_net_codec::net_types::network_position::NetworkPosition;
+use ferrumc_net_codec::net_types::var_int::VarInt;
+use ferrumc_state::GlobalState;
+use ferrumc_text::{NamedColor, TextComponentBuilder};
+use ferrumc_world::block_id::BlockId;
+use ferrumc_world::errors::WorldError;
+
+/// Undoes a block change the client has already shown, after a protected region stopped it.
+///
+/// The client predicts its own block changes, so it's sent the block that's really there before
+/// the change is acknowledged, and the player is told why on their action bar.
+pub fn reject_block_change(
+    state: &GlobalState,
+    entity: Entity,
+    conn: &StreamWriter,
+    (x, y, z): (i32, i32, i32),
+    sequence: VarInt,
+    message: &str,
+) -> Result<(), BinaryError> {
+    let block = match state.world.load_chunk(x >> 4, z >> 4, "overworld") {
+        Ok(chunk) => chunk.get_block(x, y, z)?,
+        Err(WorldError::ChunkNotFound) => BlockId::default(),
+        Err(e) => Err(e)?,
+    };
+    conn.send_packet_ref(&BlockUpdate {
+        location: NetworkPosition { x, y: y as i16, z },
+        block_id: VarInt::from(block),
+    })?;
+    conn.send_packet_ref(&BlockChangeAck { sequence })?;
+    mq::queue(
+        TextComponentBuilder::new(message)
+            .color(NamedColor::Red)
+            .build(),
+        true,
+        entity,
+    );
+    Ok(())
+}
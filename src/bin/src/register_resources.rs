@@ -1,3 +1,4 @@
+use crate::chunk_sending::ChunkStreamer;
 use crate::systems::new_connections::NewConnectionRecv;
 use bevy_ecs::prelude::World;
 use crossbeam_channel::Receiver;
@@ -16,6 +17,7 @@ pub fn register_resources(
     world.insert_resource(PlayerCountUpdateCooldown {
         last_update: std::time::Instant::now(),
     });
+    world.insert_resource(ChunkStreamer::default());
     world.insert_resource(WorldSyncTracker {
         last_synced: std::time::Instant::now(),
     });
@@ -0,0 +1,43 @@
+use bevy_ecs::prelude::Res;
+use ferrumc_config::server_config::get_global_config;
+use ferrumc_general_purpose::paths::get_root_path;
+use ferrumc_state::GlobalStateResource;
+use ferrumc_world::backups::{create_backup, prune_backups};
+use std::sync::atomic::{AtomicBool, Ordering};
+use tracing::{debug, error, info, warn};
+
+/// Set while a backup is being written, so a slow backup doesn't get a second one started
+/// on top of it.
+static BACKUP_RUNNING: AtomicBool = AtomicBool::new(false);
+
+pub fn backup_world(state: Res<GlobalStateResource>) {
+    if state.0.shut_down.load(Ordering::Relaxed) {
+        return;
+    }
+    if BACKUP_RUNNING.swap(true, Ordering::AcqRel) {
+        warn!("Skipping scheduled backup, the previous one is still running");
+        return;
+    }
+
+    // Copying the world can take a while, so keep it off the tick thread
+    let _handle = state.0.thread_pool.oneshot({
+        let state = state.0.clone();
+        move || {
+            let config = &get_global_config().backups;
+            let directory = get_root_path().join(&config.path);
+            match create_backup(&state.world, &directory) {
+                Ok(backup) => info!("Backed up world to {}", backup.path.display()),
+                Err(e) => error!("Failed to back up world: {}", e),
+            }
+            match prune_backups(&directory, config.keep_last, config.keep_daily) {
+                Ok(removed) => {
+                    for path in removed {
+                        debug!("Removed old backup {}", path.display());
+                    }
+                }
+                Err(e) => error!("Failed to remove old backups: {}", e),
+            }
+            BACKUP_RUNNING.store(false, Ordering::Release);
+        }
+    });
+}
@@ -0,0 +1,72 @@
+use bevy_ecs::prelude::{Entity, Query, Res};
+use ferrumc_core::chunks::chunk_receiver::{ChunkPos, ChunkReceiver};
+use ferrumc_net::connection::StreamWriter;
+use ferrumc_net::packets::outgoing::light_update::LightUpdatePacket;
+use ferrumc_net::packets::outgoing::section_blocks_update::SectionBlocksUpdate;
+use ferrumc_state::GlobalStateResource;
+use tracing::error;
+
+/// Sends the blocks that changed since the last tick to every player who has the chunk loaded,
+/// one section blocks update per changed section, followed by any light that changed with them.
+pub fn send_block_changes(
+    query: Query<(Entity, &ChunkReceiver, &StreamWriter)>,
+    state: Res<GlobalStateResource>,
+) {
+    let changes = state.0.world.drain_block_changes();
+    let light_changes = state.0.world.drain_light_changes();
+    if changes.is_empty() && light_changes.is_empty() {
+        return;
+    }
+    let block_packets: Vec<(ChunkPos, SectionBlocksUpdate)> = changes
+        .iter()
+        .map(|section| {
+            (
+                (section.chunk_x, section.chunk_z, section.dimension.clone()),
+                SectionBlocksUpdate::from_changes(section),
+            )
+        })
+        .collect();
+    let mut light_packets = Vec::with_capacity(light_changes.len());
+    for changed in light_changes {
+        match state
+            .0
+            .world
+            .load_chunk(changed.chunk_x, changed.chunk_z, &changed.dimension)
+        {
+            Ok(chunk) => light_packets.push((
+                (changed.chunk_x, changed.chunk_z, changed.dimension),
+                LightUpdatePacket::from_sections(&chunk, &changed.sections),
+            )),
+            Err(e) => error!(
+                "Failed to load chunk {}, {} to send its light: {}",
+                changed.chunk_x, changed.chunk_z, e
+            ),
+        }
+    }
+
+    for (eid, receiver, conn) in query.iter() {
+        if !state.0.players.is_connected(eid) {
+            continue;
+        }
+        // Clients ignore changes to chunks they haven't been sent, and chunks waiting to be sent
+        // are encoded again once they've changed
+        let block_packets = block_packets
+            .iter()
+            .filter(|(chunk, _)| receiver.seen.contains(chunk));
+        for (_, packet) in block_packets {
+            if let Err(e) = conn.send_packet_ref(packet) {
+                error!("Failed to send block changes to {:?}: {:?}", eid, e);
+                break;
+            }
+        }
+        let light_packets = light_packets
+            .iter()
+            .filter(|(chunk, _)| receiver.seen.contains(chunk));
+        for (_, packet) in light_packets {
+            if let Err(e) = conn.send_packet_ref(packet) {
+                error!("Failed to send light changes to {:?}: {:?}", eid, e);
+                break;
+            }
+        }
+    }
+}
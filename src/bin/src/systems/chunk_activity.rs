@@ -0,0 +1,45 @@
+use bevy_ecs::prelude::{Query, Res, With};
+use ferrumc_core::identity::player_identity::PlayerIdentity;
+use ferrumc_core::transform::position::Position;
+use ferrumc_state::GlobalStateResource;
+use ferrumc_world::activity::TICKS_PER_SECOND;
+use std::collections::HashSet;
+use std::time::Duration;
+use tracing::error;
+
+/// How often chunk activity is recorded.
+pub const ACTIVITY_PERIOD: Duration = Duration::from_secs(5);
+
+/// Chunks within this many chunks of a player count as inhabited, the same as vanilla's mob
+/// spawning range.
+const INHABITED_RADIUS: i32 = 8;
+
+pub fn track_chunk_activity(
+    query: Query<&Position, With<PlayerIdentity>>,
+    state: Res<GlobalStateResource>,
+) {
+    let mut chunks = HashSet::new();
+    for position in query.iter() {
+        let chunk_x = (position.x.floor() as i32) >> 4;
+        let chunk_z = (position.z.floor() as i32) >> 4;
+        for x in chunk_x - INHABITED_RADIUS..=chunk_x + INHABITED_RADIUS {
+            for z in chunk_z - INHABITED_RADIUS..=chunk_z + INHABITED_RADIUS {
+                chunks.insert((x, z));
+            }
+        }
+    }
+    if chunks.is_empty() {
+        return;
+    }
+
+    // Recording can read from the storage backend, so keep it off the tick thread
+    let _handle = state.0.thread_pool.oneshot({
+        let state = state.0.clone();
+        move || {
+            let ticks = ACTIVITY_PERIOD.as_secs() * TICKS_PER_SECOND;
+            if let Err(e) = state.world.record_activity("overworld", chunks, ticks) {
+                error!("Failed to record chunk activity: {}", e);
+            }
+        }
+    });
+}
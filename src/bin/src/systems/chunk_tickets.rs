@@ -0,0 +1,11 @@
+use bevy_ecs::prelude::Res;
+use ferrumc_state::GlobalStateResource;
+use tracing::trace;
+
+/// Lets go of the chunks temporary tickets were keeping loaded once they run out.
+pub fn expire_chunk_tickets(state: Res<GlobalStateResource>) {
+    let expired = state.0.world.expire_chunk_tickets();
+    if expired > 0 {
+        trace!("{} temporary chunk tickets expired", expired);
+    }
+}
@@ -1,17 +1,48 @@
+use crate::systems::player_data::player_data;
 use bevy_ecs::prelude::{Commands, Entity, Query, Res};
+use ferrumc_core::chunks::chunk_receiver::ChunkReceiver;
+use ferrumc_core::gamemode::GameMode;
 use ferrumc_core::identity::player_identity::PlayerIdentity;
+use ferrumc_core::transform::position::Position;
+use ferrumc_core::transform::rotation::Rotation;
+use ferrumc_inventories::hotbar::Hotbar;
+use ferrumc_inventories::inventory::Inventory;
 use ferrumc_net::connection::StreamWriter;
 use ferrumc_state::GlobalStateResource;
 use ferrumc_text::TextComponent;
-use tracing::{info, trace, warn};
+use ferrumc_world::tickets::TicketType;
+use tracing::{error, info, trace, warn};
+
+type DisconnectQuery<'a> = (
+    Entity,
+    &'a StreamWriter,
+    &'a PlayerIdentity,
+    &'a Position,
+    &'a Rotation,
+    &'a Inventory,
+    &'a Hotbar,
+    &'a GameMode,
+    &'a ChunkReceiver,
+);
 
 pub fn connection_killer(
-    query: Query<(Entity, &StreamWriter, &PlayerIdentity)>,
+    query: Query<DisconnectQuery>,
     mut cmd: Commands,
     state: Res<GlobalStateResource>,
 ) {
     while let Some((disconnecting_entity, reason)) = state.0.players.disconnection_queue.pop() {
-        for (entity, conn, player_identity) in query.iter() {
+        for (
+            entity,
+            conn,
+            player_identity,
+            position,
+            rotation,
+            inventory,
+            hotbar,
+            gamemode,
+            chunk_receiver,
+        ) in query.iter()
+        {
             if disconnecting_entity == entity {
                 info!(
                     "Player {} ({}) disconnected: {}",
@@ -42,10 +73,33 @@ pub fn connection_killer(
                         player_identity.username
                     );
                 }
+                let data = player_data(position, rotation, inventory, hotbar, gamemode);
+                if let Err(e) = state
+                    .0
+                    .world
+                    .save_player_data(player_identity.uuid.as_u128(), &data)
+                {
+                    error!(
+                        "Failed to save player data for {}: {}",
+                        player_identity.username, e
+                    );
+                }
+                let (chunk_x, chunk_z, dimension) = &chunk_receiver.last_chunk;
+                if let Err(e) = state.0.world.remove_chunk_ticket(
+                    TicketType::Player,
+                    *chunk_x,
+                    *chunk_z,
+                    dimension,
+                ) {
+                    error!(
+                        "Failed to unload the chunks around {}: {}",
+                        player_identity.username, e
+                    );
+                }
+                cmd.entity(entity).despawn();
             } else {
                 // Broadcast the disconnection to other players
             }
-            cmd.entity(entity).despawn();
         }
     }
 }
@@ -1,48 +1,61 @@
-use crate::systems::send_chunks::send_chunks;
 use bevy_ecs::prelude::{EventReader, Query, Res};
 use ferrumc_config::server_config::get_global_config;
+use ferrumc_core::chunks::chunk_receiver::ChunkReceiver;
 use ferrumc_core::chunks::cross_chunk_boundary_event::CrossChunkBoundaryEvent;
+use ferrumc_core::transform::rotation::Rotation;
 use ferrumc_net::connection::StreamWriter;
+use ferrumc_net::packets::outgoing::set_center_chunk::SetCenterChunk;
 use ferrumc_state::GlobalStateResource;
-use std::collections::HashSet;
+use ferrumc_world::errors::WorldError;
+use ferrumc_world::tickets::TicketType;
+use tracing::error;
 
+/// Queues the chunks that have come into view of players who moved into a new chunk. They're
+/// loaded and sent in the background, see [`crate::chunk_sending`].
 pub fn cross_chunk_boundary(
     mut events: EventReader<CrossChunkBoundaryEvent>,
-    mut query: Query<&mut StreamWriter>,
+    mut query: Query<(&mut ChunkReceiver, &StreamWriter, &Rotation)>,
     state: Res<GlobalStateResource>,
 ) {
     if events.is_empty() {
         return;
     }
+    let radius = get_global_config().chunk_render_distance as i32;
     for event in events.read() {
         if !state.0.players.is_connected(event.player) {
             continue; // Skip if the player is not connected
         }
-        let radius = get_global_config().chunk_render_distance as i32;
-
-        let mut old_chunk_seen = HashSet::new();
-        for x in event.old_chunk.0 - radius..event.old_chunk.0 + radius {
-            for z in event.old_chunk.1 - radius..event.old_chunk.1 + radius {
-                old_chunk_seen.insert((x, z));
-            }
+        let Ok((mut receiver, conn, rotation)) = query.get_mut(event.player) else {
+            error!("Player {} has no chunk receiver", event.player);
+            continue;
+        };
+        let (center_x, center_z) = event.new_chunk;
+        if let Err(e) = conn.send_packet_ref(&SetCenterChunk::new(center_x, center_z)) {
+            error!("Failed to send center chunk to {}: {:?}", event.player, e);
+            continue;
         }
-        let mut new_chunk_seen = HashSet::new();
-        for x in event.new_chunk.0 - radius..event.new_chunk.0 + radius {
-            for z in event.new_chunk.1 - radius..event.new_chunk.1 + radius {
-                new_chunk_seen.insert((x, z));
-            }
+        // The chunks around the player stay loaded wherever they go
+        let (old_x, old_z, old_dimension) = receiver.last_chunk.clone();
+        let moved: Result<_, WorldError> = try {
+            state
+                .0
+                .world
+                .add_chunk_ticket(TicketType::Player, center_x, center_z, "overworld")?;
+            state
+                .0
+                .world
+                .remove_chunk_ticket(TicketType::Player, old_x, old_z, &old_dimension)?;
+        };
+        if let Err(e) = moved {
+            error!(
+                "Failed to move the chunk ticket for {}: {}",
+                event.player, e
+            );
         }
-        let needed_chunks: Vec<_> = new_chunk_seen
-            .iter()
-            .filter(|chunk| !old_chunk_seen.contains(chunk))
-            .map(|chunk| {
-                let (x, z) = *chunk;
-                (x, z, "overworld".to_string())
-            })
-            .collect();
-        let center_chunk = (event.new_chunk.0, event.new_chunk.1);
-        let mut conn = query.get_mut(event.player).expect("Player does not exist");
-        send_chunks(state.0.clone(), needed_chunks, &mut conn, center_chunk)
-            .expect("Failed to send chunks")
+        receiver.recenter(
+            (center_x, center_z, "overworld".to_string()),
+            radius,
+            rotation.yaw,
+        );
     }
 }
@@ -1,18 +1,29 @@
+pub mod backups;
+mod block_changes;
+pub mod chunk_activity;
+mod chunk_tickets;
 pub mod connection_killer;
 mod cross_chunk_boundary;
 pub mod keep_alive_system;
 mod mq;
 pub mod new_connections;
 pub mod player_count_update;
+pub mod player_data;
 pub mod send_chunks;
 pub mod shutdown_systems;
 pub mod world_sync;
+pub mod world_time;
 
 pub fn register_game_systems(schedule: &mut bevy_ecs::schedule::Schedule) {
     // Tick-bound systems only (run every game tick)
     schedule.add_systems(new_connections::accept_new_connections);
     schedule.add_systems(cross_chunk_boundary::cross_chunk_boundary);
+    schedule.add_systems(send_chunks::start_chunk_loads);
+    schedule.add_systems(send_chunks::send_loaded_chunks);
     schedule.add_systems(mq::process);
+    schedule.add_systems(world_time::advance_world_time);
+    schedule.add_systems(block_changes::send_block_changes);
+    schedule.add_systems(chunk_tickets::expire_chunk_tickets);
 
     // Should always be last
     schedule.add_systems(connection_killer::connection_killer);
@@ -1,14 +1,23 @@
+use crate::systems::player_data::load_inventory;
 use bevy_ecs::prelude::{Commands, Res, Resource};
 use crossbeam_channel::Receiver;
+use ferrumc_config::server_config::get_global_config;
 use ferrumc_core::chunks::chunk_receiver::ChunkReceiver;
 use ferrumc_core::conn::keepalive::KeepAliveTracker;
+use ferrumc_core::gamemode::GameMode;
 use ferrumc_core::transform::grounded::OnGround;
 use ferrumc_core::transform::position::Position;
 use ferrumc_core::transform::rotation::Rotation;
 use ferrumc_inventories::hotbar::Hotbar;
 use ferrumc_inventories::inventory::Inventory;
-use ferrumc_net::connection::NewConnection;
+use ferrumc_inventories::slot::InventorySlot;
+use ferrumc_net::connection::{NewConnection, StreamWriter};
+use ferrumc_net::packets::outgoing::set_container_content::SetContainerContent;
+use ferrumc_net::packets::outgoing::set_held_slot::SetHeldSlotPacket;
+use ferrumc_net_codec::net_types::length_prefixed_vec::LengthPrefixedVec;
+use ferrumc_net_codec::net_types::var_int::VarInt;
 use ferrumc_state::GlobalStateResource;
+use ferrumc_world::tickets::TicketType;
 use std::time::Instant;
 use tracing::{error, trace};
 
@@ -25,11 +34,37 @@ pub fn accept_new_connections(
     }
     while let Ok(new_connection) = new_connections.0.try_recv() {
         let return_sender = new_connection.entity_return;
+        // Login already loaded the player's data and sent them to the right place
+        let player_data = new_connection.player_data;
+        let inventory = load_inventory(&player_data.inventory);
+        send_inventory(
+            &new_connection.stream,
+            &inventory,
+            player_data.selected_slot,
+        );
+        let (chunk_x, chunk_z) = (
+            (player_data.x.floor() as i32) >> 4,
+            (player_data.z.floor() as i32) >> 4,
+        );
+        // Login already sent the chunks around the player
+        let mut chunk_receiver = ChunkReceiver::default();
+        chunk_receiver.mark_sent(
+            (chunk_x, chunk_z, "overworld".to_string()),
+            get_global_config().chunk_render_distance as i32,
+        );
+        if let Err(e) =
+            state
+                .0
+                .world
+                .add_chunk_ticket(TicketType::Player, chunk_x, chunk_z, "overworld")
+        {
+            error!("Failed to load the chunks around a new player: {}", e);
+        }
         let entity = cmd.spawn((
             new_connection.stream,
-            Position::default(),
-            ChunkReceiver::default(),
-            Rotation::default(),
+            Position::new(player_data.x, player_data.y, player_data.z),
+            chunk_receiver,
+            Rotation::new(player_data.yaw, player_data.pitch),
             OnGround::default(),
             new_connection.player_identity.clone(),
             KeepAliveTracker {
@@ -37,8 +72,11 @@ pub fn accept_new_connections(
                 last_received_keep_alive: Instant::now(),
                 has_received_keep_alive: true,
             },
-            Inventory::new(46),
-            Hotbar::default(),
+            inventory,
+            Hotbar {
+                selected_slot: player_data.selected_slot,
+            },
+            GameMode::from_id(player_data.gamemode).unwrap_or_default(),
         ));
 
         state.0.players.player_list.insert(
@@ -66,3 +104,26 @@ pub fn accept_new_connections(
         }
     }
 }
+
+/// Sends a player the inventory and held slot they had when they last left.
+fn send_inventory(conn: &StreamWriter, inventory: &Inventory, selected_slot: u8) {
+    let slots = inventory
+        .slots
+        .iter()
+        .map(|slot| slot.clone().unwrap_or_default())
+        .collect();
+    let content = SetContainerContent {
+        window_id: VarInt::new(0),
+        state_id: VarInt::new(0),
+        slots: LengthPrefixedVec::new(slots),
+        carried_item: InventorySlot::default(),
+    };
+    if let Err(e) = conn.send_packet(content) {
+        error!("Failed to send inventory contents: {:?}", e);
+    }
+    if let Err(e) = conn.send_packet(SetHeldSlotPacket {
+        slot: VarInt::new(selected_slot as i32),
+    }) {
+        error!("Failed to send held slot: {:?}", e);
+    }
+}
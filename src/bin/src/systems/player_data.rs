@@ -0,0 +1,128 @@
+use bevy_ecs::prelude::{Query, Res};
+use ferrumc_core::gamemode::GameMode;
+use ferrumc_core::identity::player_identity::PlayerIdentity;
+use ferrumc_core::transform::position::Position;
+use ferrumc_core::transform::rotation::Rotation;
+use ferrumc_inventories::hotbar::Hotbar;
+use ferrumc_inventories::inventory::Inventory;
+use ferrumc_inventories::item::ItemID;
+use ferrumc_inventories::slot::InventorySlot;
+use ferrumc_net_codec::net_types::var_int::VarInt;
+use ferrumc_state::GlobalStateResource;
+use ferrumc_world::player_data::{PlayerData, SavedItem};
+use tracing::{debug, error};
+
+/// The number of slots in the player inventory window, including armour and the offhand.
+pub const PLAYER_INVENTORY_SIZE: usize = 46;
+
+type PlayerDataQuery<'a> = (
+    &'a PlayerIdentity,
+    &'a Position,
+    &'a Rotation,
+    &'a Inventory,
+    &'a Hotbar,
+    &'a GameMode,
+);
+
+/// Snapshots a player's components into the form they're saved in.
+///
+/// Item components aren't saved yet, only the item and how many there are.
+pub fn player_data(
+    position: &Position,
+    rotation: &Rotation,
+    inventory: &Inventory,
+    hotbar: &Hotbar,
+    gamemode: &GameMode,
+) -> PlayerData {
+    let inventory = inventory
+        .slots
+        .iter()
+        .enumerate()
+        .filter_map(|(slot, item)| {
+            let item = item.as_ref()?;
+            let item_id = item.item_id?;
+            if item.count.0 <= 0 {
+                return None;
+            }
+            Some(SavedItem {
+                slot: slot as u16,
+                item_id: item_id.0 .0,
+                count: item.count.0,
+            })
+        })
+        .collect();
+    PlayerData {
+        x: position.x,
+        y: position.y,
+        z: position.z,
+        dimension: "overworld".to_string(),
+        yaw: rotation.yaw,
+        pitch: rotation.pitch,
+        inventory,
+        selected_slot: hotbar.selected_slot,
+        gamemode: gamemode.id(),
+    }
+}
+
+/// Rebuilds a player's inventory from their saved items. Items in slots that don't exist are
+/// dropped.
+pub fn load_inventory(items: &[SavedItem]) -> Inventory {
+    let mut inventory = Inventory::new(PLAYER_INVENTORY_SIZE);
+    for item in items {
+        let slot = InventorySlot {
+            count: VarInt::new(item.count),
+            item_id: Some(ItemID::new(item.item_id)),
+            components_to_add_count: Some(VarInt::new(0)),
+            components_to_remove_count: Some(VarInt::new(0)),
+            ..Default::default()
+        };
+        if inventory.set_item(item.slot as usize, slot).is_err() {
+            error!("Dropping saved item in invalid slot {}", item.slot);
+        }
+    }
+    inventory
+}
+
+fn collect_players(query: &Query<PlayerDataQuery>) -> Vec<(u128, PlayerData)> {
+    query
+        .iter()
+        .map(
+            |(identity, position, rotation, inventory, hotbar, gamemode)| {
+                (
+                    identity.uuid.as_u128(),
+                    player_data(position, rotation, inventory, hotbar, gamemode),
+                )
+            },
+        )
+        .collect()
+}
+
+/// Saves every online player's data. Runs alongside the world sync.
+pub fn save_players(query: Query<PlayerDataQuery>, state: Res<GlobalStateResource>) {
+    let players = collect_players(&query);
+    if players.is_empty() {
+        return;
+    }
+    let _handle = state.0.thread_pool.oneshot({
+        let state = state.0.clone();
+        move || {
+            if let Err(e) = state.world.save_player_data_batch(&players) {
+                error!("Failed to save player data: {}", e);
+            } else {
+                debug!("Saved data for {} players", players.len());
+            }
+        }
+    });
+}
+
+/// Saves every online player's data before the server stops. This blocks, since there won't be
+/// a thread pool to hand it off to for much longer.
+pub fn save_players_on_shutdown(query: Query<PlayerDataQuery>, state: Res<GlobalStateResource>) {
+    let players = collect_players(&query);
+    if players.is_empty() {
+        return;
+    }
+    if let Err(e) = state.0.world.save_player_data_batch(&players) {
+        error!("Failed to save player data on shutdown: {}", e);
+    }
+}
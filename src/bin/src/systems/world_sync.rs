@@ -1,6 +1,7 @@
 use bevy_ecs::prelude::{Res, ResMut};
 use ferrumc_core::chunks::world_sync_tracker::WorldSyncTracker;
 use ferrumc_state::GlobalStateResource;
+use tracing::{debug, error};
 
 pub fn sync_world(state: Res<GlobalStateResource>, mut last_synced: ResMut<WorldSyncTracker>) {
     if state.0.shut_down.load(std::sync::atomic::Ordering::Relaxed) {
@@ -11,7 +12,11 @@ pub fn sync_world(state: Res<GlobalStateResource>, mut last_synced: ResMut<World
     let _handle = state.0.thread_pool.oneshot({
         let state = state.0.clone();
         move || {
-            state.world.sync().expect("Failed to sync world");
+            if let Err(e) = state.save_world_metadata() {
+                error!("Failed to save world metadata: {}", e);
+            }
+            let written = state.world.sync().expect("Failed to sync world");
+            debug!("World sync wrote {} dirty chunks", written);
         }
     });
 
@@ -0,0 +1,6 @@
+use bevy_ecs::prelude::Res;
+use ferrumc_state::GlobalStateResource;
+
+pub fn advance_world_time(state: Res<GlobalStateResource>) {
+    state.0.world_metadata.write().unwrap().tick();
+}
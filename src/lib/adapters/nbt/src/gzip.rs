@@ -0,0 +1,61 @@
+//! Support for NBT files that are gzip compressed on disk, like vanilla's `level.dat` and
+//! `playerdata/*.dat`.
+
+use crate::Result;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::io::{Read, Write};
+use std::path::Path;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Checks for the gzip header at the start of some data.
+pub fn is_gzip(data: &[u8]) -> bool {
+    data.starts_with(&GZIP_MAGIC)
+}
+
+/// Decompresses gzipped NBT so it can be parsed. Data that isn't gzipped is returned as is, since
+/// some tools write these files uncompressed.
+pub fn decompress_gzip(data: &[u8]) -> Result<Vec<u8>> {
+    if !is_gzip(data) {
+        return Ok(data.to_vec());
+    }
+    let mut decompressed = Vec::new();
+    GzDecoder::new(data).read_to_end(&mut decompressed)?;
+    Ok(decompressed)
+}
+
+/// Reads an NBT file from disk, decompressing it if needed.
+pub fn read_nbt_file(path: &Path) -> Result<Vec<u8>> {
+    decompress_gzip(&std::fs::read(path)?)
+}
+
+/// Gzip compresses NBT the way vanilla writes it to disk.
+pub fn compress_gzip(data: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+/// Writes NBT to a file on disk, gzip compressed.
+pub fn write_nbt_file(path: &Path, data: &[u8]) -> Result<()> {
+    std::fs::write(path, compress_gzip(data)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decompress_gzip() {
+        // An empty compound named "" is the smallest valid NBT file
+        let nbt = [10u8, 0, 0, 0];
+        let compressed = compress_gzip(&nbt).unwrap();
+
+        assert!(is_gzip(&compressed));
+        assert_eq!(decompress_gzip(&compressed).unwrap(), nbt);
+        assert_eq!(decompress_gzip(&nbt).unwrap(), nbt);
+    }
+}
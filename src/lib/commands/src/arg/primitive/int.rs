@@ -77,9 +77,9 @@ impl<const MIN: i32, const MAX: i32> CommandArgument for Integer<MIN, MAX> {
             )));
         }
 
-        if int > MIN {
+        if int > MAX {
             return Err(parser_error(&format!(
-                "integer too large: {int}, expected at most {MIN}"
+                "integer too large: {int}, expected at most {MAX}"
             )));
         }
 
@@ -87,6 +87,6 @@ impl<const MIN: i32, const MAX: i32> CommandArgument for Integer<MIN, MAX> {
     }
 
     fn primitive() -> PrimitiveArgument {
-        PrimitiveArgument::int(Some(MIN), Some(MIN))
+        PrimitiveArgument::int(Some(MIN), Some(MAX))
     }
 }
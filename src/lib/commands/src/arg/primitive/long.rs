@@ -77,9 +77,9 @@ impl<const MIN: i64, const MAX: i64> CommandArgument for Long<MIN, MAX> {
             )));
         }
 
-        if long > MIN {
+        if long > MAX {
             return Err(parser_error(&format!(
-                "integer too large: {long}, expected at most {MIN}"
+                "integer too large: {long}, expected at most {MAX}"
             )));
         }
 
@@ -87,6 +87,6 @@ impl<const MIN: i64, const MAX: i64> CommandArgument for Long<MIN, MAX> {
     }
 
     fn primitive() -> PrimitiveArgument {
-        PrimitiveArgument::long(Some(MIN), Some(MIN))
+        PrimitiveArgument::long(Some(MIN), Some(MAX))
     }
 }
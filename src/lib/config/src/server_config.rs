@@ -6,6 +6,7 @@ use ferrumc_general_purpose::paths::get_root_path;
 use figment::providers::Format;
 use once_cell::sync::OnceCell;
 use serde_derive::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
 
 static STATIC_CONFIG: OnceCell<ServerConfig> = OnceCell::new();
 pub(crate) const DEFAULT_CONFIG: &str =
@@ -33,10 +34,18 @@ pub fn set_global_config(config: ServerConfig) {
 /// - `tps`: The ticks per second that the server will run at.
 /// - `database` - [DatabaseConfig]: The configuration for the database.
 /// - `world`: The name of the world that the server will load.
+/// - `world_seed`: The seed to generate the world with when it's first created. Empty for a
+///   random seed. Changing this after the world has been created does nothing.
 /// - `network_compression_threshold`: The threshold at which the server will compress network packets.
 /// - `whitelist`: Whether the server whitelist is enabled or not.
+/// - `operators`: The players who can use commands that affect the whole server, by username or
+///   UUID. The console can always use them.
 /// - `chunk_render_distance`: The render distance of the chunks. This is the number of chunks that will be
 ///   loaded around the player.
+/// - `spawn_chunk_radius`: How many chunks around spawn are kept loaded. These stay in memory the
+///   whole time the server is running, on top of the chunk cache.
+/// - `backups` - [BackupConfig]: The configuration for scheduled world backups.
+/// - `anti_xray` - [AntiXrayConfig]: The configuration for hiding ores from x-ray clients.
 #[derive(Debug, Deserialize, Serialize, Default)]
 pub struct ServerConfig {
     pub host: String,
@@ -46,10 +55,15 @@ pub struct ServerConfig {
     pub tps: u32,
     pub database: DatabaseConfig,
     pub world: String,
+    pub world_seed: String,
     pub network_compression_threshold: i32, // Can be negative
     pub verify_decompressed_packets: bool,
     pub whitelist: bool,
+    pub operators: Vec<String>,
     pub chunk_render_distance: u32,
+    pub spawn_chunk_radius: u8,
+    pub backups: BackupConfig,
+    pub anti_xray: AntiXrayConfig,
 }
 
 /// The database configuration section from [ServerConfig].
@@ -63,6 +77,14 @@ pub struct ServerConfig {
 ///   but it won't actually use that much memory, it'll just show up as virtual memory use.
 /// - `cache_ttl`: The time to live for cache entries in seconds.
 /// - `cache_capacity`: How big the cache can be in kb.
+/// - `packet_cache_capacity`: How big the cache of encoded chunk packets can be in kb. This is on
+///   top of `cache_capacity`.
+/// - `backend`: [DatabaseBackend]: Which storage engine to keep the world in.
+/// - `compression` - [DatabaseCompression]: Which compression algorithm to store chunks with.
+/// - `compression_level`: The compression level to use. The valid range depends on the algorithm,
+///   and it's ignored if compression is disabled.
+/// - `zstd_dictionary`: Whether to compress chunks with a zstd dictionary trained on the world.
+///   Only used with `zstd` compression, and only once a dictionary has been trained.
 #[derive(Debug, Deserialize, Serialize, Default)]
 pub struct DatabaseConfig {
     pub db_path: String,
@@ -70,6 +92,79 @@ pub struct DatabaseConfig {
     pub map_size: u64,
     pub cache_ttl: u64,
     pub cache_capacity: u64,
+    pub packet_cache_capacity: u64,
+    pub backend: DatabaseBackend,
+    pub compression: DatabaseCompression,
+    pub compression_level: u32,
+    pub zstd_dictionary: bool,
+}
+
+/// The scheduled backups section from [ServerConfig].
+///
+/// Fields:
+/// - `enabled`: Whether to back up the world while the server is running.
+/// - `interval`: How often to back up the world, in minutes.
+/// - `path`: The directory backups are kept in. This is relative to the server root path.
+/// - `keep_last`: How many of the most recent backups to keep.
+/// - `keep_daily`: How many days to keep the newest backup of each day for, on top of `keep_last`.
+#[derive(Debug, Deserialize, Serialize, Default)]
+pub struct BackupConfig {
+    pub enabled: bool,
+    pub interval: u64,
+    pub path: String,
+    pub keep_last: usize,
+    pub keep_daily: usize,
+}
+
+/// The anti-xray section from [ServerConfig].
+///
+/// Fields:
+/// - `enabled`: Whether to hide ores players can't see in the chunks sent to them.
+/// - `dimensions` - [AntiXrayDimensionConfig]: What to hide in each dimension, by dimension name.
+///   Dimensions that aren't listed aren't changed.
+#[derive(Debug, Deserialize, Serialize, Default)]
+pub struct AntiXrayConfig {
+    pub enabled: bool,
+    pub dimensions: HashMap<String, AntiXrayDimensionConfig>,
+}
+
+/// The anti-xray settings for a single dimension, from [AntiXrayConfig].
+///
+/// Fields:
+/// - `hidden_blocks`: The names of the blocks to hide, mapped to the block each one is sent as
+///   instead, e.g. `"minecraft:diamond_ore" = "minecraft:stone"`. Every state of a hidden block is
+///   hidden, and replacements can be full block states like `minecraft:deepslate[axis=y]`.
+#[derive(Debug, Deserialize, Serialize, Default)]
+pub struct AntiXrayDimensionConfig {
+    pub hidden_blocks: BTreeMap<String, String>,
+}
+
+/// The storage engines the world can be stored in.
+///
+/// - `Lmdb`: A memory mapped LMDB database. This is the default.
+/// - `FlatFile`: Pure Rust append-only files, one per table. Doesn't need `map_size` to be set.
+#[derive(Debug, Deserialize, Serialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DatabaseBackend {
+    #[default]
+    Lmdb,
+    FlatFile,
+}
+
+/// The compression algorithms chunks can be stored with.
+///
+/// Every stored chunk records which algorithm it was compressed with, so this can be changed
+/// without converting the world.
+#[derive(Debug, Deserialize, Serialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DatabaseCompression {
+    None,
+    Gzip,
+    #[default]
+    Zstd,
+    Brotli,
+    Deflate,
+    Zlib,
 }
 
 fn create_config() -> ServerConfig {
@@ -1,16 +1,57 @@
 use bevy_ecs::prelude::Component;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
 use typename::TypeName;
 
 pub const VIEW_DISTANCE: i32 = 8;
 
+/// How many chunks a tick clients are sent until they say how many they can handle.
+pub const DEFAULT_CHUNKS_PER_TICK: f32 = 9.0;
+/// The most chunks a tick a client can ask for.
+pub const MAX_CHUNKS_PER_TICK: f32 = 64.0;
+/// How many batches can be waiting to be acknowledged once a client has acknowledged its first.
+/// Until then only one is sent at a time.
+pub const MAX_UNACKNOWLEDGED_BATCHES: u32 = 10;
+
+pub type ChunkPos = (i32, i32, String);
+
+/// An encoded chunk packet, with the revision of the chunk it was made from.
+#[derive(Clone, Debug)]
+pub struct ChunkPacket {
+    pub revision: u64,
+    pub data: Arc<Vec<u8>>,
+}
+
+/// Which chunks a player has been sent, and which they're still waiting for.
+///
+/// Chunks are sent in the background, so each one goes from `queued`, to `loading` while it's
+/// loaded or generated and encoded, to `ready` until the client can take it, to `seen` once it's
+/// been sent. A chunk is only ever in one of them, so it's never sent twice. Chunks that change
+/// while they're waiting to be sent go back to `queued`, see [`ChunkReceiver::requeue_stale`].
+///
+/// Like vanilla, chunks are sent in batches paced by the client. Each batch is acknowledged with
+/// how many chunks a tick the client wants, and only a few batches can be unacknowledged at once,
+/// so a slow client isn't sent chunks faster than it can build them.
 #[derive(TypeName, Component)]
 pub struct ChunkReceiver {
-    pub needs_reload: HashSet<(i32, i32, String)>,
-    pub seen: HashSet<(i32, i32, String)>,
-    pub last_chunk: (i32, i32, String),
+    pub needs_reload: HashSet<ChunkPos>,
+    /// Chunks the player has been sent.
+    pub seen: HashSet<ChunkPos>,
+    /// Chunks that still need to be sent, closest to the player first.
+    pub queued: VecDeque<ChunkPos>,
+    /// Chunks that are being loaded to be sent.
+    pub loading: HashSet<ChunkPos>,
+    /// Encoded chunk packets waiting to be sent.
+    pub ready: HashMap<ChunkPos, ChunkPacket>,
+    /// The chunk the player is in, which their view is centred on.
+    pub last_chunk: ChunkPos,
+    /// How many chunks a tick the client last asked for.
     pub chunks_per_tick: f32,
+    /// How many chunks can be sent now, built up by `chunks_per_tick` every tick.
+    pub batch_quota: f32,
+    pub unacknowledged_batches: u32,
+    pub max_unacknowledged_batches: u32,
     pub has_loaded: AtomicBool,
 }
 
@@ -25,9 +66,253 @@ impl ChunkReceiver {
         Self {
             needs_reload: HashSet::new(),
             seen: HashSet::new(),
+            queued: VecDeque::new(),
+            loading: HashSet::new(),
+            ready: HashMap::new(),
             last_chunk: (0, 0, "overworld".to_string()),
-            chunks_per_tick: 0.0,
+            chunks_per_tick: DEFAULT_CHUNKS_PER_TICK,
+            batch_quota: 0.0,
+            unacknowledged_batches: 0,
+            max_unacknowledged_batches: 1,
             has_loaded: AtomicBool::new(false),
         }
     }
+
+    /// Moves the player's view to a new chunk. Chunks that are now too far away are forgotten,
+    /// including any still waiting to be sent, and chunks that have come into view are queued.
+    /// Chunks in front of the player are queued first, using their yaw.
+    pub fn recenter(&mut self, center: ChunkPos, radius: i32, yaw: f32) {
+        let (center_x, center_z, dimension) = center.clone();
+        let in_view = |(x, z, dim): &ChunkPos| {
+            *dim == dimension && (x - center_x).abs() <= radius && (z - center_z).abs() <= radius
+        };
+        self.seen.retain(in_view);
+        self.queued.retain(in_view);
+        // Chunks that finish loading after they've been dropped from here aren't sent
+        self.loading.retain(in_view);
+        self.ready.retain(|chunk, _| in_view(chunk));
+
+        for x in center_x - radius..=center_x + radius {
+            for z in center_z - radius..=center_z + radius {
+                let chunk = (x, z, dimension.clone());
+                if !self.seen.contains(&chunk)
+                    && !self.loading.contains(&chunk)
+                    && !self.ready.contains_key(&chunk)
+                    && !self.queued.contains(&chunk)
+                {
+                    self.queued.push_back(chunk);
+                }
+            }
+        }
+        self.queued
+            .make_contiguous()
+            .sort_by_key(|(x, z, _)| chunk_priority((center_x, center_z), yaw, (*x, *z)));
+        self.last_chunk = center;
+    }
+
+    /// Marks every chunk around a player as sent, for when they were sent some other way, like
+    /// while logging in.
+    pub fn mark_sent(&mut self, center: ChunkPos, radius: i32) {
+        let (center_x, center_z, dimension) = center.clone();
+        for x in center_x - radius..=center_x + radius {
+            for z in center_z - radius..=center_z + radius {
+                self.seen.insert((x, z, dimension.clone()));
+            }
+        }
+        self.last_chunk = center;
+    }
+
+    /// Takes the next chunk to load, and marks it as loading.
+    pub fn next_to_load(&mut self) -> Option<ChunkPos> {
+        let chunk = self.queued.pop_front()?;
+        self.loading.insert(chunk.clone());
+        Some(chunk)
+    }
+
+    /// Marks a chunk as loaded, with its packet if it could be made. Chunks the player has moved
+    /// away from since they started loading are dropped. Chunks that couldn't be made count as
+    /// sent, so they aren't tried again until the player moves away and back.
+    pub fn finish_loading(&mut self, chunk: ChunkPos, packet: Option<ChunkPacket>) {
+        if !self.loading.remove(&chunk) {
+            return;
+        }
+        match packet {
+            Some(packet) => {
+                self.ready.insert(chunk, packet);
+            }
+            None => {
+                self.seen.insert(chunk);
+            }
+        }
+    }
+
+    /// Queues the ready chunks that have changed since their packets were made to be loaded
+    /// again, so players aren't sent an old packet after missing the block updates for it.
+    /// `revision` gives a chunk's current revision.
+    pub fn requeue_stale(&mut self, revision: impl Fn(&ChunkPos) -> u64) {
+        let stale: Vec<ChunkPos> = self
+            .ready
+            .iter()
+            .filter(|(chunk, packet)| revision(chunk) != packet.revision)
+            .map(|(chunk, _)| chunk.clone())
+            .collect();
+        for chunk in stale {
+            self.ready.remove(&chunk);
+            self.queued.push_front(chunk);
+        }
+    }
+
+    /// Takes the chunks to send this tick, if the client is ready for another batch, and marks
+    /// them as sent. Chunks are picked the same way they're queued, using the player's yaw.
+    pub fn next_batch(&mut self, yaw: f32) -> Vec<(ChunkPos, Arc<Vec<u8>>)> {
+        if self.unacknowledged_batches >= self.max_unacknowledged_batches {
+            return Vec::new();
+        }
+        let per_tick = self.chunks_per_tick;
+        self.batch_quota = (self.batch_quota + per_tick).min(per_tick.max(1.0));
+        if self.batch_quota < 1.0 || self.ready.is_empty() {
+            return Vec::new();
+        }
+        let (center_x, center_z) = (self.last_chunk.0, self.last_chunk.1);
+        let mut chunks: Vec<ChunkPos> = self.ready.keys().cloned().collect();
+        chunks.sort_by_key(|(x, z, _)| chunk_priority((center_x, center_z), yaw, (*x, *z)));
+        chunks.truncate(self.batch_quota as usize);
+
+        self.unacknowledged_batches += 1;
+        self.batch_quota -= chunks.len() as f32;
+        chunks
+            .into_iter()
+            .filter_map(|chunk| {
+                let packet = self.ready.remove(&chunk)?;
+                self.seen.insert(chunk.clone());
+                Some((chunk, packet.data))
+            })
+            .collect()
+    }
+
+    /// Handles the client acknowledging a batch, and asking for a number of chunks a tick.
+    pub fn batch_acknowledged(&mut self, chunks_per_tick: f32) {
+        self.unacknowledged_batches = self.unacknowledged_batches.saturating_sub(1);
+        self.chunks_per_tick = if chunks_per_tick.is_nan() {
+            0.01
+        } else {
+            chunks_per_tick.clamp(0.01, MAX_CHUNKS_PER_TICK)
+        };
+        if self.unacknowledged_batches == 0 {
+            self.batch_quota = 1.0;
+        }
+        self.max_unacknowledged_batches = MAX_UNACKNOWLEDGED_BATCHES;
+    }
+}
+
+/// How soon a chunk should be sent to a player, lower first. The chunks right around the player
+/// come first, then the rest closest first, with chunks behind the player counted as twice as far
+/// away.
+pub fn chunk_priority((center_x, center_z): (i32, i32), yaw: f32, (x, z): (i32, i32)) -> i32 {
+    let (dx, dz) = (x - center_x, z - center_z);
+    let distance = dx * dx + dz * dz;
+    if dx.abs() <= 1 && dz.abs() <= 1 {
+        return distance;
+    }
+    // A yaw of 0 faces south, towards positive z, and 90 faces west
+    let yaw = yaw.to_radians();
+    let facing = -yaw.sin() * dx as f32 + yaw.cos() * dz as f32;
+    if facing < 0.0 {
+        distance * 4
+    } else {
+        distance
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(x: i32, z: i32) -> ChunkPos {
+        (x, z, "overworld".to_string())
+    }
+
+    fn packet(revision: u64) -> ChunkPacket {
+        ChunkPacket {
+            revision,
+            data: Arc::new(Vec::new()),
+        }
+    }
+
+    #[test]
+    fn test_recenter_queues_closest_first() {
+        let mut receiver = ChunkReceiver::new();
+        receiver.mark_sent(chunk(0, 0), 1);
+        receiver.recenter(chunk(1, 0), 1, 0.0);
+        // Only the new column of chunks is needed
+        assert_eq!(receiver.queued.len(), 3);
+        assert_eq!(receiver.queued.front(), Some(&chunk(2, 0)));
+        assert!(!receiver.seen.contains(&chunk(-1, 0)));
+
+        receiver.recenter(chunk(1, 0), 1, 0.0);
+        assert_eq!(receiver.queued.len(), 3);
+    }
+
+    #[test]
+    fn test_loading_is_cancelled_when_moving_away() {
+        let mut receiver = ChunkReceiver::new();
+        receiver.recenter(chunk(0, 0), 0, 0.0);
+        let loading = receiver.next_to_load().unwrap();
+        assert_eq!(loading, chunk(0, 0));
+
+        receiver.recenter(chunk(5, 5), 0, 0.0);
+        receiver.finish_loading(loading, Some(packet(0)));
+        assert!(receiver.ready.is_empty());
+        let loading = receiver.next_to_load().unwrap();
+        receiver.finish_loading(loading, Some(packet(0)));
+        assert!(receiver.ready.contains_key(&chunk(5, 5)));
+        assert!(receiver.next_to_load().is_none());
+    }
+
+    #[test]
+    fn test_changed_chunks_are_requeued() {
+        let mut receiver = ChunkReceiver::new();
+        receiver.recenter(chunk(0, 0), 1, 0.0);
+        while let Some(loading) = receiver.next_to_load() {
+            receiver.finish_loading(loading, Some(packet(0)));
+        }
+        receiver.requeue_stale(|chunk| if chunk.0 == 1 { 1 } else { 0 });
+        assert_eq!(receiver.ready.len(), 6);
+        assert_eq!(receiver.queued.len(), 3);
+        assert!(receiver.queued.iter().all(|chunk| chunk.0 == 1));
+    }
+
+    #[test]
+    fn test_batches_are_paced_by_the_client() {
+        let mut receiver = ChunkReceiver::new();
+        receiver.recenter(chunk(0, 0), 3, 0.0);
+        while let Some(loading) = receiver.next_to_load() {
+            receiver.finish_loading(loading, Some(packet(0)));
+        }
+        let batch = receiver.next_batch(0.0);
+        assert_eq!(batch.len(), DEFAULT_CHUNKS_PER_TICK as usize);
+        assert_eq!(batch[0].0, chunk(0, 0));
+        // Nothing more is sent until the first batch is acknowledged
+        assert!(receiver.next_batch(0.0).is_empty());
+
+        receiver.batch_acknowledged(2.0);
+        // Once the first batch is acknowledged, more can be sent before the next one is
+        assert_eq!(receiver.next_batch(0.0).len(), 2);
+        assert_eq!(receiver.next_batch(0.0).len(), 2);
+        receiver.batch_acknowledged(f32::NAN);
+        assert_eq!(receiver.chunks_per_tick, 0.01);
+    }
+
+    #[test]
+    fn test_chunks_in_front_come_first() {
+        // Facing south, towards positive z
+        assert!(chunk_priority((0, 0), 0.0, (0, 3)) < chunk_priority((0, 0), 0.0, (0, -3)));
+        // Facing west, towards negative x
+        assert!(chunk_priority((0, 0), 90.0, (-3, 0)) < chunk_priority((0, 0), 90.0, (3, 0)));
+        // The chunks right around the player don't depend on where they're looking
+        assert_eq!(
+            chunk_priority((0, 0), 0.0, (0, -1)),
+            chunk_priority((0, 0), 0.0, (0, 1))
+        );
+    }
 }
@@ -0,0 +1,34 @@
+use bevy_ecs::prelude::Component;
+
+/// A player's game mode.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Component)]
+pub enum GameMode {
+    Survival,
+    // Everyone has been put in creative so far, so keep that as the default
+    #[default]
+    Creative,
+    Adventure,
+    Spectator,
+}
+
+impl GameMode {
+    /// The ID the protocol uses for this game mode.
+    pub fn id(self) -> u8 {
+        match self {
+            GameMode::Survival => 0,
+            GameMode::Creative => 1,
+            GameMode::Adventure => 2,
+            GameMode::Spectator => 3,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(GameMode::Survival),
+            1 => Some(GameMode::Creative),
+            2 => Some(GameMode::Adventure),
+            3 => Some(GameMode::Spectator),
+            _ => None,
+        }
+    }
+}
@@ -3,10 +3,12 @@ pub mod player_list;
 use crate::player_list::PlayerList;
 use bevy_ecs::prelude::Resource;
 use ferrumc_threadpool::ThreadPool;
+use ferrumc_world::errors::WorldError;
+use ferrumc_world::metadata::WorldMetadata;
 use ferrumc_world::World;
 use ferrumc_world_gen::WorldGenerator;
 use std::sync::atomic::AtomicBool;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 use std::time::Instant;
 
 pub struct ServerState {
@@ -16,6 +18,16 @@ pub struct ServerState {
     pub players: PlayerList, // (UUID, Username)
     pub thread_pool: ThreadPool,
     pub start_time: Instant,
+    /// The seed, spawn point, time and game rules. Written to the world on every sync.
+    pub world_metadata: RwLock<WorldMetadata>,
+}
+
+impl ServerState {
+    /// Writes the world metadata to the storage backend.
+    pub fn save_world_metadata(&self) -> Result<(), WorldError> {
+        let metadata = self.world_metadata.read().unwrap().clone();
+        self.world.save_metadata(&metadata)
+    }
 }
 
 pub type GlobalState = Arc<ServerState>;
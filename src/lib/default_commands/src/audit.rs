@@ -0,0 +1,226 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bevy_ecs::prelude::*;
+use ferrumc_commands::{
+    arg::primitive::{int::Integer, string::SingleWord},
+    Sender,
+};
+use ferrumc_core::identity::player_identity::PlayerIdentity;
+use ferrumc_core::transform::position::Position;
+use ferrumc_macros::command;
+use ferrumc_state::{GlobalState, GlobalStateResource};
+use ferrumc_text::{NamedColor, TextComponent, TextComponentBuilder};
+use ferrumc_world::audit::{AuditQuery, BlockAuditEntry};
+use ferrumc_world::block_id::BlockId;
+use ferrumc_world::regions::Region;
+
+use crate::permissions::require_operator;
+
+/// How many changes `/audit` shows.
+const SHOWN_ENTRIES: usize = 10;
+
+#[command("audit area")]
+fn audit_area(
+    #[sender] sender: Sender,
+    #[arg] radius: Integer,
+    #[arg] since: Duration,
+    state: Res<GlobalStateResource>,
+    players: Query<&PlayerIdentity>,
+    positions: Query<&Position>,
+) {
+    if !require_operator(sender, &players) {
+        return;
+    }
+    let Some(query) = area_query(sender, *radius, since, &positions) else {
+        return;
+    };
+    show_entries(sender, state.0.clone(), query);
+}
+
+#[command("audit player")]
+fn audit_player(
+    #[sender] sender: Sender,
+    #[arg] name: SingleWord,
+    #[arg] since: Duration,
+    state: Res<GlobalStateResource>,
+    players: Query<&PlayerIdentity>,
+) {
+    if !require_operator(sender, &players) {
+        return;
+    }
+    let Some(query) = player_query(sender, &name, since, &state, &players) else {
+        return;
+    };
+    show_entries(sender, state.0.clone(), query);
+}
+
+#[command("rollback area")]
+fn rollback_area(
+    #[sender] sender: Sender,
+    #[arg] radius: Integer,
+    #[arg] since: Duration,
+    state: Res<GlobalStateResource>,
+    players: Query<&PlayerIdentity>,
+    positions: Query<&Position>,
+) {
+    if !require_operator(sender, &players) {
+        return;
+    }
+    let Some(query) = area_query(sender, *radius, since, &positions) else {
+        return;
+    };
+    rollback(sender, state.0.clone(), query);
+}
+
+#[command("rollback player")]
+fn rollback_player(
+    #[sender] sender: Sender,
+    #[arg] name: SingleWord,
+    #[arg] since: Duration,
+    state: Res<GlobalStateResource>,
+    players: Query<&PlayerIdentity>,
+) {
+    if !require_operator(sender, &players) {
+        return;
+    }
+    let Some(query) = player_query(sender, &name, since, &state, &players) else {
+        return;
+    };
+    rollback(sender, state.0.clone(), query);
+}
+
+fn send_error(sender: Sender, message: &str) {
+    sender.send_message(
+        TextComponentBuilder::new(message)
+            .color(NamedColor::Red)
+            .build(),
+        false,
+    );
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Changes in the overworld within `radius` blocks of the sender, at any height.
+fn area_query(
+    sender: Sender,
+    radius: i32,
+    since: Duration,
+    positions: &Query<&Position>,
+) -> Option<AuditQuery> {
+    if radius < 0 {
+        send_error(sender, "The radius can't be negative");
+        return None;
+    }
+    let Sender::Player(entity) = sender else {
+        send_error(sender, "Only players can look up changes around them");
+        return None;
+    };
+    let Ok(position) = positions.get(entity) else {
+        send_error(sender, "You don't have a position");
+        return None;
+    };
+    let (x, z) = (position.x.floor() as i32, position.z.floor() as i32);
+    Some(AuditQuery {
+        dimension: Some("overworld".to_string()),
+        area: Some(Region::new(
+            (x - radius, i32::MIN, z - radius),
+            (x + radius, i32::MAX, z + radius),
+        )),
+        since: Some(now_millis().saturating_sub(since.as_millis() as u64)),
+        ..Default::default()
+    })
+}
+
+/// Changes made by a player, who doesn't have to be online.
+fn player_query(
+    sender: Sender,
+    name: &str,
+    since: Duration,
+    state: &GlobalStateResource,
+    players: &Query<&PlayerIdentity>,
+) -> Option<AuditQuery> {
+    let online = players
+        .iter()
+        .find(|identity| identity.username.eq_ignore_ascii_case(name))
+        .map(|identity| identity.uuid.as_u128());
+    let Some(player) = online.or_else(|| state.0.world.find_audited_player(name)) else {
+        send_error(sender, &format!("{name} hasn't changed any blocks"));
+        return None;
+    };
+    Some(AuditQuery {
+        player: Some(player),
+        since: Some(now_millis().saturating_sub(since.as_millis() as u64)),
+        ..Default::default()
+    })
+}
+
+fn describe_block(block: BlockId) -> String {
+    block
+        .to_block_data()
+        .map(|data| data.name)
+        .unwrap_or_else(|| format!("block {}", block.0))
+}
+
+fn describe_entry(entry: &BlockAuditEntry, now: u64) -> String {
+    let ago = Duration::from_millis(now.saturating_sub(entry.timestamp)).as_secs();
+    let action = if entry.new == BlockId::default() {
+        format!("broke {}", describe_block(entry.old))
+    } else {
+        format!("placed {}", describe_block(entry.new))
+    };
+    format!(
+        "{}s ago: {} {action} at {} {} {}",
+        ago, entry.player_name, entry.x, entry.y, entry.z
+    )
+}
+
+/// Shows the newest changes that match a query. The log is searched in the background since it
+/// can be large.
+fn show_entries(sender: Sender, state: GlobalState, mut query: AuditQuery) {
+    query.limit = Some(SHOWN_ENTRIES);
+    let _handle = state.thread_pool.oneshot({
+        let state = state.clone();
+        move || match state.world.query_block_audit(&query) {
+            Ok(entries) if entries.is_empty() => {
+                sender.send_message(TextComponent::from("No changes found"), false)
+            }
+            Ok(entries) => {
+                let now = now_millis();
+                sender.send_message(
+                    TextComponent::from(format!("Latest {} changes:", entries.len())),
+                    false,
+                );
+                for entry in entries.iter().rev() {
+                    sender.send_message(TextComponent::from(describe_entry(entry, now)), false);
+                }
+            }
+            Err(e) => send_error(sender, &format!("Failed to search the audit log: {e}")),
+        }
+    });
+}
+
+/// Rolls back the changes that match a query in the background, letting the sender know once
+/// it's done.
+fn rollback(sender: Sender, state: GlobalState, query: AuditQuery) {
+    sender.send_message(TextComponent::from("Rolling back changes..."), false);
+    let _handle = state.thread_pool.oneshot({
+        let state = state.clone();
+        move || match state.world.rollback_block_audit(&query) {
+            Ok(edits) => {
+                let restored: usize = edits.iter().map(|edit| edit.len()).sum();
+                let missing: usize = edits.iter().map(|edit| edit.missing_chunks.len()).sum();
+                let mut message = format!("Restored {restored} blocks");
+                if missing > 0 {
+                    message += &format!(", skipping {missing} chunks that no longer exist");
+                }
+                sender.send_message(TextComponent::from(message), false);
+            }
+            Err(e) => send_error(sender, &format!("Failed to roll back changes: {e}")),
+        }
+    });
+}
@@ -0,0 +1,100 @@
+use bevy_ecs::prelude::*;
+use ferrumc_commands::{arg::primitive::int::Integer, Sender};
+use ferrumc_core::identity::player_identity::PlayerIdentity;
+use ferrumc_macros::command;
+use ferrumc_state::GlobalStateResource;
+use ferrumc_text::{NamedColor, TextComponent, TextComponentBuilder};
+use ferrumc_world::tickets::TicketType;
+
+use crate::permissions::require_operator;
+
+#[command("forceload add")]
+fn forceload_add(
+    #[sender] sender: Sender,
+    #[arg] x: Integer,
+    #[arg] z: Integer,
+    state: Res<GlobalStateResource>,
+    players: Query<&PlayerIdentity>,
+) {
+    if !require_operator(sender, &players) {
+        return;
+    }
+    // Like vanilla, chunks are picked by the coordinates of a block in them
+    let (chunk_x, chunk_z) = (*x >> 4, *z >> 4);
+    match state
+        .0
+        .world
+        .add_chunk_ticket(TicketType::Forced, chunk_x, chunk_z, "overworld")
+    {
+        Ok(true) => sender.send_message(
+            TextComponent::from(format!("Chunk {chunk_x} {chunk_z} is now force loaded")),
+            false,
+        ),
+        Ok(false) => send_error(
+            sender,
+            &format!("Chunk {chunk_x} {chunk_z} is already force loaded"),
+        ),
+        Err(e) => send_error(sender, &format!("Failed to force load the chunk: {e}")),
+    }
+}
+
+#[command("forceload remove")]
+fn forceload_remove(
+    #[sender] sender: Sender,
+    #[arg] x: Integer,
+    #[arg] z: Integer,
+    state: Res<GlobalStateResource>,
+    players: Query<&PlayerIdentity>,
+) {
+    if !require_operator(sender, &players) {
+        return;
+    }
+    let (chunk_x, chunk_z) = (*x >> 4, *z >> 4);
+    match state
+        .0
+        .world
+        .remove_chunk_ticket(TicketType::Forced, chunk_x, chunk_z, "overworld")
+    {
+        Ok(true) => sender.send_message(
+            TextComponent::from(format!(
+                "Chunk {chunk_x} {chunk_z} is no longer force loaded"
+            )),
+            false,
+        ),
+        Ok(false) => send_error(
+            sender,
+            &format!("Chunk {chunk_x} {chunk_z} isn't force loaded"),
+        ),
+        Err(e) => send_error(sender, &format!("Failed to stop force loading: {e}")),
+    }
+}
+
+#[command("forceload query")]
+fn forceload_query(#[sender] sender: Sender, state: Res<GlobalStateResource>) {
+    let forced = state.0.world.forced_chunks();
+    if forced.is_empty() {
+        sender.send_message(TextComponent::from("No chunks are force loaded"), false);
+        return;
+    }
+    let chunks: Vec<String> = forced
+        .iter()
+        .map(|(x, z, dimension)| format!("{x} {z} in {dimension}"))
+        .collect();
+    sender.send_message(
+        TextComponent::from(format!(
+            "{} force loaded chunks: {}",
+            forced.len(),
+            chunks.join(", ")
+        )),
+        false,
+    );
+}
+
+fn send_error(sender: Sender, message: &str) {
+    sender.send_message(
+        TextComponentBuilder::new(message)
+            .color(NamedColor::Red)
+            .build(),
+        false,
+    );
+}
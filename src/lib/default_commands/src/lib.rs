@@ -1,5 +1,10 @@
+pub mod audit;
 pub mod echo;
+pub mod forceload;
 pub mod nested;
+mod permissions;
+pub mod prune;
+pub mod region;
 
 /// Static library initialisation shenanigans.
 pub fn init() {}
@@ -0,0 +1,34 @@
+use bevy_ecs::prelude::*;
+use ferrumc_commands::Sender;
+use ferrumc_config::server_config::get_global_config;
+use ferrumc_core::identity::player_identity::PlayerIdentity;
+use ferrumc_text::{NamedColor, TextComponentBuilder};
+
+/// Whether a player is listed in the config's operators, by username or UUID.
+fn is_listed_operator(identity: &PlayerIdentity) -> bool {
+    let hyphenated = identity.uuid.hyphenated().to_string();
+    let simple = identity.uuid.simple().to_string();
+    get_global_config().operators.iter().any(|operator| {
+        operator.eq_ignore_ascii_case(&identity.username)
+            || operator.eq_ignore_ascii_case(&hyphenated)
+            || operator.eq_ignore_ascii_case(&simple)
+    })
+}
+
+/// Checks the sender can use commands that affect the whole server, telling them if they can't.
+/// The console always can.
+pub(crate) fn require_operator(sender: Sender, players: &Query<&PlayerIdentity>) -> bool {
+    let allowed = match sender {
+        Sender::Server => true,
+        Sender::Player(entity) => players.get(entity).is_ok_and(is_listed_operator),
+    };
+    if !allowed {
+        sender.send_message(
+            TextComponentBuilder::new("Only operators can use this command")
+                .color(NamedColor::Red)
+                .build(),
+            false,
+        );
+    }
+    allowed
+}
@@ -0,0 +1,122 @@
+use bevy_ecs::prelude::*;
+use ferrumc_commands::{arg::primitive::int::Integer, Sender};
+use ferrumc_config::server_config::get_global_config;
+use ferrumc_core::identity::player_identity::PlayerIdentity;
+use ferrumc_core::transform::position::Position;
+use ferrumc_macros::command;
+use ferrumc_state::GlobalStateResource;
+use ferrumc_text::{NamedColor, TextComponent, TextComponentBuilder};
+use ferrumc_world::activity::TICKS_PER_SECOND;
+use ferrumc_world::pruning::PruneOptions;
+use ferrumc_world::spatial::ChunkBounds;
+
+use crate::permissions::require_operator;
+
+#[command("prune radius")]
+fn prune_radius(
+    #[sender] sender: Sender,
+    #[arg] radius: Integer,
+    #[arg] dry_run: bool,
+    state: Res<GlobalStateResource>,
+    players: Query<&PlayerIdentity>,
+    positions: Query<&Position, With<PlayerIdentity>>,
+) {
+    if !require_operator(sender, &players) {
+        return;
+    }
+    let Ok(radius) = u32::try_from(*radius) else {
+        send_error(sender, "The radius can't be negative");
+        return;
+    };
+    let options = PruneOptions {
+        radius: Some(radius),
+        dry_run,
+        ..Default::default()
+    };
+    prune(sender, options, &state, &positions);
+}
+
+#[command("prune inhabited")]
+fn prune_inhabited(
+    #[sender] sender: Sender,
+    #[arg] seconds: Integer,
+    #[arg] dry_run: bool,
+    state: Res<GlobalStateResource>,
+    players: Query<&PlayerIdentity>,
+    positions: Query<&Position, With<PlayerIdentity>>,
+) {
+    if !require_operator(sender, &players) {
+        return;
+    }
+    let Ok(seconds) = u64::try_from(*seconds) else {
+        send_error(sender, "The inhabited time can't be negative");
+        return;
+    };
+    let options = PruneOptions {
+        min_inhabited_time: Some(seconds * TICKS_PER_SECOND),
+        dry_run,
+        ..Default::default()
+    };
+    prune(sender, options, &state, &positions);
+}
+
+fn send_error(sender: Sender, message: &str) {
+    sender.send_message(
+        TextComponentBuilder::new(message)
+            .color(NamedColor::Red)
+            .build(),
+        false,
+    );
+}
+
+/// Prunes the overworld in the background, never touching the chunks online players can see.
+fn prune(
+    sender: Sender,
+    mut options: PruneOptions,
+    state: &GlobalStateResource,
+    players: &Query<&Position, With<PlayerIdentity>>,
+) {
+    let view_distance = get_global_config().chunk_render_distance as i32;
+    options.keep = players
+        .iter()
+        .map(|position| {
+            ChunkBounds::around(
+                (position.x.floor() as i32) >> 4,
+                (position.z.floor() as i32) >> 4,
+                view_distance,
+            )
+        })
+        .collect();
+
+    sender.send_message(
+        TextComponent::from(if options.dry_run {
+            "Checking which chunks would be pruned..."
+        } else {
+            "Pruning chunks..."
+        }),
+        false,
+    );
+    let _handle = state.0.thread_pool.oneshot({
+        let state = state.0.clone();
+        move || match state.world.prune("overworld", &options) {
+            Ok(report) if report.dry_run => sender.send_message(
+                TextComponent::from(format!(
+                    "{} of {} chunks would be pruned. Run the command again with dry_run set to \
+                     false to delete them.",
+                    report.pruned.len(),
+                    report.checked
+                )),
+                false,
+            ),
+            Ok(report) => sender.send_message(
+                TextComponent::from(format!(
+                    "Pruned {} of {} chunks",
+                    report.pruned.len(),
+                    report.checked
+                )),
+                false,
+            ),
+            Err(e) => send_error(sender, &format!("Failed to prune chunks: {e}")),
+        }
+    });
+}
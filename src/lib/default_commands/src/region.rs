@@ -0,0 +1,324 @@
+use bevy_ecs::prelude::*;
+use ferrumc_commands::{
+    arg::primitive::{int::Integer, string::SingleWord},
+    Sender,
+};
+use ferrumc_core::identity::player_identity::PlayerIdentity;
+use ferrumc_core::transform::position::Position;
+use ferrumc_macros::command;
+use ferrumc_state::GlobalStateResource;
+use ferrumc_text::{NamedColor, TextComponent, TextComponentBuilder};
+use ferrumc_world::chunk_format::{MAX_Y, MIN_Y};
+use ferrumc_world::protection::{ProtectedRegion, ProtectionAction};
+use ferrumc_world::regions::Region;
+
+use crate::permissions::require_operator;
+
+/// The largest radius `/region create` accepts.
+const MAX_REGION_RADIUS: i32 = 128;
+
+#[command("region create")]
+fn region_create(
+    #[sender] sender: Sender,
+    #[arg] name: SingleWord,
+    #[arg] radius: Integer,
+    state: Res<GlobalStateResource>,
+    players: Query<&PlayerIdentity>,
+    positions: Query<&Position>,
+) {
+    if !require_operator(sender, &players) {
+        return;
+    }
+    let radius = *radius;
+    if !(0..=MAX_REGION_RADIUS).contains(&radius) {
+        send_error(
+            sender,
+            &format!("The radius has to be between 0 and {MAX_REGION_RADIUS}"),
+        );
+        return;
+    }
+    let Sender::Player(entity) = sender else {
+        send_error(sender, "Only players can create regions around them");
+        return;
+    };
+    let (Ok(identity), Ok(position)) = (players.get(entity), positions.get(entity)) else {
+        send_error(sender, "You don't have a position");
+        return;
+    };
+    let (x, z) = (position.x.floor() as i32, position.z.floor() as i32);
+    // Regions cover every height, so nobody can build over or dig under them
+    let area = Region::new(
+        (x - radius, MIN_Y, z - radius),
+        (x + radius, MAX_Y, z + radius),
+    );
+    let owner = identity.uuid.as_u128();
+    if let Some(overlapping) = state
+        .0
+        .world
+        .protected_regions()
+        .into_iter()
+        .find(|region| {
+            region.dimension == "overworld"
+                && region.area.intersects(&area)
+                && !region.is_owner(owner)
+        })
+    {
+        send_error(
+            sender,
+            &format!(
+                "That would overlap {}, which you don't own",
+                overlapping.name
+            ),
+        );
+        return;
+    }
+    let region = ProtectedRegion::new(name.as_str(), "overworld", area, owner);
+    match state.0.world.add_protected_region(region) {
+        Ok(()) => sender.send_message(
+            TextComponent::from(format!(
+                "Created region {} from {} {} to {} {}",
+                *name, area.min.0, area.min.2, area.max.0, area.max.2
+            )),
+            false,
+        ),
+        Err(e) => send_error(sender, &e.to_string()),
+    }
+}
+
+#[command("region remove")]
+fn region_remove(
+    #[sender] sender: Sender,
+    #[arg] name: SingleWord,
+    state: Res<GlobalStateResource>,
+    players: Query<&PlayerIdentity>,
+) {
+    if !can_manage(sender, &name, &state, &players) {
+        return;
+    }
+    match state.0.world.remove_protected_region(&name) {
+        Ok(_) => sender.send_message(
+            TextComponent::from(format!("Removed region {}", *name)),
+            false,
+        ),
+        Err(e) => send_error(sender, &e.to_string()),
+    }
+}
+
+#[command("region addmember")]
+fn region_add_member(
+    #[sender] sender: Sender,
+    #[arg] name: SingleWord,
+    #[arg] player: SingleWord,
+    state: Res<GlobalStateResource>,
+    players: Query<&PlayerIdentity>,
+) {
+    if !can_manage(sender, &name, &state, &players) {
+        return;
+    }
+    let Some(member) = find_player(sender, &player, &state, &players) else {
+        return;
+    };
+    match state
+        .0
+        .world
+        .update_protected_region(&name, |region| region.members.insert(member))
+    {
+        Ok(true) => sender.send_message(
+            TextComponent::from(format!("Added {} to {}", *player, *name)),
+            false,
+        ),
+        Ok(false) => send_error(
+            sender,
+            &format!("{} is already a member of {}", *player, *name),
+        ),
+        Err(e) => send_error(sender, &e.to_string()),
+    }
+}
+
+#[command("region removemember")]
+fn region_remove_member(
+    #[sender] sender: Sender,
+    #[arg] name: SingleWord,
+    #[arg] player: SingleWord,
+    state: Res<GlobalStateResource>,
+    players: Query<&PlayerIdentity>,
+) {
+    if !can_manage(sender, &name, &state, &players) {
+        return;
+    }
+    let Some(member) = find_player(sender, &player, &state, &players) else {
+        return;
+    };
+    match state
+        .0
+        .world
+        .update_protected_region(&name, |region| region.members.remove(&member))
+    {
+        Ok(true) => sender.send_message(
+            TextComponent::from(format!("Removed {} from {}", *player, *name)),
+            false,
+        ),
+        Ok(false) => send_error(sender, &format!("{} isn't a member of {}", *player, *name)),
+        Err(e) => send_error(sender, &e.to_string()),
+    }
+}
+
+#[command("region flag")]
+fn region_flag(
+    #[sender] sender: Sender,
+    #[arg] name: SingleWord,
+    #[arg] flag: SingleWord,
+    #[arg] allowed: bool,
+    state: Res<GlobalStateResource>,
+    players: Query<&PlayerIdentity>,
+) {
+    let Some(action) = ProtectionAction::from_name(&flag) else {
+        send_error(
+            sender,
+            &format!("Unknown flag {}, expected build, break or interact", *flag),
+        );
+        return;
+    };
+    if !can_manage(sender, &name, &state, &players) {
+        return;
+    }
+    match state
+        .0
+        .world
+        .update_protected_region(&name, |region| region.flags.set(action, allowed))
+    {
+        Ok(()) => sender.send_message(
+            TextComponent::from(format!(
+                "Everyone {} {} in {}",
+                if allowed { "can now" } else { "can no longer" },
+                flag.to_ascii_lowercase(),
+                *name
+            )),
+            false,
+        ),
+        Err(e) => send_error(sender, &e.to_string()),
+    }
+}
+
+#[command("region info")]
+fn region_info(
+    #[sender] sender: Sender,
+    state: Res<GlobalStateResource>,
+    positions: Query<&Position>,
+) {
+    let Sender::Player(entity) = sender else {
+        send_error(sender, "Only players can look up the regions they're in");
+        return;
+    };
+    let Ok(position) = positions.get(entity) else {
+        send_error(sender, "You don't have a position");
+        return;
+    };
+    let regions = state.0.world.protected_regions_at(
+        "overworld",
+        position.x.floor() as i32,
+        position.y.floor() as i32,
+        position.z.floor() as i32,
+    );
+    if regions.is_empty() {
+        sender.send_message(TextComponent::from("You aren't in any regions"), false);
+        return;
+    }
+    for region in regions {
+        sender.send_message(TextComponent::from(describe_region(&region)), false);
+    }
+}
+
+#[command("region list")]
+fn region_list(#[sender] sender: Sender, state: Res<GlobalStateResource>) {
+    let regions = state.0.world.protected_regions();
+    if regions.is_empty() {
+        sender.send_message(TextComponent::from("There are no regions"), false);
+        return;
+    }
+    for region in regions {
+        sender.send_message(TextComponent::from(describe_region(&region)), false);
+    }
+}
+
+fn send_error(sender: Sender, message: &str) {
+    sender.send_message(
+        TextComponentBuilder::new(message)
+            .color(NamedColor::Red)
+            .build(),
+        false,
+    );
+}
+
+fn describe_region(region: &ProtectedRegion) -> String {
+    let allowed: Vec<&str> = [
+        (region.flags.build, "build"),
+        (region.flags.break_blocks, "break"),
+        (region.flags.interact, "interact"),
+    ]
+    .into_iter()
+    .filter_map(|(allowed, flag)| allowed.then_some(flag))
+    .collect();
+    format!(
+        "{} in {} from {} {} to {} {}: {} owners, {} members, everyone can {}",
+        region.name,
+        region.dimension,
+        region.area.min.0,
+        region.area.min.2,
+        region.area.max.0,
+        region.area.max.2,
+        region.owners.len(),
+        region.members.len(),
+        if allowed.is_empty() {
+            "do nothing".to_string()
+        } else {
+            allowed.join(", ")
+        }
+    )
+}
+
+/// Only the region's owners and the console can change a region.
+fn can_manage(
+    sender: Sender,
+    name: &str,
+    state: &GlobalStateResource,
+    players: &Query<&PlayerIdentity>,
+) -> bool {
+    let Some(region) = state.0.world.protected_region(name) else {
+        send_error(sender, &format!("There's no region named {name}"));
+        return false;
+    };
+    match sender {
+        Sender::Server => true,
+        Sender::Player(entity) => {
+            let owner = players
+                .get(entity)
+                .is_ok_and(|identity| region.is_owner(identity.uuid.as_u128()));
+            if !owner {
+                send_error(sender, &format!("You don't own {name}"));
+            }
+            owner
+        }
+    }
+}
+
+/// Finds a player by name. Players who are offline can still be found if they've changed blocks
+/// before.
+fn find_player(
+    sender: Sender,
+    name: &str,
+    state: &GlobalStateResource,
+    players: &Query<&PlayerIdentity>,
+) -> Option<u128> {
+    if let Some(identity) = players
+        .iter()
+        .find(|identity| identity.username.eq_ignore_ascii_case(name))
+    {
+        return Some(identity.uuid.as_u128());
+    }
+    let player = state.0.world.find_audited_player(name);
+    if player.is_none() {
+        send_error(sender, &format!("Couldn't find a player named {name}"));
+    }
+    player
+}
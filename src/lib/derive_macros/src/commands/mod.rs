@@ -128,8 +128,22 @@ pub fn command(attr: TokenStream, item: TokenStream) -> TokenStream {
         .clone()
         .iter()
         .map(|arg| {
-            let (pat, _) = arg;
-            quote!(#pat)
+            let (pat, ty) = arg;
+            // the handler is called once per dispatched command, so resources can't be moved
+            // into it. read-only queries are copy already
+            let is_res = match ty {
+                Type::Path(path) => path
+                    .path
+                    .segments
+                    .last()
+                    .is_some_and(|seg| seg.ident == "Res"),
+                _ => false,
+            };
+            if is_res {
+                quote!(bevy_ecs::prelude::Res::clone(&#pat),)
+            } else {
+                quote!(#pat,)
+            }
         })
         .collect::<Vec<proc_macro2::TokenStream>>();
 
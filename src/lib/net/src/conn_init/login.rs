@@ -7,12 +7,15 @@ use crate::packets::incoming::packet_skeleton::PacketSkeleton;
 use crate::packets::outgoing::{commands::CommandsPacket, registry_data::REGISTRY_PACKETS};
 use crate::ConnState::*;
 use ferrumc_config::server_config::get_global_config;
+use ferrumc_core::gamemode::GameMode;
 use ferrumc_core::identity::player_identity::PlayerIdentity;
 use ferrumc_macros::lookup_packet;
 use ferrumc_net_codec::decode::NetDecode;
 use ferrumc_net_codec::encode::NetEncodeOpts;
 use ferrumc_net_codec::net_types::length_prefixed_vec::LengthPrefixedVec;
+use ferrumc_net_codec::net_types::network_position::NetworkPosition;
 use ferrumc_state::GlobalState;
+use ferrumc_world::player_data::PlayerData;
 use tokio::net::tcp::OwnedReadHalf;
 use tracing::{error, trace};
 use uuid::Uuid;
@@ -194,16 +197,55 @@ pub(super) async fn login(
 
     // =============================================================================================
     // 11 Send login_play packet to switch to Play state
-    let login_play =
+    let metadata = state.world_metadata.read().unwrap().clone();
+    let spawn = metadata.spawn;
+    // Players who've been here before pick up where they left off, everyone else starts at spawn
+    let player_data = match state.world.load_player_data(player_identity.uuid.as_u128()) {
+        Ok(Some(player_data)) => player_data,
+        Ok(None) => PlayerData {
+            x: spawn.x as f64 + 0.5,
+            y: spawn.y as f64,
+            z: spawn.z as f64 + 0.5,
+            yaw: spawn.angle,
+            gamemode: GameMode::default().id(),
+            ..Default::default()
+        },
+        Err(e) => {
+            error!(
+                "Failed to load player data for {}: {}",
+                player_identity.username, e
+            );
+            return Err(e.into());
+        }
+    };
+    let mut login_play =
         crate::packets::outgoing::login_play::LoginPlayPacket::new(player_identity.short_uuid);
+    login_play.is_hardcore = metadata.hardcore;
+    login_play.gamemode = player_data.gamemode;
     conn_write.send_packet(login_play)?;
 
+    let spawn_position =
+        crate::packets::outgoing::set_default_spawn_position::SetDefaultSpawnPositionPacket {
+            spawn_position: NetworkPosition {
+                x: spawn.x,
+                y: spawn.y as i16,
+                z: spawn.z,
+            },
+            angle: spawn.angle,
+        };
+    conn_write.send_packet(spawn_position)?;
+
     // =============================================================================================
     // 12 Send initial player position sync (requires teleport confirmation)
     let teleport_id_i32: i32 = (rand::random::<u32>() & 0x3FFF_FFFF) as i32;
     let sync_player_pos =
         crate::packets::outgoing::synchronize_player_position::SynchronizePlayerPositionPacket {
             teleport_id: VarInt::new(teleport_id_i32),
+            x: player_data.x,
+            y: player_data.y,
+            z: player_data.z,
+            yaw: player_data.yaw,
+            pitch: player_data.pitch,
             ..Default::default()
         };
     conn_write.send_packet(sync_player_pos)?;
@@ -258,7 +300,10 @@ pub(super) async fn login(
 
     // =============================================================================================
     // 16 Send center chunk packet (player spawn location)
-    let center_chunk = crate::packets::outgoing::set_center_chunk::SetCenterChunk::new(0, 0);
+    let chunk_x = (player_data.x.floor() as i32) >> 4;
+    let chunk_z = (player_data.z.floor() as i32) >> 4;
+    let center_chunk =
+        crate::packets::outgoing::set_center_chunk::SetCenterChunk::new(chunk_x, chunk_z);
     conn_write.send_packet(center_chunk)?;
 
     // =============================================================================================
@@ -267,14 +312,15 @@ pub(super) async fn login(
 
     let mut batch = state.thread_pool.batch();
 
-    for x in -radius..=radius {
-        for z in -radius..=radius {
+    for x in chunk_x - radius..=chunk_x + radius {
+        for z in chunk_z - radius..=chunk_z + radius {
             batch.execute({
                 let state = state.clone();
                 move || -> Result<Vec<u8>, NetError> {
                     let chunk = state.world.load_chunk(x, z, "overworld")?;
                     let chunk_data =
-                        crate::packets::outgoing::chunk_and_light_data::ChunkAndLightData::from_chunk(
+                        crate::packets::outgoing::chunk_and_light_data::ChunkAndLightData::from_chunk_for_players(
+                            &state.world,
                             &chunk,
                         )?;
                     let compressed_packet = compress_packet(&chunk_data, compressed, &NetEncodeOpts::WithLength)?;
@@ -315,6 +361,7 @@ pub(super) async fn login(
         false,
         LoginResult {
             player_identity: Some(player_identity),
+            player_data: Some(player_data),
             compression: compressed,
         },
     ))
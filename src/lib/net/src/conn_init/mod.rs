@@ -13,6 +13,7 @@ use ferrumc_net_codec::decode::{NetDecode, NetDecodeOpts};
 use ferrumc_net_codec::net_types::var_int::VarInt;
 use ferrumc_state::GlobalState;
 use ferrumc_text::{ComponentBuilder, NamedColor, TextComponent};
+use ferrumc_world::player_data::PlayerData;
 use std::sync::atomic::Ordering;
 use tokio::net::tcp::OwnedReadHalf;
 use tracing::{error, trace};
@@ -20,9 +21,11 @@ use tracing::{error, trace};
 /// Represents the result of a login attempt after the handshake process.
 ///
 /// - `player_identity`: Populated when login is successful and a player is identified.
+/// - `player_data`: The player's saved state, or where they start if they're new.
 /// - `compression`: Indicates whether network compression should be enabled for this connection.
 pub(crate) struct LoginResult {
     pub player_identity: Option<PlayerIdentity>,
+    pub player_data: Option<PlayerData>,
     pub compression: bool,
 }
 
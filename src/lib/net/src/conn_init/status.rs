@@ -96,6 +96,7 @@ pub(super) async fn status(
         true,
         LoginResult {
             player_identity: None,
+            player_data: None,
             compression: false,
         },
     ))
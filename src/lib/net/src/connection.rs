@@ -13,6 +13,7 @@ use ferrumc_core::identity::player_identity::PlayerIdentity;
 use ferrumc_net_codec::encode::NetEncode;
 use ferrumc_net_codec::encode::NetEncodeOpts;
 use ferrumc_state::ServerState;
+use ferrumc_world::player_data::PlayerData;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
@@ -29,6 +30,22 @@ use typename::TypeName;
 /// Connections exceeding this duration will be dropped to avoid resource hogging.
 const MAX_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
 
+/// Bytes waiting to be written to a client. Packets sent to lots of clients, like cached chunks,
+/// are shared instead of copied for each one.
+enum OutgoingBytes {
+    Owned(Vec<u8>),
+    Shared(Arc<Vec<u8>>),
+}
+
+impl AsRef<[u8]> for OutgoingBytes {
+    fn as_ref(&self) -> &[u8] {
+        match self {
+            OutgoingBytes::Owned(bytes) => bytes,
+            OutgoingBytes::Shared(bytes) => bytes,
+        }
+    }
+}
+
 /// StreamWriter manages asynchronous writes to a client's TCP connection.
 ///
 /// It:
@@ -38,7 +55,7 @@ const MAX_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
 /// - Gracefully handles disconnection when dropped.
 #[derive(TypeName, Component)]
 pub struct StreamWriter {
-    sender: UnboundedSender<Vec<u8>>,
+    sender: UnboundedSender<OutgoingBytes>,
     pub running: Arc<AtomicBool>,
     pub compress: Arc<AtomicBool>,
 }
@@ -57,8 +74,10 @@ impl StreamWriter {
     /// and writes bytes to the network socket.
     pub async fn new(mut writer: OwnedWriteHalf, running: Arc<AtomicBool>) -> Self {
         let compress = Arc::new(AtomicBool::new(false)); // Default: no compression
-        let (sender, mut receiver): (UnboundedSender<Vec<u8>>, UnboundedReceiver<Vec<u8>>) =
-            tokio::sync::mpsc::unbounded_channel();
+        let (sender, mut receiver): (
+            UnboundedSender<OutgoingBytes>,
+            UnboundedReceiver<OutgoingBytes>,
+        ) = tokio::sync::mpsc::unbounded_channel();
         let running_clone = running.clone();
 
         // Task: forward packets from channel to socket
@@ -68,7 +87,7 @@ impl StreamWriter {
                     break;
                 };
 
-                if let Err(e) = writer.write_all(&bytes).await {
+                if let Err(e) = writer.write_all(bytes.as_ref()).await {
                     error!("Failed to write to client: {:?}", e);
                     running_clone.store(false, Ordering::Relaxed);
                     break;
@@ -120,8 +139,7 @@ impl StreamWriter {
             )))
         })?;
 
-        self.sender.send(raw_bytes).map_err(std::io::Error::other)?;
-        Ok(())
+        self.send_bytes(OutgoingBytes::Owned(raw_bytes))
     }
 
     /// Sends pre-encoded raw bytes to the client without additional processing.
@@ -132,7 +150,22 @@ impl StreamWriter {
             return Err(NetError::ConnectionDropped);
         }
 
-        self.sender.send(raw_bytes).map_err(std::io::Error::other)?;
+        self.send_bytes(OutgoingBytes::Owned(raw_bytes))
+    }
+
+    /// Sends pre-encoded raw bytes that are shared with other connections, without copying them.
+    pub fn send_shared_packet(&self, raw_bytes: Arc<Vec<u8>>) -> Result<(), NetError> {
+        if !self.running.load(Ordering::Relaxed) {
+            #[cfg(debug_assertions)]
+            warn!("Attempted to send raw bytes on closed connection");
+            return Err(NetError::ConnectionDropped);
+        }
+
+        self.send_bytes(OutgoingBytes::Shared(raw_bytes))
+    }
+
+    fn send_bytes(&self, bytes: OutgoingBytes) -> Result<(), NetError> {
+        self.sender.send(bytes).map_err(std::io::Error::other)?;
         Ok(())
     }
 }
@@ -142,6 +175,7 @@ impl StreamWriter {
 pub struct NewConnection {
     pub stream: StreamWriter,
     pub player_identity: PlayerIdentity,
+    pub player_data: PlayerData,
     pub entity_return: oneshot::Sender<Entity>,
 }
 
@@ -235,6 +269,7 @@ pub async fn handle_connection(
         .send(NewConnection {
             stream,
             player_identity: login_result.player_identity.unwrap_or_default(),
+            player_data: login_result.player_data.unwrap_or_default(),
             entity_return,
         })
         .map_err(|_| NetError::Misc("Failed to register new connection".to_string()))?;
@@ -1,13 +1,16 @@
 use crate::errors::NetError;
+use crate::packets::outgoing::light_update::LightData;
 use byteorder::{BigEndian, WriteBytesExt};
 use ferrumc_macros::{packet, NetEncode};
 use ferrumc_net_codec::net_types::bitset::BitSet;
 use ferrumc_net_codec::net_types::byte_array::ByteArray;
 use ferrumc_net_codec::net_types::length_prefixed_vec::LengthPrefixedVec;
 use ferrumc_net_codec::net_types::var_int::VarInt;
+use ferrumc_world::biomes::MAX_INDIRECT_BIOME_BITS;
 use ferrumc_world::chunk_format::{Chunk, PaletteType};
+use ferrumc_world::heightmaps::HeightmapType;
+use ferrumc_world::World;
 use std::io::Cursor;
-use std::ops::Not;
 use tracing::warn;
 
 const SECTIONS: usize = 24; // Number of sections, adjust for your Y range (-64 to 319)
@@ -22,7 +25,7 @@ pub struct BlockEntity {
 
 #[derive(NetEncode)]
 pub struct NetHeightmap {
-    // Define the structure of your heightmaps here
+    // The protocol ID of the heightmap type, see `HeightmapType`
     pub id: VarInt,
     pub data: LengthPrefixedVec<i64>,
 }
@@ -71,31 +74,24 @@ impl ChunkAndLightData {
         }
     }
 
+    /// Builds the packet for a chunk as players should see it, with ores they can't see hidden
+    /// if anti-xray is enabled in its dimension. See [`World::obfuscate_chunk`].
+    pub fn from_chunk_for_players(world: &World, chunk: &Chunk) -> Result<Self, NetError> {
+        match world.obfuscate_chunk(chunk)? {
+            Some(hidden) => Self::from_chunk(&hidden),
+            None => Self::from_chunk(chunk),
+        }
+    }
+
     pub fn from_chunk(chunk: &Chunk) -> Result<Self, NetError> {
         let mut raw_data = Cursor::new(Vec::new());
-        let mut sky_light_data = Vec::new();
-        let mut block_light_data = Vec::new();
         for section in &chunk.sections {
-            let section_sky_light_data = if section.sky_light.len() != 2048 {
+            if section.sky_light.len() != 2048 || section.block_light.len() != 2048 {
                 warn!(
-                    "Sky light data for section at {}, {} is not 2048 bytes long",
-                    chunk.x, chunk.z
+                    "Light data for section {} at {}, {} is not 2048 bytes long",
+                    section.y, chunk.x, chunk.z
                 );
-                vec![255; 2048]
-            } else {
-                section.sky_light.clone()
-            };
-            sky_light_data.push(section_sky_light_data);
-            let section_block_light_data = if section.block_light.len() != 2048 {
-                warn!(
-                    "Block light data for section at {}, {} is not 2048 bytes long",
-                    chunk.x, chunk.z
-                );
-                vec![255; 2048]
-            } else {
-                section.block_light.clone()
-            };
-            block_light_data.push(section_block_light_data);
+            }
 
             raw_data.write_u16::<BigEndian>(section.block_states.non_air_blocks)?;
 
@@ -122,70 +118,68 @@ impl ChunkAndLightData {
                         raw_data.write_i64::<BigEndian>(*data_entry)?;
                     }
                 }
-                PaletteType::Direct { .. } => {
-                    todo!("Direct palette type")
+                PaletteType::Direct {
+                    bits_per_block,
+                    data,
+                } => {
+                    // No palette is sent, the client knows the global bits per entry
+                    raw_data.write_u8(*bits_per_block)?;
+                    for data_entry in data {
+                        raw_data.write_i64::<BigEndian>(*data_entry)?;
+                    }
                 }
             }
 
-            // Empty biome data for now
-            raw_data.write_u8(0)?;
-            // Forest biome id
-            raw_data.write_u8(21)?;
-        }
-        let mut sky_light_mask = BitSet::new(SECTIONS + 2);
-        let mut block_light_mask = BitSet::new(SECTIONS + 2);
-
-        // Populate masks based on light data
-        for (i, section) in chunk.sections.iter().enumerate() {
-            if !section.sky_light.is_empty() && section.sky_light.len() == 2048 {
-                sky_light_mask.set(i, true);
+            let biomes = &section.biome_states;
+            raw_data.write_u8(biomes.bits_per_biome)?;
+            match biomes.bits_per_biome {
+                0 => {
+                    biomes
+                        .palette
+                        .first()
+                        .copied()
+                        .unwrap_or_default()
+                        .write(&mut raw_data)?;
+                }
+                // Bigger palettes are direct, and don't send the palette
+                1..=MAX_INDIRECT_BIOME_BITS => {
+                    VarInt::new(biomes.palette.len() as i32).write(&mut raw_data)?;
+                    for palette_entry in &biomes.palette {
+                        palette_entry.write(&mut raw_data)?;
+                    }
+                }
+                _ => {}
             }
-            if !section.block_light.is_empty() && section.block_light.len() == 2048 {
-                block_light_mask.set(i, true);
+            for data_entry in &biomes.data {
+                raw_data.write_i64::<BigEndian>(*data_entry)?;
             }
         }
-
-        // Invert masks to create empty masks
-        let empty_sky_light_mask = sky_light_mask.clone().not();
-        let empty_block_light_mask = block_light_mask.clone().not();
-
-        // Align light arrays with masks
-        let sky_light_arrays = chunk
-            .sections
+        let light = LightData::new(chunk, None);
+        let heightmaps = HeightmapType::CLIENT
             .iter()
-            .filter(|section| !section.sky_light.is_empty())
-            .map(|section| ByteArray::new(section.sky_light.clone()))
+            .filter_map(|heightmap| {
+                chunk
+                    .heightmaps
+                    .packed(*heightmap)
+                    .map(|data| NetHeightmap {
+                        id: VarInt::new(*heightmap as i32),
+                        data: LengthPrefixedVec::new(data.to_vec()),
+                    })
+            })
             .collect();
 
-        let block_light_arrays = chunk
-            .sections
-            .iter()
-            .filter(|section| !section.block_light.is_empty())
-            .map(|section| ByteArray::new(section.block_light.clone()))
-            .collect();
-        let heightmaps = vec![
-            NetHeightmap {
-                id: VarInt::new(1), // Placeholder for heightmap ID
-                data: LengthPrefixedVec::new(chunk.heightmaps.world_surface.clone()),
-            },
-            NetHeightmap {
-                id: VarInt::new(4), // Placeholder for heightmap ID
-                data: LengthPrefixedVec::new(chunk.heightmaps.motion_blocking.clone()),
-            },
-        ];
-
         Ok(ChunkAndLightData {
             chunk_x: chunk.x,
             chunk_z: chunk.z,
             heightmaps: LengthPrefixedVec::new(heightmaps),
             data: ByteArray::new(raw_data.into_inner()),
             block_entities: LengthPrefixedVec::new(Vec::new()),
-            sky_light_mask,
-            block_light_mask,
-            empty_sky_light_mask,
-            empty_block_light_mask,
-            sky_light_arrays: LengthPrefixedVec::new(sky_light_arrays),
-            block_light_arrays: LengthPrefixedVec::new(block_light_arrays),
+            sky_light_mask: light.sky_light_mask,
+            block_light_mask: light.block_light_mask,
+            empty_sky_light_mask: light.empty_sky_light_mask,
+            empty_block_light_mask: light.empty_block_light_mask,
+            sky_light_arrays: LengthPrefixedVec::new(light.sky_light_arrays),
+            block_light_arrays: LengthPrefixedVec::new(light.block_light_arrays),
         })
     }
 }
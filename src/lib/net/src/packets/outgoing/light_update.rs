@@ -0,0 +1,108 @@
+use ferrumc_macros::{packet, NetEncode};
+use ferrumc_net_codec::net_types::bitset::BitSet;
+use ferrumc_net_codec::net_types::byte_array::ByteArray;
+use ferrumc_net_codec::net_types::length_prefixed_vec::LengthPrefixedVec;
+use ferrumc_net_codec::net_types::var_int::VarInt;
+use ferrumc_world::chunk_format::Chunk;
+
+#[derive(NetEncode)]
+#[packet(packet_id = "light_update", state = "play")]
+pub struct LightUpdatePacket {
+    pub chunk_x: VarInt,
+    pub chunk_z: VarInt,
+    pub sky_light_mask: BitSet,
+    pub block_light_mask: BitSet,
+    pub empty_sky_light_mask: BitSet,
+    pub empty_block_light_mask: BitSet,
+    pub sky_light_arrays: LengthPrefixedVec<ByteArray>,
+    pub block_light_arrays: LengthPrefixedVec<ByteArray>,
+}
+
+impl LightUpdatePacket {
+    /// Creates a light update containing only the given sections of a chunk.
+    pub fn from_sections(chunk: &Chunk, sections: &[i8]) -> Self {
+        let light = LightData::new(chunk, Some(sections));
+        LightUpdatePacket {
+            chunk_x: VarInt::new(chunk.x),
+            chunk_z: VarInt::new(chunk.z),
+            sky_light_mask: light.sky_light_mask,
+            block_light_mask: light.block_light_mask,
+            empty_sky_light_mask: light.empty_sky_light_mask,
+            empty_block_light_mask: light.empty_block_light_mask,
+            sky_light_arrays: LengthPrefixedVec::new(light.sky_light_arrays),
+            block_light_arrays: LengthPrefixedVec::new(light.block_light_arrays),
+        }
+    }
+}
+
+/// The light masks and arrays shared by the light update and chunk data packets.
+///
+/// Bit `n` of each mask refers to the section `n - 1` sections above the lowest section of the
+/// chunk, since the client also tracks light for one section below and above the world.
+pub(crate) struct LightData {
+    pub sky_light_mask: BitSet,
+    pub block_light_mask: BitSet,
+    pub empty_sky_light_mask: BitSet,
+    pub empty_block_light_mask: BitSet,
+    pub sky_light_arrays: Vec<ByteArray>,
+    pub block_light_arrays: Vec<ByteArray>,
+}
+
+impl LightData {
+    /// Builds the light data for a chunk. If `sections` is `None`, every section is included, as
+    /// well as the open sky above the chunk.
+    pub(crate) fn new(chunk: &Chunk, sections: Option<&[i8]>) -> Self {
+        let section_count = chunk.sections.len() + 2;
+        let mut light = LightData {
+            sky_light_mask: BitSet::new(section_count),
+            block_light_mask: BitSet::new(section_count),
+            empty_sky_light_mask: BitSet::new(section_count),
+            empty_block_light_mask: BitSet::new(section_count),
+            sky_light_arrays: Vec::new(),
+            block_light_arrays: Vec::new(),
+        };
+        let min_y = chunk.sections.iter().map(|s| s.y).min().unwrap_or(0) as i32;
+
+        // The arrays have to be in the same order as the bits in the masks
+        let mut chunk_sections: Vec<_> = chunk
+            .sections
+            .iter()
+            .filter(|section| sections.is_none_or(|sections| sections.contains(&section.y)))
+            .collect();
+        chunk_sections.sort_by_key(|section| section.y);
+
+        for section in chunk_sections {
+            let bit = (section.y as i32 - min_y + 1) as usize;
+            if section.sky_light.len() == 2048 {
+                if section.sky_light.iter().all(|&b| b == 0) {
+                    light.empty_sky_light_mask.set(bit, true);
+                } else {
+                    light.sky_light_mask.set(bit, true);
+                    light
+                        .sky_light_arrays
+                        .push(ByteArray::new(section.sky_light.clone()));
+                }
+            }
+            if section.block_light.len() == 2048 {
+                if section.block_light.iter().all(|&b| b == 0) {
+                    light.empty_block_light_mask.set(bit, true);
+                } else {
+                    light.block_light_mask.set(bit, true);
+                    light
+                        .block_light_arrays
+                        .push(ByteArray::new(section.block_light.clone()));
+                }
+            }
+        }
+
+        if sections.is_none() {
+            // Everything above the world is in full sky light
+            light.sky_light_mask.set(section_count - 1, true);
+            light
+                .sky_light_arrays
+                .push(ByteArray::new(vec![0xff; 2048]));
+        }
+
+        light
+    }
+}
@@ -6,6 +6,7 @@ pub mod disconnect;
 pub mod finish_configuration;
 pub mod game_event;
 pub mod keep_alive;
+pub mod light_update;
 pub mod login_disconnect;
 pub mod login_play;
 pub mod login_success;
@@ -36,6 +37,7 @@ pub mod update_entity_rotation;
 pub mod block_change_ack;
 
 pub mod block_update;
+pub mod section_blocks_update;
 
 pub mod command_suggestions;
 pub mod commands;
@@ -44,4 +46,5 @@ pub(crate) mod set_compression;
 
 pub mod set_container_content;
 pub mod set_container_slot;
+pub mod set_held_slot;
 pub mod set_player_inventory_slot;
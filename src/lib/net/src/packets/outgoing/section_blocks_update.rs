@@ -0,0 +1,53 @@
+use ferrumc_macros::{packet, NetEncode};
+use ferrumc_net_codec::net_types::length_prefixed_vec::LengthPrefixedVec;
+use ferrumc_net_codec::net_types::var_long::VarLong;
+use ferrumc_world::block_changes::SectionChanges;
+
+/// Changes any number of blocks within a single section. Much cheaper than sending a block update
+/// for every block, and doesn't make the client rebuild the whole chunk like resending it would.
+#[derive(NetEncode)]
+#[packet(packet_id = "section_blocks_update", state = "play")]
+pub struct SectionBlocksUpdate {
+    /// The section's coordinates, packed as `x << 42 | z << 20 | y` with 22 bits for `x` and `z`
+    /// and 20 bits for `y`.
+    pub section_position: i64,
+    /// Each changed block, packed as `block_id << 12 | x << 8 | z << 4 | y` where `x`, `y` and `z`
+    /// are relative to the section.
+    pub blocks: LengthPrefixedVec<VarLong>,
+}
+
+impl SectionBlocksUpdate {
+    pub fn from_changes(changes: &SectionChanges) -> Self {
+        Self {
+            section_position: pack_section_position(
+                changes.chunk_x,
+                changes.section_y,
+                changes.chunk_z,
+            ),
+            blocks: LengthPrefixedVec::new(
+                changes
+                    .blocks
+                    .iter()
+                    .map(|(index, block)| VarLong::new(((block.0 as i64) << 12) | *index as i64))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+fn pack_section_position(x: i32, y: i32, z: i32) -> i64 {
+    ((x as i64 & 0x3FFFFF) << 42) | ((z as i64 & 0x3FFFFF) << 20) | (y as i64 & 0xFFFFF)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pack_section_position() {
+        assert_eq!(pack_section_position(0, 0, 0), 0);
+        assert_eq!(pack_section_position(1, 2, 3), (1 << 42) | (3 << 20) | 2);
+        // Negative coordinates are two's complement within their bits, so all 1s for x and z
+        assert_eq!(pack_section_position(-1, -4, -1), -4);
+    }
+}
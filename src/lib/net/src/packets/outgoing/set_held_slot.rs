@@ -0,0 +1,8 @@
+use ferrumc_macros::{packet, NetEncode};
+use ferrumc_net_codec::net_types::var_int::VarInt;
+
+#[derive(NetEncode)]
+#[packet(packet_id = "set_held_slot", state = "play")]
+pub struct SetHeldSlotPacket {
+    pub slot: VarInt,
+}
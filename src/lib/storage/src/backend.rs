@@ -0,0 +1,79 @@
+use crate::errors::StorageError;
+use crate::flatfile::FlatFileBackend;
+use crate::lmdb::LmdbBackend;
+use ferrumc_config::server_config::DatabaseBackend;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// A key-value store made up of named tables with `u128` keys.
+///
+/// Operations that read or modify a single key fail with [`StorageError::TableError`] if the table
+/// doesn't exist, while inserts and batch writes create the table if needed.
+pub trait StorageBackend: Send + Sync {
+    /// Inserts a new key, failing with [`StorageError::KeyExists`] if it's already present.
+    fn insert(&self, table: String, key: u128, value: Vec<u8>) -> Result<(), StorageError>;
+
+    fn get(&self, table: String, key: u128) -> Result<Option<Vec<u8>>, StorageError>;
+
+    /// Deletes a key, failing with [`StorageError::KeyNotFound`] if it isn't present.
+    fn delete(&self, table: String, key: u128) -> Result<(), StorageError>;
+
+    /// Replaces the value of a key, failing with [`StorageError::KeyNotFound`] if it isn't present.
+    fn update(&self, table: String, key: u128, value: Vec<u8>) -> Result<(), StorageError>;
+
+    /// Inserts or replaces the value of a key.
+    fn upsert(&self, table: String, key: u128, value: Vec<u8>) -> Result<bool, StorageError>;
+
+    /// Inserts or replaces many keys at once. The whole batch is written in one go.
+    fn batch_upsert(&self, table: String, data: Vec<(u128, Vec<u8>)>) -> Result<(), StorageError>;
+
+    /// Inserts many new keys at once, failing if any of them are already present.
+    fn batch_insert(&self, table: String, data: Vec<(u128, Vec<u8>)>) -> Result<(), StorageError>;
+
+    /// Gets many keys at once. The results are in the same order as the keys.
+    fn batch_get(
+        &self,
+        table: String,
+        keys: Vec<u128>,
+    ) -> Result<Vec<Option<Vec<u8>>>, StorageError>;
+
+    fn exists(&self, table: String, key: u128) -> Result<bool, StorageError>;
+
+    /// Lists every key in a table, in ascending order.
+    fn keys(&self, table: String) -> Result<Vec<u128>, StorageError>;
+
+    /// Lists the keys in a table between `start` and `end` inclusive, in ascending order.
+    fn keys_in_range(
+        &self,
+        table: String,
+        start: u128,
+        end: u128,
+    ) -> Result<Vec<u128>, StorageError>;
+
+    fn table_exists(&self, table: String) -> Result<bool, StorageError>;
+
+    fn create_table(&self, table: String) -> Result<(), StorageError>;
+
+    /// A human-readable description of the backend, for logging.
+    fn details(&self) -> String;
+
+    /// Makes sure everything written so far is persisted to disk.
+    fn flush(&self) -> Result<(), StorageError>;
+
+    /// Writes a consistent copy of every table to a new directory, which can be opened as a
+    /// store of the same kind. Writes made while the copy is running are not included.
+    fn snapshot(&self, destination: &Path) -> Result<(), StorageError>;
+
+    fn close(&self) -> Result<(), StorageError>;
+}
+
+/// Opens the storage backend selected in the database config at the given path.
+pub fn initialize_backend(
+    backend: DatabaseBackend,
+    store_path: Option<PathBuf>,
+) -> Result<Arc<dyn StorageBackend>, StorageError> {
+    Ok(match backend {
+        DatabaseBackend::Lmdb => Arc::new(LmdbBackend::initialize(store_path)?),
+        DatabaseBackend::FlatFile => Arc::new(FlatFileBackend::initialize(store_path)?),
+    })
+}
@@ -1,3 +1,5 @@
+use ferrumc_storage::backend::StorageBackend;
+use ferrumc_storage::flatfile::FlatFileBackend;
 use ferrumc_storage::lmdb::LmdbBackend;
 use rand::Rng;
 use std::collections::HashSet;
@@ -25,14 +27,19 @@ fn select_random<T: Clone + Copy>(choices: Vec<T>) -> T {
 }
 
 pub(crate) fn db_benches(c: &mut criterion::Criterion) {
-    let mut used_keys = HashSet::new();
-    let tempdir = tempfile::TempDir::new().unwrap().keep();
+    let lmdb = LmdbBackend::initialize(Some(tempfile::TempDir::new().unwrap().keep())).unwrap();
+    backend_benches(c, "LMDB", &lmdb);
+    let flatfile =
+        FlatFileBackend::initialize(Some(tempfile::TempDir::new().unwrap().keep())).unwrap();
+    backend_benches(c, "Flat file", &flatfile);
+}
 
-    let db = LmdbBackend::initialize(Some(tempdir)).unwrap();
+fn backend_benches(c: &mut criterion::Criterion, name: &str, db: &impl StorageBackend) {
+    let mut used_keys = HashSet::new();
 
     db.create_table("insert_test".to_string()).unwrap();
 
-    let mut insert_group = c.benchmark_group("Insert");
+    let mut insert_group = c.benchmark_group(format!("{name} Insert"));
 
     insert_group.bench_function("512b".to_string(), |b| {
         b.iter(|| {
@@ -69,7 +76,7 @@ pub(crate) fn db_benches(c: &mut criterion::Criterion) {
 
     insert_group.finish();
 
-    let mut read_group = c.benchmark_group("Read");
+    let mut read_group = c.benchmark_group(format!("{name} Read"));
 
     db.create_table("read_test".to_string()).unwrap();
 
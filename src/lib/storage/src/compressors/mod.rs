@@ -4,23 +4,40 @@ use crate::compressors::gzip::{compress_gzip, decompress_gzip};
 use crate::compressors::zlib::{compress_zlib, decompress_zlib};
 use crate::compressors::zstd::{compress_zstd, decompress_zstd};
 use crate::errors::StorageError;
+use ferrumc_config::server_config::DatabaseCompression;
 
 pub mod brotli;
 pub mod deflate;
 pub mod gzip;
+pub mod record;
 pub mod zlib;
 pub mod zstd;
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum CompressorType {
     Gzip,
     Zstd,
     Brotli,
     Deflate,
     Zlib,
+    /// Stores the data as-is.
+    None,
 }
 
-#[derive(Clone, Copy)]
+impl From<DatabaseCompression> for CompressorType {
+    fn from(compression: DatabaseCompression) -> Self {
+        match compression {
+            DatabaseCompression::None => CompressorType::None,
+            DatabaseCompression::Gzip => CompressorType::Gzip,
+            DatabaseCompression::Zstd => CompressorType::Zstd,
+            DatabaseCompression::Brotli => CompressorType::Brotli,
+            DatabaseCompression::Deflate => CompressorType::Deflate,
+            DatabaseCompression::Zlib => CompressorType::Zlib,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
 pub struct Compressor {
     pub algorithm: CompressorType,
     pub level: u32,
@@ -38,6 +55,7 @@ impl Compressor {
             CompressorType::Brotli => compress_brotli(self.level, data),
             CompressorType::Deflate => compress_deflate(self.level, data),
             CompressorType::Zlib => compress_zlib(self.level, data),
+            CompressorType::None => Ok(data.to_vec()),
         }
     }
 
@@ -48,6 +66,7 @@ impl Compressor {
             CompressorType::Brotli => decompress_brotli(data),
             CompressorType::Deflate => decompress_deflate(data),
             CompressorType::Zlib => decompress_zlib(data),
+            CompressorType::None => Ok(data.to_vec()),
         }
     }
 }
@@ -0,0 +1,284 @@
+//! Self-describing compressed records.
+//!
+//! Every record starts with a header saying how its payload was compressed, so records written
+//! with different compressors can live side by side in the same table:
+//!
+//! | Size | Contents                                                   |
+//! |------|------------------------------------------------------------|
+//! | 1    | [`RECORD_MAGIC`]                                           |
+//! | 1    | The algorithm tag                                          |
+//! | 4    | CRC32 of the uncompressed data, little endian              |
+//! | 4    | The dictionary ID, little endian. Only for dictionary mode |
+//! | ..   | The compressed payload                                     |
+//!
+//! The magic byte can never start a zlib stream, so data from before records existed can still be
+//! told apart.
+
+use crate::compressors::zstd::{
+    compress_zstd_with_dictionary, decompress_zstd_with_dictionary, train_zstd_dictionary,
+};
+use crate::compressors::{Compressor, CompressorType};
+use crate::errors::StorageError;
+use std::sync::Arc;
+
+pub const RECORD_MAGIC: u8 = 0xFE;
+
+const TAG_NONE: u8 = 0;
+const TAG_GZIP: u8 = 1;
+const TAG_ZSTD: u8 = 2;
+const TAG_BROTLI: u8 = 3;
+const TAG_DEFLATE: u8 = 4;
+const TAG_ZLIB: u8 = 5;
+const TAG_ZSTD_DICTIONARY: u8 = 6;
+
+const HEADER_SIZE: usize = 6;
+
+/// A zstd dictionary, identified by an ID that's stored in every record compressed with it.
+pub struct ZstdDictionary {
+    id: u32,
+    data: Vec<u8>,
+}
+
+impl ZstdDictionary {
+    pub fn new(id: u32, data: Vec<u8>) -> Self {
+        Self { id, data }
+    }
+
+    /// Trains a dictionary of at most `max_size` bytes from a set of uncompressed payloads.
+    ///
+    /// zstd needs a reasonable amount of sample data to train from; a few hundred samples is
+    /// usually enough.
+    pub fn train(id: u32, samples: &[Vec<u8>], max_size: usize) -> Result<Self, StorageError> {
+        Ok(Self::new(id, train_zstd_dictionary(samples, max_size)?))
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+/// Compresses data into records, optionally with a zstd dictionary.
+#[derive(Clone)]
+pub struct RecordCompressor {
+    compressor: Compressor,
+    dictionary: Option<Arc<ZstdDictionary>>,
+}
+
+impl RecordCompressor {
+    pub fn new(compressor: Compressor) -> Self {
+        Self {
+            compressor,
+            dictionary: None,
+        }
+    }
+
+    /// Uses the given dictionary when compressing. This is only used by zstd.
+    pub fn with_dictionary(mut self, dictionary: Arc<ZstdDictionary>) -> Self {
+        self.dictionary = Some(dictionary);
+        self
+    }
+
+    pub fn compressor(&self) -> Compressor {
+        self.compressor
+    }
+
+    /// The dictionary records are compressed with, if dictionary mode is in use.
+    pub fn dictionary(&self) -> Option<&Arc<ZstdDictionary>> {
+        match self.compressor.algorithm {
+            CompressorType::Zstd => self.dictionary.as_ref(),
+            _ => None,
+        }
+    }
+
+    pub fn compress(&self, data: &[u8]) -> Result<Vec<u8>, StorageError> {
+        let mut crc = flate2::Crc::new();
+        crc.update(data);
+
+        let (tag, payload) = match self.dictionary() {
+            Some(dictionary) => (
+                TAG_ZSTD_DICTIONARY,
+                compress_zstd_with_dictionary(self.compressor.level, dictionary.data(), data)?,
+            ),
+            None => (
+                algorithm_tag(self.compressor.algorithm),
+                self.compressor.compress(data)?,
+            ),
+        };
+
+        let mut record = Vec::with_capacity(HEADER_SIZE + 4 + payload.len());
+        record.push(RECORD_MAGIC);
+        record.push(tag);
+        record.extend_from_slice(&crc.sum().to_le_bytes());
+        if let Some(dictionary) = self.dictionary() {
+            record.extend_from_slice(&dictionary.id().to_le_bytes());
+        }
+        record.extend_from_slice(&payload);
+        Ok(record)
+    }
+}
+
+/// Whether the data starts with a record header.
+pub fn is_record(data: &[u8]) -> bool {
+    data.first() == Some(&RECORD_MAGIC)
+}
+
+/// Decompresses a record, whichever algorithm it was compressed with.
+///
+/// `dictionary` is used to look up zstd dictionaries by ID. If `verify` is set, the checksum of
+/// the decompressed data is checked against the one in the header.
+pub fn decompress_record(
+    data: &[u8],
+    verify: bool,
+    dictionary: impl Fn(u32) -> Option<Arc<ZstdDictionary>>,
+) -> Result<Vec<u8>, StorageError> {
+    if data.len() < HEADER_SIZE || !is_record(data) {
+        return Err(StorageError::DecompressionError(
+            "Data is not a compressed record".to_string(),
+        ));
+    }
+    let tag = data[1];
+    let expected_checksum = u32::from_le_bytes(data[2..6].try_into().unwrap());
+    let payload = &data[HEADER_SIZE..];
+
+    let decompressed = if tag == TAG_ZSTD_DICTIONARY {
+        if payload.len() < 4 {
+            return Err(StorageError::DecompressionError(
+                "Record header is truncated".to_string(),
+            ));
+        }
+        let id = u32::from_le_bytes(payload[..4].try_into().unwrap());
+        let dictionary = dictionary(id).ok_or(StorageError::MissingDictionary(id))?;
+        decompress_zstd_with_dictionary(dictionary.data(), &payload[4..])?
+    } else {
+        Compressor::create(tag_algorithm(tag)?, 0).decompress(payload)?
+    };
+
+    if verify {
+        let mut crc = flate2::Crc::new();
+        crc.update(&decompressed);
+        if crc.sum() != expected_checksum {
+            return Err(StorageError::ChecksumMismatch(crc.sum(), expected_checksum));
+        }
+    }
+    Ok(decompressed)
+}
+
+fn algorithm_tag(algorithm: CompressorType) -> u8 {
+    match algorithm {
+        CompressorType::None => TAG_NONE,
+        CompressorType::Gzip => TAG_GZIP,
+        CompressorType::Zstd => TAG_ZSTD,
+        CompressorType::Brotli => TAG_BROTLI,
+        CompressorType::Deflate => TAG_DEFLATE,
+        CompressorType::Zlib => TAG_ZLIB,
+    }
+}
+
+fn tag_algorithm(tag: u8) -> Result<CompressorType, StorageError> {
+    Ok(match tag {
+        TAG_NONE => CompressorType::None,
+        TAG_GZIP => CompressorType::Gzip,
+        TAG_ZSTD => CompressorType::Zstd,
+        TAG_BROTLI => CompressorType::Brotli,
+        TAG_DEFLATE => CompressorType::Deflate,
+        TAG_ZLIB => CompressorType::Zlib,
+        _ => return Err(StorageError::UnknownCompressor(tag)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ferrumc_utils::root;
+
+    fn samples() -> Vec<Vec<u8>> {
+        (0..500u32)
+            .map(|i| {
+                let mut sample = b"minecraft:stone minecraft:dirt minecraft:grass_block ".to_vec();
+                sample.extend_from_slice(&i.to_le_bytes());
+                sample.extend(std::iter::repeat_n((i % 7) as u8, 64 + (i % 32) as usize));
+                sample.extend_from_slice(b"minecraft:oak_log minecraft:water");
+                sample
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let data = std::fs::read(root!(".etc/codec.nbt")).unwrap();
+        for algorithm in [
+            CompressorType::None,
+            CompressorType::Gzip,
+            CompressorType::Zstd,
+            CompressorType::Brotli,
+            CompressorType::Deflate,
+            CompressorType::Zlib,
+        ] {
+            let record = RecordCompressor::new(Compressor::create(algorithm, 6))
+                .compress(&data)
+                .unwrap();
+            assert!(is_record(&record));
+            let decompressed = decompress_record(&record, true, |_| None).unwrap();
+            assert_eq!(data, decompressed, "{algorithm:?}");
+        }
+    }
+
+    #[test]
+    fn test_dictionary_round_trip() {
+        let samples = samples();
+        let dictionary = Arc::new(ZstdDictionary::train(7, &samples, 4096).unwrap());
+        let compressor = RecordCompressor::new(Compressor::create(CompressorType::Zstd, 3))
+            .with_dictionary(dictionary.clone());
+        let record = compressor.compress(&samples[42]).unwrap();
+
+        let decompressed =
+            decompress_record(&record, true, |id| (id == 7).then(|| dictionary.clone())).unwrap();
+        assert_eq!(samples[42], decompressed);
+        assert!(matches!(
+            decompress_record(&record, true, |_| None),
+            Err(StorageError::MissingDictionary(7))
+        ));
+    }
+
+    #[test]
+    fn test_dictionary_ignored_for_other_algorithms() {
+        let samples = samples();
+        let dictionary = Arc::new(ZstdDictionary::train(1, &samples, 4096).unwrap());
+        let compressor = RecordCompressor::new(Compressor::create(CompressorType::Gzip, 6))
+            .with_dictionary(dictionary);
+        let record = compressor.compress(&samples[0]).unwrap();
+        assert_eq!(record[1], TAG_GZIP);
+        assert_eq!(
+            decompress_record(&record, true, |_| None).unwrap(),
+            samples[0]
+        );
+    }
+
+    #[test]
+    fn test_checksum_mismatch() {
+        let data = std::fs::read(root!(".etc/codec.nbt")).unwrap();
+        let mut record = RecordCompressor::new(Compressor::create(CompressorType::None, 0))
+            .compress(&data)
+            .unwrap();
+        let last = record.len() - 1;
+        record[last] ^= 0xFF;
+        assert!(matches!(
+            decompress_record(&record, true, |_| None),
+            Err(StorageError::ChecksumMismatch(_, _))
+        ));
+        assert!(decompress_record(&record, false, |_| None).is_ok());
+    }
+
+    #[test]
+    fn test_zlib_is_not_a_record() {
+        let data = std::fs::read(root!(".etc/codec.nbt")).unwrap();
+        let zlib = Compressor::create(CompressorType::Zlib, 6)
+            .compress(&data)
+            .unwrap();
+        assert!(!is_record(&zlib));
+    }
+}
@@ -15,6 +15,40 @@ pub(crate) fn decompress_zstd(data: &[u8]) -> Result<Vec<u8>, StorageError> {
     Ok(decompressed)
 }
 
+pub(crate) fn compress_zstd_with_dictionary(
+    level: u32,
+    dictionary: &[u8],
+    data: &[u8],
+) -> Result<Vec<u8>, StorageError> {
+    let mut compressor = zstd::bulk::Compressor::with_dictionary(level as i32, dictionary)
+        .map_err(|e| StorageError::CompressionError(e.to_string()))?;
+    compressor
+        .compress(data)
+        .map_err(|e| StorageError::CompressionError(e.to_string()))
+}
+
+pub(crate) fn decompress_zstd_with_dictionary(
+    dictionary: &[u8],
+    data: &[u8],
+) -> Result<Vec<u8>, StorageError> {
+    let mut decoder = zstd::Decoder::with_dictionary(data, dictionary)
+        .map_err(|e| StorageError::DecompressionError(e.to_string()))?;
+    let mut decompressed = Vec::new();
+    decoder
+        .read_to_end(&mut decompressed)
+        .map_err(|e| StorageError::DecompressionError(e.to_string()))?;
+    Ok(decompressed)
+}
+
+/// Trains a zstd dictionary of at most `max_size` bytes from a set of sample payloads.
+pub(crate) fn train_zstd_dictionary(
+    samples: &[Vec<u8>],
+    max_size: usize,
+) -> Result<Vec<u8>, StorageError> {
+    zstd::dict::from_samples(samples, max_size)
+        .map_err(|e| StorageError::CompressionError(e.to_string()))
+}
+
 #[cfg(test)]
 mod tests {
 
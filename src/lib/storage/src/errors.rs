@@ -9,6 +9,12 @@ pub enum StorageError {
     CompressionError(String),
     #[error("Decompression error: {0}")]
     DecompressionError(String),
+    #[error("Unknown compression algorithm: {0}")]
+    UnknownCompressor(u8),
+    #[error("Missing zstd dictionary: {0}")]
+    MissingDictionary(u32),
+    #[error("Corrupted record: got checksum {0:X}, expected checksum {1:X}")]
+    ChecksumMismatch(u32, u32),
     #[error("Invalid path")]
     InvalidPath,
     #[error("Failed to write to database: {0}")]
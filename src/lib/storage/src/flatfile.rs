@@ -0,0 +1,582 @@
+//! A simple pure-Rust storage backend that keeps each table in a single append-only file.
+//!
+//! Every write appends a record to the end of the table's file, and an in-memory index maps each
+//! key to the offset of its latest value. Deletes append a tombstone record. When enough of a file
+//! is taken up by overwritten records, it is compacted on the next flush by rewriting only the live
+//! records to a new file.
+//!
+//! Records are laid out as:
+//!
+//! | Field  | Size     | Description                                     |
+//! |--------|----------|-------------------------------------------------|
+//! | key    | 16 bytes | Big endian `u128`                               |
+//! | length | 4 bytes  | Big endian `u32`, `u32::MAX` for tombstones     |
+//! | value  | length   | The value bytes, absent for tombstones          |
+
+use crate::backend::StorageBackend;
+use crate::errors::StorageError;
+use parking_lot::{Mutex, RwLock};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::{debug, warn};
+
+const TABLE_EXTENSION: &str = "ffdb";
+const HEADER_SIZE: u64 = 20;
+const TOMBSTONE: u32 = u32::MAX;
+/// Compaction only kicks in once there is at least this much dead data in a table.
+const MIN_COMPACTION_GARBAGE: u64 = 16 * 1024 * 1024;
+
+struct Table {
+    path: PathBuf,
+    file: File,
+    /// Maps each key to the offset and length of its current value.
+    index: HashMap<u128, (u64, u32)>,
+    len: u64,
+    /// Bytes taken up by records that have since been overwritten or deleted.
+    garbage: u64,
+}
+
+impl Table {
+    fn open(path: PathBuf) -> Result<Self, StorageError> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&path)?;
+        let mut index = HashMap::new();
+        let mut garbage = 0;
+        let mut offset = 0;
+        let file_len = file.metadata()?.len();
+        {
+            let mut reader = BufReader::new(&mut file);
+            let mut header = [0u8; HEADER_SIZE as usize];
+            loop {
+                match reader.read_exact(&mut header) {
+                    Ok(()) => {}
+                    Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+                    Err(e) => return Err(e.into()),
+                }
+                let key = u128::from_be_bytes(header[..16].try_into().unwrap());
+                let len = u32::from_be_bytes(header[16..].try_into().unwrap());
+                let record_len = if len == TOMBSTONE {
+                    HEADER_SIZE
+                } else {
+                    HEADER_SIZE + len as u64
+                };
+                if offset + record_len > file_len {
+                    break;
+                }
+                if len != TOMBSTONE {
+                    reader.seek_relative(len as i64)?;
+                }
+                if let Some((_, old_len)) = index.remove(&key) {
+                    garbage += HEADER_SIZE + old_len as u64;
+                }
+                if len == TOMBSTONE {
+                    garbage += HEADER_SIZE;
+                } else {
+                    index.insert(key, (offset + HEADER_SIZE, len));
+                }
+                offset += record_len;
+            }
+        }
+        if offset != file_len {
+            // The server probably stopped in the middle of a write, so drop the partial record
+            warn!(
+                "Discarding {} bytes of incomplete data at the end of {}",
+                file_len - offset,
+                path.display()
+            );
+            file.set_len(offset)?;
+        }
+        Ok(Table {
+            path,
+            file,
+            index,
+            len: offset,
+            garbage,
+        })
+    }
+
+    fn read(&mut self, key: u128) -> Result<Option<Vec<u8>>, StorageError> {
+        let Some(&(offset, len)) = self.index.get(&key) else {
+            return Ok(None);
+        };
+        let mut value = vec![0; len as usize];
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.read_exact(&mut value)?;
+        Ok(Some(value))
+    }
+
+    /// Appends records to the end of the file in a single write and updates the index.
+    fn append<'a>(
+        &mut self,
+        records: impl IntoIterator<Item = (u128, Option<&'a [u8]>)>,
+    ) -> Result<(), StorageError> {
+        let mut buffer = Vec::new();
+        let mut updates = Vec::new();
+        for (key, value) in records {
+            buffer.extend_from_slice(&key.to_be_bytes());
+            match value {
+                Some(value) => {
+                    let len = u32::try_from(value.len())
+                        .ok()
+                        .filter(|len| *len != TOMBSTONE)
+                        .ok_or_else(|| {
+                            StorageError::WriteError(format!(
+                                "Value of {} bytes is too large",
+                                value.len()
+                            ))
+                        })?;
+                    buffer.extend_from_slice(&len.to_be_bytes());
+                    updates.push((key, Some((self.len + buffer.len() as u64, len))));
+                    buffer.extend_from_slice(value);
+                }
+                None => {
+                    buffer.extend_from_slice(&TOMBSTONE.to_be_bytes());
+                    updates.push((key, None));
+                }
+            }
+        }
+        if let Err(e) = self.file.write_all(&buffer) {
+            // Don't leave half a batch behind
+            self.file.set_len(self.len)?;
+            return Err(StorageError::WriteError(e.to_string()));
+        }
+        self.len += buffer.len() as u64;
+        for (key, location) in updates {
+            if let Some((_, old_len)) = self.index.remove(&key) {
+                self.garbage += HEADER_SIZE + old_len as u64;
+            }
+            match location {
+                Some(location) => {
+                    self.index.insert(key, location);
+                }
+                None => self.garbage += HEADER_SIZE,
+            }
+        }
+        Ok(())
+    }
+
+    /// Rewrites the table with only the live records if enough of it is dead data.
+    fn maybe_compact(&mut self) -> Result<(), StorageError> {
+        if self.garbage < MIN_COMPACTION_GARBAGE || self.garbage < self.len / 2 {
+            return Ok(());
+        }
+        debug!(
+            "Compacting {} ({} of {} bytes are garbage)",
+            self.path.display(),
+            self.garbage,
+            self.len
+        );
+        let tmp_path = self.path.with_extension(format!("{TABLE_EXTENSION}.tmp"));
+        let mut new_index = HashMap::with_capacity(self.index.len());
+        let mut new_len = 0;
+        {
+            let mut writer = BufWriter::new(File::create(&tmp_path)?);
+            let mut keys: Vec<(u128, (u64, u32))> =
+                self.index.iter().map(|(k, v)| (*k, *v)).collect();
+            // Read the old file sequentially
+            keys.sort_by_key(|(_, (offset, _))| *offset);
+            for (key, _) in keys {
+                let value = self.read(key)?.expect("Key is in the index");
+                writer.write_all(&key.to_be_bytes())?;
+                writer.write_all(&(value.len() as u32).to_be_bytes())?;
+                writer.write_all(&value)?;
+                new_index.insert(key, (new_len + HEADER_SIZE, value.len() as u32));
+                new_len += HEADER_SIZE + value.len() as u64;
+            }
+            writer.flush()?;
+            writer.get_ref().sync_all()?;
+        }
+        std::fs::rename(&tmp_path, &self.path)?;
+        self.file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .open(&self.path)?;
+        self.index = new_index;
+        self.len = new_len;
+        self.garbage = 0;
+        Ok(())
+    }
+}
+
+/// A storage backend that stores each table in a single append-only file.
+///
+/// See the module documentation for details on the file format.
+#[derive(Clone)]
+pub struct FlatFileBackend {
+    root: PathBuf,
+    tables: Arc<RwLock<HashMap<String, Arc<Mutex<Table>>>>>,
+}
+
+impl FlatFileBackend {
+    pub fn initialize(store_path: Option<PathBuf>) -> Result<Self, StorageError> {
+        let Some(root) = store_path else {
+            return Err(StorageError::InvalidPath);
+        };
+        if !root.exists() {
+            std::fs::create_dir_all(&root)?;
+        }
+        let mut tables = HashMap::new();
+        for entry in std::fs::read_dir(&root)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some(TABLE_EXTENSION) {
+                continue;
+            }
+            let Some(name) = path.file_stem().and_then(|s| s.to_str()) else {
+                continue;
+            };
+            let name = name.to_string();
+            tables.insert(name, Arc::new(Mutex::new(Table::open(path)?)));
+        }
+        Ok(FlatFileBackend {
+            root,
+            tables: Arc::new(RwLock::new(tables)),
+        })
+    }
+
+    fn table_path(root: &Path, table: &str) -> Result<PathBuf, StorageError> {
+        if table.is_empty()
+            || !table
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            return Err(StorageError::TableError(format!(
+                "Invalid table name: {table}"
+            )));
+        }
+        Ok(root.join(format!("{table}.{TABLE_EXTENSION}")))
+    }
+
+    fn open_table(&self, table: &str) -> Result<Arc<Mutex<Table>>, StorageError> {
+        self.tables
+            .read()
+            .get(table)
+            .cloned()
+            .ok_or(StorageError::TableError("Table not found".to_string()))
+    }
+
+    fn open_or_create_table(&self, table: &str) -> Result<Arc<Mutex<Table>>, StorageError> {
+        if let Some(existing) = self.tables.read().get(table) {
+            return Ok(existing.clone());
+        }
+        let mut tables = self.tables.write();
+        // Someone else might have created it while we were waiting for the lock
+        if let Some(existing) = tables.get(table) {
+            return Ok(existing.clone());
+        }
+        let path = Self::table_path(&self.root, table)?;
+        let created = Arc::new(Mutex::new(Table::open(path)?));
+        tables.insert(table.to_string(), created.clone());
+        Ok(created)
+    }
+}
+
+impl StorageBackend for FlatFileBackend {
+    fn insert(&self, table: String, key: u128, value: Vec<u8>) -> Result<(), StorageError> {
+        let table = self.open_or_create_table(&table)?;
+        let mut table = table.lock();
+        if table.index.contains_key(&key) {
+            return Err(StorageError::KeyExists(key as u64));
+        }
+        table.append([(key, Some(value.as_slice()))])
+    }
+
+    fn get(&self, table: String, key: u128) -> Result<Option<Vec<u8>>, StorageError> {
+        self.open_table(&table)?.lock().read(key)
+    }
+
+    fn delete(&self, table: String, key: u128) -> Result<(), StorageError> {
+        let table = self.open_table(&table)?;
+        let mut table = table.lock();
+        if !table.index.contains_key(&key) {
+            return Err(StorageError::KeyNotFound(key as u64));
+        }
+        table.append([(key, None)])
+    }
+
+    fn update(&self, table: String, key: u128, value: Vec<u8>) -> Result<(), StorageError> {
+        let table = self.open_table(&table)?;
+        let mut table = table.lock();
+        if !table.index.contains_key(&key) {
+            return Err(StorageError::KeyNotFound(key as u64));
+        }
+        table.append([(key, Some(value.as_slice()))])
+    }
+
+    fn upsert(&self, table: String, key: u128, value: Vec<u8>) -> Result<bool, StorageError> {
+        let table = self.open_table(&table)?;
+        table.lock().append([(key, Some(value.as_slice()))])?;
+        Ok(true)
+    }
+
+    fn batch_upsert(&self, table: String, data: Vec<(u128, Vec<u8>)>) -> Result<(), StorageError> {
+        let table = self.open_or_create_table(&table)?;
+        let mut table = table.lock();
+        table.append(data.iter().map(|(k, v)| (*k, Some(v.as_slice()))))
+    }
+
+    fn batch_insert(&self, table: String, data: Vec<(u128, Vec<u8>)>) -> Result<(), StorageError> {
+        let table = self.open_or_create_table(&table)?;
+        let mut table = table.lock();
+        if let Some((key, _)) = data.iter().find(|(k, _)| table.index.contains_key(k)) {
+            return Err(StorageError::KeyExists(*key as u64));
+        }
+        table.append(data.iter().map(|(k, v)| (*k, Some(v.as_slice()))))
+    }
+
+    fn batch_get(
+        &self,
+        table: String,
+        keys: Vec<u128>,
+    ) -> Result<Vec<Option<Vec<u8>>>, StorageError> {
+        let table = self.open_table(&table)?;
+        let mut table = table.lock();
+        keys.into_iter().map(|key| table.read(key)).collect()
+    }
+
+    fn exists(&self, table: String, key: u128) -> Result<bool, StorageError> {
+        Ok(self.open_table(&table)?.lock().index.contains_key(&key))
+    }
+
+    fn keys(&self, table: String) -> Result<Vec<u128>, StorageError> {
+        let mut keys: Vec<u128> = self
+            .open_table(&table)?
+            .lock()
+            .index
+            .keys()
+            .copied()
+            .collect();
+        keys.sort_unstable();
+        Ok(keys)
+    }
+
+    fn keys_in_range(
+        &self,
+        table: String,
+        start: u128,
+        end: u128,
+    ) -> Result<Vec<u128>, StorageError> {
+        let mut keys: Vec<u128> = self
+            .open_table(&table)?
+            .lock()
+            .index
+            .keys()
+            .copied()
+            .filter(|key| (start..=end).contains(key))
+            .collect();
+        keys.sort_unstable();
+        Ok(keys)
+    }
+
+    fn table_exists(&self, table: String) -> Result<bool, StorageError> {
+        Ok(self.tables.read().contains_key(&table))
+    }
+
+    fn create_table(&self, table: String) -> Result<(), StorageError> {
+        self.open_or_create_table(&table)?;
+        Ok(())
+    }
+
+    fn details(&self) -> String {
+        let tables = self.tables.read();
+        let keys: usize = tables.values().map(|t| t.lock().index.len()).sum();
+        format!(
+            "Flat file ({} tables, {} keys): {}",
+            tables.len(),
+            keys,
+            self.root.display()
+        )
+    }
+
+    fn flush(&self) -> Result<(), StorageError> {
+        let tables: Vec<_> = self.tables.read().values().cloned().collect();
+        for table in tables {
+            let mut table = table.lock();
+            table.maybe_compact()?;
+            table
+                .file
+                .sync_data()
+                .map_err(|e| StorageError::FlushError(e.to_string()))?;
+        }
+        Ok(())
+    }
+
+    fn snapshot(&self, destination: &Path) -> Result<(), StorageError> {
+        std::fs::create_dir_all(destination)?;
+        // Lock every table up front so the copies all line up with each other
+        let tables = self.tables.read();
+        let locked: Vec<_> = tables.values().map(|table| table.lock()).collect();
+        for table in &locked {
+            let Some(file_name) = table.path.file_name() else {
+                continue;
+            };
+            std::fs::copy(&table.path, destination.join(file_name))?;
+        }
+        Ok(())
+    }
+
+    fn close(&self) -> Result<(), StorageError> {
+        self.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::remove_dir_all;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_write() {
+        let path = tempdir().unwrap().keep();
+        {
+            let backend = FlatFileBackend::initialize(Some(path.clone())).unwrap();
+            backend.create_table("test_table".to_string()).unwrap();
+            let key = 12345678901234567890u128;
+            let value = vec![1, 2, 3, 4, 5];
+            backend
+                .insert("test_table".to_string(), key, value.clone())
+                .unwrap();
+            let retrieved_value = backend.get("test_table".to_string(), key).unwrap();
+            assert_eq!(retrieved_value, Some(value));
+            assert!(backend
+                .insert("test_table".to_string(), key, vec![6])
+                .is_err());
+        }
+        remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_reopen() {
+        let path = tempdir().unwrap().keep();
+        {
+            let backend = FlatFileBackend::initialize(Some(path.clone())).unwrap();
+            backend
+                .batch_insert(
+                    "test_table".to_string(),
+                    vec![(1, vec![1, 2, 3]), (2, vec![4, 5, 6]), (3, vec![7])],
+                )
+                .unwrap();
+            backend
+                .upsert("test_table".to_string(), 2, vec![8, 9])
+                .unwrap();
+            backend.delete("test_table".to_string(), 3).unwrap();
+            backend.close().unwrap();
+        }
+        {
+            let backend = FlatFileBackend::initialize(Some(path.clone())).unwrap();
+            assert!(backend.table_exists("test_table".to_string()).unwrap());
+            assert_eq!(
+                backend
+                    .batch_get("test_table".to_string(), vec![1, 2, 3])
+                    .unwrap(),
+                vec![Some(vec![1, 2, 3]), Some(vec![8, 9]), None]
+            );
+            assert_eq!(backend.keys("test_table".to_string()).unwrap(), vec![1, 2]);
+            assert_eq!(
+                backend
+                    .keys_in_range("test_table".to_string(), 2, 3)
+                    .unwrap(),
+                vec![2]
+            );
+        }
+        remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let path = tempdir().unwrap().keep();
+        let snapshot_path = tempdir().unwrap().keep().join("snapshot");
+        {
+            let backend = FlatFileBackend::initialize(Some(path.clone())).unwrap();
+            backend
+                .insert("test_table".to_string(), 1, vec![1, 2, 3])
+                .unwrap();
+            backend.snapshot(&snapshot_path).unwrap();
+            backend
+                .insert("test_table".to_string(), 2, vec![4, 5, 6])
+                .unwrap();
+        }
+        {
+            let snapshot = FlatFileBackend::initialize(Some(snapshot_path.clone())).unwrap();
+            assert_eq!(
+                snapshot.get("test_table".to_string(), 1).unwrap(),
+                Some(vec![1, 2, 3])
+            );
+            assert_eq!(snapshot.get("test_table".to_string(), 2).unwrap(), None);
+        }
+        remove_dir_all(path).unwrap();
+        remove_dir_all(snapshot_path).unwrap();
+    }
+
+    #[test]
+    fn test_truncated_record_is_discarded() {
+        let path = tempdir().unwrap().keep();
+        {
+            let backend = FlatFileBackend::initialize(Some(path.clone())).unwrap();
+            backend
+                .insert("test_table".to_string(), 1, vec![1; 100])
+                .unwrap();
+            backend
+                .insert("test_table".to_string(), 2, vec![2; 100])
+                .unwrap();
+            backend.close().unwrap();
+        }
+        let file = path.join(format!("test_table.{TABLE_EXTENSION}"));
+        let len = std::fs::metadata(&file).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&file)
+            .unwrap()
+            .set_len(len - 10)
+            .unwrap();
+        {
+            let backend = FlatFileBackend::initialize(Some(path.clone())).unwrap();
+            assert_eq!(
+                backend.get("test_table".to_string(), 1).unwrap(),
+                Some(vec![1; 100])
+            );
+            assert_eq!(backend.get("test_table".to_string(), 2).unwrap(), None);
+        }
+        remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_compaction() {
+        let path = tempdir().unwrap().keep();
+        {
+            let backend = FlatFileBackend::initialize(Some(path.clone())).unwrap();
+            let value = vec![7; 1024 * 1024];
+            for _ in 0..40 {
+                backend
+                    .batch_upsert("test_table".to_string(), vec![(1, value.clone())])
+                    .unwrap();
+            }
+            backend.flush().unwrap();
+            let table = backend.open_table("test_table").unwrap();
+            assert_eq!(table.lock().len, HEADER_SIZE + value.len() as u64);
+            assert_eq!(
+                backend.get("test_table".to_string(), 1).unwrap(),
+                Some(value)
+            );
+        }
+        remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_missing_table() {
+        let path = tempdir().unwrap().keep();
+        {
+            let backend = FlatFileBackend::initialize(Some(path.clone())).unwrap();
+            assert!(backend.get("missing".to_string(), 1).is_err());
+            assert!(!backend.table_exists("missing".to_string()).unwrap());
+            assert!(backend.create_table("../escape".to_string()).is_err());
+        }
+        remove_dir_all(path).unwrap();
+    }
+}
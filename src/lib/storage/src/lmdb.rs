@@ -1,11 +1,12 @@
+use crate::backend::StorageBackend;
 use crate::errors::StorageError;
 use heed;
 use heed::byteorder::BigEndian;
-use heed::types::{Bytes, U128};
-use heed::{Database, Env, EnvOpenOptions, WithoutTls};
+use heed::types::{Bytes, DecodeIgnore, U128};
+use heed::{CompactionOption, Database, Env, EnvOpenOptions, WithoutTls};
 use parking_lot::Mutex;
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 #[derive(Debug, Clone)]
@@ -50,7 +51,7 @@ impl LmdbBackend {
                     EnvOpenOptions::new()
                         .read_txn_without_tls()
                         // Change this as more tables are needed.
-                        .max_dbs(2)
+                        .max_dbs(32)
                         .map_size(rounded_map_size)
                         .open(checked_path)
                         .map_err(|e| StorageError::DatabaseInitError(e.to_string()))?,
@@ -59,8 +60,10 @@ impl LmdbBackend {
             Ok(backend)
         }
     }
+}
 
-    pub fn insert(&self, table: String, key: u128, value: Vec<u8>) -> Result<(), StorageError> {
+impl StorageBackend for LmdbBackend {
+    fn insert(&self, table: String, key: u128, value: Vec<u8>) -> Result<(), StorageError> {
         let env = self.env.lock();
         let mut rw_txn = env.write_txn()?;
         let db: Database<U128<BigEndian>, Bytes> =
@@ -73,7 +76,7 @@ impl LmdbBackend {
         Ok(())
     }
 
-    pub fn get(&self, table: String, key: u128) -> Result<Option<Vec<u8>>, StorageError> {
+    fn get(&self, table: String, key: u128) -> Result<Option<Vec<u8>>, StorageError> {
         let env = self.env.lock();
         let ro_txn = env.read_txn()?;
         let db: Database<U128<BigEndian>, Bytes> = env
@@ -87,7 +90,7 @@ impl LmdbBackend {
         }
     }
 
-    pub fn delete(&self, table: String, key: u128) -> Result<(), StorageError> {
+    fn delete(&self, table: String, key: u128) -> Result<(), StorageError> {
         let env = self.env.lock();
         let mut rw_txn = env.write_txn()?;
         let db: Database<U128<BigEndian>, Bytes> = env
@@ -101,7 +104,7 @@ impl LmdbBackend {
         Ok(())
     }
 
-    pub fn update(&self, table: String, key: u128, value: Vec<u8>) -> Result<(), StorageError> {
+    fn update(&self, table: String, key: u128, value: Vec<u8>) -> Result<(), StorageError> {
         let env = self.env.lock();
         let mut rw_txn = env.write_txn()?;
         let db: Database<U128<BigEndian>, Bytes> = env
@@ -115,7 +118,7 @@ impl LmdbBackend {
         Ok(())
     }
 
-    pub fn upsert(&self, table: String, key: u128, value: Vec<u8>) -> Result<bool, StorageError> {
+    fn upsert(&self, table: String, key: u128, value: Vec<u8>) -> Result<bool, StorageError> {
         let env = self.env.lock();
         let mut rw_txn = env.write_txn()?;
         let db: Database<U128<BigEndian>, Bytes> = env
@@ -126,11 +129,7 @@ impl LmdbBackend {
         Ok(true)
     }
 
-    pub fn batch_upsert(
-        &self,
-        table: String,
-        data: Vec<(u128, Vec<u8>)>,
-    ) -> Result<(), StorageError> {
+    fn batch_upsert(&self, table: String, data: Vec<(u128, Vec<u8>)>) -> Result<(), StorageError> {
         let env = self.env.lock();
         let mut rw_txn = env.write_txn()?;
 
@@ -161,7 +160,7 @@ impl LmdbBackend {
         Ok(())
     }
 
-    pub fn exists(&self, table: String, key: u128) -> Result<bool, StorageError> {
+    fn exists(&self, table: String, key: u128) -> Result<bool, StorageError> {
         let env = self.env.lock();
         let ro_txn = env.read_txn()?;
         let db: Database<U128<BigEndian>, Bytes> = env
@@ -170,22 +169,49 @@ impl LmdbBackend {
         Ok(db.get(&ro_txn, &key)?.is_some())
     }
 
-    pub fn table_exists(&self, table: String) -> Result<bool, StorageError> {
+    fn keys(&self, table: String) -> Result<Vec<u128>, StorageError> {
+        let env = self.env.lock();
+        let ro_txn = env.read_txn()?;
+        let db: Database<U128<BigEndian>, DecodeIgnore> = env
+            .open_database(&ro_txn, Some(&table))?
+            .ok_or(StorageError::TableError("Table not found".to_string()))?;
+        let mut keys = Vec::new();
+        for entry in db.iter(&ro_txn)? {
+            keys.push(entry?.0);
+        }
+        Ok(keys)
+    }
+
+    fn keys_in_range(
+        &self,
+        table: String,
+        start: u128,
+        end: u128,
+    ) -> Result<Vec<u128>, StorageError> {
+        let env = self.env.lock();
+        let ro_txn = env.read_txn()?;
+        let db: Database<U128<BigEndian>, DecodeIgnore> = env
+            .open_database(&ro_txn, Some(&table))?
+            .ok_or(StorageError::TableError("Table not found".to_string()))?;
+        let mut keys = Vec::new();
+        for entry in db.range(&ro_txn, &(start..=end))? {
+            keys.push(entry?.0);
+        }
+        Ok(keys)
+    }
+
+    fn table_exists(&self, table: String) -> Result<bool, StorageError> {
         let env = self.env.lock();
         let ro_txn = env.read_txn()?;
         let db = env.open_database::<U128<BigEndian>, Bytes>(&ro_txn, Some(&table))?;
         Ok(db.is_some())
     }
 
-    pub fn details(&self) -> String {
+    fn details(&self) -> String {
         format!("LMDB (heed 0.20.5): {:?}", self.env.lock().info())
     }
 
-    pub fn batch_insert(
-        &self,
-        table: String,
-        data: Vec<(u128, Vec<u8>)>,
-    ) -> Result<(), StorageError> {
+    fn batch_insert(&self, table: String, data: Vec<(u128, Vec<u8>)>) -> Result<(), StorageError> {
         let env = self.env.lock();
         let mut rw_txn = env.write_txn()?;
         let db = env.create_database::<U128<BigEndian>, Bytes>(&mut rw_txn, Some(&table))?;
@@ -204,7 +230,7 @@ impl LmdbBackend {
         Ok(())
     }
 
-    pub fn batch_get(
+    fn batch_get(
         &self,
         table: String,
         keys: Vec<u128>,
@@ -226,14 +252,22 @@ impl LmdbBackend {
         Ok(values)
     }
 
-    pub fn flush(&self) -> Result<(), StorageError> {
+    fn flush(&self) -> Result<(), StorageError> {
         let env = self.env.lock();
         env.clear_stale_readers()?;
         env.force_sync()?;
         Ok(())
     }
 
-    pub fn create_table(&self, table: String) -> Result<(), StorageError> {
+    fn snapshot(&self, destination: &Path) -> Result<(), StorageError> {
+        // Copying happens inside a read transaction, so it doesn't need to block other users
+        let env = self.env.lock().clone();
+        std::fs::create_dir_all(destination)?;
+        env.copy_to_path(destination.join("data.mdb"), CompactionOption::Enabled)?;
+        Ok(())
+    }
+
+    fn create_table(&self, table: String) -> Result<(), StorageError> {
         let env = self.env.lock();
         let mut rw_txn = env.write_txn()?;
         env.create_database::<U128<BigEndian>, Bytes>(&mut rw_txn, Some(&table))?;
@@ -241,7 +275,7 @@ impl LmdbBackend {
         Ok(())
     }
 
-    pub fn close(&self) -> Result<(), StorageError> {
+    fn close(&self) -> Result<(), StorageError> {
         self.flush()?;
         Ok(())
     }
@@ -278,6 +312,29 @@ mod tests {
         remove_dir_all(path).unwrap();
     }
 
+    #[test]
+    fn test_upsert_needs_table() {
+        let path = tempdir().unwrap().keep();
+        {
+            let backend = LmdbBackend::initialize(Some(path.clone())).unwrap();
+            assert!(backend
+                .upsert("test_table".to_string(), 1, vec![1, 2, 3])
+                .is_err());
+            backend.create_table("test_table".to_string()).unwrap();
+            backend
+                .upsert("test_table".to_string(), 1, vec![1, 2, 3])
+                .unwrap();
+            backend
+                .upsert("test_table".to_string(), 1, vec![4, 5, 6])
+                .unwrap();
+            assert_eq!(
+                backend.get("test_table".to_string(), 1).unwrap(),
+                Some(vec![4, 5, 6])
+            );
+        }
+        remove_dir_all(path).unwrap();
+    }
+
     #[test]
     fn test_batch_insert() {
         let path = tempdir().unwrap().keep();
@@ -299,6 +356,59 @@ mod tests {
         remove_dir_all(path).unwrap();
     }
 
+    #[test]
+    fn test_keys() {
+        let path = tempdir().unwrap().keep();
+        {
+            let backend = LmdbBackend::initialize(Some(path.clone())).unwrap();
+            backend
+                .batch_insert(
+                    "test_table".to_string(),
+                    vec![(3, vec![1]), (1, vec![2]), (u128::MAX, vec![3])],
+                )
+                .unwrap();
+            backend.delete("test_table".to_string(), 3).unwrap();
+            assert_eq!(
+                backend.keys("test_table".to_string()).unwrap(),
+                vec![1, u128::MAX]
+            );
+            assert_eq!(
+                backend
+                    .keys_in_range("test_table".to_string(), 0, 2)
+                    .unwrap(),
+                vec![1]
+            );
+            assert!(backend.keys("missing_table".to_string()).is_err());
+        }
+        remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let path = tempdir().unwrap().keep();
+        let snapshot_path = tempdir().unwrap().keep().join("snapshot");
+        {
+            let backend = LmdbBackend::initialize(Some(path.clone())).unwrap();
+            backend
+                .insert("test_table".to_string(), 1, vec![1, 2, 3])
+                .unwrap();
+            backend.snapshot(&snapshot_path).unwrap();
+            backend
+                .insert("test_table".to_string(), 2, vec![4, 5, 6])
+                .unwrap();
+        }
+        {
+            let snapshot = LmdbBackend::initialize(Some(snapshot_path.clone())).unwrap();
+            assert_eq!(
+                snapshot.get("test_table".to_string(), 1).unwrap(),
+                Some(vec![1, 2, 3])
+            );
+            assert_eq!(snapshot.get("test_table".to_string(), 2).unwrap(), None);
+        }
+        remove_dir_all(path).unwrap();
+        remove_dir_all(snapshot_path).unwrap();
+    }
+
     #[test]
     fn test_concurrent_write() {
         let path = tempdir().unwrap().keep();
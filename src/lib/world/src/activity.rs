@@ -0,0 +1,214 @@
+//! How long players have spent near each chunk, and when they were last there.
+//!
+//! This is kept in its own table rather than in the chunks, so keeping it up to date doesn't mean
+//! rewriting every chunk near a player on every sync. It's mostly used to find chunks nobody
+//! cares about when pruning the world.
+
+use crate::errors::WorldError;
+use crate::spatial::{chunk_key, decode_chunk_key, DimensionRegistry};
+use crate::World;
+use bitcode_derive::{Decode, Encode};
+use ferrumc_storage::backend::StorageBackend;
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The table chunk activity is stored in, keyed the same way as the chunks.
+const ACTIVITY_TABLE: &str = "chunk_activity";
+
+/// Inhabited time is counted in vanilla ticks, whatever the server's TPS is set to, so it lines up
+/// with imported worlds.
+pub const TICKS_PER_SECOND: u64 = 20;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Encode, Decode)]
+pub struct ChunkActivity {
+    /// How long players have spent near the chunk in total, in ticks. This is the same as
+    /// vanilla's `InhabitedTime`.
+    pub inhabited_time: u64,
+    /// When a player was last near the chunk, in seconds since the Unix epoch, or 0 if no player
+    /// has been near it since it was tracked.
+    pub last_visited: u64,
+}
+
+/// Creates the activity table if the world doesn't have one yet, so activity can be saved.
+pub(crate) fn create_activity_table(
+    storage_backend: &dyn StorageBackend,
+) -> Result<(), WorldError> {
+    if !storage_backend.table_exists(ACTIVITY_TABLE.to_string())? {
+        storage_backend.create_table(ACTIVITY_TABLE.to_string())?;
+    }
+    Ok(())
+}
+
+pub(crate) fn save_activity_internal(
+    storage_backend: &dyn StorageBackend,
+    dimensions: &DimensionRegistry,
+    dimension: &str,
+    x: i32,
+    z: i32,
+    activity: ChunkActivity,
+) -> Result<(), WorldError> {
+    let dimension_id = dimensions.get_or_register(storage_backend, dimension)?;
+    storage_backend.upsert(
+        ACTIVITY_TABLE.to_string(),
+        chunk_key(dimension_id, x, z),
+        bitcode::encode(&activity),
+    )?;
+    Ok(())
+}
+
+fn decode_activity(data: &[u8]) -> Result<ChunkActivity, WorldError> {
+    bitcode::decode(data).map_err(|e| WorldError::BitcodeDecodeError(e.to_string()))
+}
+
+impl World {
+    /// Gets the activity of a chunk. Chunks nobody has been near have the default activity.
+    pub fn chunk_activity(
+        &self,
+        x: i32,
+        z: i32,
+        dimension: &str,
+    ) -> Result<ChunkActivity, WorldError> {
+        let key = (x, z, dimension.to_string());
+        if let Some(activity) = self.activity.get(&key) {
+            return Ok(*activity);
+        }
+        self.load_activity(x, z, dimension)
+    }
+
+    fn load_activity(&self, x: i32, z: i32, dimension: &str) -> Result<ChunkActivity, WorldError> {
+        let Some(dimension_id) = self.dimensions.id(dimension) else {
+            return Ok(ChunkActivity::default());
+        };
+        if !self
+            .storage_backend
+            .table_exists(ACTIVITY_TABLE.to_string())?
+        {
+            return Ok(ChunkActivity::default());
+        }
+        match self
+            .storage_backend
+            .get(ACTIVITY_TABLE.to_string(), chunk_key(dimension_id, x, z))?
+        {
+            Some(data) => decode_activity(&data),
+            None => Ok(ChunkActivity::default()),
+        }
+    }
+
+    /// Records that players have spent `ticks` near each of the given chunks, just now.
+    ///
+    /// The changes are written to the storage backend on the next sync.
+    pub fn record_activity(
+        &self,
+        dimension: &str,
+        chunks: impl IntoIterator<Item = (i32, i32)>,
+        ticks: u64,
+    ) -> Result<(), WorldError> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_secs())
+            .unwrap_or_default();
+        for (x, z) in chunks {
+            let key = (x, z, dimension.to_string());
+            let mut activity = match self.activity.get_mut(&key) {
+                Some(activity) => activity,
+                None => {
+                    // Load outside the entry so the map isn't locked while reading from storage
+                    let stored = self.load_activity(x, z, dimension)?;
+                    self.activity.entry(key).or_insert(stored)
+                }
+            };
+            activity.inhabited_time += ticks;
+            activity.last_visited = now;
+        }
+        Ok(())
+    }
+
+    /// Writes activity recorded since the last sync to the storage backend.
+    pub(crate) fn sync_activity(&self) -> Result<(), WorldError> {
+        let keys: Vec<_> = self
+            .activity
+            .iter()
+            .map(|entry| entry.key().clone())
+            .collect();
+        for key in keys {
+            let Some(((x, z, dimension), activity)) = self.activity.remove(&key) else {
+                continue;
+            };
+            save_activity_internal(
+                self.storage_backend.as_ref(),
+                &self.dimensions,
+                &dimension,
+                x,
+                z,
+                activity,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Gets the activity of every chunk in a dimension that players have been near.
+    pub(crate) fn dimension_activity(
+        &self,
+        dimension: &str,
+    ) -> Result<HashMap<(i32, i32), ChunkActivity>, WorldError> {
+        let mut activity = HashMap::new();
+        if let Some(dimension_id) = self.dimensions.id(dimension) {
+            if self
+                .storage_backend
+                .table_exists(ACTIVITY_TABLE.to_string())?
+            {
+                let start = (dimension_id as u128) << 96;
+                let keys = self.storage_backend.keys_in_range(
+                    ACTIVITY_TABLE.to_string(),
+                    start,
+                    start | ((1 << 96) - 1),
+                )?;
+                let values = self
+                    .storage_backend
+                    .batch_get(ACTIVITY_TABLE.to_string(), keys.clone())?;
+                for (key, value) in keys.into_iter().zip(values) {
+                    if let Some(data) = value {
+                        let (_, x, z) = decode_chunk_key(key);
+                        activity.insert((x, z), decode_activity(&data)?);
+                    }
+                }
+            }
+        }
+        // Activity that hasn't been synced yet is newer than what's stored
+        for entry in self.activity.iter() {
+            let (x, z, chunk_dimension) = entry.key();
+            if chunk_dimension == dimension {
+                activity.insert((*x, *z), *entry.value());
+            }
+        }
+        Ok(activity)
+    }
+
+    /// Forgets the activity of a chunk.
+    pub(crate) fn delete_activity(
+        &self,
+        x: i32,
+        z: i32,
+        dimension: &str,
+    ) -> Result<(), WorldError> {
+        self.activity.remove(&(x, z, dimension.to_string()));
+        let Some(dimension_id) = self.dimensions.id(dimension) else {
+            return Ok(());
+        };
+        if !self
+            .storage_backend
+            .table_exists(ACTIVITY_TABLE.to_string())?
+        {
+            return Ok(());
+        }
+        let key = chunk_key(dimension_id, x, z);
+        if self
+            .storage_backend
+            .exists(ACTIVITY_TABLE.to_string(), key)?
+        {
+            self.storage_backend
+                .delete(ACTIVITY_TABLE.to_string(), key)?;
+        }
+        Ok(())
+    }
+}
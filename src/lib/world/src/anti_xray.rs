@@ -0,0 +1,277 @@
+//! Hiding ores from x-ray clients.
+//!
+//! Ores that aren't next to air or another see-through block can't be seen without x-ray, so the
+//! copy of a chunk sent to players has them swapped for the block around them, like stone. Once
+//! a block next to one is broken the ore can be seen, so the real block is sent again.
+//!
+//! Only the packets are changed, the stored chunks always have the real blocks.
+
+use crate::block_changes::BlockChange;
+use crate::block_id::{BlockId, BLOCK2ID, ID2BLOCK};
+use crate::chunk_format::Chunk;
+use crate::edit_batch::EditBatch;
+use crate::errors::WorldError;
+use crate::lighting::{light_properties, MAX_LIGHT};
+use crate::vanilla_chunk_format::BlockData;
+use crate::World;
+use ahash::AHashMap;
+use ferrumc_config::server_config::{AntiXrayConfig, AntiXrayDimensionConfig};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::warn;
+
+const NEIGHBOURS: [(i32, i32, i32); 6] = [
+    (1, 0, 0),
+    (-1, 0, 0),
+    (0, 1, 0),
+    (0, -1, 0),
+    (0, 0, 1),
+    (0, 0, -1),
+];
+
+/// Which blocks are hidden in a dimension, and what they're sent as instead.
+#[derive(Clone, Debug, Default)]
+pub struct AntiXray {
+    hidden: AHashMap<BlockId, BlockId>,
+}
+
+/// Every state of the block with this name.
+fn block_states(name: &str) -> Vec<BlockId> {
+    ID2BLOCK
+        .iter()
+        .enumerate()
+        .filter(|(_, block)| block.name == name)
+        .map(|(id, _)| BlockId(id as u32))
+        .collect()
+}
+
+/// Finds a block from a state string. Blocks with properties can be given by just their name, in
+/// which case their first state is used.
+fn resolve_state(state: &str) -> Option<BlockId> {
+    let block = BlockData::from_state_string(state)?;
+    if let Some(id) = BLOCK2ID.get(&block) {
+        return Some(BlockId(*id as u32));
+    }
+    block_states(&block.name).first().copied()
+}
+
+/// Whether a block can be seen through, so anything next to it can be seen.
+fn is_see_through(block: BlockId) -> bool {
+    light_properties(block).opacity < MAX_LIGHT
+}
+
+impl AntiXray {
+    /// Builds the anti-xray settings for a dimension. Blocks that don't exist are skipped.
+    pub fn new(config: &AntiXrayDimensionConfig) -> Self {
+        let mut hidden = AHashMap::new();
+        for (name, replacement) in &config.hidden_blocks {
+            let Some(replacement) = resolve_state(replacement) else {
+                warn!("Unknown anti-xray replacement block {replacement} for {name}");
+                continue;
+            };
+            let name = BlockData::from_state_string(name)
+                .map(|block| block.name)
+                .unwrap_or_default();
+            let states = block_states(&name);
+            if states.is_empty() {
+                warn!("Unknown anti-xray hidden block {name}");
+            }
+            hidden.extend(states.into_iter().map(|state| (state, replacement)));
+        }
+        Self { hidden }
+    }
+
+    /// Builds the anti-xray settings for every dimension in the config, or none if it's disabled.
+    pub fn from_config(config: &AntiXrayConfig) -> HashMap<String, AntiXray> {
+        if !config.enabled {
+            return HashMap::new();
+        }
+        config
+            .dimensions
+            .iter()
+            .map(|(dimension, config)| (dimension.clone(), AntiXray::new(config)))
+            .filter(|(_, anti_xray)| !anti_xray.hidden.is_empty())
+            .collect()
+    }
+
+    pub fn is_hidden(&self, block: BlockId) -> bool {
+        self.hidden.contains_key(&block)
+    }
+
+    /// Makes a copy of a chunk with every hidden block that can't be seen replaced, or `None` if
+    /// there's nothing to hide.
+    ///
+    /// `outside` looks up blocks in the chunks around this one. Blocks it can't find, like ones in
+    /// chunks that haven't been generated, are treated as solid.
+    pub fn obfuscate(
+        &self,
+        chunk: &Chunk,
+        outside: impl Fn(i32, i32, i32) -> Option<BlockId>,
+    ) -> Result<Option<Chunk>, WorldError> {
+        let mut sections = AHashMap::with_capacity(chunk.sections.len());
+        let mut has_hidden = false;
+        for section in &chunk.sections {
+            let blocks = section.block_states.blocks()?;
+            has_hidden |= blocks.iter().any(|block| self.is_hidden(*block));
+            sections.insert(section.y as i32, blocks);
+        }
+        if !has_hidden {
+            return Ok(None);
+        }
+        let (min_section, max_section) = (
+            sections.keys().min().copied().unwrap_or_default(),
+            sections.keys().max().copied().unwrap_or_default(),
+        );
+        // Coordinates here are relative to the chunk horizontally and absolute vertically
+        let block_at = |x: i32, y: i32, z: i32| -> Option<BlockId> {
+            if !(0..16).contains(&x) || !(0..16).contains(&z) {
+                return outside(chunk.x * 16 + x, y, chunk.z * 16 + z);
+            }
+            let section_y = y >> 4;
+            if section_y > max_section {
+                return Some(BlockId::default());
+            }
+            if section_y < min_section {
+                return None;
+            }
+            let index = ((y & 0xf) * 256 + z * 16 + x) as usize;
+            sections.get(&section_y).map(|blocks| blocks[index])
+        };
+
+        let mut hidden_chunk = chunk.clone();
+        let mut batch = EditBatch::new(&mut hidden_chunk);
+        let mut replaced = false;
+        for (section_y, blocks) in &sections {
+            for (index, block) in blocks.iter().enumerate() {
+                let Some(replacement) = self.hidden.get(block) else {
+                    continue;
+                };
+                let index = index as i32;
+                let (x, y, z) = (
+                    index & 0xf,
+                    section_y * 16 + (index >> 8),
+                    (index >> 4) & 0xf,
+                );
+                let visible = NEIGHBOURS.iter().any(|(dx, dy, dz)| {
+                    block_at(x + dx, y + dy, z + dz).is_some_and(is_see_through)
+                });
+                if !visible {
+                    batch.set_block(x, y, z, *replacement);
+                    replaced = true;
+                }
+            }
+        }
+        if !replaced {
+            return Ok(None);
+        }
+        batch.apply()?;
+        Ok(Some(hidden_chunk))
+    }
+}
+
+impl World {
+    /// The anti-xray settings for a dimension, or `None` if nothing is hidden in it.
+    pub fn anti_xray(&self, dimension: &str) -> Option<&AntiXray> {
+        self.anti_xray.get(dimension)
+    }
+
+    /// Makes the copy of a chunk that should be sent to players, with the ores they can't see
+    /// hidden. Returns `None` if the chunk can be sent as it is.
+    pub fn obfuscate_chunk(&self, chunk: &Chunk) -> Result<Option<Chunk>, WorldError> {
+        let Some(anti_xray) = self.anti_xray(&chunk.dimension) else {
+            return Ok(None);
+        };
+        let mut neighbours: AHashMap<(i32, i32), Option<Arc<Chunk>>> = AHashMap::new();
+        for (x, z) in [(-1, 0), (1, 0), (0, -1), (0, 1)] {
+            let (x, z) = (chunk.x + x, chunk.z + z);
+            // Neighbours that haven't been generated yet are treated as solid
+            let neighbour = self.load_chunk(x, z, &chunk.dimension).ok();
+            neighbours.insert((x, z), neighbour);
+        }
+        anti_xray.obfuscate(chunk, |x, y, z| {
+            neighbours
+                .get(&(x >> 4, z >> 4))
+                .and_then(|neighbour| neighbour.as_ref())
+                .and_then(|neighbour| neighbour.get_block(x, y, z).ok())
+        })
+    }
+
+    /// Finds the hidden blocks next to a block that players can now see, because the block has
+    /// just been broken or replaced with something see-through. Players should be sent these, as
+    /// their copy of the chunk has the replacement instead.
+    pub fn revealed_blocks(
+        &self,
+        dimension: &str,
+        (x, y, z): (i32, i32, i32),
+    ) -> Result<Vec<BlockChange>, WorldError> {
+        let Some(anti_xray) = self.anti_xray(dimension) else {
+            return Ok(Vec::new());
+        };
+        let mut revealed = Vec::new();
+        for (dx, dy, dz) in NEIGHBOURS {
+            let (x, y, z) = (x + dx, y + dy, z + dz);
+            let chunk = match self.load_chunk(x >> 4, z >> 4, dimension) {
+                Ok(chunk) => chunk,
+                Err(WorldError::ChunkNotFound) => continue,
+                Err(e) => return Err(e),
+            };
+            // Positions above or below the world don't have a block
+            let Ok(block) = chunk.get_block(x, y, z) else {
+                continue;
+            };
+            if anti_xray.is_hidden(block) {
+                revealed.push(BlockChange { x, y, z, block });
+            }
+        }
+        Ok(revealed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn block(state: &str) -> BlockId {
+        BlockData::from_state_string(state).unwrap().to_block_id()
+    }
+
+    fn anti_xray() -> AntiXray {
+        AntiXray::new(&AntiXrayDimensionConfig {
+            hidden_blocks: BTreeMap::from([(
+                "minecraft:diamond_ore".to_string(),
+                "minecraft:stone".to_string(),
+            )]),
+        })
+    }
+
+    #[test]
+    fn test_only_buried_ores_are_hidden() {
+        let mut chunk = Chunk::new(0, 0, "overworld".to_string());
+        let (stone, diamond) = (block("stone"), block("diamond_ore"));
+        chunk
+            .set_section(0, BlockData::from_state_string("stone").unwrap())
+            .unwrap();
+        chunk.set_block(5, 5, 5, diamond).unwrap();
+        chunk.set_block(8, 8, 8, diamond).unwrap();
+        chunk.set_block(8, 9, 8, BlockId::default()).unwrap();
+
+        let hidden = anti_xray()
+            .obfuscate(&chunk, |_, _, _| None)
+            .unwrap()
+            .unwrap();
+        assert_eq!(hidden.get_block(5, 5, 5).unwrap(), stone);
+        assert_eq!(hidden.get_block(8, 8, 8).unwrap(), diamond);
+        // The original chunk isn't changed
+        assert_eq!(chunk.get_block(5, 5, 5).unwrap(), diamond);
+    }
+
+    #[test]
+    fn test_nothing_to_hide() {
+        let chunk = Chunk::new(0, 0, "overworld".to_string());
+        assert!(anti_xray()
+            .obfuscate(&chunk, |_, _, _| None)
+            .unwrap()
+            .is_none());
+    }
+}
@@ -0,0 +1,412 @@
+//! A log of who changed which blocks, for tracking down griefing and undoing it.
+//!
+//! Entries are kept in their own table, keyed by when they were made, so looking through a time
+//! range only reads the entries in it. Filtering by area or player is done while reading.
+//!
+//! | Bits    | Contents                                     |
+//! |---------|----------------------------------------------|
+//! | 64..128 | Milliseconds since the Unix epoch            |
+//! | 0..64   | A counter, so changes in the same ms differ  |
+//!
+//! Entries are logged from the tick thread, so they're kept in memory and written along with the
+//! chunks on the next [`World::sync`]. The name each player last changed blocks under is kept in
+//! another table, so players can be looked up by name after they've gone offline.
+
+use crate::block_changes::BlockChange;
+use crate::block_id::BlockId;
+use crate::errors::WorldError;
+use crate::regions::{Region, RegionEdit};
+use crate::World;
+use ahash::AHashMap;
+use bitcode_derive::{Decode, Encode};
+use dashmap::DashMap;
+use ferrumc_storage::backend::StorageBackend;
+use parking_lot::Mutex;
+use std::collections::{BTreeMap, HashMap};
+use std::mem;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::info;
+
+/// The table the audit log is stored in.
+const AUDIT_TABLE: &str = "block_audit";
+/// The table of the name each player last changed blocks under, keyed by UUID.
+const AUDIT_PLAYERS_TABLE: &str = "block_audit_players";
+
+/// How many entries are read from the storage backend at once while searching the log.
+const READ_BATCH_SIZE: usize = 1024;
+
+/// A block a player changed.
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct BlockAuditEntry {
+    /// The UUID of the player who changed the block.
+    pub player: u128,
+    /// The player's name when they changed the block, so entries can be found by name after
+    /// they've logged off.
+    pub player_name: String,
+    pub dimension: String,
+    pub x: i32,
+    pub y: i32,
+    pub z: i32,
+    pub old: BlockId,
+    pub new: BlockId,
+    /// When the block was changed, in milliseconds since the Unix epoch.
+    pub timestamp: u64,
+}
+
+impl BlockAuditEntry {
+    /// Creates an entry for a change made just now.
+    pub fn now(
+        player: u128,
+        player_name: impl Into<String>,
+        dimension: impl Into<String>,
+        (x, y, z): (i32, i32, i32),
+        old: BlockId,
+        new: BlockId,
+    ) -> Self {
+        Self {
+            player,
+            player_name: player_name.into(),
+            dimension: dimension.into(),
+            x,
+            y,
+            z,
+            old,
+            new,
+            timestamp: now_millis(),
+        }
+    }
+}
+
+/// Which entries to look for in the audit log. Every filter that's set has to match.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AuditQuery {
+    /// Only entries in this dimension.
+    pub dimension: Option<String>,
+    /// Only entries inside this area. This is usually set along with `dimension`.
+    pub area: Option<Region>,
+    /// Only entries made by the player with this UUID.
+    pub player: Option<u128>,
+    /// Only entries made at or after this time, in milliseconds since the Unix epoch.
+    pub since: Option<u64>,
+    /// Only entries made at or before this time, in milliseconds since the Unix epoch.
+    pub until: Option<u64>,
+    /// At most this many entries, keeping the newest ones.
+    pub limit: Option<usize>,
+}
+
+impl AuditQuery {
+    pub fn matches(&self, entry: &BlockAuditEntry) -> bool {
+        self.dimension
+            .as_ref()
+            .is_none_or(|dimension| *dimension == entry.dimension)
+            && self
+                .area
+                .is_none_or(|area| area.contains(entry.x, entry.y, entry.z))
+            && self.player.is_none_or(|player| player == entry.player)
+            && self.since.is_none_or(|since| entry.timestamp >= since)
+            && self.until.is_none_or(|until| entry.timestamp <= until)
+    }
+}
+
+/// The audit log entries and player names that haven't been written yet.
+#[derive(Default)]
+struct PendingAudit {
+    entries: Vec<(u128, Vec<u8>)>,
+    names: HashMap<u128, String>,
+}
+
+/// The parts of the audit log kept in memory.
+pub(crate) struct AuditLog {
+    /// Tells apart entries made in the same millisecond.
+    sequence: AtomicU64,
+    pending: Mutex<PendingAudit>,
+    /// The UUID each player name was last logged with, by lowercase name.
+    players: DashMap<String, u128>,
+}
+
+impl AuditLog {
+    pub(crate) fn load(storage_backend: &dyn StorageBackend) -> Result<Self, WorldError> {
+        if !storage_backend.table_exists(AUDIT_TABLE.to_string())? {
+            storage_backend.create_table(AUDIT_TABLE.to_string())?;
+        }
+        let players = DashMap::new();
+        if storage_backend.table_exists(AUDIT_PLAYERS_TABLE.to_string())? {
+            let uuids = storage_backend.keys(AUDIT_PLAYERS_TABLE.to_string())?;
+            let names =
+                storage_backend.batch_get(AUDIT_PLAYERS_TABLE.to_string(), uuids.clone())?;
+            for (uuid, name) in uuids.into_iter().zip(names) {
+                if let Some(name) = name {
+                    players.insert(String::from_utf8_lossy(&name).to_lowercase(), uuid);
+                }
+            }
+        } else {
+            storage_backend.create_table(AUDIT_PLAYERS_TABLE.to_string())?;
+            index_logged_players(storage_backend, &players)?;
+        }
+        Ok(Self {
+            sequence: AtomicU64::new(0),
+            pending: Mutex::new(PendingAudit::default()),
+            players,
+        })
+    }
+}
+
+/// Builds the player name index from the entries in the log, for worlds that were logged before
+/// the index was kept. This reads the whole log, so it's only done once.
+fn index_logged_players(
+    storage_backend: &dyn StorageBackend,
+    players: &DashMap<String, u128>,
+) -> Result<(), WorldError> {
+    let keys = storage_backend.keys(AUDIT_TABLE.to_string())?;
+    if keys.is_empty() {
+        return Ok(());
+    }
+    info!("Indexing the players in {} audit log entries", keys.len());
+    let mut names = HashMap::new();
+    // Oldest first, so players end up with the last name they used
+    for keys in keys.chunks(READ_BATCH_SIZE) {
+        let values = storage_backend.batch_get(AUDIT_TABLE.to_string(), keys.to_vec())?;
+        for data in values.into_iter().flatten() {
+            let entry = decode_entry(&data)?;
+            players.insert(entry.player_name.to_lowercase(), entry.player);
+            names.insert(entry.player, entry.player_name);
+        }
+    }
+    storage_backend.batch_upsert(
+        AUDIT_PLAYERS_TABLE.to_string(),
+        names
+            .into_iter()
+            .map(|(uuid, name)| (uuid, name.into_bytes()))
+            .collect(),
+    )?;
+    Ok(())
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+fn decode_entry(data: &[u8]) -> Result<BlockAuditEntry, WorldError> {
+    bitcode::decode(data).map_err(|e| WorldError::BitcodeDecodeError(e.to_string()))
+}
+
+/// Works out what each block should be put back to, which is the block from before the oldest
+/// of the entries that changed it. `entries` should be oldest first.
+fn rollback_changes(entries: &[BlockAuditEntry]) -> BTreeMap<String, Vec<BlockChange>> {
+    let mut restored: AHashMap<(&str, i32, i32, i32), BlockId> = AHashMap::new();
+    for entry in entries {
+        restored
+            .entry((entry.dimension.as_str(), entry.x, entry.y, entry.z))
+            .or_insert(entry.old);
+    }
+    let mut by_dimension: BTreeMap<String, Vec<BlockChange>> = BTreeMap::new();
+    for ((dimension, x, y, z), block) in restored {
+        by_dimension
+            .entry(dimension.to_string())
+            .or_default()
+            .push(BlockChange { x, y, z, block });
+    }
+    by_dimension
+}
+
+impl World {
+    /// Adds block changes to the audit log. They're written to the storage backend on the next
+    /// [`World::sync`], but can be searched straight away.
+    pub fn log_block_changes(&self, entries: &[BlockAuditEntry]) {
+        if entries.is_empty() {
+            return;
+        }
+        let mut pending = self.audit.pending.lock();
+        for entry in entries {
+            let sequence = self.audit.sequence.fetch_add(1, Ordering::Relaxed);
+            let key = ((entry.timestamp as u128) << 64) | sequence as u128;
+            pending.entries.push((key, bitcode::encode(entry)));
+            let name = entry.player_name.to_lowercase();
+            if self.audit.players.insert(name, entry.player) != Some(entry.player) {
+                pending
+                    .names
+                    .insert(entry.player, entry.player_name.clone());
+            }
+        }
+    }
+
+    /// Writes the audit log entries that have been logged since the last time.
+    pub(crate) fn flush_block_audit(&self) -> Result<(), WorldError> {
+        let pending = mem::take(&mut *self.audit.pending.lock());
+        if pending.entries.is_empty() && pending.names.is_empty() {
+            return Ok(());
+        }
+        let result = self.write_pending_audit(&pending);
+        if result.is_err() {
+            // Keep them around so the next sync can try again
+            let mut current = self.audit.pending.lock();
+            let newer = mem::replace(&mut *current, pending);
+            current.entries.extend(newer.entries);
+            current.names.extend(newer.names);
+        }
+        result
+    }
+
+    fn write_pending_audit(&self, pending: &PendingAudit) -> Result<(), WorldError> {
+        self.storage_backend
+            .batch_upsert(AUDIT_TABLE.to_string(), pending.entries.clone())?;
+        let names = pending
+            .names
+            .iter()
+            .map(|(uuid, name)| (*uuid, name.as_bytes().to_vec()))
+            .collect();
+        self.storage_backend
+            .batch_upsert(AUDIT_PLAYERS_TABLE.to_string(), names)?;
+        Ok(())
+    }
+
+    /// Finds the entries in the audit log that match a query, oldest first.
+    pub fn query_block_audit(
+        &self,
+        query: &AuditQuery,
+    ) -> Result<Vec<BlockAuditEntry>, WorldError> {
+        self.flush_block_audit()?;
+        let start = (query.since.unwrap_or(0) as u128) << 64;
+        let end = ((query.until.unwrap_or(u64::MAX) as u128) << 64) | u64::MAX as u128;
+        let keys = self
+            .storage_backend
+            .keys_in_range(AUDIT_TABLE.to_string(), start, end)?;
+
+        let mut entries = Vec::new();
+        // Read newest first, so a limit can stop early
+        for keys in keys.rchunks(READ_BATCH_SIZE) {
+            let values = self
+                .storage_backend
+                .batch_get(AUDIT_TABLE.to_string(), keys.to_vec())?;
+            for data in values.into_iter().rev().flatten() {
+                let entry = decode_entry(&data)?;
+                if query.matches(&entry) {
+                    entries.push(entry);
+                }
+                if query.limit.is_some_and(|limit| entries.len() >= limit) {
+                    entries.reverse();
+                    return Ok(entries);
+                }
+            }
+        }
+        entries.reverse();
+        Ok(entries)
+    }
+
+    /// Finds the UUID a player name was last logged with, so players who've gone offline can
+    /// still be looked up.
+    pub fn find_audited_player(&self, name: &str) -> Option<u128> {
+        self.audit
+            .players
+            .get(&name.to_lowercase())
+            .map(|uuid| *uuid)
+    }
+
+    /// Puts back every block changed by the entries that match a query, as it was before the
+    /// oldest of them. The query's limit is ignored, so a rollback never stops part way through
+    /// someone's changes.
+    ///
+    /// The blocks are set with [`World::set_blocks_on_current_thread`], so players are sent the
+    /// changes and light is updated the same as any other edit. Searching the log and editing the
+    /// chunks can take a while, so this should be run on the thread pool. Rollbacks aren't logged
+    /// themselves, and the entries are kept so they can still be looked at. Blocks in chunks that
+    /// no longer exist are skipped and listed in each edit's `missing_chunks`.
+    pub fn rollback_block_audit(&self, query: &AuditQuery) -> Result<Vec<RegionEdit>, WorldError> {
+        let query = AuditQuery {
+            limit: None,
+            ..query.clone()
+        };
+        let entries = self.query_block_audit(&query)?;
+        rollback_changes(&entries)
+            .into_iter()
+            .map(|(dimension, changes)| self.set_blocks_on_current_thread(&dimension, changes))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(player: u128, (x, y, z): (i32, i32, i32), old: u32, new: u32) -> BlockAuditEntry {
+        BlockAuditEntry {
+            player,
+            player_name: format!("player{player}"),
+            dimension: "overworld".to_string(),
+            x,
+            y,
+            z,
+            old: BlockId(old),
+            new: BlockId(new),
+            timestamp: 1000,
+        }
+    }
+
+    #[test]
+    fn test_query_matches() {
+        let entry = entry(1, (5, 64, -3), 0, 1);
+        assert!(AuditQuery::default().matches(&entry));
+        assert!(AuditQuery {
+            dimension: Some("overworld".to_string()),
+            area: Some(Region::new((0, 0, 0), (10, 100, -10))),
+            player: Some(1),
+            since: Some(1000),
+            until: Some(1000),
+            limit: None,
+        }
+        .matches(&entry));
+        assert!(!AuditQuery {
+            player: Some(2),
+            ..Default::default()
+        }
+        .matches(&entry));
+        assert!(!AuditQuery {
+            area: Some(Region::new((6, 0, 0), (10, 100, -10))),
+            ..Default::default()
+        }
+        .matches(&entry));
+        assert!(!AuditQuery {
+            since: Some(1001),
+            ..Default::default()
+        }
+        .matches(&entry));
+        assert!(!AuditQuery {
+            dimension: Some("the_nether".to_string()),
+            ..Default::default()
+        }
+        .matches(&entry));
+    }
+
+    #[test]
+    fn test_rollback_uses_oldest_block() {
+        let entries = vec![
+            entry(1, (0, 64, 0), 1, 2),
+            entry(2, (0, 64, 0), 2, 3),
+            entry(2, (1, 64, 0), 0, 4),
+        ];
+        let changes = rollback_changes(&entries);
+        let mut overworld = changes["overworld"].clone();
+        overworld.sort_by_key(|change| change.x);
+        assert_eq!(
+            overworld,
+            vec![
+                BlockChange {
+                    x: 0,
+                    y: 64,
+                    z: 0,
+                    block: BlockId(1)
+                },
+                BlockChange {
+                    x: 1,
+                    y: 64,
+                    z: 0,
+                    block: BlockId(0)
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,235 @@
+//! Online snapshots of the world, and managing a directory of backups made from them.
+
+use crate::errors::WorldError;
+use crate::migrations::CHUNK_FORMAT_VERSION;
+use crate::World;
+use ferrumc_config::server_config::{get_global_config, DatabaseBackend};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+/// The file in each snapshot describing what's in it.
+const MANIFEST_FILE: &str = "backup.json";
+const BACKUP_PREFIX: &str = "backup-";
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Describes a snapshot of the world.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupManifest {
+    /// The storage backend the snapshot was taken from, which is needed to open it.
+    pub backend: DatabaseBackend,
+    /// When the snapshot was taken, in seconds since the Unix epoch.
+    pub created: u64,
+    pub chunk_format_version: u32,
+}
+
+/// A snapshot in a backup directory.
+#[derive(Debug, Clone)]
+pub struct Backup {
+    pub path: PathBuf,
+    pub manifest: BackupManifest,
+}
+
+impl World {
+    /// Writes a consistent copy of the world to a new directory.
+    ///
+    /// Dirty chunks are written out first, so the snapshot has everything saved up to the point
+    /// it was called. The server can keep running while the copy is made, but copying a large
+    /// world takes a while, so this shouldn't be called from the tick thread.
+    pub fn snapshot(&self, path: &Path) -> Result<BackupManifest, WorldError> {
+        if path.exists() && path.read_dir()?.next().is_some() {
+            return Err(WorldError::InvalidWorldPath(format!(
+                "Snapshot directory {} is not empty",
+                path.display()
+            )));
+        }
+        self.sync()?;
+        self.storage_backend.snapshot(path)?;
+
+        let manifest = BackupManifest {
+            backend: get_global_config().database.backend,
+            created: unix_time(),
+            chunk_format_version: CHUNK_FORMAT_VERSION,
+        };
+        let manifest_json = serde_json::to_vec_pretty(&manifest)
+            .map_err(|e| WorldError::GenericIOError(e.to_string()))?;
+        fs::write(path.join(MANIFEST_FILE), manifest_json)?;
+        Ok(manifest)
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or_default()
+}
+
+fn read_manifest(path: &Path) -> Result<BackupManifest, WorldError> {
+    let manifest = fs::read(path.join(MANIFEST_FILE))?;
+    serde_json::from_slice(&manifest).map_err(|e| {
+        WorldError::InvalidWorldPath(format!(
+            "{} has an invalid backup manifest: {}",
+            path.display(),
+            e
+        ))
+    })
+}
+
+/// Snapshots the world into a new timestamped directory inside `directory`.
+pub fn create_backup(world: &World, directory: &Path) -> Result<Backup, WorldError> {
+    fs::create_dir_all(directory)?;
+    let created = unix_time();
+    let mut path = directory.join(format!("{BACKUP_PREFIX}{created}"));
+    // Backups taken within the same second get a suffix
+    let mut suffix = 1;
+    while path.exists() {
+        path = directory.join(format!("{BACKUP_PREFIX}{created}-{suffix}"));
+        suffix += 1;
+    }
+    let manifest = world.snapshot(&path)?;
+    Ok(Backup { path, manifest })
+}
+
+/// Lists the backups in a directory, oldest first.
+pub fn list_backups(directory: &Path) -> Result<Vec<Backup>, WorldError> {
+    if !directory.exists() {
+        return Ok(Vec::new());
+    }
+    let mut backups = Vec::new();
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        if !path.is_dir() || !path.join(MANIFEST_FILE).exists() {
+            continue;
+        }
+        match read_manifest(&path) {
+            Ok(manifest) => backups.push(Backup { path, manifest }),
+            Err(e) => warn!("Skipping backup: {}", e),
+        }
+    }
+    backups.sort_by(|a, b| {
+        a.manifest
+            .created
+            .cmp(&b.manifest.created)
+            .then_with(|| a.path.cmp(&b.path))
+    });
+    Ok(backups)
+}
+
+/// Works out which backups the retention rules no longer want, given their creation times
+/// sorted newest first. Returns the indices of the backups to delete.
+///
+/// The newest `keep_last` backups are kept, as well as the newest backup from each of the
+/// `keep_daily` most recent days that have a backup.
+fn expired_backups(
+    created_newest_first: &[u64],
+    keep_last: usize,
+    keep_daily: usize,
+) -> Vec<usize> {
+    let mut days_kept = HashSet::new();
+    let mut expired = Vec::new();
+    for (index, created) in created_newest_first.iter().enumerate() {
+        let day = created / SECONDS_PER_DAY;
+        let keep_for_day = days_kept.len() < keep_daily && !days_kept.contains(&day);
+        if keep_for_day {
+            days_kept.insert(day);
+        }
+        if index >= keep_last && !keep_for_day {
+            expired.push(index);
+        }
+    }
+    expired
+}
+
+/// Deletes the backups in a directory that fall outside the retention rules, returning the
+/// paths of the deleted backups.
+pub fn prune_backups(
+    directory: &Path,
+    keep_last: usize,
+    keep_daily: usize,
+) -> Result<Vec<PathBuf>, WorldError> {
+    let mut backups = list_backups(directory)?;
+    backups.reverse();
+    let created: Vec<u64> = backups.iter().map(|b| b.manifest.created).collect();
+    let mut removed = Vec::new();
+    for index in expired_backups(&created, keep_last, keep_daily) {
+        let path = &backups[index].path;
+        fs::remove_dir_all(path)?;
+        removed.push(path.clone());
+    }
+    Ok(removed)
+}
+
+/// Replaces the world database at `db_path` with a snapshot.
+///
+/// The server must not be running. The current world isn't deleted, it's moved next to the
+/// database with a `.before-restore` suffix. Returns where the old world was moved to, if there
+/// was one.
+pub fn restore_backup(backup: &Path, db_path: &Path) -> Result<Option<PathBuf>, WorldError> {
+    let manifest = read_manifest(backup)?;
+    let backend = get_global_config().database.backend;
+    if manifest.backend != backend {
+        return Err(WorldError::InvalidBackend(format!(
+            "The backup was taken from a {:?} database but the server is set to use {:?}",
+            manifest.backend, backend
+        )));
+    }
+    if manifest.chunk_format_version > CHUNK_FORMAT_VERSION {
+        return Err(WorldError::UnsupportedChunkVersion(
+            manifest.chunk_format_version,
+        ));
+    }
+
+    let mut moved_to = None;
+    if db_path.exists() {
+        let mut old_path = db_path.as_os_str().to_owned();
+        old_path.push(format!(".before-restore-{}", unix_time()));
+        let old_path = PathBuf::from(old_path);
+        fs::rename(db_path, &old_path)?;
+        info!("Moved the current world to {}", old_path.display());
+        moved_to = Some(old_path);
+    }
+
+    fs::create_dir_all(db_path)?;
+    for entry in fs::read_dir(backup)? {
+        let entry = entry?;
+        if entry.file_name() == MANIFEST_FILE || !entry.path().is_file() {
+            continue;
+        }
+        fs::copy(entry.path(), db_path.join(entry.file_name()))?;
+    }
+    Ok(moved_to)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: u64 = SECONDS_PER_DAY;
+
+    #[test]
+    fn test_keep_last() {
+        let created = [50, 40, 30, 20, 10];
+        assert_eq!(expired_backups(&created, 2, 0), vec![2, 3, 4]);
+        assert_eq!(expired_backups(&created, 10, 0), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn test_keep_daily() {
+        // Two backups a day for three days
+        let created = [
+            3 * DAY + 20,
+            3 * DAY + 10,
+            2 * DAY + 20,
+            2 * DAY + 10,
+            DAY + 20,
+            DAY + 10,
+        ];
+        // The newest of each of the two most recent days, plus the newest backup overall
+        assert_eq!(expired_backups(&created, 1, 2), vec![1, 3, 4, 5]);
+        assert_eq!(expired_backups(&created, 3, 3), vec![3, 5]);
+    }
+}
@@ -0,0 +1,302 @@
+//! Biomes, and the biome palettes stored in each section.
+//!
+//! Biome IDs are indexes into the `minecraft:worldgen/biome` registry that's sent to clients in
+//! `registry_data`, so they're read from the same file the registry packets are built from.
+//! Biomes are stored per 4x4x4 cell, so a section has 64 of them.
+
+use crate::chunk_format::{BiomeStates, Chunk};
+use crate::edits::{pack_entries, unpack_n_entries};
+use crate::errors::WorldError;
+use ahash::RandomState;
+use bitcode_derive::{Decode, Encode};
+use deepsize::DeepSizeOf;
+use ferrumc_net_codec::net_types::var_int::VarInt;
+use indexmap::IndexMap;
+use lazy_static::lazy_static;
+use serde::de::IgnoredAny;
+use std::collections::HashMap;
+
+const REGISTRY_PACKETS_FILE: &str = include_str!("../../../../assets/data/registry_packets.json");
+
+const BIOME_REGISTRY: &str = "minecraft:worldgen/biome";
+
+/// The biome used when nothing else is known, e.g. for unknown biomes in imported chunks.
+pub const DEFAULT_BIOME: &str = "minecraft:plains";
+
+/// The number of biome cells in a section.
+pub const BIOMES_PER_SECTION: usize = 64;
+
+/// The most bits per biome an indirect palette can use. Sections that need more than this use
+/// the global palette instead.
+pub const MAX_INDIRECT_BIOME_BITS: u8 = 3;
+
+lazy_static! {
+    /// Biome names in registry order, so a biome's ID is its index.
+    pub static ref ID2BIOME: Vec<String> = {
+        let registries: IndexMap<String, IndexMap<String, IgnoredAny>> =
+            serde_json::from_str(REGISTRY_PACKETS_FILE).expect("Invalid registry packets file");
+        registries
+            .get(BIOME_REGISTRY)
+            .expect("Registry packets file has no biome registry")
+            .keys()
+            .map(|name| namespaced(name))
+            .collect()
+    };
+    pub static ref BIOME2ID: HashMap<String, u32, RandomState> = ID2BIOME
+        .iter()
+        .enumerate()
+        .map(|(id, name)| (name.clone(), id as u32))
+        .collect();
+    /// The number of bits needed to store any biome ID, used by sections with a direct palette.
+    pub static ref GLOBAL_BITS_PER_BIOME: u8 =
+        (usize::BITS - (ID2BIOME.len() - 1).leading_zeros()) as u8;
+}
+
+fn namespaced(name: &str) -> String {
+    if name.contains(':') {
+        name.to_string()
+    } else {
+        format!("minecraft:{name}")
+    }
+}
+
+/// An ID for a biome in the biome registry.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Encode, Decode, DeepSizeOf)]
+pub struct BiomeId(pub u32);
+
+impl BiomeId {
+    /// Looks a biome up by name, with or without the `minecraft:` namespace.
+    pub fn from_name(name: &str) -> Option<Self> {
+        BIOME2ID.get(&namespaced(name)).copied().map(BiomeId)
+    }
+
+    /// Returns the biome's namespaced name, or `None` if the ID isn't in the registry.
+    pub fn name(&self) -> Option<&'static str> {
+        ID2BIOME.get(self.0 as usize).map(String::as_str)
+    }
+
+    pub fn from_varint(var_int: VarInt) -> Self {
+        BiomeId(var_int.0 as u32)
+    }
+
+    pub fn to_varint(&self) -> VarInt {
+        VarInt(self.0 as i32)
+    }
+}
+
+impl Default for BiomeId {
+    fn default() -> Self {
+        BiomeId::from_name(DEFAULT_BIOME).expect("The default biome isn't in the biome registry")
+    }
+}
+
+/// Returns the bits per biome needed for a palette with `len` entries.
+pub fn bits_for_biome_palette(len: usize) -> u8 {
+    (usize::BITS - len.saturating_sub(1).leading_zeros()) as u8
+}
+
+/// The index of the biome cell containing a block, from the block's coordinates.
+fn cell_index(x: i32, y: i32, z: i32) -> usize {
+    let (x, y, z) = ((x >> 2) & 3, (y >> 2) & 3, (z >> 2) & 3);
+    (y * 16 + z * 4 + x) as usize
+}
+
+impl BiomeStates {
+    /// A section that's entirely one biome.
+    pub fn single(biome: BiomeId) -> Self {
+        Self {
+            bits_per_biome: 0,
+            data: vec![],
+            palette: vec![biome.to_varint()],
+        }
+    }
+
+    /// Builds the smallest biome palette that can hold the given biomes, which must be in cell
+    /// order.
+    pub fn from_biomes(biomes: &[BiomeId]) -> Result<Self, WorldError> {
+        if biomes.len() != BIOMES_PER_SECTION {
+            return Err(WorldError::InvalidBiomeData(format!(
+                "Expected {BIOMES_PER_SECTION} biomes, but got {}",
+                biomes.len()
+            )));
+        }
+        let mut palette: Vec<BiomeId> = Vec::new();
+        let mut indexes = Vec::with_capacity(BIOMES_PER_SECTION);
+        for biome in biomes {
+            let index = match palette.iter().position(|entry| entry == biome) {
+                Some(index) => index,
+                None => {
+                    palette.push(*biome);
+                    palette.len() - 1
+                }
+            };
+            indexes.push(index as u32);
+        }
+        if palette.len() == 1 {
+            return Ok(Self::single(palette[0]));
+        }
+        let bits_per_biome = bits_for_biome_palette(palette.len());
+        if bits_per_biome > MAX_INDIRECT_BIOME_BITS {
+            let ids: Vec<u32> = biomes.iter().map(|biome| biome.0).collect();
+            return Ok(Self {
+                bits_per_biome: *GLOBAL_BITS_PER_BIOME,
+                data: pack_entries(*GLOBAL_BITS_PER_BIOME, &ids)?,
+                palette: vec![],
+            });
+        }
+        Ok(Self {
+            bits_per_biome,
+            data: pack_entries(bits_per_biome, &indexes)?,
+            palette: palette.iter().map(BiomeId::to_varint).collect(),
+        })
+    }
+
+    /// Returns every biome in the section, in cell order.
+    pub fn biomes(&self) -> Result<Vec<BiomeId>, WorldError> {
+        if self.bits_per_biome == 0 {
+            let biome = self
+                .palette
+                .first()
+                .map(|id| BiomeId::from_varint(*id))
+                .unwrap_or_default();
+            return Ok(vec![biome; BIOMES_PER_SECTION]);
+        }
+        let entries = unpack_n_entries(self.bits_per_biome, &self.data, BIOMES_PER_SECTION)?;
+        if self.palette.is_empty() {
+            return Ok(entries.into_iter().map(BiomeId).collect());
+        }
+        entries
+            .into_iter()
+            .map(|index| {
+                self.palette
+                    .get(index as usize)
+                    .map(|id| BiomeId::from_varint(*id))
+                    .ok_or_else(|| {
+                        WorldError::InvalidBiomeData(format!("Palette index {index} out of range"))
+                    })
+            })
+            .collect()
+    }
+
+    /// Gets the biome at some block coordinates within the section.
+    pub fn get_biome(&self, x: i32, y: i32, z: i32) -> Result<BiomeId, WorldError> {
+        if self.bits_per_biome == 0 {
+            return Ok(self
+                .palette
+                .first()
+                .map(|id| BiomeId::from_varint(*id))
+                .unwrap_or_default());
+        }
+        Ok(self.biomes()?[cell_index(x, y, z)])
+    }
+
+    /// Sets the biome of the cell containing some block coordinates within the section.
+    pub fn set_biome(&mut self, x: i32, y: i32, z: i32, biome: BiomeId) -> Result<(), WorldError> {
+        let mut biomes = self.biomes()?;
+        biomes[cell_index(x, y, z)] = biome;
+        *self = Self::from_biomes(&biomes)?;
+        Ok(())
+    }
+}
+
+impl Chunk {
+    /// Gets the biome at the specified block coordinates.
+    ///
+    /// Biomes are stored per 4x4x4 cell, so every block in a cell has the same biome.
+    pub fn get_biome(&self, x: i32, y: i32, z: i32) -> Result<BiomeId, WorldError> {
+        let section = self
+            .sections
+            .iter()
+            .find(|section| section.y == (y >> 4) as i8)
+            .ok_or(WorldError::SectionOutOfBounds(y >> 4))?;
+        section.biome_states.get_biome(x, y, z)
+    }
+
+    /// Sets the biome of the 4x4x4 cell containing the specified block coordinates.
+    pub fn set_biome(&mut self, x: i32, y: i32, z: i32, biome: BiomeId) -> Result<(), WorldError> {
+        let section = self
+            .sections
+            .iter_mut()
+            .find(|section| section.y == (y >> 4) as i8)
+            .ok_or(WorldError::SectionOutOfBounds(y >> 4))?;
+        section.biome_states.set_biome(x, y, z, biome)
+    }
+
+    /// Sets the biome of a whole column of cells, from the bottom of the world to the top.
+    pub fn set_column_biome(&mut self, x: i32, z: i32, biome: BiomeId) -> Result<(), WorldError> {
+        for section in &mut self.sections {
+            let mut biomes = section.biome_states.biomes()?;
+            for y in 0..4 {
+                biomes[cell_index(x, y * 4, z)] = biome;
+            }
+            section.biome_states = BiomeStates::from_biomes(&biomes)?;
+        }
+        Ok(())
+    }
+
+    /// Sets every biome in the chunk.
+    pub fn fill_biome(&mut self, biome: BiomeId) {
+        for section in &mut self.sections {
+            section.biome_states = BiomeStates::single(biome);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registry_ids() {
+        // Registry IDs are the order biomes are sent in, which is alphabetical
+        assert_eq!(BiomeId::from_name("badlands"), Some(BiomeId(0)));
+        assert_eq!(
+            BiomeId::from_name("minecraft:forest"),
+            BiomeId::from_name("forest")
+        );
+        assert_eq!(BiomeId::from_name("not_a_biome"), None);
+        let plains = BiomeId::default();
+        assert_eq!(plains.name(), Some(DEFAULT_BIOME));
+    }
+
+    #[test]
+    fn test_set_biome() {
+        let mut chunk = Chunk::new(0, 0, "overworld".to_string());
+        let desert = BiomeId::from_name("desert").unwrap();
+        assert_eq!(chunk.get_biome(0, 0, 0).unwrap(), BiomeId::default());
+        chunk.set_biome(5, 70, 13, desert).unwrap();
+        assert_eq!(chunk.get_biome(4, 68, 12).unwrap(), desert);
+        assert_eq!(chunk.get_biome(7, 71, 15).unwrap(), desert);
+        assert_eq!(chunk.get_biome(8, 70, 13).unwrap(), BiomeId::default());
+        assert_eq!(chunk.sections[8].biome_states.bits_per_biome, 1);
+    }
+
+    #[test]
+    fn test_palette_growth() {
+        // More than 8 different biomes doesn't fit an indirect palette
+        let biomes: Vec<BiomeId> = (0..BIOMES_PER_SECTION as u32)
+            .map(|i| BiomeId(i % 10))
+            .collect();
+        let states = BiomeStates::from_biomes(&biomes).unwrap();
+        assert_eq!(states.bits_per_biome, *GLOBAL_BITS_PER_BIOME);
+        assert!(states.palette.is_empty());
+        assert_eq!(states.biomes().unwrap(), biomes);
+
+        let biomes: Vec<BiomeId> = (0..BIOMES_PER_SECTION as u32)
+            .map(|i| BiomeId(i % 5))
+            .collect();
+        let states = BiomeStates::from_biomes(&biomes).unwrap();
+        assert_eq!(states.bits_per_biome, 3);
+        assert_eq!(states.biomes().unwrap(), biomes);
+    }
+
+    #[test]
+    fn test_set_column_biome() {
+        let mut chunk = Chunk::new(0, 0, "overworld".to_string());
+        let ocean = BiomeId::from_name("ocean").unwrap();
+        chunk.set_column_biome(3, 3, ocean).unwrap();
+        assert_eq!(chunk.get_biome(0, -64, 0).unwrap(), ocean);
+        assert_eq!(chunk.get_biome(2, 319, 1).unwrap(), ocean);
+        assert_eq!(chunk.get_biome(4, 0, 0).unwrap(), BiomeId::default());
+    }
+}
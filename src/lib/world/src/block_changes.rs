@@ -0,0 +1,241 @@
+//! Blocks that have changed since players were last told about them.
+//!
+//! Edits that change lots of blocks at once, like [`World::edit_batch`], record what they changed
+//! here instead of sending anything themselves, since the world doesn't know about players. The
+//! server drains the changes every tick and sends one packet per changed section. Sections whose
+//! light changed are recorded the same way.
+
+use crate::block_id::BlockId;
+use crate::edit_batch::EditBatch;
+use crate::errors::WorldError;
+use crate::{ChunkKey, World};
+use dashmap::DashMap;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// A section with changed blocks, identified by its dimension and section coordinates.
+pub(crate) type SectionKey = (String, i32, i32, i32);
+
+/// A single block that was changed, in world coordinates.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlockChange {
+    pub x: i32,
+    pub y: i32,
+    pub z: i32,
+    pub block: BlockId,
+}
+
+/// Every block that changed in a section since the last time changes were drained.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SectionChanges {
+    pub dimension: String,
+    pub chunk_x: i32,
+    pub section_y: i32,
+    pub chunk_z: i32,
+    /// The changed blocks, keyed by their position in the section packed as `x << 8 | z << 4 | y`,
+    /// which is how the section blocks update packet wants them.
+    pub blocks: BTreeMap<u16, BlockId>,
+}
+
+/// The sections of a chunk whose light changed since the last time changes were drained.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LightChanges {
+    pub dimension: String,
+    pub chunk_x: i32,
+    pub chunk_z: i32,
+    pub sections: Vec<i8>,
+}
+
+/// Packs a block's position within its section the way the section blocks update packet does.
+fn section_index(x: i32, y: i32, z: i32) -> u16 {
+    (((x & 0xf) << 8) | ((z & 0xf) << 4) | (y & 0xf)) as u16
+}
+
+fn record_light_changes(
+    light_changes: &DashMap<ChunkKey, BTreeSet<i8>>,
+    dimension: &str,
+    sections: &[(i32, i32, i8)],
+) {
+    for &(chunk_x, chunk_z, section_y) in sections {
+        light_changes
+            .entry((chunk_x, chunk_z, dimension.to_string()))
+            .or_default()
+            .insert(section_y);
+    }
+}
+
+fn take_light_changes(light_changes: &DashMap<ChunkKey, BTreeSet<i8>>) -> Vec<LightChanges> {
+    let keys: Vec<ChunkKey> = light_changes
+        .iter()
+        .map(|entry| entry.key().clone())
+        .collect();
+    let mut drained: Vec<LightChanges> = keys
+        .into_iter()
+        .filter_map(|key| light_changes.remove(&key))
+        .map(|((chunk_x, chunk_z, dimension), sections)| LightChanges {
+            dimension,
+            chunk_x,
+            chunk_z,
+            sections: sections.into_iter().collect(),
+        })
+        .collect();
+    drained.sort_by(|a, b| {
+        (&a.dimension, a.chunk_x, a.chunk_z).cmp(&(&b.dimension, b.chunk_x, b.chunk_z))
+    });
+    drained
+}
+
+impl World {
+    /// Records blocks that have changed, so players near them can be told about them.
+    ///
+    /// Only the last change to each block is kept, so recording a block twice before the changes
+    /// are drained only sends the final block.
+    pub fn notify_block_changes(
+        &self,
+        dimension: &str,
+        changes: impl IntoIterator<Item = BlockChange>,
+    ) {
+        for change in changes {
+            let key = (
+                dimension.to_string(),
+                change.x >> 4,
+                change.y >> 4,
+                change.z >> 4,
+            );
+            self.block_changes
+                .entry(key)
+                .or_default()
+                .insert(section_index(change.x, change.y, change.z), change.block);
+        }
+    }
+
+    /// Takes every block change recorded since the last call, grouped by section.
+    pub fn drain_block_changes(&self) -> Vec<SectionChanges> {
+        let keys: Vec<SectionKey> = self
+            .block_changes
+            .iter()
+            .map(|entry| entry.key().clone())
+            .collect();
+        let mut drained: Vec<SectionChanges> = keys
+            .into_iter()
+            .filter_map(|key| self.block_changes.remove(&key))
+            .map(
+                |((dimension, chunk_x, section_y, chunk_z), blocks)| SectionChanges {
+                    dimension,
+                    chunk_x,
+                    section_y,
+                    chunk_z,
+                    blocks,
+                },
+            )
+            .collect();
+        drained.sort_by(|a, b| {
+            (&a.dimension, a.chunk_x, a.chunk_z, a.section_y).cmp(&(
+                &b.dimension,
+                b.chunk_x,
+                b.chunk_z,
+                b.section_y,
+            ))
+        });
+        drained
+    }
+
+    /// Records sections whose light has changed, as returned by [`World::relight`], so players
+    /// near them can be sent the new light.
+    pub fn notify_light_changes(&self, dimension: &str, sections: &[(i32, i32, i8)]) {
+        record_light_changes(&self.light_changes, dimension, sections);
+    }
+
+    /// Takes every light change recorded since the last call, grouped by chunk.
+    pub fn drain_light_changes(&self) -> Vec<LightChanges> {
+        take_light_changes(&self.light_changes)
+    }
+
+    /// Edits a chunk with an [`EditBatch`], then records every block the batch changed so players
+    /// near the chunk are sent the new blocks. The light around the changed blocks is worked out
+    /// again with [`World::relight`] and recorded the same way.
+    ///
+    /// The chunk is locked while it's edited, the same as [`World::edit`], but not while it's
+    /// relit, since that needs the chunks around it too. Doing nothing in `edit` is fine, the chunk
+    /// is just left alone.
+    pub fn edit_batch(
+        &self,
+        x: i32,
+        z: i32,
+        dimension: &str,
+        edit: impl FnOnce(&mut EditBatch),
+    ) -> Result<(), WorldError> {
+        let changes = self.edit(x, z, dimension, |chunk| {
+            let mut batch = EditBatch::new(chunk);
+            edit(&mut batch);
+            if batch.edits.is_empty() {
+                return Ok(Vec::new());
+            }
+            batch.apply()?;
+            Ok(batch.changes().to_vec())
+        })?;
+        if changes.is_empty() {
+            return Ok(());
+        }
+        let positions: Vec<(i32, i32, i32)> = changes
+            .iter()
+            .map(|change| (change.x, change.y, change.z))
+            .collect();
+        self.notify_block_changes(dimension, changes);
+        let lit_sections = self.relight(dimension, &positions)?;
+        self.notify_light_changes(dimension, &lit_sections);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_section_index() {
+        assert_eq!(section_index(0, 0, 0), 0);
+        assert_eq!(section_index(1, 2, 3), 0x132);
+        // Only the position within the section matters
+        assert_eq!(section_index(-1, -1, -1), 0xfff);
+        assert_eq!(section_index(17, 18, 19), 0x132);
+    }
+
+    #[test]
+    fn test_light_changes_round_trip() {
+        let light_changes = DashMap::new();
+        record_light_changes(
+            &light_changes,
+            "overworld",
+            &[(1, 2, 3), (1, 2, -1), (0, 0, 0)],
+        );
+        // Sections recorded twice are only sent once
+        record_light_changes(&light_changes, "overworld", &[(1, 2, 3)]);
+        record_light_changes(&light_changes, "the_nether", &[(1, 2, 3)]);
+
+        let drained = take_light_changes(&light_changes);
+        assert_eq!(
+            drained,
+            vec![
+                LightChanges {
+                    dimension: "overworld".to_string(),
+                    chunk_x: 0,
+                    chunk_z: 0,
+                    sections: vec![0],
+                },
+                LightChanges {
+                    dimension: "overworld".to_string(),
+                    chunk_x: 1,
+                    chunk_z: 2,
+                    sections: vec![-1, 3],
+                },
+                LightChanges {
+                    dimension: "the_nether".to_string(),
+                    chunk_x: 1,
+                    chunk_z: 2,
+                    sections: vec![3],
+                },
+            ]
+        );
+        assert!(take_light_changes(&light_changes).is_empty());
+    }
+}
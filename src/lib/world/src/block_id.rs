@@ -12,6 +12,9 @@ use tracing::error;
 // Go to the .etc/blockstates.json file, see what the last ID is, and add 1 to it.
 const BLOCK_ENTRIES: usize = 27914;
 
+/// The number of bits needed to store any block ID, used by sections with a direct palette.
+pub const GLOBAL_BITS_PER_BLOCK: u8 = (usize::BITS - (BLOCK_ENTRIES - 1).leading_zeros()) as u8;
+
 const BLOCKSFILE: &str = include_str!("../../../../assets/data/blockstates.json");
 
 lazy_static! {
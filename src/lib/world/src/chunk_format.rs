@@ -1,13 +1,13 @@
-use crate::block_id::{BlockId, BLOCK2ID};
+use crate::biomes::{bits_for_biome_palette, BiomeId, BIOMES_PER_SECTION};
+use crate::block_id::{BlockId, BLOCK2ID, GLOBAL_BITS_PER_BLOCK};
+use crate::edits::{bits_for_palette, unpack_entries, unpack_n_entries, MAX_INDIRECT_BITS};
 use crate::vanilla_chunk_format;
 use crate::vanilla_chunk_format::VanillaChunk;
 use crate::{errors::WorldError, vanilla_chunk_format::VanillaHeightmaps};
 use bitcode_derive::{Decode, Encode};
 use deepsize::DeepSizeOf;
-use ferrumc_general_purpose::data_packing::i32::read_nbit_i32;
 use ferrumc_macros::{NBTDeserialize, NBTSerialize};
 use ferrumc_net_codec::net_types::var_int::VarInt;
-use std::cmp::max;
 use std::collections::HashMap;
 use tracing::error;
 use vanilla_chunk_format::BlockData;
@@ -21,6 +21,11 @@ use vanilla_chunk_format::BlockData;
 // in the binary.
 // #[cfg(not(test))]
 
+/// The lowest block a chunk has.
+pub const MIN_Y: i32 = -64;
+/// The highest block a chunk has.
+pub const MAX_Y: i32 = 319;
+
 #[derive(Encode, Decode, Clone, DeepSizeOf, Eq, PartialEq, Debug)]
 // This is a placeholder for the actual chunk format
 pub struct Chunk {
@@ -37,6 +42,10 @@ pub struct Chunk {
 pub struct Heightmaps {
     #[nbt(rename = "MOTION_BLOCKING")]
     pub motion_blocking: Vec<i64>,
+    #[nbt(rename = "MOTION_BLOCKING_NO_LEAVES")]
+    pub motion_blocking_no_leaves: Vec<i64>,
+    #[nbt(rename = "OCEAN_FLOOR")]
+    pub ocean_floor: Vec<i64>,
     #[nbt(rename = "WORLD_SURFACE")]
     pub world_surface: Vec<i64>,
 }
@@ -89,10 +98,45 @@ fn convert_to_net_palette(vanilla_palettes: Vec<BlockData>) -> Result<Vec<VarInt
     Ok(new_palette)
 }
 
+/// Converts a section's biomes from vanilla's palette of names to registry IDs. Vanilla can use
+/// a bigger palette than the protocol allows, so the palette is rebuilt rather than copied.
+fn convert_biomes(biomes: &vanilla_chunk_format::Biomes) -> Result<BiomeStates, WorldError> {
+    let palette: Vec<BiomeId> = biomes
+        .palette
+        .iter()
+        .map(|name| {
+            BiomeId::from_name(name).unwrap_or_else(|| {
+                error!("Could not find biome id for palette entry: {}", name);
+                BiomeId::default()
+            })
+        })
+        .collect();
+    let data = biomes.data.clone().unwrap_or_default();
+    if data.is_empty() {
+        return Ok(BiomeStates::single(
+            palette.first().copied().unwrap_or_default(),
+        ));
+    }
+    let bits_per_biome = bits_for_biome_palette(palette.len());
+    let biomes = unpack_n_entries(bits_per_biome, &data, BIOMES_PER_SECTION)?
+        .into_iter()
+        .map(|index| match palette.get(index as usize) {
+            Some(biome) => *biome,
+            None => {
+                error!("Could not find biome for palette index: {}", index);
+                BiomeId::default()
+            }
+        })
+        .collect::<Vec<_>>();
+    BiomeStates::from_biomes(&biomes)
+}
+
 impl Heightmaps {
     pub fn new() -> Self {
         Heightmaps {
             motion_blocking: vec![],
+            motion_blocking_no_leaves: vec![],
+            ocean_floor: vec![],
             world_surface: vec![],
         }
     }
@@ -108,6 +152,8 @@ impl From<VanillaHeightmaps> for Heightmaps {
     fn from(value: VanillaHeightmaps) -> Self {
         Self {
             motion_blocking: value.motion_blocking.unwrap_or_default(),
+            motion_blocking_no_leaves: value.motion_blocking_no_leaves.unwrap_or_default(),
+            ocean_floor: value.ocean_floor.unwrap_or_default(),
             world_surface: value.world_surface.unwrap_or_default(),
         }
     }
@@ -128,37 +174,29 @@ impl VanillaChunk {
                 .as_ref()
                 .and_then(|bs| bs.palette.clone())
                 .unwrap_or_default();
-            let bits_per_block = max((palette.len() as f32).log2().ceil() as u8, 4);
+            let net_palette = convert_to_net_palette(palette)?;
             let mut block_counts = HashMap::new();
-            for chunk in &raw_block_data {
-                let mut i = 0;
-                while i + bits_per_block < 64 {
-                    let palette_index = read_nbit_i32(chunk, bits_per_block as usize, i as u32)?;
-                    let block = match palette.get(palette_index as usize) {
-                        Some(block) => block,
+            let block_data = if raw_block_data.is_empty() {
+                // Sections with a single block in the palette don't store any block data
+                let block = net_palette.first().copied().unwrap_or_default();
+                block_counts.insert(BlockId::from_varint(block), 4096);
+                PaletteType::Single(block)
+            } else {
+                let bits_per_block = bits_for_palette(net_palette.len());
+                for palette_index in unpack_entries(bits_per_block, &raw_block_data)? {
+                    let block = match net_palette.get(palette_index as usize) {
+                        Some(block) => BlockId::from_varint(*block),
                         None => {
                             error!("Could not find block for palette index: {}", palette_index);
-                            &BlockData::default()
+                            BlockId::default()
                         }
                     };
-
-                    if let Some(count) = block_counts.get_mut(&block.to_block_id()) {
-                        *count += 1;
-                    } else {
-                        block_counts.insert(block.to_block_id(), 0);
-                    }
-
-                    i += bits_per_block;
+                    *block_counts.entry(block).or_insert(0) += 1;
                 }
-            }
-            let block_data = if raw_block_data.is_empty() {
-                block_counts.insert(BlockId::default(), 4096);
-                PaletteType::Single(VarInt::from(0))
-            } else {
                 PaletteType::Indirect {
                     bits_per_block,
                     data: raw_block_data,
-                    palette: convert_to_net_palette(palette)?,
+                    palette: net_palette,
                 }
             };
             // Count the number of blocks that are either air, void air, or cave air
@@ -182,11 +220,17 @@ impl VanillaChunk {
                 )
                 .unwrap_or(&0) as u16;
             let non_air_blocks = 4096 - air_blocks;
-            let block_states = BlockStates {
+            let mut block_states = BlockStates {
                 block_counts,
                 non_air_blocks,
                 block_data,
             };
+            // Vanilla always uses a palette on disk, but the protocol only allows up to 8 bits
+            if let PaletteType::Indirect { bits_per_block, .. } = block_states.block_data {
+                if bits_per_block > MAX_INDIRECT_BITS {
+                    block_states.resize(GLOBAL_BITS_PER_BLOCK as usize)?;
+                }
+            }
             let block_light = section
                 .block_light
                 .as_ref()
@@ -201,11 +245,9 @@ impl VanillaChunk {
                 .iter()
                 .map(|&x| x as u8)
                 .collect();
-            let biome_states = BiomeStates {
-                // TODO: Implement biome states properly
-                bits_per_biome: 4,
-                data: vec![],
-                palette: vec![VarInt::from(0); 1],
+            let biome_states = match &section.biomes {
+                Some(biomes) => convert_biomes(biomes)?,
+                None => BiomeStates::single(BiomeId::default()),
             };
             let section = Section {
                 y,
@@ -221,19 +263,28 @@ impl VanillaChunk {
 
         let heightmaps: Heightmaps = self.heightmaps.clone().map(Into::into).unwrap_or_default();
 
-        Ok(Chunk {
+        let mut chunk = Chunk {
             x: self.x_pos,
             z: self.z_pos,
             dimension,
             sections,
             heightmaps,
-        })
+        };
+        // Chunks that were saved before the vanilla light engine ran don't have usable light data
+        if self.is_light_on != Some(1) {
+            chunk.compute_light();
+        }
+        // Proto-chunks only have the worldgen heightmaps, so fill in whatever is missing
+        if chunk.heightmaps.is_incomplete() {
+            chunk.compute_heightmaps();
+        }
+        Ok(chunk)
     }
 }
 
 impl Chunk {
     pub fn new(x: i32, z: i32, dimension: String) -> Self {
-        let mut sections: Vec<Section> = (-4..20)
+        let mut sections: Vec<Section> = (MIN_Y >> 4..=MAX_Y >> 4)
             .map(|y| Section {
                 y: y as i8,
                 block_states: BlockStates {
@@ -241,31 +292,30 @@ impl Chunk {
                     block_data: PaletteType::Single(VarInt::from(0)),
                     block_counts: HashMap::from([(BlockId::default(), 4096)]),
                 },
-                biome_states: BiomeStates {
-                    bits_per_biome: 0,
-                    data: vec![],
-                    palette: vec![VarInt::from(0)],
-                },
-                block_light: vec![255; 2048],
+                biome_states: BiomeStates::single(BiomeId::default()),
+                block_light: vec![0; 2048],
                 sky_light: vec![255; 2048],
             })
             .collect();
         for section in &mut sections {
             section.optimise().expect("Failed to optimise section");
         }
-        Chunk {
+        let mut chunk = Chunk {
             x,
             z,
             dimension,
             sections,
             heightmaps: Heightmaps::new(),
-        }
+        };
+        chunk.compute_heightmaps();
+        chunk
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::edits::pack_entries;
 
     #[test]
     fn test_chunk_set_block() {
@@ -354,4 +404,154 @@ mod tests {
         assert!(chunk.set_block(0, 0, 0, block.clone()).is_ok());
         assert!(chunk.get_block(0, 0, 0).is_ok());
     }
+
+    #[test]
+    fn test_palette_grows_and_shrinks() {
+        let mut chunk = Chunk::new(0, 0, "overworld".to_string());
+        for i in 0..20 {
+            chunk
+                .set_block(i % 16, i / 16, 0, BlockId(i as u32 + 1))
+                .unwrap();
+        }
+        let section = chunk.sections.iter().find(|s| s.y == 0).unwrap();
+        assert!(matches!(
+            section.block_states.block_data,
+            PaletteType::Indirect {
+                bits_per_block: 5,
+                ..
+            }
+        ));
+        for i in 0..20 {
+            assert_eq!(
+                chunk.get_block(i % 16, i / 16, 0).unwrap(),
+                BlockId(i as u32 + 1)
+            );
+        }
+
+        for i in 10..20 {
+            chunk
+                .set_block(i % 16, i / 16, 0, BlockId::default())
+                .unwrap();
+        }
+        let section = chunk.sections.iter().find(|s| s.y == 0).unwrap();
+        match &section.block_states.block_data {
+            PaletteType::Indirect {
+                bits_per_block,
+                palette,
+                ..
+            } => {
+                assert_eq!(*bits_per_block, 4);
+                assert_eq!(palette.len(), 11);
+            }
+            other => panic!("Expected an indirect palette, got {other:?}"),
+        }
+        for i in 0..10 {
+            assert_eq!(
+                chunk.get_block(i % 16, i / 16, 0).unwrap(),
+                BlockId(i as u32 + 1)
+            );
+        }
+
+        for i in 0..10 {
+            chunk
+                .set_block(i % 16, i / 16, 0, BlockId::default())
+                .unwrap();
+        }
+        let section = chunk.sections.iter().find(|s| s.y == 0).unwrap();
+        assert_eq!(
+            section.block_states.block_data,
+            PaletteType::Single(VarInt::from(0))
+        );
+    }
+
+    #[test]
+    fn test_direct_palette_round_trip() {
+        let mut chunk = Chunk::new(0, 0, "overworld".to_string());
+        // Air plus 300 other blocks doesn't fit in an 8 bit palette
+        for i in 0..300 {
+            chunk
+                .set_block(i % 16, i / 256, (i / 16) % 16, BlockId(i as u32 + 1))
+                .unwrap();
+        }
+        let section = chunk.sections.iter().find(|s| s.y == 0).unwrap();
+        assert!(matches!(
+            section.block_states.block_data,
+            PaletteType::Direct {
+                bits_per_block: GLOBAL_BITS_PER_BLOCK,
+                ..
+            }
+        ));
+        for i in 0..300 {
+            assert_eq!(
+                chunk.get_block(i % 16, i / 256, (i / 16) % 16).unwrap(),
+                BlockId(i as u32 + 1)
+            );
+        }
+        assert_eq!(chunk.get_block(15, 15, 15).unwrap(), BlockId::default());
+
+        // Removing enough blocks should switch back to an indirect palette
+        for i in 100..300 {
+            chunk
+                .set_block(i % 16, i / 256, (i / 16) % 16, BlockId::default())
+                .unwrap();
+        }
+        let section = chunk.sections.iter().find(|s| s.y == 0).unwrap();
+        assert!(matches!(
+            section.block_states.block_data,
+            PaletteType::Indirect {
+                bits_per_block: 7,
+                ..
+            }
+        ));
+        for i in 0..300 {
+            let expected = if i < 100 {
+                BlockId(i as u32 + 1)
+            } else {
+                BlockId::default()
+            };
+            assert_eq!(
+                chunk.get_block(i % 16, i / 256, (i / 16) % 16).unwrap(),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_resize_round_trip() {
+        let mut block_states = BlockStates {
+            non_air_blocks: 0,
+            block_data: PaletteType::Single(VarInt::from(7)),
+            block_counts: HashMap::from([(BlockId(7), 4096)]),
+        };
+        block_states.resize(4).unwrap();
+        block_states.resize(GLOBAL_BITS_PER_BLOCK as usize).unwrap();
+        assert!(matches!(
+            block_states.block_data,
+            PaletteType::Direct { .. }
+        ));
+        block_states.resize(6).unwrap();
+        assert!(matches!(
+            block_states.block_data,
+            PaletteType::Indirect {
+                bits_per_block: 6,
+                ..
+            }
+        ));
+        assert!(block_states
+            .blocks()
+            .unwrap()
+            .iter()
+            .all(|block| *block == BlockId(7)));
+    }
+
+    #[test]
+    fn test_pack_entries_round_trip() {
+        let entries: Vec<u32> = (0..4096).map(|i| i % 300).collect();
+        for bits in [9, 12, GLOBAL_BITS_PER_BLOCK] {
+            let packed = pack_entries(bits, &entries).unwrap();
+            assert_eq!(packed.len(), 4096usize.div_ceil(64 / bits as usize));
+            assert_eq!(unpack_entries(bits, &packed).unwrap(), entries);
+        }
+        assert!(pack_entries(8, &entries).is_err());
+    }
 }
@@ -0,0 +1,224 @@
+use crate::db_functions::{rewrite_stored_chunks, StoredChunk};
+use crate::errors::WorldError;
+use crate::errors::WorldError::CorruptedChunkData;
+use crate::spatial::CHUNK_TABLE;
+use crate::World;
+use ferrumc_config::server_config::{get_global_config, DatabaseCompression};
+use ferrumc_storage::backend::StorageBackend;
+use ferrumc_storage::compressors::record::{
+    decompress_record, is_record, RecordCompressor, ZstdDictionary,
+};
+use ferrumc_storage::compressors::Compressor;
+use ferrumc_storage::errors::StorageError;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use tracing::{debug, info, warn};
+
+/// The table trained zstd dictionaries are kept in, keyed by their ID.
+const DICTIONARY_TABLE: &str = "dictionaries";
+
+/// Compresses and decompresses stored chunks according to the database config.
+///
+/// Chunks are stored as [records](ferrumc_storage::compressors::record), so chunks compressed
+/// with a different algorithm or dictionary than the current one can still be loaded. Chunks
+/// saved before compression was configurable are plain zlib and are also still readable.
+pub(crate) struct ChunkCompression {
+    compressor: Compressor,
+    use_dictionary: bool,
+    dictionaries: RwLock<Dictionaries>,
+}
+
+#[derive(Default)]
+struct Dictionaries {
+    by_id: HashMap<u32, Arc<ZstdDictionary>>,
+    /// The newest dictionary, which new chunks are compressed with.
+    latest: Option<Arc<ZstdDictionary>>,
+}
+
+impl Dictionaries {
+    fn add(&mut self, dictionary: Arc<ZstdDictionary>) {
+        if self
+            .latest
+            .as_ref()
+            .is_none_or(|latest| latest.id() < dictionary.id())
+        {
+            self.latest = Some(dictionary.clone());
+        }
+        self.by_id.insert(dictionary.id(), dictionary);
+    }
+}
+
+impl ChunkCompression {
+    /// Sets up compression from the database config, loading any trained dictionaries.
+    pub(crate) fn load(storage_backend: &dyn StorageBackend) -> Result<Self, WorldError> {
+        let config = &get_global_config().database;
+        let mut dictionaries = Dictionaries::default();
+        if storage_backend.table_exists(DICTIONARY_TABLE.to_string())? {
+            for id in storage_backend.keys(DICTIONARY_TABLE.to_string())? {
+                if let Some(data) = storage_backend.get(DICTIONARY_TABLE.to_string(), id)? {
+                    dictionaries.add(Arc::new(ZstdDictionary::new(id as u32, data)));
+                }
+            }
+        } else {
+            storage_backend.create_table(DICTIONARY_TABLE.to_string())?;
+        }
+
+        let use_dictionary =
+            config.zstd_dictionary && config.compression == DatabaseCompression::Zstd;
+        if use_dictionary && dictionaries.latest.is_none() {
+            warn!(
+                "zstd dictionary compression is enabled but no dictionary has been trained yet. \
+                 Run the recompress command with --train-dictionary to train one."
+            );
+        }
+        debug!(
+            "Compressing chunks with {:?} at level {} ({} dictionaries loaded)",
+            config.compression,
+            config.compression_level,
+            dictionaries.by_id.len()
+        );
+
+        Ok(Self {
+            compressor: Compressor::create(config.compression.into(), config.compression_level),
+            use_dictionary,
+            dictionaries: RwLock::new(dictionaries),
+        })
+    }
+
+    pub(crate) fn compress(&self, data: &[u8]) -> Result<Vec<u8>, WorldError> {
+        let mut compressor = RecordCompressor::new(self.compressor);
+        if self.use_dictionary {
+            if let Some(dictionary) = &self.dictionaries.read().unwrap().latest {
+                compressor = compressor.with_dictionary(dictionary.clone());
+            }
+        }
+        Ok(compressor.compress(data)?)
+    }
+
+    /// Whether the data was stored before chunks were compressed as records.
+    pub(crate) fn is_legacy(data: &[u8]) -> bool {
+        !is_record(data)
+    }
+
+    pub(crate) fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, WorldError> {
+        let verify = get_global_config().database.verify_chunk_data;
+        if Self::is_legacy(data) {
+            return decompress_legacy(data, verify);
+        }
+        let dictionaries = self.dictionaries.read().unwrap();
+        decompress_record(data, verify, |id| dictionaries.by_id.get(&id).cloned()).map_err(|e| {
+            match e {
+                StorageError::ChecksumMismatch(real, expected) => {
+                    CorruptedChunkData(real, expected)
+                }
+                e => e.into(),
+            }
+        })
+    }
+
+    /// Stores a new dictionary and starts using it for new chunks.
+    fn add_dictionary(
+        &self,
+        storage_backend: &dyn StorageBackend,
+        dictionary: ZstdDictionary,
+    ) -> Result<(), WorldError> {
+        storage_backend.upsert(
+            DICTIONARY_TABLE.to_string(),
+            dictionary.id() as u128,
+            dictionary.data().to_vec(),
+        )?;
+        self.dictionaries.write().unwrap().add(Arc::new(dictionary));
+        Ok(())
+    }
+
+    fn next_dictionary_id(&self) -> u32 {
+        self.dictionaries
+            .read()
+            .unwrap()
+            .latest
+            .as_ref()
+            .map_or(1, |latest| latest.id() + 1)
+    }
+}
+
+/// Chunks from before records existed are zlib compressed with yazi.
+fn decompress_legacy(data: &[u8], verify: bool) -> Result<Vec<u8>, WorldError> {
+    let (data, checksum) = yazi::decompress(data, yazi::Format::Zlib)?;
+    if verify {
+        if let Some(expected_checksum) = checksum {
+            let real_checksum = yazi::Adler32::from_buf(data.as_slice()).finish();
+            if real_checksum != expected_checksum {
+                return Err(CorruptedChunkData(real_checksum, expected_checksum));
+            }
+        } else {
+            warn!("Chunk data does not have a checksum, skipping verification.");
+        }
+    }
+    Ok(data)
+}
+
+impl World {
+    /// Trains a zstd dictionary on up to `sample_count` stored chunks and saves it.
+    ///
+    /// The dictionary is at most `max_size` bytes. New chunks are compressed with it if
+    /// `zstd_dictionary` is enabled in the config, and chunks compressed with older dictionaries
+    /// can still be loaded. Returns the ID of the new dictionary.
+    pub fn train_chunk_dictionary(
+        &self,
+        sample_count: usize,
+        max_size: usize,
+    ) -> Result<u32, WorldError> {
+        self.sync()?;
+        if !self.storage_backend.table_exists(CHUNK_TABLE.to_string())? {
+            return Err(WorldError::CompressionError(
+                "There are no chunks to train a dictionary on".to_string(),
+            ));
+        }
+        let keys = self.storage_backend.keys(CHUNK_TABLE.to_string())?;
+        // Spread the samples over the whole world rather than just one corner of it
+        let step = (keys.len() / sample_count.max(1)).max(1);
+        let sample_keys: Vec<u128> = keys.into_iter().step_by(step).take(sample_count).collect();
+
+        let mut samples = Vec::with_capacity(sample_keys.len());
+        for compressed in self
+            .storage_backend
+            .batch_get(CHUNK_TABLE.to_string(), sample_keys)?
+            .into_iter()
+            .flatten()
+        {
+            samples.push(StoredChunk::read(self, &compressed)?.data);
+        }
+        if samples.len() < 8 {
+            return Err(WorldError::CompressionError(format!(
+                "Not enough chunks to train a dictionary on, found {}",
+                samples.len()
+            )));
+        }
+
+        let id = self.compression.next_dictionary_id();
+        info!(
+            "Training zstd dictionary {} from {} chunks...",
+            id,
+            samples.len()
+        );
+        let dictionary = ZstdDictionary::train(id, &samples, max_size)?;
+        info!(
+            "Trained zstd dictionary {} ({} bytes)",
+            id,
+            dictionary.data().len()
+        );
+        self.compression
+            .add_dictionary(self.storage_backend.as_ref(), dictionary)?;
+        self.storage_backend.flush()?;
+        Ok(id)
+    }
+
+    /// Rewrites every stored chunk with the compression settings from the config.
+    ///
+    /// Returns the number of chunks that were rewritten.
+    pub fn recompress(&self) -> Result<u64, WorldError> {
+        rewrite_stored_chunks(self, "Recompressing chunks...", |stored| {
+            Ok(Some(stored.into_payload()))
+        })
+    }
+}
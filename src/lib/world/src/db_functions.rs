@@ -1,40 +1,63 @@
 use crate::chunk_format::Chunk;
+use crate::compression::ChunkCompression;
 use crate::errors::WorldError;
-use crate::errors::WorldError::CorruptedChunkData;
 // db_functions.rs
-use crate::warn;
-use crate::World;
-use ferrumc_config::server_config::get_global_config;
-use std::hash::Hasher;
+use crate::migrations::{encode_chunk, split_version, CHUNK_FORMAT_VERSION};
+use crate::spatial::{chunk_key, DimensionRegistry, CHUNK_TABLE};
+use crate::{migrations, ChunkKey, World};
+use dashmap::mapref::entry::Entry;
+use dashmap::DashMap;
+use ferrumc_storage::backend::StorageBackend;
+use ferrumc_storage::errors::StorageError;
+use indicatif::{ProgressBar, ProgressStyle};
 use std::sync::Arc;
 use tracing::trace;
-use yazi::CompressionLevel;
+
+/// How many chunks are read and rewritten at once when working through the whole world.
+pub(crate) const REWRITE_BATCH_SIZE: usize = 256;
 
 impl World {
-    /// Save a chunk to the storage backend
+    /// Save a chunk to the world
+    ///
+    /// This function will update the cache with the new chunk data and mark the chunk as dirty.
+    /// Dirty chunks are written to the storage backend on the next [`World::sync`], or when they
+    /// are evicted from the cache, so repeated edits to the same chunk only get written once.
     ///
-    /// This function will save a chunk to the storage backend and update the cache with the new
-    /// chunk data. If the chunk already exists in the cache, it will be updated with the new data.
+    /// This replaces whatever was saved before, so use [`World::edit`] to change a chunk that
+    /// something else might be changing at the same time.
     pub fn save_chunk(&self, chunk: Arc<Chunk>) -> Result<(), WorldError> {
-        let ret = save_chunk_internal(self, &chunk);
-        self.cache
-            .insert((chunk.x, chunk.z, chunk.dimension.clone()), chunk);
-        ret
+        let key = (chunk.x, chunk.z, chunk.dimension.clone());
+        self.bump_revision(key.clone());
+        self.invalidate_chunk_packets(chunk.x, chunk.z, &chunk.dimension);
+        self.dirty_chunks.insert(key.clone(), chunk.clone());
+        self.tickets.keep_if_loaded(key.clone(), chunk.clone());
+        self.cache.insert(key, chunk);
+        Ok(())
     }
 
     /// Load a chunk from the storage backend. If the chunk is in the cache, it will be returned
     /// from the cache instead of the storage backend. If the chunk is not in the cache, it will be
     /// loaded from the storage backend and inserted into the cache.
     pub fn load_chunk(&self, x: i32, z: i32, dimension: &str) -> Result<Arc<Chunk>, WorldError> {
-        if let Some(chunk) = self.cache.get(&(x, z, dimension.to_string())) {
+        let key = (x, z, dimension.to_string());
+        if let Some(chunk) = self.cache.get(&key) {
             return Ok(chunk);
         }
-        let chunk = load_chunk_internal(self, x, z, dimension);
-        if let Ok(ref chunk) = chunk {
-            self.cache
-                .insert((x, z, dimension.to_string()), Arc::from(chunk.clone()));
+        // Loaded chunks stay in memory after the cache has let go of them
+        if let Some(chunk) = self.tickets.resident(&key) {
+            self.cache.insert(key, chunk.clone());
+            return Ok(chunk);
+        }
+        // An evicted chunk that failed to write is still only in the dirty set. The entry has to
+        // be let go of before the cache is touched, since evicting writes dirty chunks
+        if let Some(chunk) = self.dirty_chunks.get(&key).map(|chunk| chunk.clone()) {
+            self.cache.insert(key, chunk.clone());
+            return Ok(chunk);
         }
-        chunk.map(Arc::new)
+        let chunk = Arc::new(load_chunk_internal(self, x, z, dimension)?);
+        self.tickets.keep_if_loaded(key.clone(), chunk.clone());
+        self.cache.insert(key, chunk.clone());
+        Ok(chunk)
     }
 
     pub fn load_chunk_owned(&self, x: i32, z: i32, dimension: &str) -> Result<Chunk, WorldError> {
@@ -47,7 +70,8 @@ impl World {
     /// chunk is not in the cache, it will check the storage backend for the chunk, returning true
     /// if it exists and false if it does not.
     pub fn chunk_exists(&self, x: i32, z: i32, dimension: &str) -> Result<bool, WorldError> {
-        if self.cache.contains_key(&(x, z, dimension.to_string())) {
+        let key = (x, z, dimension.to_string());
+        if self.cache.contains_key(&key) || self.dirty_chunks.contains_key(&key) {
             return Ok(true);
         }
         chunk_exists_internal(self, x, z, dimension)
@@ -56,22 +80,63 @@ impl World {
     /// Delete a chunk from the storage backend.
     ///
     /// This function will remove the chunk from the cache and delete it from the storage backend.
+    /// Chunks that were never written to the storage backend are simply discarded.
     pub fn delete_chunk(&self, x: i32, z: i32, dimension: &str) -> Result<(), WorldError> {
-        self.cache.remove(&(x, z, dimension.to_string()));
-        delete_chunk_internal(self, x, z, dimension)
+        let key = (x, z, dimension.to_string());
+        // Pruned on the next sync, once nothing can be in the middle of using it
+        self.bump_revision(key.clone());
+        self.invalidate_chunk_packets(x, z, dimension);
+        self.tickets.forget(&key);
+        self.cache.remove(&key);
+        let was_dirty = self.dirty_chunks.remove(&key).is_some();
+        match delete_chunk_internal(self, x, z, dimension) {
+            Err(WorldError::DatabaseError(StorageError::KeyNotFound(_)))
+            | Err(WorldError::ChunkNotFound)
+                if was_dirty =>
+            {
+                Ok(())
+            }
+            res => res,
+        }
     }
 
     /// Sync the storage backend.
     ///
-    /// This function will save all chunks in the cache to the storage backend and then sync the
+    /// This function will write every dirty chunk to the storage backend and then sync the
     /// storage backend. This should be run after inserting or updating a large number of chunks
     /// to ensure that the data is properly saved to disk.
-    pub fn sync(&self) -> Result<(), WorldError> {
-        for (k, v) in self.cache.iter() {
-            trace!("Syncing chunk: {:?}", (k.0, k.1));
-            save_chunk_internal(self, &v)?;
+    ///
+    /// Returns the number of chunks that were written.
+    pub fn sync(&self) -> Result<usize, WorldError> {
+        let keys: Vec<_> = self
+            .dirty_chunks
+            .iter()
+            .map(|entry| entry.key().clone())
+            .collect();
+        let mut written = 0;
+        for key in keys {
+            trace!("Syncing chunk: {:?}", (key.0, key.1));
+            // Chunks saved again while syncing are picked up by the next sync
+            if write_dirty_chunk(
+                self.storage_backend.as_ref(),
+                &self.compression,
+                &self.dimensions,
+                &self.dirty_chunks,
+                key,
+            )? {
+                written += 1;
+            }
         }
-        sync_internal(self)
+        self.sync_activity()?;
+        self.flush_block_audit()?;
+        sync_internal(self)?;
+        self.prune_revisions();
+        Ok(written)
+    }
+
+    /// The number of chunks that have been saved but not yet written to the storage backend.
+    pub fn dirty_chunk_count(&self) -> usize {
+        self.dirty_chunks.len()
     }
 
     /// Load a batch of chunks from the storage backend.
@@ -86,8 +151,11 @@ impl World {
         let mut found_chunks = Vec::new();
         let mut missing_chunks = Vec::new();
         for coord in coords {
-            if let Some(chunk) = self.cache.get(&(coord.0, coord.1, coord.2.to_string())) {
+            let key = (coord.0, coord.1, coord.2.to_string());
+            if let Some(chunk) = self.cache.get(&key) {
                 found_chunks.push(chunk);
+            } else if let Some(chunk) = self.dirty_chunks.get(&key) {
+                found_chunks.push(chunk.clone());
             } else {
                 missing_chunks.push(*coord);
             }
@@ -109,54 +177,59 @@ impl World {
     /// they are needed.
     pub fn pre_cache(&self, x: i32, z: i32, dimension: &str) -> Result<(), WorldError> {
         if self.cache.get(&(x, z, dimension.to_string())).is_none() {
-            let chunk = load_chunk_internal(self, x, z, dimension)?;
-            self.cache
-                .insert((x, z, dimension.to_string()), Arc::new(chunk));
+            self.load_chunk(x, z, dimension)?;
         }
         Ok(())
     }
 }
 
-pub(crate) fn save_chunk_internal(world: &World, chunk: &Chunk) -> Result<(), WorldError> {
-    if !world.storage_backend.table_exists("chunks".to_string())? {
-        world.storage_backend.create_table("chunks".to_string())?;
+pub(crate) fn save_chunk_internal(
+    storage_backend: &dyn StorageBackend,
+    compression: &ChunkCompression,
+    dimensions: &DimensionRegistry,
+    chunk: &Chunk,
+) -> Result<(), WorldError> {
+    if !storage_backend.table_exists(CHUNK_TABLE.to_string())? {
+        storage_backend.create_table(CHUNK_TABLE.to_string())?;
     }
-    let as_bytes = yazi::compress(
-        &bitcode::encode(chunk),
-        yazi::Format::Zlib,
-        CompressionLevel::BestSpeed,
-    )?;
-    let digest = create_key(chunk.dimension.as_str(), chunk.x, chunk.z);
-    world
-        .storage_backend
-        .upsert("chunks".to_string(), digest, as_bytes)?;
+    let as_bytes = compression.compress(&encode_chunk(chunk))?;
+    let dimension_id = dimensions.get_or_register(storage_backend, &chunk.dimension)?;
+    let digest = chunk_key(dimension_id, chunk.x, chunk.z);
+    storage_backend.upsert(CHUNK_TABLE.to_string(), digest, as_bytes)?;
     Ok(())
 }
 
+/// Writes a dirty chunk to the storage backend and marks it as clean, returning whether it was
+/// still dirty. The entry stays locked while the chunk is written, so a newer save of the same
+/// chunk waits for the write to finish instead of being overwritten by it. If the write fails the
+/// chunk is left dirty, so the next sync can try again.
+pub(crate) fn write_dirty_chunk(
+    storage_backend: &dyn StorageBackend,
+    compression: &ChunkCompression,
+    dimensions: &DimensionRegistry,
+    dirty_chunks: &DashMap<ChunkKey, Arc<Chunk>>,
+    key: ChunkKey,
+) -> Result<bool, WorldError> {
+    let Entry::Occupied(entry) = dirty_chunks.entry(key) else {
+        return Ok(false);
+    };
+    save_chunk_internal(storage_backend, compression, dimensions, entry.get())?;
+    entry.remove();
+    Ok(true)
+}
+
 pub(crate) fn load_chunk_internal(
     world: &World,
     x: i32,
     z: i32,
     dimension: &str,
 ) -> Result<Chunk, WorldError> {
-    let digest = create_key(dimension, x, z);
-    match world.storage_backend.get("chunks".to_string(), digest)? {
-        Some(compressed) => {
-            let (data, checksum) = yazi::decompress(compressed.as_slice(), yazi::Format::Zlib)?;
-            if get_global_config().database.verify_chunk_data {
-                if let Some(expected_checksum) = checksum {
-                    let real_checksum = yazi::Adler32::from_buf(data.as_slice()).finish();
-                    if real_checksum != expected_checksum {
-                        return Err(CorruptedChunkData(real_checksum, expected_checksum));
-                    }
-                } else {
-                    warn!("Chunk data does not have a checksum, skipping verification.");
-                }
-            }
-            let chunk: Chunk = bitcode::decode(&data)
-                .map_err(|e| WorldError::BitcodeDecodeError(e.to_string()))?;
-            Ok(chunk)
-        }
+    let Some(dimension_id) = world.dimensions.id(dimension) else {
+        return Err(WorldError::ChunkNotFound);
+    };
+    let digest = chunk_key(dimension_id, x, z);
+    match world.storage_backend.get(CHUNK_TABLE.to_string(), digest)? {
+        Some(compressed) => decode_chunk(world, &compressed),
         None => Err(WorldError::ChunkNotFound),
     }
 }
@@ -167,45 +240,134 @@ pub(crate) fn load_chunk_batch_internal(
 ) -> Result<Vec<Chunk>, WorldError> {
     let digests = coords
         .iter()
-        .map(|&(x, z, dim)| create_key(dim, x, z))
-        .collect();
+        .map(|&(x, z, dim)| {
+            let dimension_id = world.dimensions.id(dim).ok_or(WorldError::ChunkNotFound)?;
+            Ok(chunk_key(dimension_id, x, z))
+        })
+        .collect::<Result<_, WorldError>>()?;
     world
         .storage_backend
-        .batch_get("chunks".to_string(), digests)?
+        .batch_get(CHUNK_TABLE.to_string(), digests)?
         .iter()
         .map(|chunk| match chunk {
-            Some(compressed) => {
-                let (data, checksum) = yazi::decompress(compressed, yazi::Format::Zlib)?;
-                if get_global_config().database.verify_chunk_data {
-                    if let Some(expected_checksum) = checksum {
-                        let real_checksum = yazi::Adler32::from_buf(data.as_slice()).finish();
-                        if real_checksum != expected_checksum {
-                            return Err(CorruptedChunkData(real_checksum, expected_checksum));
-                        }
-                    } else {
-                        warn!("Chunk data does not have a checksum, skipping verification.");
-                    }
-                }
-                let chunk: Chunk = bitcode::decode(&data)
-                    .map_err(|e| WorldError::BitcodeDecodeError(e.to_string()))?;
-                Ok(chunk)
-            }
+            Some(compressed) => decode_chunk(world, compressed),
             None => Err(WorldError::ChunkNotFound),
         })
         .collect()
 }
 
+/// A chunk as it's stored in the storage backend, decompressed but not decoded yet.
+pub(crate) struct StoredChunk {
+    /// The chunk format version the chunk was encoded with.
+    pub version: u32,
+    pub data: Vec<u8>,
+}
+
+impl StoredChunk {
+    pub(crate) fn read(world: &World, compressed: &[u8]) -> Result<Self, WorldError> {
+        let mut data = world.compression.decompress(compressed)?;
+        if ChunkCompression::is_legacy(compressed) {
+            return Ok(StoredChunk { version: 0, data });
+        }
+        let (version, _) = split_version(&data)?;
+        data.drain(..4);
+        Ok(StoredChunk { version, data })
+    }
+
+    /// The decompressed data as it's stored, with the version header.
+    pub(crate) fn into_payload(self) -> Vec<u8> {
+        let mut payload = self.version.to_le_bytes().to_vec();
+        payload.extend_from_slice(&self.data);
+        payload
+    }
+}
+
+/// Decodes a stored chunk. Chunks in an old format are migrated and marked dirty, so they get
+/// written back in the current format on the next sync.
+fn decode_chunk(world: &World, compressed: &[u8]) -> Result<Chunk, WorldError> {
+    let stored = StoredChunk::read(world, compressed)?;
+    let chunk = migrations::decode_chunk(stored.version, &stored.data)?;
+    if stored.version != CHUNK_FORMAT_VERSION {
+        world.dirty_chunks.insert(
+            (chunk.x, chunk.z, chunk.dimension.clone()),
+            Arc::new(chunk.clone()),
+        );
+    }
+    Ok(chunk)
+}
+
+/// Rewrites every stored chunk, for offline maintenance like migrations and recompression.
+///
+/// `rewrite` gets each stored chunk and returns the new payload, or `None` to leave the chunk
+/// alone. Returns the number of chunks that were rewritten.
+pub(crate) fn rewrite_stored_chunks(
+    world: &World,
+    message: &'static str,
+    mut rewrite: impl FnMut(StoredChunk) -> Result<Option<Vec<u8>>, WorldError>,
+) -> Result<u64, WorldError> {
+    world.sync()?;
+    if !world
+        .storage_backend
+        .table_exists(CHUNK_TABLE.to_string())?
+    {
+        return Ok(0);
+    }
+    let keys = world.storage_backend.keys(CHUNK_TABLE.to_string())?;
+
+    let progress = ProgressBar::new(keys.len() as u64);
+    progress.set_style(
+        ProgressStyle::default_bar()
+            .template("[{elapsed_precise}/{eta_precise} eta] {bar:40.cyan/blue} {percent}%, {pos:>7}/{len:7}, {msg}")
+            .unwrap(),
+    );
+    progress.set_message(message);
+
+    let mut rewritten = 0;
+    for batch in keys.chunks(REWRITE_BATCH_SIZE) {
+        let values = world
+            .storage_backend
+            .batch_get(CHUNK_TABLE.to_string(), batch.to_vec())?;
+        let mut updated = Vec::with_capacity(batch.len());
+        for (key, value) in batch.iter().zip(values) {
+            let Some(compressed) = value else {
+                continue;
+            };
+            if let Some(payload) = rewrite(StoredChunk::read(world, &compressed)?)? {
+                updated.push((*key, world.compression.compress(&payload)?));
+            }
+        }
+        rewritten += updated.len() as u64;
+        if !updated.is_empty() {
+            world
+                .storage_backend
+                .batch_upsert(CHUNK_TABLE.to_string(), updated)?;
+        }
+        progress.inc(batch.len() as u64);
+    }
+    world.storage_backend.flush()?;
+    progress.finish_with_message("Done");
+    Ok(rewritten)
+}
+
 pub(crate) fn chunk_exists_internal(
     world: &World,
     x: i32,
     z: i32,
     dimension: &str,
 ) -> Result<bool, WorldError> {
-    if !world.storage_backend.table_exists("chunks".to_string())? {
+    if !world
+        .storage_backend
+        .table_exists(CHUNK_TABLE.to_string())?
+    {
         return Ok(false);
     }
-    let digest = create_key(dimension, x, z);
-    Ok(world.storage_backend.exists("chunks".to_string(), digest)?)
+    let Some(dimension_id) = world.dimensions.id(dimension) else {
+        return Ok(false);
+    };
+    let digest = chunk_key(dimension_id, x, z);
+    Ok(world
+        .storage_backend
+        .exists(CHUNK_TABLE.to_string(), digest)?)
 }
 
 pub(crate) fn delete_chunk_internal(
@@ -214,8 +376,13 @@ pub(crate) fn delete_chunk_internal(
     z: i32,
     dimension: &str,
 ) -> Result<(), WorldError> {
-    let digest = create_key(dimension, x, z);
-    world.storage_backend.delete("chunks".to_string(), digest)?;
+    let Some(dimension_id) = world.dimensions.id(dimension) else {
+        return Err(WorldError::ChunkNotFound);
+    };
+    let digest = chunk_key(dimension_id, x, z);
+    world
+        .storage_backend
+        .delete(CHUNK_TABLE.to_string(), digest)?;
     Ok(())
 }
 
@@ -223,19 +390,3 @@ pub(crate) fn sync_internal(world: &World) -> Result<(), WorldError> {
     world.storage_backend.flush()?;
     Ok(())
 }
-
-fn create_key(dimension: &str, x: i32, z: i32) -> u128 {
-    let mut key = 0u128;
-    let mut hasher = wyhash::WyHash::with_seed(0);
-    hasher.write(dimension.as_bytes());
-    hasher.write_u8(0xFF);
-    let dim_hash = hasher.finish();
-    // Insert the dimension hash into the key as the first 32 bits
-    key |= (dim_hash as u128) << 96;
-    // Convert the x coordinate to a 48 bit integer and insert it into the key
-    key |= ((x as u128) & 0x0000_0000_FFFF_FFFF) << 48;
-    // Convert the z coordinate to a 48 bit integer and insert it into the key
-    key |= (z as u128) & 0x0000_0000_FFFF_FFFF;
-
-    key
-}
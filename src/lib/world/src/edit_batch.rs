@@ -1,9 +1,11 @@
+use crate::biomes::BiomeId;
+use crate::block_changes::BlockChange;
 use crate::block_id::BlockId;
 use crate::chunk_format::{BiomeStates, BlockStates, Chunk, PaletteType};
+use crate::edits::{bits_for_palette, MIN_INDIRECT_BITS};
 use crate::WorldError;
 use ahash::{AHashMap, AHashSet, AHasher};
-use ferrumc_general_purpose::data_packing::i32::read_nbit_i32;
-use ferrumc_general_purpose::data_packing::u32::write_nbit_u32;
+use ferrumc_general_purpose::data_packing::u32::{read_nbit_u32, write_nbit_u32};
 use ferrumc_net_codec::net_types::var_int::VarInt;
 use std::collections::HashMap;
 use std::hash::{Hash, Hasher};
@@ -36,6 +38,7 @@ pub struct EditBatch<'a> {
     chunk: &'a mut Chunk,
     tmp_palette_map: AHashMap<BlockId, usize>,
     used: bool,
+    changes: Vec<BlockChange>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -69,6 +72,7 @@ impl<'a> EditBatch<'a> {
             chunk,
             tmp_palette_map: AHashMap::with_capacity(map_capacity),
             used: false,
+            changes: Vec::new(),
         }
     }
 
@@ -88,6 +92,11 @@ impl<'a> EditBatch<'a> {
     ///
     /// This will modify the chunk in place and clear the batch.
     /// Will return an error if the batch has already been used or if there are no edits.
+    ///
+    /// Light isn't updated, since it can spread into the chunks around this one. Use
+    /// [`World::edit_batch`](crate::World::edit_batch) to edit a chunk in the world, which relights
+    /// it, or call [`World::relight`](crate::World::relight) with the [`changes`](Self::changes)
+    /// afterwards.
     pub fn apply(&mut self) -> Result<(), WorldError> {
         if self.used {
             return Err(WorldError::InvalidBatchingOperation(
@@ -100,6 +109,7 @@ impl<'a> EditBatch<'a> {
             ));
         }
 
+        let (chunk_x, chunk_z) = (self.chunk.x, self.chunk.z);
         let mut section_edits: AHashMap<i8, Vec<Option<&Edit>>> = AHashMap::new();
         let mut all_blocks = AHashSet::new();
 
@@ -144,13 +154,8 @@ impl<'a> EditBatch<'a> {
                             block_data: PaletteType::Single(VarInt::default()),
                             block_counts: HashMap::from([(BlockId::default(), 4096)]),
                         },
-                        // Biomes don't really matter for this, so we can just use empty data
-                        biome_states: BiomeStates {
-                            bits_per_biome: 0,
-                            data: vec![],
-                            palette: vec![],
-                        },
-                        block_light: vec![255; 2048],
+                        biome_states: BiomeStates::single(BiomeId::default()),
+                        block_light: vec![0; 2048],
                         sky_light: vec![255; 2048],
                     };
                     self.chunk.sections.push(new_section);
@@ -184,53 +189,84 @@ impl<'a> EditBatch<'a> {
             // }
 
             // Convert from Single to Indirect palette if needed to support multiple block types
-            if let PaletteType::Single(val) = &section.block_states.block_data {
-                section.block_states.block_data = PaletteType::Indirect {
-                    bits_per_block: 4,
-                    data: vec![0; 256],
-                    palette: vec![*val],
-                };
+            if let PaletteType::Single(_) = &section.block_states.block_data {
+                section.block_states.resize(MIN_INDIRECT_BITS as usize)?;
             }
 
-            let PaletteType::Indirect {
+            // Grow the palette up front so every new block fits, switching to the global palette
+            // if there are too many
+            if let PaletteType::Indirect {
                 bits_per_block,
-                data,
                 palette,
-            } = &mut section.block_states.block_data
-            else {
-                return Err(WorldError::InvalidBlockStateData(
-                    "Unsupported palette type".to_string(),
-                ));
+                ..
+            } = &section.block_states.block_data
+            {
+                let existing: AHashSet<VarInt> = palette.iter().copied().collect();
+                let new_blocks: AHashSet<BlockId> = edits_vec
+                    .iter()
+                    .flatten()
+                    .map(|edit| edit.block)
+                    .filter(|block| !existing.contains(&block.to_varint()))
+                    .collect();
+                let required_bits = bits_for_palette(palette.len() + new_blocks.len());
+                if required_bits > *bits_per_block {
+                    section.block_states.resize(required_bits as usize)?;
+                }
+            }
+
+            let (bits_per_block, data, mut palette) = match &mut section.block_states.block_data {
+                PaletteType::Indirect {
+                    bits_per_block,
+                    data,
+                    palette,
+                } => (*bits_per_block, data, Some(palette)),
+                PaletteType::Direct {
+                    bits_per_block,
+                    data,
+                } => (*bits_per_block, data, None),
+                PaletteType::Single(_) => {
+                    return Err(WorldError::InvalidBatchingOperation(
+                        "Unsupported palette type".to_string(),
+                    ));
+                }
             };
 
             // Hash current palette so we can detect changes after edits
-            let palette_hash = get_palette_hash(palette);
+            let palette_hash = palette.as_deref().map(|palette| get_palette_hash(palette));
 
             // Rebuild temporary palette index lookup (block ID -> palette index)
             self.tmp_palette_map.clear();
-            for (i, p) in palette.iter().enumerate() {
-                self.tmp_palette_map.insert(BlockId::from_varint(*p), i);
+            if let Some(palette) = palette.as_deref() {
+                for (i, p) in palette.iter().enumerate() {
+                    self.tmp_palette_map.insert(BlockId::from_varint(*p), i);
+                }
             }
 
             // Determine how many blocks fit into each i64 (based on bits per block)
-            let blocks_per_i64 = (64f64 / *bits_per_block as f64).floor() as usize;
+            let blocks_per_i64 = 64 / bits_per_block as usize;
 
             for maybe_edit in edits_vec.iter() {
                 let Some(edit) = maybe_edit else { continue };
                 let index = ((edit.y & 0xf) * 256 + (edit.z & 0xf) * 16 + (edit.x & 0xf)) as usize;
 
-                let palette_index = if let Some(&idx) = self.tmp_palette_map.get(&edit.block) {
-                    idx
-                } else {
-                    let idx = palette.len();
-                    palette.push(edit.block.to_varint());
-                    self.tmp_palette_map.insert(edit.block, idx);
-                    idx
+                // Sections using the global palette store block IDs directly
+                let new_value = match palette.as_mut() {
+                    Some(palette) => {
+                        if let Some(&idx) = self.tmp_palette_map.get(&edit.block) {
+                            idx as u32
+                        } else {
+                            let idx = palette.len();
+                            palette.push(edit.block.to_varint());
+                            self.tmp_palette_map.insert(edit.block, idx);
+                            idx as u32
+                        }
+                    }
+                    None => edit.block.0,
                 };
 
                 // Calculate i64 slot and bit offset for packed storage
                 let i64_index = index / blocks_per_i64;
-                let offset = (index % blocks_per_i64) * (*bits_per_block as usize);
+                let offset = (index % blocks_per_i64) * (bits_per_block as usize);
 
                 debug_assert!(
                     i64_index < data.len(),
@@ -243,37 +279,43 @@ impl<'a> EditBatch<'a> {
                 let packed = unsafe { data.get_unchecked_mut(i64_index) };
 
                 // get old block
-                let old_block_index =
-                    read_nbit_i32(packed, *bits_per_block as usize, offset as u32).map_err(
-                        |e| WorldError::InvalidBlockStateData(format!("Unpacking error: {e}")),
-                    )?;
+                let old_value =
+                    read_nbit_u32(packed, bits_per_block, offset as u32).map_err(|e| {
+                        WorldError::InvalidBlockStateData(format!("Unpacking error: {e}"))
+                    })?;
                 // If the block is the same, skip
-                if old_block_index == palette_index as i32 {
+                if old_value == new_value {
                     continue;
                 }
 
-                if let Some(old_block_id) = palette.get(old_block_index as usize) {
-                    if let Some(count) =
-                        block_count_removes.get_mut(&BlockId::from_varint(*old_block_id))
-                    {
-                        *count -= 1;
-                    } else {
-                        block_count_removes.insert(BlockId::from_varint(*old_block_id), 1);
-                    }
-                }
-
-                if let Some(count) = block_count_adds.get_mut(&edit.block) {
-                    *count += 1;
-                } else {
-                    block_count_adds.insert(edit.block, 1);
+                let old_block_id = match palette.as_deref() {
+                    Some(palette) => palette
+                        .get(old_value as usize)
+                        .map(|id| BlockId::from_varint(*id)),
+                    None => Some(BlockId(old_value)),
+                };
+                if let Some(old_block_id) = old_block_id {
+                    *block_count_removes.entry(old_block_id).or_insert(0) += 1;
                 }
 
-                write_nbit_u32(packed, offset as u32, palette_index as u32, *bits_per_block)
-                    .map_err(|e| {
-                        WorldError::InvalidBlockStateData(format!("Packing error: {e}"))
-                    })?;
+                *block_count_adds.entry(edit.block).or_insert(0) += 1;
+
+                write_nbit_u32(packed, offset as u32, new_value, bits_per_block).map_err(|e| {
+                    WorldError::InvalidBlockStateData(format!("Packing error: {e}"))
+                })?;
+                self.changes.push(BlockChange {
+                    x: chunk_x * 16 + (edit.x & 0xf),
+                    y: edit.y,
+                    z: chunk_z * 16 + (edit.z & 0xf),
+                    block: edit.block,
+                });
             }
 
+            let palette_changed = match (palette.as_deref(), palette_hash) {
+                (Some(palette), Some(hash)) => get_palette_hash(palette) != hash,
+                _ => true,
+            };
+
             // Update block counts
             for (block_id, count) in block_count_adds {
                 let current_count = section
@@ -293,24 +335,49 @@ impl<'a> EditBatch<'a> {
                 *current_count -= count;
             }
 
-            section.block_states.non_air_blocks = *section
+            section.block_states.non_air_blocks = section
                 .block_states
                 .block_counts
-                .get(&BlockId::default())
-                .unwrap_or(&4096) as u16;
-
-            // Only optimise if the palette changed after edits
-            if get_palette_hash(palette) != palette_hash {
+                .iter()
+                .filter(|(block, _)| {
+                    // Air, void air and cave air respectively
+                    ![0, 12958, 12959].contains(&block.0)
+                })
+                .map(|(_, count)| *count as u16)
+                .sum();
+
+            // Only optimise if the palette changed after edits, or if the section might fit in an
+            // indirect palette again
+            if palette_changed {
                 section.optimise()?;
             }
         }
 
+        let columns: AHashSet<(i32, i32)> = self
+            .edits
+            .iter()
+            .map(|edit| (edit.x & 0xf, edit.z & 0xf))
+            .collect();
+        if self.chunk.heightmaps.is_incomplete() {
+            self.chunk.compute_heightmaps();
+        } else {
+            for (x, z) in columns {
+                self.chunk.recalculate_heightmap_column(x, z);
+            }
+        }
+
         // Clear edits after applying
         self.edits.clear();
         self.used = true;
 
         Ok(())
     }
+
+    /// The blocks that were actually changed by `apply()`, in world coordinates. Edits that set a
+    /// block to what it already was aren't included.
+    pub fn changes(&self) -> &[BlockChange] {
+        &self.changes
+    }
 }
 
 #[cfg(test)]
@@ -367,4 +434,26 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_changes() {
+        let mut chunk = Chunk::new(-1, 2, "overworld".to_string());
+        let stone = make_test_block("minecraft:stone");
+
+        let mut batch = EditBatch::new(&mut chunk);
+        batch.set_block(1, 64, 1, stone);
+        // Already air, so nothing changes
+        batch.set_block(2, 64, 1, BlockId::default());
+        batch.apply().unwrap();
+
+        assert_eq!(
+            batch.changes(),
+            &[BlockChange {
+                x: -15,
+                y: 64,
+                z: 33,
+                block: stone,
+            }]
+        );
+    }
 }
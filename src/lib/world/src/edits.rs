@@ -1,13 +1,11 @@
-use crate::block_id::{BlockId, BLOCK2ID, ID2BLOCK};
+use crate::block_id::{BlockId, GLOBAL_BITS_PER_BLOCK, ID2BLOCK};
 use crate::chunk_format::{BlockStates, Chunk, PaletteType, Section};
 use crate::errors::WorldError;
 use crate::vanilla_chunk_format::BlockData;
 use crate::World;
-use ferrumc_general_purpose::data_packing::i32::read_nbit_i32;
-use ferrumc_net_codec::net_types::var_int::VarInt;
+use ferrumc_general_purpose::data_packing::u32::{read_nbit_u32, write_nbit_u32};
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
-use std::sync::Arc;
 use tracing::{debug, error, warn};
 
 impl World {
@@ -47,7 +45,8 @@ impl World {
 
     /// Sets the block data at the specified coordinates in the given dimension.
     /// Under the hood, this function just fetches the chunk containing the block and then calls
-    /// [`Chunk::set_block`] on it.
+    /// [`Chunk::set_block`] on it, then updates the light around the block with
+    /// [`World::relight`].
     ///
     /// # Arguments
     ///
@@ -76,39 +75,143 @@ impl World {
         // Get chunk
         let chunk_x = x >> 4;
         let chunk_z = z >> 4;
-        let mut chunk = self.load_chunk_owned(chunk_x, chunk_z, dimension)?;
-
         debug!("Chunk: {}, {}", chunk_x, chunk_z);
+        self.edit(chunk_x, chunk_z, dimension, |chunk| {
+            chunk.set_block(x, y, z, block)?;
+            for section in &mut chunk.sections {
+                section.optimise()?;
+            }
+            Ok(())
+        })?;
+        self.relight(dimension, &[(x, y, z)])?;
+        Ok(())
+    }
+}
+
+/// The most bits per block an indirect palette can use. Sections that need more than this use the
+/// global palette instead.
+pub const MAX_INDIRECT_BITS: u8 = 8;
+
+/// The fewest bits per block an indirect palette can use.
+pub const MIN_INDIRECT_BITS: u8 = 4;
+
+/// Returns the bits per block needed for an indirect palette with `len` entries.
+pub fn bits_for_palette(len: usize) -> u8 {
+    let bits = (usize::BITS - len.saturating_sub(1).leading_zeros()) as u8;
+    bits.max(MIN_INDIRECT_BITS)
+}
+
+/// Unpacks the 4096 entries of a section's block data.
+///
+/// Entries never span across two longs, any leftover bits at the end of a long are padding.
+pub fn unpack_entries(bits_per_entry: u8, data: &[i64]) -> Result<Vec<u32>, WorldError> {
+    unpack_n_entries(bits_per_entry, data, 4096)
+}
 
-        chunk.set_block(x, y, z, block)?;
-        for section in &mut chunk.sections {
-            section.optimise()?;
+/// Unpacks `count` entries packed the same way as block data, e.g. the 64 biomes of a section.
+pub fn unpack_n_entries(
+    bits_per_entry: u8,
+    data: &[i64],
+    count: usize,
+) -> Result<Vec<u32>, WorldError> {
+    let per_long = 64 / bits_per_entry as usize;
+    let expected_len = count.div_ceil(per_long);
+    if data.len() != expected_len {
+        return Err(WorldError::InvalidBlockStateData(format!(
+            "Expected {expected_len} longs of {bits_per_entry}-bit data, but got {}",
+            data.len()
+        )));
+    }
+    let mut entries = Vec::with_capacity(count);
+    for long in data {
+        for i in 0..per_long {
+            if entries.len() == count {
+                break;
+            }
+            entries.push(read_nbit_u32(
+                long,
+                bits_per_entry,
+                (i * bits_per_entry as usize) as u32,
+            )?);
         }
+    }
+    Ok(entries)
+}
 
-        // Save chunk
-        self.save_chunk(Arc::new(chunk))?;
-        Ok(())
+/// Packs section entries into longs, the inverse of [`unpack_entries`].
+pub fn pack_entries(bits_per_entry: u8, entries: &[u32]) -> Result<Vec<i64>, WorldError> {
+    let per_long = 64 / bits_per_entry as usize;
+    let max_value = (1u64 << bits_per_entry) - 1;
+    let mut data = vec![0i64; entries.len().div_ceil(per_long)];
+    for (index, &entry) in entries.iter().enumerate() {
+        if entry as u64 > max_value {
+            return Err(WorldError::InvalidBlockStateData(format!(
+                "Value {entry} exceeds maximum value for {bits_per_entry}-bit block state"
+            )));
+        }
+        write_nbit_u32(
+            &mut data[index / per_long],
+            ((index % per_long) * bits_per_entry as usize) as u32,
+            entry,
+            bits_per_entry,
+        )?;
     }
+    Ok(data)
 }
 
 impl BlockStates {
+    /// Returns every block in the section, in the same order as the packed block data.
+    pub fn blocks(&self) -> Result<Vec<BlockId>, WorldError> {
+        match &self.block_data {
+            PaletteType::Single(val) => Ok(vec![BlockId::from_varint(*val); 4096]),
+            PaletteType::Indirect {
+                bits_per_block,
+                data,
+                palette,
+            } => unpack_entries(*bits_per_block, data)?
+                .into_iter()
+                .map(|index| {
+                    palette
+                        .get(index as usize)
+                        .map(|id| BlockId::from_varint(*id))
+                        .ok_or_else(|| {
+                            WorldError::InvalidBlockStateData(format!(
+                                "Palette index {index} is out of bounds"
+                            ))
+                        })
+                })
+                .collect(),
+            PaletteType::Direct {
+                bits_per_block,
+                data,
+            } => Ok(unpack_entries(*bits_per_block, data)?
+                .into_iter()
+                .map(BlockId)
+                .collect()),
+        }
+    }
+
+    /// Changes the number of bits used for each block.
+    ///
+    /// Sizes above [`MAX_INDIRECT_BITS`] switch the section to the global palette, and smaller
+    /// sizes switch it back to an indirect palette. Shrinking fails if the palette wouldn't fit.
     pub fn resize(&mut self, new_bit_size: usize) -> Result<(), WorldError> {
-        match &mut self.block_data {
+        let new_bit_size = new_bit_size as u8;
+        if new_bit_size > MAX_INDIRECT_BITS {
+            let ids: Vec<u32> = self.blocks()?.into_iter().map(|block| block.0).collect();
+            self.block_data = PaletteType::Direct {
+                bits_per_block: GLOBAL_BITS_PER_BLOCK,
+                data: pack_entries(GLOBAL_BITS_PER_BLOCK, &ids)?,
+            };
+            return Ok(());
+        }
+        match &self.block_data {
             PaletteType::Single(val) => {
-                let block = ID2BLOCK
-                    .get(val.0 as usize)
-                    .cloned()
-                    .unwrap_or(BlockData::default());
-                let mut new_palette = vec![VarInt::from(0); 1];
-                if let Some(id) = BLOCK2ID.get(&block) {
-                    new_palette[0] = VarInt::from(*id);
-                } else {
-                    error!("Could not find block id for palette entry: {:?}", block);
-                }
+                let val = *val;
                 self.block_data = PaletteType::Indirect {
-                    bits_per_block: new_bit_size as u8,
-                    data: vec![],
-                    palette: new_palette,
+                    bits_per_block: new_bit_size,
+                    data: pack_entries(new_bit_size, &[0; 4096])?,
+                    palette: vec![val],
                 }
             }
             PaletteType::Indirect {
@@ -116,87 +219,36 @@ impl BlockStates {
                 data,
                 palette,
             } => {
-                // Step 1: Read existing packed data into a list of normal integers
-                let mut normalised_ints = Vec::with_capacity(4096);
-                let mut values_read = 0;
-
-                for long in data {
-                    let mut bit_offset = 0;
-
-                    while bit_offset + *bits_per_block as usize <= 64 {
-                        if values_read >= 4096 {
-                            break;
-                        }
-
-                        // Extract value at the current bit offset
-                        let value =
-                            read_nbit_i32(long, *bits_per_block as usize, bit_offset as u32)?;
-                        let max_int_value = (1 << new_bit_size) - 1;
-                        if value > max_int_value {
-                            return Err(WorldError::InvalidBlockStateData(format!(
-                                "Value {value} exceeds maximum value for {new_bit_size}-bit block state"
-                            )));
-                        }
-                        normalised_ints.push(value);
-                        values_read += 1;
-
-                        bit_offset += *bits_per_block as usize;
-                    }
-
-                    // Stop reading if we’ve already hit 4096 values
-                    if values_read >= 4096 {
-                        break;
-                    }
-                }
-
-                // Check if we read exactly 4096 block states
-                if normalised_ints.len() != 4096 {
-                    return Err(WorldError::InvalidBlockStateData(format!(
-                        "Expected 4096 block states, but got {}",
-                        normalised_ints.len()
-                    )));
-                }
-
-                // Step 2: Write the normalised integers into the new packed format
-                let mut new_data = Vec::new();
-                let mut current_long: i64 = 0;
-                let mut bit_position = 0;
-
-                for &value in &normalised_ints {
-                    current_long |= (value as i64) << bit_position;
-                    bit_position += new_bit_size;
-
-                    if bit_position >= 64 {
-                        new_data.push(current_long);
-                        current_long = (value as i64) >> (new_bit_size - (bit_position - 64));
-                        bit_position -= 64;
-                    }
+                let entries = unpack_entries(*bits_per_block, data)?;
+                self.block_data = PaletteType::Indirect {
+                    bits_per_block: new_bit_size,
+                    data: pack_entries(new_bit_size, &entries)?,
+                    palette: palette.clone(),
                 }
-
-                // Push any remaining bits in the final long
-                if bit_position > 0 {
-                    new_data.push(current_long);
+            }
+            PaletteType::Direct { .. } => {
+                let mut palette = Vec::new();
+                let mut palette_indexes = HashMap::new();
+                let mut entries = Vec::with_capacity(4096);
+                for block in self.blocks()? {
+                    let index = *palette_indexes.entry(block).or_insert_with(|| {
+                        palette.push(block.to_varint());
+                        palette.len() - 1
+                    });
+                    entries.push(index as u32);
                 }
-
-                // Verify the size of the new data matches expectations
-                let expected_size = (4096 * new_bit_size).div_ceil(64);
-                if new_data.len() != expected_size {
+                if palette.len() > 1 << new_bit_size {
                     return Err(WorldError::InvalidBlockStateData(format!(
-                        "Expected packed data size of {}, but got {}",
-                        expected_size,
-                        new_data.len()
+                        "{} distinct blocks don't fit in a {new_bit_size}-bit palette",
+                        palette.len()
                     )));
                 }
-                // Update the chunk with the new packed data and a bit size
                 self.block_data = PaletteType::Indirect {
-                    bits_per_block: new_bit_size as u8,
-                    data: new_data,
-                    palette: palette.clone(),
+                    bits_per_block: new_bit_size,
+                    data: pack_entries(new_bit_size, &entries)?,
+                    palette,
                 }
             }
-            _ => {
-                todo!("Implement resizing for direct palette")
-            }
         };
         Ok(())
     }
@@ -224,6 +276,9 @@ impl Chunk {
     /// The positions are modulo'd by 16 to get the block index in the section anyway, so converting
     /// the coordinates to section coordinates isn't really necessary, but you should probably do it
     /// anyway for readability's sake.
+    ///
+    /// The chunk's heightmaps are kept up to date, but its light isn't. Call [`World::relight`]
+    /// once the chunk is saved.
     pub fn set_block(
         &mut self,
         x: i32,
@@ -245,28 +300,56 @@ impl Chunk {
             .find(|section| section.y == (y >> 4) as i8)
             .ok_or(WorldError::SectionOutOfBounds(y >> 4))?;
 
-        let mut converted = false;
-        let mut new_contents = PaletteType::Indirect {
-            bits_per_block: 4,
-            data: vec![],
-            palette: vec![],
-        };
-
-        if let PaletteType::Single(val) = &section.block_states.block_data {
-            new_contents = PaletteType::Indirect {
-                bits_per_block: 4,
-                data: vec![0; 256],
-                palette: vec![*val],
-            };
-            converted = true;
+        // Single block sections don't have any block data to write to, so give them a palette
+        if let PaletteType::Single(_) = section.block_states.block_data {
+            section.block_states.resize(MIN_INDIRECT_BITS as usize)?;
+        }
+        // Make room in the palette if the block isn't in it yet, switching to the global palette
+        // if it gets too big
+        if let PaletteType::Indirect {
+            bits_per_block,
+            palette,
+            ..
+        } = &section.block_states.block_data
+        {
+            if !palette.contains(&block.to_varint()) {
+                let required_bits = bits_for_palette(palette.len() + 1);
+                if required_bits > *bits_per_block {
+                    section.block_states.resize(required_bits as usize)?;
+                }
+            }
         }
 
-        if converted {
-            section.block_states.block_data = new_contents;
+        match section.block_states.block_counts.entry(old_block) {
+            Entry::Occupied(mut occ_entry) => {
+                let count = occ_entry.get_mut();
+                if *count <= 0 {
+                    return match old_block.to_block_data() {
+                        Some(block_data) => {
+                            error!("Block count is zero for block: {:?}", block_data);
+                            Err(WorldError::InvalidBlockStateData(format!(
+                                "Block count is zero for block: {block_data:?}"
+                            )))
+                        }
+                        None => {
+                            error!("Block count is zero for unknown block ID: {}", old_block.0);
+                            Err(WorldError::InvalidBlockId(old_block.0))
+                        }
+                    };
+                }
+                *count -= 1;
+            }
+            Entry::Vacant(empty_entry) => {
+                warn!("Block not found in block counts: {:?}", old_block);
+                empty_entry.insert(0);
+            }
         }
+        // Add new block
+        *section.block_states.block_counts.entry(block).or_insert(0) += 1;
 
+        let index = ((y & 0xf) * 256 + (z & 0xf) * 16 + (x & 0xf)) as usize;
         // Do different things based on the palette type
-        match &mut section.block_states.block_data {
+        let (bits_per_block, data, value) = match &mut section.block_states.block_data {
             PaletteType::Single(_val) => {
                 panic!("Single palette type should have been converted to indirect palette type");
             }
@@ -275,80 +358,35 @@ impl Chunk {
                 data,
                 palette,
             } => {
-                // debug!("Indirect mode");
-                match section.block_states.block_counts.entry(old_block) {
-                    Entry::Occupied(mut occ_entry) => {
-                        let count = occ_entry.get_mut();
-                        if *count <= 0 {
-                            return match old_block.to_block_data() {
-                                Some(block_data) => {
-                                    error!("Block count is zero for block: {:?}", block_data);
-                                    Err(WorldError::InvalidBlockStateData(format!(
-                                        "Block count is zero for block: {block_data:?}"
-                                    )))
-                                }
-                                None => {
-                                    error!(
-                                        "Block count is zero for unknown block ID: {}",
-                                        old_block.0
-                                    );
-                                    Err(WorldError::InvalidBlockId(old_block.0))
-                                }
-                            };
-                        }
-                        *count -= 1;
-                    }
-                    Entry::Vacant(empty_entry) => {
-                        warn!("Block not found in block counts: {:?}", old_block);
-                        empty_entry.insert(0);
-                    }
-                }
-                // Add new block
-                if let Some(e) = section.block_states.block_counts.get(&block) {
-                    section.block_states.block_counts.insert(block, e + 1);
-                } else {
-                    // debug!("Adding block to block counts");
-                    section.block_states.block_counts.insert(block, 1);
-                }
-                // let required_bits = max((palette.len() as f32).log2().ceil() as u8, 4);
-                // if *bits_per_block != required_bits {
-                //     section.block_states.resize(required_bits as usize)?;
-                // }
                 // Get block index
                 let block_palette_index = palette
                     .iter()
                     .position(|p| *p == block.to_varint())
                     .unwrap_or_else(|| {
                         // Add block to palette if it doesn't exist
-                        let index = palette.len() as i16;
                         palette.push(block.to_varint());
-                        index as usize
+                        palette.len() - 1
                     });
-                // Set block
-                let blocks_per_i64 = (64f64 / *bits_per_block as f64).floor() as usize;
-                let index =
-                    ((y.abs() & 0xf) * 256 + (z.abs() & 0xf) * 16 + (x.abs() & 0xf)) as usize;
-                let i64_index = index / blocks_per_i64;
-                let packed_u64 =
-                    data.get_mut(i64_index)
-                        .ok_or(WorldError::InvalidBlockStateData(format!(
-                            "Invalid block state data at index {i64_index}"
-                        )))?;
-                let offset = (index % blocks_per_i64) * *bits_per_block as usize;
-                if let Err(e) = ferrumc_general_purpose::data_packing::u32::write_nbit_u32(
-                    packed_u64,
-                    offset as u32,
-                    block_palette_index as u32,
-                    *bits_per_block,
-                ) {
-                    return Err(WorldError::InvalidBlockStateData(format!(
-                        "Failed to write block: {e}"
-                    )));
-                }
-            }
-            PaletteType::Direct { .. } => {
-                todo!("Implement direct palette for set_block");
+                (*bits_per_block, data, block_palette_index as u32)
             }
+            PaletteType::Direct {
+                bits_per_block,
+                data,
+            } => (*bits_per_block, data, block.0),
+        };
+        // Set block
+        let blocks_per_i64 = 64 / bits_per_block as usize;
+        let i64_index = index / blocks_per_i64;
+        let packed_u64 = data
+            .get_mut(i64_index)
+            .ok_or(WorldError::InvalidBlockStateData(format!(
+                "Invalid block state data at index {i64_index}"
+            )))?;
+        let offset = (index % blocks_per_i64) * bits_per_block as usize;
+        if let Err(e) = write_nbit_u32(packed_u64, offset as u32, value, bits_per_block) {
+            return Err(WorldError::InvalidBlockStateData(format!(
+                "Failed to write block: {e}"
+            )));
         }
 
         section.block_states.non_air_blocks = section
@@ -362,6 +400,8 @@ impl Chunk {
             .map(|(_, count)| *count as u16)
             .sum();
 
+        self.update_heightmaps(x, y, z, block);
+
         self.sections
             .iter_mut()
             .for_each(|section| section.optimise().unwrap());
@@ -389,37 +429,9 @@ impl Chunk {
         let section = self
             .sections
             .iter()
-            .find(|section| section.y == (y / 16) as i8)
+            .find(|section| section.y == (y >> 4) as i8)
             .ok_or(WorldError::SectionOutOfBounds(y >> 4))?;
-        match &section.block_states.block_data {
-            PaletteType::Single(val) => Ok(BlockId::from_varint(*val)),
-            PaletteType::Indirect {
-                bits_per_block,
-                data,
-                palette,
-            } => {
-                if palette.len() == 1 || *bits_per_block == 0 {
-                    return Ok(BlockId::from_varint(palette[0]));
-                }
-                let blocks_per_i64 = (64f64 / *bits_per_block as f64).floor() as usize;
-                let index = ((y & 0xf) * 256 + (z & 0xf) * 16 + (x & 0xf)) as usize;
-                let i64_index = index / blocks_per_i64;
-                let packed_u64 = data
-                    .get(i64_index)
-                    .ok_or(WorldError::InvalidBlockStateData(format!(
-                        "Invalid block state data at index {i64_index}"
-                    )))?;
-                let offset = (index % blocks_per_i64) * *bits_per_block as usize;
-                let id = ferrumc_general_purpose::data_packing::u32::read_nbit_u32(
-                    packed_u64,
-                    *bits_per_block,
-                    offset as u32,
-                )?;
-                let palette_id = palette.get(id as usize).ok_or(WorldError::ChunkNotFound)?;
-                Ok(BlockId::from_varint(*palette_id))
-            }
-            &PaletteType::Direct { .. } => todo!("Implement direct palette for get_block"),
-        }
+        section.get_block(x, y, z)
     }
 
     /// Sets the section at the specified index to the specified block data.
@@ -440,7 +452,9 @@ impl Chunk {
             .iter_mut()
             .find(|section| section.y == section_y)
         {
-            section.fill(block.clone())
+            section.fill(block.clone())?;
+            self.compute_heightmaps();
+            Ok(())
         } else {
             Err(WorldError::SectionOutOfBounds(section_y as i32))
         }
@@ -460,11 +474,59 @@ impl Chunk {
         for section in &mut self.sections {
             section.fill(block.clone())?;
         }
+        self.compute_heightmaps();
         Ok(())
     }
 }
 
 impl Section {
+    /// Gets the block at the specified coordinates within this section.
+    ///
+    /// Only the lowest 4 bits of each coordinate are used, so both section-relative and global
+    /// coordinates can be passed in.
+    pub fn get_block(&self, x: i32, y: i32, z: i32) -> Result<BlockId, WorldError> {
+        match &self.block_states.block_data {
+            PaletteType::Single(val) => Ok(BlockId::from_varint(*val)),
+            PaletteType::Indirect {
+                bits_per_block,
+                data,
+                palette,
+            } => {
+                if palette.len() == 1 || *bits_per_block == 0 {
+                    return Ok(BlockId::from_varint(palette[0]));
+                }
+                let blocks_per_i64 = (64f64 / *bits_per_block as f64).floor() as usize;
+                let index = ((y & 0xf) * 256 + (z & 0xf) * 16 + (x & 0xf)) as usize;
+                let i64_index = index / blocks_per_i64;
+                let packed_u64 = data
+                    .get(i64_index)
+                    .ok_or(WorldError::InvalidBlockStateData(format!(
+                        "Invalid block state data at index {i64_index}"
+                    )))?;
+                let offset = (index % blocks_per_i64) * *bits_per_block as usize;
+                let id = read_nbit_u32(packed_u64, *bits_per_block, offset as u32)?;
+                let palette_id = palette.get(id as usize).ok_or(WorldError::ChunkNotFound)?;
+                Ok(BlockId::from_varint(*palette_id))
+            }
+            PaletteType::Direct {
+                bits_per_block,
+                data,
+            } => {
+                let blocks_per_i64 = 64 / *bits_per_block as usize;
+                let index = ((y & 0xf) * 256 + (z & 0xf) * 16 + (x & 0xf)) as usize;
+                let i64_index = index / blocks_per_i64;
+                let packed_u64 = data
+                    .get(i64_index)
+                    .ok_or(WorldError::InvalidBlockStateData(format!(
+                        "Invalid block state data at index {i64_index}"
+                    )))?;
+                let offset = (index % blocks_per_i64) * *bits_per_block as usize;
+                let id = read_nbit_u32(packed_u64, *bits_per_block, offset as u32)?;
+                Ok(BlockId(id))
+            }
+        }
+    }
+
     /// Fills the section with the specified block.
     ///
     /// # Arguments
@@ -488,70 +550,65 @@ impl Section {
         Ok(())
     }
 
-    /// This function trims out unnecessary data from the section. Primarily it does 2 things:
+    /// This function trims out unnecessary data from the section. Primarily it does 3 things:
     ///
     /// 1. Removes any palette entries that are not used in the block states data.
     ///
-    /// 2. If there is only one block in the palette, it converts the palette to single block mode.
+    /// 2. Shrinks the palette to the fewest bits per block that still fit every entry, switching
+    ///    from the global palette back to an indirect one if possible.
+    ///
+    /// 3. If there is only one block in the palette, it converts the palette to single block mode.
+    ///
+    /// The section is only rebuilt when the block counts show that something can be trimmed, so
+    /// this is cheap to call after every edit.
     pub fn optimise(&mut self) -> Result<(), WorldError> {
-        match &mut self.block_states.block_data {
-            PaletteType::Single(_) => {
-                // If the section is already in single block mode, there's nothing to optimise
-                return Ok(());
-            }
+        self.block_states.block_counts.retain(|_, count| *count > 0);
+        let used_blocks = self.block_states.block_counts.len();
+        let can_shrink = match &self.block_states.block_data {
+            // If the section is already in single block mode, there's nothing to optimise
+            PaletteType::Single(_) => return Ok(()),
             PaletteType::Indirect {
                 bits_per_block,
-                data,
                 palette,
+                ..
             } => {
-                // Remove empty blocks from palette
-                let mut remove_indexes = Vec::new();
-                for (block, count) in &self.block_states.block_counts {
-                    if *count <= 0 {
-                        let index = palette.iter().position(|p| *p == block.to_varint());
-                        if let Some(index) = index {
-                            remove_indexes.push(index);
-                        } else {
-                            return Err(WorldError::InvalidBlockId(block.0));
-                        }
-                    }
-                }
-                for index in remove_indexes {
-                    // Decrement any data entries that are higher than the removed index
-                    for data_point in &mut *data {
-                        let mut i = 0;
-                        while (i + *bits_per_block as usize) < 64 {
-                            let block_index =
-                                ferrumc_general_purpose::data_packing::u32::read_nbit_u32(
-                                    data_point,
-                                    *bits_per_block,
-                                    i as u32,
-                                )?;
-                            if block_index > index as u32 {
-                                ferrumc_general_purpose::data_packing::u32::write_nbit_u32(
-                                    data_point,
-                                    i as u32,
-                                    block_index - 1,
-                                    *bits_per_block,
-                                )?;
-                            }
-                            i += *bits_per_block as usize;
-                        }
-                    }
-                }
+                palette.len() != used_blocks
+                    || used_blocks <= 1
+                    || *bits_per_block > bits_for_palette(used_blocks)
+            }
+            PaletteType::Direct { .. } => used_blocks <= 1 << MAX_INDIRECT_BITS,
+        };
+        if !can_shrink {
+            return Ok(());
+        }
 
-                {
-                    // If there is only one block in the palette, convert to single block mode
-                    if palette.len() == 1 {
-                        let block = BlockId::from(palette[0]);
-                        self.block_states.block_data = PaletteType::Single(palette[0]);
-                        self.block_states.block_counts.clear();
-                        self.block_states.block_counts.insert(block, 4096);
-                    }
-                }
+        // Rebuild the palette from the block data itself rather than trusting the counts
+        let mut palette = Vec::new();
+        let mut palette_indexes = HashMap::new();
+        let mut block_counts = HashMap::new();
+        let mut entries = Vec::with_capacity(4096);
+        for block in self.block_states.blocks()? {
+            let index = *palette_indexes.entry(block).or_insert_with(|| {
+                palette.push(block.to_varint());
+                palette.len() - 1
+            });
+            *block_counts.entry(block).or_insert(0) += 1;
+            entries.push(index as u32);
+        }
+        self.block_states.block_counts = block_counts;
+
+        self.block_states.block_data = if palette.len() == 1 {
+            PaletteType::Single(palette[0])
+        } else {
+            let bits_per_block = bits_for_palette(palette.len());
+            if bits_per_block > MAX_INDIRECT_BITS {
+                // Still too many different blocks for an indirect palette
+                return Ok(());
             }
-            PaletteType::Direct { .. } => {
-                todo!("Implement optimisation for direct palette");
+            PaletteType::Indirect {
+                bits_per_block,
+                data: pack_entries(bits_per_block, &entries)?,
+                palette,
             }
         };
 
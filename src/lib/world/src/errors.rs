@@ -34,6 +34,8 @@ pub enum WorldError {
     BitcodeEncodeError(String),
     #[error("Chunk not found")]
     ChunkNotFound,
+    #[error("Chunk {0}, {1} isn't part of the transaction")]
+    ChunkNotInTransaction(i32, i32),
     #[error("Anvil Decode Error: {0}")]
     AnvilDecodeError(AnvilError),
     #[error("Missing block mapping: {0}")]
@@ -46,8 +48,16 @@ pub enum WorldError {
     SectionOutOfBounds(i32),
     #[error("Invalid block state data")]
     InvalidBlockStateData(String),
+    #[error("Invalid biome data: {0}")]
+    InvalidBiomeData(String),
     #[error("Invalid block: {0}")]
     InvalidBlock(BlockData),
+    #[error("Invalid clipboard: {0}")]
+    InvalidClipboard(String),
+    #[error("Invalid schematic: {0}")]
+    InvalidSchematic(String),
+    #[error("Invalid protected region: {0}")]
+    InvalidProtectedRegion(String),
     #[error("Invalid batching operation: {0}")]
     InvalidBatchingOperation(String),
     #[error("Invalid block ID: {0}")]
@@ -60,6 +70,12 @@ pub enum WorldError {
     DecompressionError(String),
     #[error("Corrupted chunk data: got checksum {0}, expected checksum {1}")]
     CorruptedChunkData(u32, u32),
+    #[error("There can't be more than {0} forced chunks")]
+    TooManyForcedChunks(usize),
+    #[error("Invalid dimension: {0}")]
+    InvalidDimension(String),
+    #[error("Chunk format version {0} is newer than this server supports")]
+    UnsupportedChunkVersion(u32),
     #[error("NBT data error: {0}")]
     NBTError(#[from] ferrumc_nbt::errors::NBTError),
 }
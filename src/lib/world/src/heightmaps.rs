@@ -0,0 +1,488 @@
+//! Heightmap calculation and maintenance.
+//!
+//! A heightmap stores, for every column of a chunk, the Y level just above the highest block that
+//! matches the heightmap's predicate, relative to the bottom of the chunk. The values are packed
+//! into longs the same way vanilla does it, with `ceil(log2(height + 1))` bits per entry and no
+//! entries spanning across longs, so they can be sent to clients and read from imported chunks
+//! as-is.
+
+use crate::block_id::{BlockId, ID2BLOCK};
+use crate::chunk_format::{Chunk, Heightmaps, PaletteType};
+use lazy_static::lazy_static;
+
+/// The heightmap types used by vanilla. The discriminants are the IDs used by the protocol.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum HeightmapType {
+    WorldSurface = 1,
+    OceanFloor = 3,
+    MotionBlocking = 4,
+    MotionBlockingNoLeaves = 5,
+}
+
+impl HeightmapType {
+    pub const ALL: [HeightmapType; 4] = [
+        HeightmapType::WorldSurface,
+        HeightmapType::OceanFloor,
+        HeightmapType::MotionBlocking,
+        HeightmapType::MotionBlockingNoLeaves,
+    ];
+
+    /// The heightmaps the client uses, for rendering rain and snow among other things.
+    pub const CLIENT: [HeightmapType; 3] = [
+        HeightmapType::WorldSurface,
+        HeightmapType::MotionBlocking,
+        HeightmapType::MotionBlockingNoLeaves,
+    ];
+
+    fn flag(self) -> u8 {
+        match self {
+            HeightmapType::WorldSurface => 1,
+            HeightmapType::OceanFloor => 1 << 1,
+            HeightmapType::MotionBlocking => 1 << 2,
+            HeightmapType::MotionBlockingNoLeaves => 1 << 3,
+        }
+    }
+}
+
+// Blocks that aren't air but can be walked through. Anything not matched here is treated as solid.
+const NON_SOLID_BLOCKS: &[&str] = &[
+    "water",
+    "lava",
+    "bubble_column",
+    "short_grass",
+    "tall_grass",
+    "fern",
+    "large_fern",
+    "dead_bush",
+    "seagrass",
+    "tall_seagrass",
+    "kelp",
+    "kelp_plant",
+    "sugar_cane",
+    "fire",
+    "soul_fire",
+    "wheat",
+    "carrots",
+    "potatoes",
+    "beetroots",
+    "sweet_berry_bush",
+    "nether_wart",
+    "cobweb",
+    "glow_lichen",
+    "sculk_vein",
+    "nether_portal",
+    "end_portal",
+    "end_gateway",
+    "structure_void",
+    "light",
+    "tripwire",
+    "redstone_wire",
+    "dandelion",
+    "poppy",
+    "allium",
+    "azure_bluet",
+    "oxeye_daisy",
+    "cornflower",
+    "lily_of_the_valley",
+    "wither_rose",
+    "sunflower",
+    "lilac",
+    "rose_bush",
+    "peony",
+    "brown_mushroom",
+    "red_mushroom",
+    "crimson_fungus",
+    "warped_fungus",
+    "spore_blossom",
+    "hanging_roots",
+    "pumpkin_stem",
+    "melon_stem",
+    "attached_pumpkin_stem",
+    "attached_melon_stem",
+    "twisting_vines",
+    "twisting_vines_plant",
+    "weeping_vines",
+    "weeping_vines_plant",
+    "cave_vines",
+    "cave_vines_plant",
+];
+
+const NON_SOLID_PATTERNS: &[&str] = &[
+    "torch",
+    "sapling",
+    "_sign",
+    "button",
+    "pressure_plate",
+    "rail",
+    "lever",
+    "_tulip",
+    "_orchid",
+    "_coral",
+    "_banner",
+];
+
+lazy_static! {
+    static ref HEIGHTMAP_FLAGS: Vec<u8> = ID2BLOCK
+        .iter()
+        .map(|block| {
+            let name = block.name.strip_prefix("minecraft:").unwrap_or(&block.name);
+            let waterlogged = block
+                .properties
+                .as_ref()
+                .and_then(|p| p.get("waterlogged"))
+                .is_some_and(|v| v == "true");
+            classify_block(name, waterlogged)
+        })
+        .collect();
+}
+
+fn classify_block(name: &str, waterlogged: bool) -> u8 {
+    if matches!(name, "air" | "cave_air" | "void_air") {
+        return 0;
+    }
+    let fluid = matches!(name, "water" | "lava" | "bubble_column") || waterlogged;
+    // Snow layers only have collision once they're more than one layer thick, but vanilla treats
+    // them as solid for heightmaps anyway
+    let solid = name == "snow"
+        || !(NON_SOLID_BLOCKS.contains(&name)
+            || NON_SOLID_PATTERNS.iter().any(|p| name.contains(p)));
+    let mut flags = HeightmapType::WorldSurface.flag();
+    if solid {
+        flags |= HeightmapType::OceanFloor.flag();
+    }
+    if solid || fluid {
+        flags |= HeightmapType::MotionBlocking.flag();
+        if !name.ends_with("_leaves") {
+            flags |= HeightmapType::MotionBlockingNoLeaves.flag();
+        }
+    }
+    flags
+}
+
+/// Returns true if the block counts towards the given heightmap.
+pub fn is_heightmap_block(heightmap: HeightmapType, block: BlockId) -> bool {
+    HEIGHTMAP_FLAGS
+        .get(block.0 as usize)
+        .is_some_and(|flags| flags & heightmap.flag() != 0)
+}
+
+fn bits_per_entry(world_height: usize) -> usize {
+    (usize::BITS - world_height.leading_zeros()) as usize
+}
+
+impl Heightmaps {
+    fn data(&self, heightmap: HeightmapType) -> &Vec<i64> {
+        match heightmap {
+            HeightmapType::WorldSurface => &self.world_surface,
+            HeightmapType::OceanFloor => &self.ocean_floor,
+            HeightmapType::MotionBlocking => &self.motion_blocking,
+            HeightmapType::MotionBlockingNoLeaves => &self.motion_blocking_no_leaves,
+        }
+    }
+
+    fn data_mut(&mut self, heightmap: HeightmapType) -> &mut Vec<i64> {
+        match heightmap {
+            HeightmapType::WorldSurface => &mut self.world_surface,
+            HeightmapType::OceanFloor => &mut self.ocean_floor,
+            HeightmapType::MotionBlocking => &mut self.motion_blocking,
+            HeightmapType::MotionBlockingNoLeaves => &mut self.motion_blocking_no_leaves,
+        }
+    }
+
+    /// Returns the packed data for a heightmap, or `None` if it hasn't been calculated.
+    pub fn packed(&self, heightmap: HeightmapType) -> Option<&[i64]> {
+        let data = self.data(heightmap);
+        if data.is_empty() {
+            None
+        } else {
+            Some(data)
+        }
+    }
+
+    /// Returns true if any of the heightmaps are missing.
+    pub fn is_incomplete(&self) -> bool {
+        HeightmapType::ALL
+            .iter()
+            .any(|heightmap| self.data(*heightmap).is_empty())
+    }
+
+    /// Gets the raw heightmap value for a column, which is the number of blocks between the
+    /// bottom of the chunk and the lowest free block above the surface.
+    ///
+    /// Returns `None` if the heightmap hasn't been calculated.
+    pub fn get(
+        &self,
+        heightmap: HeightmapType,
+        world_height: usize,
+        x: i32,
+        z: i32,
+    ) -> Option<u16> {
+        let data = self.data(heightmap);
+        let bits = bits_per_entry(world_height);
+        let per_long = 64 / bits;
+        let index = ((z & 0xf) * 16 + (x & 0xf)) as usize;
+        let long = data.get(index / per_long)?;
+        let offset = (index % per_long) * bits;
+        Some(((*long as u64 >> offset) & ((1 << bits) - 1)) as u16)
+    }
+
+    /// Sets the raw heightmap value for a column. Missing heightmap data is created as all zeroes.
+    pub fn set(
+        &mut self,
+        heightmap: HeightmapType,
+        world_height: usize,
+        x: i32,
+        z: i32,
+        value: u16,
+    ) {
+        let bits = bits_per_entry(world_height);
+        let per_long = 64 / bits;
+        let data = self.data_mut(heightmap);
+        let longs = 256usize.div_ceil(per_long);
+        if data.len() != longs {
+            *data = vec![0; longs];
+        }
+        let index = ((z & 0xf) * 16 + (x & 0xf)) as usize;
+        let offset = (index % per_long) * bits;
+        let mask = ((1u64 << bits) - 1) << offset;
+        let long = &mut data[index / per_long];
+        *long = ((*long as u64 & !mask) | ((value as u64) << offset & mask)) as i64;
+    }
+}
+
+impl Chunk {
+    fn vertical_bounds(&self) -> Option<(i32, i32)> {
+        let min_y = self.sections.iter().map(|s| s.y as i32 * 16).min()?;
+        let max_y = self.sections.iter().map(|s| s.y as i32 * 16 + 15).max()?;
+        Some((min_y, max_y))
+    }
+
+    /// Gets the Y level of the lowest free block above the highest block matching the heightmap,
+    /// in world coordinates. `x` and `z` can be either chunk-relative or global coordinates.
+    ///
+    /// Returns `None` if the heightmap hasn't been calculated.
+    pub fn get_height(&self, heightmap: HeightmapType, x: i32, z: i32) -> Option<i32> {
+        let (min_y, max_y) = self.vertical_bounds()?;
+        let world_height = (max_y - min_y + 1) as usize;
+        self.heightmaps
+            .get(heightmap, world_height, x, z)
+            .map(|value| value as i32 + min_y)
+    }
+
+    /// Finds the height of a column for every heightmap type by scanning down from the top.
+    fn scan_column(&self, x: i32, z: i32, min_y: i32, max_y: i32) -> [i32; 4] {
+        let mut heights = [min_y; 4];
+        let mut remaining = (1u8 << HeightmapType::ALL.len()) - 1;
+        let mut sections: Vec<_> = self.sections.iter().collect();
+        sections.sort_by_key(|section| std::cmp::Reverse(section.y));
+        for section in sections {
+            let base_y = section.y as i32 * 16;
+            if base_y > max_y {
+                continue;
+            }
+            // Uniform sections can be checked with a single lookup
+            let uniform = match &section.block_states.block_data {
+                PaletteType::Single(val) => Some(BlockId::from_varint(*val)),
+                _ => None,
+            };
+            for y in (base_y..(base_y + 16).min(max_y + 1)).rev() {
+                let Some(block) = uniform.or_else(|| section.get_block(x, y, z).ok()) else {
+                    continue;
+                };
+                for (i, heightmap) in HeightmapType::ALL.iter().enumerate() {
+                    if remaining & (1 << i) != 0 && is_heightmap_block(*heightmap, block) {
+                        heights[i] = y + 1;
+                        remaining &= !(1 << i);
+                    }
+                }
+                if remaining == 0 {
+                    return heights;
+                }
+                if uniform.is_some() {
+                    // Every block in the section is the same, so the rest of it won't match either
+                    break;
+                }
+            }
+        }
+        heights
+    }
+
+    /// Recalculates every heightmap for a single column.
+    pub(crate) fn recalculate_heightmap_column(&mut self, x: i32, z: i32) {
+        let Some((min_y, max_y)) = self.vertical_bounds() else {
+            return;
+        };
+        let world_height = (max_y - min_y + 1) as usize;
+        let heights = self.scan_column(x, z, min_y, max_y);
+        for (heightmap, height) in HeightmapType::ALL.iter().zip(heights) {
+            self.heightmaps
+                .set(*heightmap, world_height, x, z, (height - min_y) as u16);
+        }
+    }
+
+    /// Calculates all heightmaps for this chunk from scratch.
+    pub fn compute_heightmaps(&mut self) {
+        self.heightmaps = Heightmaps::new();
+        for x in 0..16 {
+            for z in 0..16 {
+                self.recalculate_heightmap_column(x, z);
+            }
+        }
+    }
+
+    /// Updates the heightmaps after the block at the given position has been changed.
+    ///
+    /// Only the changed column is touched, and it is only rescanned if the highest matching block
+    /// was removed.
+    pub(crate) fn update_heightmaps(&mut self, x: i32, y: i32, z: i32, block: BlockId) {
+        let Some((min_y, max_y)) = self.vertical_bounds() else {
+            return;
+        };
+        let world_height = (max_y - min_y + 1) as usize;
+        if self.heightmaps.is_incomplete() {
+            self.compute_heightmaps();
+            return;
+        }
+        let mut needs_rescan = false;
+        for heightmap in HeightmapType::ALL {
+            let Some(current) = self.heightmaps.get(heightmap, world_height, x, z) else {
+                continue;
+            };
+            let current = current as i32 + min_y;
+            if is_heightmap_block(heightmap, block) {
+                if y + 1 > current {
+                    self.heightmaps
+                        .set(heightmap, world_height, x, z, (y + 1 - min_y) as u16);
+                }
+            } else if y + 1 == current {
+                needs_rescan = true;
+            }
+        }
+        if needs_rescan {
+            // The top block was removed, so the new surface has to be found
+            let heights = self.scan_column(x, z, min_y, y);
+            for (heightmap, height) in HeightmapType::ALL.iter().zip(heights) {
+                let current = self
+                    .heightmaps
+                    .get(*heightmap, world_height, x, z)
+                    .unwrap_or_default() as i32
+                    + min_y;
+                if current == y + 1 {
+                    self.heightmaps
+                        .set(*heightmap, world_height, x, z, (height - min_y) as u16);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vanilla_chunk_format::BlockData;
+
+    fn block(name: &str, properties: &[(&str, &str)]) -> BlockData {
+        BlockData {
+            name: name.to_string(),
+            properties: if properties.is_empty() {
+                None
+            } else {
+                Some(
+                    properties
+                        .iter()
+                        .map(|(k, v)| (k.to_string(), v.to_string()))
+                        .collect(),
+                )
+            },
+        }
+    }
+
+    #[test]
+    fn test_packing_matches_vanilla_layout() {
+        let mut heightmaps = Heightmaps::new();
+        heightmaps.set(HeightmapType::WorldSurface, 384, 15, 15, 300);
+        // 9 bits per entry, 7 entries per long, 37 longs
+        assert_eq!(heightmaps.world_surface.len(), 37);
+        assert_eq!(heightmaps.world_surface[36], 300 << 9);
+        assert_eq!(
+            heightmaps.get(HeightmapType::WorldSurface, 384, 15, 15),
+            Some(300)
+        );
+    }
+
+    #[test]
+    fn test_empty_chunk_heightmaps() {
+        let mut chunk = Chunk::new(0, 0, "overworld".to_string());
+        chunk.compute_heightmaps();
+        for heightmap in HeightmapType::ALL {
+            assert_eq!(chunk.get_height(heightmap, 4, 4), Some(-64));
+        }
+    }
+
+    #[test]
+    fn test_heightmaps_follow_edits() {
+        let mut chunk = Chunk::new(0, 0, "overworld".to_string());
+        chunk.set_section(0, block("minecraft:stone", &[])).unwrap();
+        chunk.compute_heightmaps();
+        assert_eq!(
+            chunk.get_height(HeightmapType::WorldSurface, 3, 3),
+            Some(16)
+        );
+
+        chunk
+            .set_block(
+                3,
+                40,
+                3,
+                block(
+                    "minecraft:oak_leaves",
+                    &[
+                        ("distance", "7"),
+                        ("persistent", "false"),
+                        ("waterlogged", "false"),
+                    ],
+                ),
+            )
+            .unwrap();
+        assert_eq!(
+            chunk.get_height(HeightmapType::WorldSurface, 3, 3),
+            Some(41)
+        );
+        assert_eq!(
+            chunk.get_height(HeightmapType::MotionBlocking, 3, 3),
+            Some(41)
+        );
+        assert_eq!(
+            chunk.get_height(HeightmapType::MotionBlockingNoLeaves, 3, 3),
+            Some(16)
+        );
+
+        chunk.set_block(3, 40, 3, BlockData::default()).unwrap();
+        assert_eq!(
+            chunk.get_height(HeightmapType::WorldSurface, 3, 3),
+            Some(16)
+        );
+        assert_eq!(
+            chunk.get_height(HeightmapType::MotionBlocking, 3, 3),
+            Some(16)
+        );
+
+        chunk.set_block(3, 15, 3, BlockData::default()).unwrap();
+        assert_eq!(chunk.get_height(HeightmapType::OceanFloor, 3, 3), Some(15));
+    }
+
+    #[test]
+    fn test_fluids_only_block_motion() {
+        let mut chunk = Chunk::new(0, 0, "overworld".to_string());
+        chunk.set_section(0, block("minecraft:stone", &[])).unwrap();
+        chunk.compute_heightmaps();
+        chunk
+            .set_block(5, 16, 5, block("minecraft:water", &[("level", "0")]))
+            .unwrap();
+        assert_eq!(
+            chunk.get_height(HeightmapType::MotionBlocking, 5, 5),
+            Some(17)
+        );
+        assert_eq!(chunk.get_height(HeightmapType::OceanFloor, 5, 5), Some(16));
+    }
+}
@@ -1,14 +1,20 @@
+use crate::activity::{save_activity_internal, ChunkActivity};
+use crate::db_functions::save_chunk_internal;
 use crate::errors::WorldError;
+use crate::spatial::CHUNK_TABLE;
 use crate::vanilla_chunk_format::VanillaChunk;
+use crate::vanilla_level_format::{VanillaLevel, VanillaPlayer};
 use crate::World;
 use ferrumc_anvil::load_anvil_file;
+use ferrumc_nbt::read_nbt_file;
 use ferrumc_threadpool::ThreadPool;
 use indicatif::{ProgressBar, ProgressStyle};
 use rayon::prelude::*;
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use tracing::{error, info};
+use tracing::{error, info, warn};
+use uuid::Uuid;
 
 impl World {
     fn get_chunk_count(&self, import_dir: &Path) -> Result<u64, WorldError> {
@@ -51,7 +57,7 @@ impl World {
 
         progress.set_message("Setting up database and preparing import...");
 
-        self.storage_backend.create_table("chunks".to_string())?;
+        self.storage_backend.create_table(CHUNK_TABLE.to_string())?;
 
         let start = std::time::Instant::now();
 
@@ -91,8 +97,32 @@ impl World {
                             let self_clone = arc_self.clone();
                             let progress = progress.clone();
                             move || {
-                                let res =
-                                    self_clone.save_chunk(vanilla_chunk.to_custom_format()?.into());
+                                // Imported chunks go straight to the storage backend rather
+                                // than piling up in the dirty set until the next sync
+                                let chunk = vanilla_chunk.to_custom_format()?;
+                                let res = save_chunk_internal(
+                                    self_clone.storage_backend.as_ref(),
+                                    &self_clone.compression,
+                                    &self_clone.dimensions,
+                                    &chunk,
+                                );
+                                // Keep how long players spent in the chunk, so pruning
+                                // doesn't treat the whole imported world as unvisited
+                                if let Some(inhabited_time) =
+                                    vanilla_chunk.inhabited_time.filter(|time| *time > 0)
+                                {
+                                    save_activity_internal(
+                                        self_clone.storage_backend.as_ref(),
+                                        &self_clone.dimensions,
+                                        &chunk.dimension,
+                                        chunk.x,
+                                        chunk.z,
+                                        ChunkActivity {
+                                            inhabited_time: inhabited_time as u64,
+                                            last_visited: 0,
+                                        },
+                                    )?;
+                                }
                                 progress.inc(1);
                                 if index == location_count - 1 {
                                     self_clone.storage_backend.flush()?;
@@ -124,6 +154,67 @@ impl World {
             start.elapsed()
         );
 
+        // The chunks are the important part, so a broken level.dat or player file shouldn't
+        // throw the rest of the import away
+        if let Err(e) = arc_self.import_level(&import_dir) {
+            error!("Failed to import level.dat: {}", e);
+        }
+        if let Err(e) = arc_self.import_players(&import_dir) {
+            error!("Failed to import player data: {}", e);
+        }
+
+        Ok(())
+    }
+
+    /// Imports the seed, spawn, time and game rules from a vanilla `level.dat`.
+    fn import_level(&self, import_dir: &Path) -> Result<(), WorldError> {
+        let level_path = import_dir.join("level.dat");
+        if !level_path.is_file() {
+            info!("No level.dat found, keeping the default world settings");
+            return Ok(());
+        }
+        let data = read_nbt_file(&level_path)?;
+        let level = VanillaLevel::from_bytes(&data)?;
+        let metadata = level.data.to_metadata();
+        self.save_metadata(&metadata)?;
+        info!(
+            "Imported level.dat with seed {} and spawn at {}, {}, {}",
+            metadata.seed as i64, metadata.spawn.x, metadata.spawn.y, metadata.spawn.z
+        );
+        Ok(())
+    }
+
+    /// Imports every player's position, inventory and game mode from `playerdata/*.dat`.
+    fn import_players(&self, import_dir: &Path) -> Result<(), WorldError> {
+        let player_dir = import_dir.join("playerdata");
+        if !player_dir.is_dir() {
+            return Ok(());
+        }
+        let mut players = Vec::new();
+        for entry in player_dir.read_dir()? {
+            let path = entry?.path();
+            // Skip the dat_old backups vanilla keeps next to each file
+            if path.extension().is_none_or(|extension| extension != "dat") {
+                continue;
+            }
+            let Some(uuid) = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| Uuid::parse_str(stem).ok())
+            else {
+                warn!("Skipping player file with invalid name {}", path.display());
+                continue;
+            };
+            let player = read_nbt_file(&path)
+                .map_err(WorldError::from)
+                .and_then(|data| Ok(VanillaPlayer::from_bytes(&data)?.to_player_data()));
+            match player {
+                Ok(player) => players.push((uuid.as_u128(), player)),
+                Err(e) => error!("Failed to import player file {}: {}", path.display(), e),
+            }
+        }
+        self.save_player_data_batch(&players)?;
+        info!("Imported data for {} players", players.len());
         Ok(())
     }
 }
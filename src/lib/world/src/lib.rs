@@ -1,30 +1,100 @@
+pub mod activity;
+pub mod anti_xray;
+pub mod audit;
+pub mod backups;
+pub mod biomes;
+pub mod block_changes;
 pub mod block_id;
 pub mod chunk_format;
+mod compression;
 mod db_functions;
 pub mod edit_batch;
 pub mod edits;
 pub mod errors;
+pub mod heightmaps;
 mod importing;
+pub mod lighting;
+pub mod metadata;
+pub mod migrations;
+mod packet_cache;
+pub mod player_data;
+pub mod protection;
+pub mod pruning;
+pub mod regions;
+pub mod schematics;
+pub mod spatial;
+pub mod tickets;
+pub mod transactions;
 pub mod vanilla_chunk_format;
+mod vanilla_level_format;
 
+use crate::activity::{create_activity_table, ChunkActivity};
+use crate::anti_xray::AntiXray;
+use crate::audit::AuditLog;
+use crate::block_changes::SectionKey;
+use crate::block_id::BlockId;
 use crate::chunk_format::Chunk;
+use crate::compression::ChunkCompression;
+use crate::db_functions::write_dirty_chunk;
 use crate::errors::WorldError;
+use crate::metadata::create_metadata_table;
+use crate::packet_cache::{new_packet_cache, CachedPacket, PacketCacheKey};
+use crate::player_data::create_player_table;
+use crate::protection::ProtectedRegions;
+use crate::spatial::DimensionRegistry;
+use crate::tickets::ChunkTickets;
+use crate::transactions::ChunkLocks;
+use dashmap::DashMap;
 use deepsize::DeepSizeOf;
 use ferrumc_config::server_config::get_global_config;
+use ferrumc_config::server_config::DatabaseBackend;
 use ferrumc_general_purpose::paths::get_root_path;
-use ferrumc_storage::lmdb::LmdbBackend;
+use ferrumc_storage::backend::{initialize_backend, StorageBackend};
+use moka::notification::RemovalCause;
 use moka::sync::Cache;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fs::create_dir_all;
 use std::path::{Path, PathBuf};
 use std::process::exit;
+use std::sync::atomic::AtomicU64;
 use std::sync::Arc;
 use std::time::Duration;
-use tracing::{error, trace, warn};
+use tracing::{debug, error, trace, warn};
+
+type ChunkKey = (i32, i32, String);
 
 #[derive(Clone)]
 pub struct World {
-    storage_backend: LmdbBackend,
-    cache: Cache<(i32, i32, String), Arc<Chunk>>,
+    storage_backend: Arc<dyn StorageBackend>,
+    cache: Cache<ChunkKey, Arc<Chunk>>,
+    compression: Arc<ChunkCompression>,
+    dimensions: Arc<DimensionRegistry>,
+    /// Chunks that have been saved but not yet written to the storage backend.
+    dirty_chunks: Arc<DashMap<ChunkKey, Arc<Chunk>>>,
+    /// Chunk activity that's been recorded but not yet written to the storage backend.
+    activity: Arc<DashMap<ChunkKey, ChunkActivity>>,
+    /// Locks for chunks that are being edited, see [`World::edit`].
+    chunk_locks: Arc<ChunkLocks>,
+    /// The revision of each chunk that's changed since it was loaded, see
+    /// [`World::chunk_revision`].
+    revisions: Arc<DashMap<ChunkKey, u64>>,
+    /// The last revision handed out to any chunk.
+    next_revision: Arc<AtomicU64>,
+    /// Blocks that have changed but haven't been sent to players yet, see
+    /// [`World::notify_block_changes`].
+    block_changes: Arc<DashMap<SectionKey, BTreeMap<u16, BlockId>>>,
+    /// Sections whose light has changed but hasn't been sent to players yet, keyed by chunk.
+    light_changes: Arc<DashMap<ChunkKey, BTreeSet<i8>>>,
+    /// Audit log entries that haven't been written yet, see [`World::log_block_changes`].
+    audit: Arc<AuditLog>,
+    /// Every protected region, see [`World::can_modify_block`].
+    protection: Arc<ProtectedRegions>,
+    /// Which blocks are hidden from players in each dimension, see [`World::obfuscate_chunk`].
+    anti_xray: Arc<HashMap<String, AntiXray>>,
+    /// Encoded chunk packets, see [`World::cached_chunk_packet`].
+    packet_cache: Cache<PacketCacheKey, CachedPacket>,
+    /// Which chunks are loaded and simulated, see [`World::add_chunk_ticket`].
+    tickets: Arc<ChunkTickets>,
 }
 
 fn check_config_validity() -> Result<(), WorldError> {
@@ -35,7 +105,7 @@ fn check_config_validity() -> Result<(), WorldError> {
     let config = get_global_config();
     let db_path = get_root_path().join(&config.database.db_path);
 
-    if config.database.map_size == 0 {
+    if config.database.backend == DatabaseBackend::Lmdb && config.database.map_size == 0 {
         error!("Map size is set to 0. Please set the map size in the configuration file.");
         return Err(WorldError::InvalidMapSize(config.database.map_size));
     }
@@ -89,7 +159,32 @@ impl World {
             backend_path = get_root_path().join(backend_path);
         }
         let storage_backend =
-            LmdbBackend::initialize(Some(backend_path)).expect("Failed to initialize database");
+            initialize_backend(get_global_config().database.backend, Some(backend_path))
+                .expect("Failed to initialize database");
+        debug!("Using storage backend: {}", storage_backend.details());
+        let compression = Arc::new(
+            ChunkCompression::load(storage_backend.as_ref())
+                .expect("Failed to set up chunk compression"),
+        );
+        let dimensions = Arc::new(
+            DimensionRegistry::load(storage_backend.as_ref()).expect("Failed to load dimensions"),
+        );
+        let protection = Arc::new(
+            ProtectedRegions::load(storage_backend.as_ref())
+                .expect("Failed to load protected regions"),
+        );
+        create_activity_table(storage_backend.as_ref())
+            .expect("Failed to create the chunk activity table");
+        create_metadata_table(storage_backend.as_ref())
+            .expect("Failed to create the world metadata table");
+        create_player_table(storage_backend.as_ref())
+            .expect("Failed to create the player data table");
+        let audit = Arc::new(
+            AuditLog::load(storage_backend.as_ref()).expect("Failed to load the audit log"),
+        );
+        let tickets = Arc::new(
+            ChunkTickets::load(storage_backend.as_ref()).expect("Failed to load chunk tickets"),
+        );
 
         if get_global_config().database.cache_ttl != 0
             && get_global_config().database.cache_capacity == 0
@@ -98,8 +193,34 @@ impl World {
             exit(1);
         }
 
-        let eviction_listener = move |key, _, cause| {
-            trace!("Evicting key: {:?}, cause: {:?}", key, cause);
+        let dirty_chunks: Arc<DashMap<ChunkKey, Arc<Chunk>>> = Arc::new(DashMap::new());
+
+        // Chunks that fall out of the cache still need to make it to disk if they've changed
+        // since the last sync. Replacements and explicit removals are handled by the caller.
+        let eviction_listener = {
+            let storage_backend = storage_backend.clone();
+            let compression = compression.clone();
+            let dimensions = dimensions.clone();
+            let dirty_chunks = dirty_chunks.clone();
+            move |key: Arc<ChunkKey>, _, cause: RemovalCause| {
+                trace!("Evicting key: {:?}, cause: {:?}", key, cause);
+                if !cause.was_evicted() {
+                    return;
+                }
+                // Chunks that fail to write are kept dirty, so the next sync can try again
+                if let Err(e) = write_dirty_chunk(
+                    storage_backend.as_ref(),
+                    &compression,
+                    &dimensions,
+                    &dirty_chunks,
+                    key.as_ref().clone(),
+                ) {
+                    error!(
+                        "Failed to write evicted chunk ({}, {}) in {}: {}",
+                        key.0, key.1, key.2, e
+                    );
+                }
+            }
         };
 
         let cache = Cache::builder()
@@ -109,10 +230,29 @@ impl World {
             .max_capacity(get_global_config().database.cache_capacity * 1024)
             .build();
 
-        World {
+        let world = World {
             storage_backend,
             cache,
+            compression,
+            dimensions,
+            dirty_chunks,
+            activity: Arc::new(DashMap::new()),
+            chunk_locks: Arc::new(ChunkLocks::default()),
+            revisions: Arc::new(DashMap::new()),
+            next_revision: Arc::new(AtomicU64::new(0)),
+            block_changes: Arc::new(DashMap::new()),
+            light_changes: Arc::new(DashMap::new()),
+            audit,
+            protection,
+            anti_xray: Arc::new(AntiXray::from_config(&get_global_config().anti_xray)),
+            packet_cache: new_packet_cache(),
+            tickets,
+        };
+        if let Err(e) = world.migrate_legacy_chunk_keys() {
+            error!("Failed to move chunks to spatial keys: {}", e);
+            exit(1);
         }
+        world
     }
 }
 
@@ -130,7 +270,7 @@ mod tests {
         );
         let chunk = world.load_chunk(1, 1, "overworld").expect(
             "Failed to load chunk. If it's a bitcode error, chances are the chunk format \
-             has changed since last generating a world so you'll need to regenerate",
+             has changed without a migration being added, see the migrations module",
         );
         let encoded = bitcode::encode(&chunk);
         std::fs::write("../../../.etc/raw_chunk.dat", encoded).unwrap();
@@ -0,0 +1,847 @@
+//! Sky and block light propagation.
+//!
+//! Light is stored per section as two 2048 byte nibble arrays (one for sky light and one for block
+//! light), using the same layout as the vanilla client: the index of a block is
+//! `y * 256 + z * 16 + x`, and even indexes are stored in the low nibble of a byte.
+//!
+//! Full recalculation of a single chunk is done with [`Chunk::compute_light`], which is what world
+//! generation uses. Block edits are relit incrementally with [`World::relight`], which loads the
+//! chunks around the edited blocks so light can flow across chunk borders.
+
+use crate::block_id::{BlockId, ID2BLOCK};
+use crate::chunk_format::{Chunk, PaletteType, Section};
+use crate::errors::WorldError;
+use crate::World;
+use ahash::{AHashMap, AHashSet};
+use lazy_static::lazy_static;
+use std::collections::{BTreeMap, VecDeque};
+
+/// The maximum light level a block can have.
+pub const MAX_LIGHT: u8 = 15;
+
+const LIGHT_ARRAY_SIZE: usize = 2048;
+
+const DIRECTIONS: [(i32, i32, i32); 6] = [
+    (1, 0, 0),
+    (-1, 0, 0),
+    (0, 1, 0),
+    (0, -1, 0),
+    (0, 0, 1),
+    (0, 0, -1),
+];
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum LightType {
+    Sky,
+    Block,
+}
+
+/// How a block state interacts with light.
+///
+/// `emission` is the block light level the block gives off, `opacity` is how much light is lost
+/// when passing through it. Light always loses at least 1 level per block travelled, so an opacity
+/// of 0 and 1 only differ for sky light travelling straight down.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct LightProperties {
+    pub emission: u8,
+    pub opacity: u8,
+}
+
+lazy_static! {
+    static ref LIGHT_PROPERTIES: Vec<LightProperties> = ID2BLOCK
+        .iter()
+        .map(|block| classify_block(&block.name, block.properties.as_ref()))
+        .collect();
+}
+
+// The blockstates file doesn't include any light data, so blocks are classified by name. Anything
+// not listed here is treated as a full opaque block.
+const TRANSPARENT_BLOCKS: &[&str] = &[
+    "air",
+    "cave_air",
+    "void_air",
+    "short_grass",
+    "tall_grass",
+    "fern",
+    "large_fern",
+    "dead_bush",
+    "snow",
+    "sugar_cane",
+    "bamboo",
+    "cactus",
+    "end_rod",
+    "beacon",
+    "conduit",
+    "fire",
+    "soul_fire",
+    "wheat",
+    "carrots",
+    "potatoes",
+    "beetroots",
+    "sweet_berry_bush",
+    "glow_lichen",
+    "sculk_vein",
+    "spore_blossom",
+    "azalea",
+    "flowering_azalea",
+    "small_dripleaf",
+    "big_dripleaf",
+    "big_dripleaf_stem",
+    "brown_mushroom",
+    "red_mushroom",
+    "crimson_fungus",
+    "warped_fungus",
+    "dandelion",
+    "poppy",
+    "allium",
+    "azure_bluet",
+    "oxeye_daisy",
+    "cornflower",
+    "lily_of_the_valley",
+    "wither_rose",
+    "sunflower",
+    "lilac",
+    "rose_bush",
+    "peony",
+    "pitcher_plant",
+    "hopper",
+    "cauldron",
+    "water_cauldron",
+    "lava_cauldron",
+    "powder_snow_cauldron",
+    "anvil",
+    "chipped_anvil",
+    "damaged_anvil",
+    "brewing_stand",
+    "enchanting_table",
+    "lectern",
+    "grindstone",
+    "stonecutter",
+    "bell",
+    "tripwire",
+    "tripwire_hook",
+    "nether_wart",
+    "cocoa",
+    "sea_pickle",
+    "frogspawn",
+    "chest",
+    "trapped_chest",
+    "ender_chest",
+    "flower_pot",
+    "pumpkin_stem",
+    "melon_stem",
+    "attached_pumpkin_stem",
+    "attached_melon_stem",
+    "lantern",
+    "soul_lantern",
+    "chain",
+];
+
+const TRANSPARENT_PATTERNS: &[&str] = &[
+    "glass",
+    "torch",
+    "sapling",
+    "_sign",
+    "button",
+    "pressure_plate",
+    "rail",
+    "lever",
+    "ladder",
+    "vine",
+    "carpet",
+    "fence",
+    "door",
+    "stairs",
+    "redstone_wire",
+    "repeater",
+    "comparator",
+    "iron_bars",
+    "potted_",
+    "banner",
+    "candle",
+    "campfire",
+    "lily_pad",
+    "scaffolding",
+    "portal",
+    "lightning_rod",
+    "pointed_dripstone",
+    "amethyst_cluster",
+    "_bud",
+];
+
+const TRANSPARENT_SUFFIXES: &[&str] = &[
+    "_wall",
+    "_bed",
+    "_head",
+    "_skull",
+    "_coral",
+    "_coral_fan",
+    "_roots",
+    "_tulip",
+    "_orchid",
+    "_petals",
+];
+
+const FILTERING_BLOCKS: &[&str] = &[
+    "water",
+    "bubble_column",
+    "ice",
+    "frosted_ice",
+    "cobweb",
+    "slime_block",
+    "honey_block",
+    "seagrass",
+    "tall_seagrass",
+    "kelp",
+    "kelp_plant",
+];
+
+fn classify_block(name: &str, properties: Option<&BTreeMap<String, String>>) -> LightProperties {
+    let name = name.strip_prefix("minecraft:").unwrap_or(name);
+    let property = |key: &str| properties.and_then(|p| p.get(key)).map(String::as_str);
+    let lit = property("lit") != Some("false");
+
+    let emission = match name {
+        "glowstone"
+        | "sea_lantern"
+        | "jack_o_lantern"
+        | "lantern"
+        | "shroomlight"
+        | "beacon"
+        | "conduit"
+        | "lava"
+        | "fire"
+        | "end_gateway"
+        | "end_portal"
+        | "ochre_froglight"
+        | "verdant_froglight"
+        | "pearlescent_froglight" => 15,
+        "redstone_lamp" | "campfire" if lit => 15,
+        "torch" | "wall_torch" | "end_rod" => 14,
+        "furnace" | "blast_furnace" | "smoker" if lit => 13,
+        "nether_portal" => 11,
+        "soul_torch" | "soul_wall_torch" | "soul_lantern" | "soul_fire" | "crying_obsidian" => 10,
+        "soul_campfire" if lit => 10,
+        "redstone_ore" | "deepslate_redstone_ore" if lit => 9,
+        "redstone_torch" | "redstone_wall_torch" if lit => 7,
+        "enchanting_table" | "ender_chest" | "glow_lichen" => 7,
+        "magma_block" => 3,
+        "brewing_stand" | "brown_mushroom" | "dragon_egg" | "end_portal_frame" | "sculk_sensor" => {
+            1
+        }
+        _ => 0,
+    };
+
+    let opacity = if FILTERING_BLOCKS.contains(&name)
+        || name.ends_with("_leaves")
+        || property("waterlogged") == Some("true")
+    {
+        1
+    } else if name.ends_with("_slab") {
+        if property("type") == Some("double") {
+            MAX_LIGHT
+        } else {
+            0
+        }
+    } else if name == "tinted_glass" {
+        MAX_LIGHT
+    } else if TRANSPARENT_BLOCKS.contains(&name)
+        || TRANSPARENT_PATTERNS.iter().any(|p| name.contains(p))
+        || TRANSPARENT_SUFFIXES.iter().any(|s| name.ends_with(s))
+    {
+        0
+    } else {
+        MAX_LIGHT
+    };
+
+    LightProperties { emission, opacity }
+}
+
+/// Returns the light properties of a block. Unknown block IDs are treated as opaque.
+pub fn light_properties(block: BlockId) -> LightProperties {
+    LIGHT_PROPERTIES
+        .get(block.0 as usize)
+        .copied()
+        .unwrap_or(LightProperties {
+            emission: 0,
+            opacity: MAX_LIGHT,
+        })
+}
+
+fn light_index(x: i32, y: i32, z: i32) -> usize {
+    ((y & 0xf) * 256 + (z & 0xf) * 16 + (x & 0xf)) as usize
+}
+
+impl Section {
+    fn light_array(&self, light_type: LightType) -> &Vec<u8> {
+        match light_type {
+            LightType::Sky => &self.sky_light,
+            LightType::Block => &self.block_light,
+        }
+    }
+
+    fn light_array_mut(&mut self, light_type: LightType) -> &mut Vec<u8> {
+        match light_type {
+            LightType::Sky => &mut self.sky_light,
+            LightType::Block => &mut self.block_light,
+        }
+    }
+
+    /// Gets the light level at the specified coordinates within this section.
+    ///
+    /// Sections without light data are treated as completely dark.
+    pub fn get_light(&self, light_type: LightType, x: i32, y: i32, z: i32) -> u8 {
+        let array = self.light_array(light_type);
+        if array.len() != LIGHT_ARRAY_SIZE {
+            return 0;
+        }
+        let index = light_index(x, y, z);
+        let byte = array[index / 2];
+        if index.is_multiple_of(2) {
+            byte & 0xf
+        } else {
+            byte >> 4
+        }
+    }
+
+    /// Sets the light level at the specified coordinates within this section.
+    ///
+    /// If the section doesn't have any light data yet, a dark light array is created for it.
+    pub fn set_light(&mut self, light_type: LightType, x: i32, y: i32, z: i32, level: u8) {
+        let array = self.light_array_mut(light_type);
+        if array.len() != LIGHT_ARRAY_SIZE {
+            *array = vec![0; LIGHT_ARRAY_SIZE];
+        }
+        let index = light_index(x, y, z);
+        let byte = &mut array[index / 2];
+        let level = level.min(MAX_LIGHT);
+        if index.is_multiple_of(2) {
+            *byte = (*byte & 0xf0) | level;
+        } else {
+            *byte = (*byte & 0x0f) | (level << 4);
+        }
+    }
+
+    /// Returns true if any block in this section gives off light.
+    fn has_light_emitters(&self) -> bool {
+        match &self.block_states.block_data {
+            PaletteType::Single(val) => light_properties(BlockId::from_varint(*val)).emission > 0,
+            PaletteType::Indirect { palette, .. } => palette
+                .iter()
+                .any(|block| light_properties(BlockId::from_varint(*block)).emission > 0),
+            PaletteType::Direct { .. } => true,
+        }
+    }
+}
+
+struct RegionChunk<'a> {
+    chunk: &'a mut Chunk,
+    // Maps a section Y (offset by 128) to the index of the section in `chunk.sections`, since the
+    // sections aren't guaranteed to be sorted.
+    section_lookup: [u8; 256],
+    min_section: i8,
+    max_section: i8,
+}
+
+/// A group of chunks that light can propagate across.
+///
+/// Positions are in global block coordinates. Blocks in chunks that aren't part of the region are
+/// ignored, so light simply stops at the edge of the region.
+#[derive(Default)]
+pub(crate) struct LightRegion<'a> {
+    chunks: AHashMap<(i32, i32), RegionChunk<'a>>,
+    /// Every (chunk x, chunk z, section y) that had its light changed.
+    pub(crate) changed_sections: AHashSet<(i32, i32, i8)>,
+}
+
+impl<'a> LightRegion<'a> {
+    pub(crate) fn insert(&mut self, chunk: &'a mut Chunk) {
+        let mut section_lookup = [u8::MAX; 256];
+        let mut min_section = i8::MAX;
+        let mut max_section = i8::MIN;
+        for (index, section) in chunk.sections.iter().enumerate() {
+            section_lookup[(section.y as i16 + 128) as usize] = index as u8;
+            min_section = min_section.min(section.y);
+            max_section = max_section.max(section.y);
+        }
+        self.chunks.insert(
+            (chunk.x, chunk.z),
+            RegionChunk {
+                chunk,
+                section_lookup,
+                min_section,
+                max_section,
+            },
+        );
+    }
+
+    fn section(&self, x: i32, y: i32, z: i32) -> Option<&Section> {
+        let region_chunk = self.chunks.get(&(x >> 4, z >> 4))?;
+        let section_y = y >> 4;
+        if section_y < i8::MIN as i32 || section_y > i8::MAX as i32 {
+            return None;
+        }
+        let index = region_chunk.section_lookup[(section_y + 128) as usize];
+        region_chunk.chunk.sections.get(index as usize)
+    }
+
+    fn section_mut(&mut self, x: i32, y: i32, z: i32) -> Option<&mut Section> {
+        let region_chunk = self.chunks.get_mut(&(x >> 4, z >> 4))?;
+        let section_y = y >> 4;
+        if section_y < i8::MIN as i32 || section_y > i8::MAX as i32 {
+            return None;
+        }
+        let index = region_chunk.section_lookup[(section_y + 128) as usize];
+        region_chunk.chunk.sections.get_mut(index as usize)
+    }
+
+    fn block_properties(&self, x: i32, y: i32, z: i32) -> LightProperties {
+        self.section(x, y, z)
+            .and_then(|section| section.get_block(x, y, z).ok())
+            .map(light_properties)
+            .unwrap_or_default()
+    }
+
+    /// Gets the light level at a position.
+    ///
+    /// Returns `None` if the position is outside the region. Positions above the highest section
+    /// of a chunk are treated as being in open sky and positions below the lowest section are
+    /// treated as being completely dark.
+    fn light(&self, light_type: LightType, x: i32, y: i32, z: i32) -> Option<u8> {
+        let region_chunk = self.chunks.get(&(x >> 4, z >> 4))?;
+        let section_y = y >> 4;
+        if section_y > region_chunk.max_section as i32 {
+            return Some(match light_type {
+                LightType::Sky => MAX_LIGHT,
+                LightType::Block => 0,
+            });
+        }
+        if section_y < region_chunk.min_section as i32 {
+            return Some(0);
+        }
+        Some(
+            self.section(x, y, z)
+                .map(|section| section.get_light(light_type, x, y, z))
+                .unwrap_or(0),
+        )
+    }
+
+    /// Sets the light level at a position, returning false if there is no section to store it in.
+    fn set_light(&mut self, light_type: LightType, x: i32, y: i32, z: i32, level: u8) -> bool {
+        let Some(section) = self.section_mut(x, y, z) else {
+            return false;
+        };
+        section.set_light(light_type, x, y, z, level);
+        let section_y = section.y;
+        self.changed_sections.insert((x >> 4, z >> 4, section_y));
+        true
+    }
+
+    fn propagate_increase(&mut self, light_type: LightType, queue: &mut VecDeque<(i32, i32, i32)>) {
+        while let Some((x, y, z)) = queue.pop_front() {
+            let Some(level) = self.light(light_type, x, y, z) else {
+                continue;
+            };
+            if level <= 1 {
+                continue;
+            }
+            for (dx, dy, dz) in DIRECTIONS {
+                let (nx, ny, nz) = (x + dx, y + dy, z + dz);
+                let Some(current) = self.light(light_type, nx, ny, nz) else {
+                    continue;
+                };
+                let opacity = self.block_properties(nx, ny, nz).opacity;
+                if opacity >= MAX_LIGHT {
+                    continue;
+                }
+                // Unobstructed sky light travels straight down without losing any strength
+                let new_level = if light_type == LightType::Sky
+                    && dy == -1
+                    && level == MAX_LIGHT
+                    && opacity == 0
+                {
+                    MAX_LIGHT
+                } else {
+                    level.saturating_sub(opacity.max(1))
+                };
+                if new_level > current && self.set_light(light_type, nx, ny, nz, new_level) {
+                    queue.push_back((nx, ny, nz));
+                }
+            }
+        }
+    }
+
+    fn propagate_decrease(
+        &mut self,
+        light_type: LightType,
+        removals: &mut VecDeque<(i32, i32, i32, u8)>,
+        increases: &mut VecDeque<(i32, i32, i32)>,
+    ) {
+        while let Some((x, y, z, old_level)) = removals.pop_front() {
+            for (dx, dy, dz) in DIRECTIONS {
+                let (nx, ny, nz) = (x + dx, y + dy, z + dz);
+                let Some(level) = self.light(light_type, nx, ny, nz) else {
+                    continue;
+                };
+                if level == 0 {
+                    continue;
+                }
+                let lit_from_above = light_type == LightType::Sky
+                    && dy == -1
+                    && old_level == MAX_LIGHT
+                    && level == MAX_LIGHT;
+                if level < old_level || lit_from_above {
+                    if !self.set_light(light_type, nx, ny, nz, 0) {
+                        continue;
+                    }
+                    removals.push_back((nx, ny, nz, level));
+                    // Light sources that were caught in the removal need to shine again
+                    if light_type == LightType::Block {
+                        let emission = self.block_properties(nx, ny, nz).emission;
+                        if emission > 0 {
+                            self.set_light(light_type, nx, ny, nz, emission);
+                            increases.push_back((nx, ny, nz));
+                        }
+                    }
+                } else {
+                    // This neighbour is lit by something else, so it can fill the gap back in
+                    increases.push_back((nx, ny, nz));
+                }
+            }
+        }
+    }
+
+    /// Incrementally updates the light around blocks that have been changed.
+    pub(crate) fn relight(&mut self, positions: &[(i32, i32, i32)]) {
+        for light_type in [LightType::Sky, LightType::Block] {
+            let mut removals = VecDeque::new();
+            let mut increases = VecDeque::new();
+            for &(x, y, z) in positions {
+                let Some(level) = self.light(light_type, x, y, z) else {
+                    continue;
+                };
+                if level > 0 && self.set_light(light_type, x, y, z, 0) {
+                    removals.push_back((x, y, z, level));
+                }
+                if light_type == LightType::Block {
+                    let emission = self.block_properties(x, y, z).emission;
+                    if emission > 0 && self.set_light(light_type, x, y, z, emission) {
+                        increases.push_back((x, y, z));
+                    }
+                }
+                // The block may have become transparent, so let the neighbours shine into it
+                for (dx, dy, dz) in DIRECTIONS {
+                    let (nx, ny, nz) = (x + dx, y + dy, z + dz);
+                    if self
+                        .light(light_type, nx, ny, nz)
+                        .is_some_and(|level| level > 0)
+                    {
+                        increases.push_back((nx, ny, nz));
+                    }
+                }
+            }
+            self.propagate_decrease(light_type, &mut removals, &mut increases);
+            self.propagate_increase(light_type, &mut increases);
+        }
+    }
+}
+
+impl Chunk {
+    /// Calculates the sky and block light for this whole chunk from scratch.
+    ///
+    /// Neighbouring chunks aren't taken into account, so light from blocks just over the chunk
+    /// border won't be included. Use [`World::relight`] after editing blocks in a chunk that has
+    /// already been lit, since that also updates the surrounding chunks.
+    pub fn compute_light(&mut self) {
+        for section in &mut self.sections {
+            section.sky_light = vec![0; LIGHT_ARRAY_SIZE];
+            section.block_light = vec![0; LIGHT_ARRAY_SIZE];
+        }
+        let Some(min_y) = self.sections.iter().map(|s| s.y as i32 * 16).min() else {
+            return;
+        };
+        let max_y = self
+            .sections
+            .iter()
+            .map(|s| s.y as i32 * 16 + 15)
+            .max()
+            .unwrap_or(min_y);
+
+        let mut block_queue = VecDeque::new();
+        for section in &mut self.sections {
+            if !section.has_light_emitters() {
+                continue;
+            }
+            let base_y = section.y as i32 * 16;
+            for y in 0..16 {
+                for z in 0..16 {
+                    for x in 0..16 {
+                        let Ok(block) = section.get_block(x, y, z) else {
+                            continue;
+                        };
+                        let emission = light_properties(block).emission;
+                        if emission > 0 {
+                            section.set_light(LightType::Block, x, y, z, emission);
+                            block_queue.push_back((self.x * 16 + x, base_y + y, self.z * 16 + z));
+                        }
+                    }
+                }
+            }
+        }
+
+        let (chunk_x, chunk_z) = (self.x, self.z);
+        let mut region = LightRegion::default();
+        region.insert(self);
+
+        // Work out how far down the sky is fully visible in each column first, so only the blocks
+        // next to a column that goes deeper need to spread light sideways.
+        let mut sky_floor = [[max_y + 1; 16]; 16];
+        for (x, column) in sky_floor.iter_mut().enumerate() {
+            for (z, floor) in column.iter_mut().enumerate() {
+                let global_x = chunk_x * 16 + x as i32;
+                let global_z = chunk_z * 16 + z as i32;
+                let mut y = max_y;
+                while y >= min_y && region.block_properties(global_x, y, global_z).opacity == 0 {
+                    y -= 1;
+                }
+                *floor = y + 1;
+            }
+        }
+
+        let mut sky_queue = VecDeque::new();
+        for x in 0..16 {
+            for z in 0..16 {
+                let global_x = chunk_x * 16 + x;
+                let global_z = chunk_z * 16 + z;
+                let floor = sky_floor[x as usize][z as usize];
+                // Only blocks next to a column with a higher floor can light anything sideways
+                let highest_neighbour = [(1, 0), (-1, 0), (0, 1), (0, -1)]
+                    .iter()
+                    .filter_map(|(dx, dz)| {
+                        let (nx, nz) = (x + dx, z + dz);
+                        if (0..16).contains(&nx) && (0..16).contains(&nz) {
+                            Some(sky_floor[nx as usize][nz as usize])
+                        } else {
+                            None
+                        }
+                    })
+                    .max()
+                    .unwrap_or(floor);
+                for y in floor..=max_y {
+                    region.set_light(LightType::Sky, global_x, y, global_z, MAX_LIGHT);
+                    if y < highest_neighbour {
+                        sky_queue.push_back((global_x, y, global_z));
+                    }
+                }
+                // Sky light that filters through blocks like water and leaves
+                let mut level = MAX_LIGHT;
+                let mut y = floor - 1;
+                while y >= min_y && level > 1 {
+                    let opacity = region.block_properties(global_x, y, global_z).opacity;
+                    if opacity >= MAX_LIGHT {
+                        break;
+                    }
+                    level = level.saturating_sub(opacity.max(1));
+                    region.set_light(LightType::Sky, global_x, y, global_z, level);
+                    sky_queue.push_back((global_x, y, global_z));
+                    y -= 1;
+                }
+            }
+        }
+
+        region.propagate_increase(LightType::Sky, &mut sky_queue);
+        region.propagate_increase(LightType::Block, &mut block_queue);
+    }
+}
+
+impl World {
+    /// Incrementally recalculates sky and block light around blocks that have been changed.
+    ///
+    /// The chunks containing the positions and all of their neighbours are loaded, so light can
+    /// spread into and out of the surrounding chunks. Neighbours that haven't been saved yet are
+    /// skipped. Any chunk whose light changed is saved again.
+    ///
+    /// # Arguments
+    ///
+    /// * `dimension` - The dimension the blocks are in.
+    /// * `positions` - The global coordinates of every block that changed.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Vec<(i32, i32, i8)>)` - The (chunk x, chunk z, section y) of every section that had
+    ///   its light changed, so light updates can be sent to clients.
+    /// * `Err(WorldError)` - If a chunk couldn't be loaded or saved.
+    pub fn relight(
+        &self,
+        dimension: &str,
+        positions: &[(i32, i32, i32)],
+    ) -> Result<Vec<(i32, i32, i8)>, WorldError> {
+        if positions.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut coords = AHashSet::new();
+        for &(x, _, z) in positions {
+            for dx in -1..=1 {
+                for dz in -1..=1 {
+                    coords.insert(((x >> 4) + dx, (z >> 4) + dz));
+                }
+            }
+        }
+        // Light spills across chunk borders, so all the chunks are edited together
+        self.transaction(dimension, coords, |transaction| {
+            let changed_sections = {
+                let mut region = LightRegion::default();
+                for chunk in transaction.chunks_mut() {
+                    region.insert(chunk);
+                }
+                region.relight(positions);
+                region.changed_sections
+            };
+
+            let changed_chunks: AHashSet<(i32, i32)> = changed_sections
+                .iter()
+                .map(|(chunk_x, chunk_z, _)| (*chunk_x, *chunk_z))
+                .collect();
+            let unchanged: Vec<_> = transaction
+                .chunk_coords()
+                .filter(|coords| !changed_chunks.contains(coords))
+                .collect();
+            for (chunk_x, chunk_z) in unchanged {
+                transaction.discard(chunk_x, chunk_z);
+            }
+
+            let mut changed_sections: Vec<_> = changed_sections.into_iter().collect();
+            changed_sections.sort();
+            Ok(changed_sections)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vanilla_chunk_format::BlockData;
+
+    fn block(name: &str) -> BlockId {
+        BlockData {
+            name: name.to_string(),
+            properties: None,
+        }
+        .to_block_id()
+    }
+
+    fn light_at(chunk: &Chunk, light_type: LightType, x: i32, y: i32, z: i32) -> u8 {
+        chunk
+            .sections
+            .iter()
+            .find(|s| s.y == (y >> 4) as i8)
+            .unwrap()
+            .get_light(light_type, x, y, z)
+    }
+
+    #[test]
+    fn test_open_sky_is_fully_lit() {
+        let mut chunk = Chunk::new(0, 0, "overworld".to_string());
+        chunk.compute_light();
+        assert_eq!(light_at(&chunk, LightType::Sky, 3, -64, 7), MAX_LIGHT);
+        assert_eq!(light_at(&chunk, LightType::Block, 3, -64, 7), 0);
+    }
+
+    #[test]
+    fn test_sky_light_blocked_by_roof() {
+        let mut chunk = Chunk::new(0, 0, "overworld".to_string());
+        chunk
+            .set_section(
+                4,
+                BlockData {
+                    name: "minecraft:stone".to_string(),
+                    properties: None,
+                },
+            )
+            .unwrap();
+        chunk.compute_light();
+        assert_eq!(light_at(&chunk, LightType::Sky, 8, 80, 8), MAX_LIGHT);
+        assert_eq!(light_at(&chunk, LightType::Sky, 8, 60, 8), 0);
+    }
+
+    #[test]
+    fn test_block_light_falls_off() {
+        let mut chunk = Chunk::new(0, 0, "overworld".to_string());
+        chunk
+            .set_block(8, 10, 8, block("minecraft:glowstone"))
+            .unwrap();
+        chunk.compute_light();
+        assert_eq!(light_at(&chunk, LightType::Block, 8, 10, 8), 15);
+        assert_eq!(light_at(&chunk, LightType::Block, 9, 10, 8), 14);
+        assert_eq!(light_at(&chunk, LightType::Block, 11, 12, 8), 10);
+    }
+
+    #[test]
+    fn test_incremental_relight_matches_full_recompute() {
+        let mut chunk = Chunk::new(0, 0, "overworld".to_string());
+        chunk
+            .set_section(
+                4,
+                BlockData {
+                    name: "minecraft:stone".to_string(),
+                    properties: None,
+                },
+            )
+            .unwrap();
+        chunk.compute_light();
+
+        // Punch a hole in the roof and put a torch under it
+        chunk.set_block(4, 70, 4, BlockData::default()).unwrap();
+        chunk
+            .set_block(10, 40, 10, block("minecraft:torch"))
+            .unwrap();
+        {
+            let mut region = LightRegion::default();
+            region.insert(&mut chunk);
+            region.relight(&[(4, 70, 4), (10, 40, 10)]);
+        }
+
+        let mut expected = chunk.clone();
+        expected.compute_light();
+        assert_eq!(chunk, expected);
+
+        // Then fill the hole back in and remove the torch
+        chunk.set_block(4, 70, 4, block("minecraft:stone")).unwrap();
+        chunk.set_block(10, 40, 10, BlockData::default()).unwrap();
+        {
+            let mut region = LightRegion::default();
+            region.insert(&mut chunk);
+            region.relight(&[(4, 70, 4), (10, 40, 10)]);
+        }
+        let mut expected = chunk.clone();
+        expected.compute_light();
+        assert_eq!(chunk, expected);
+    }
+
+    #[test]
+    fn test_light_crosses_chunk_borders() {
+        let mut left = Chunk::new(0, 0, "overworld".to_string());
+        let mut right = Chunk::new(1, 0, "overworld".to_string());
+        for chunk in [&mut left, &mut right] {
+            chunk
+                .set_section(
+                    4,
+                    BlockData {
+                        name: "minecraft:stone".to_string(),
+                        properties: None,
+                    },
+                )
+                .unwrap();
+            chunk.compute_light();
+        }
+        left.set_block(15, 20, 8, block("minecraft:glowstone"))
+            .unwrap();
+        let mut region = LightRegion::default();
+        region.insert(&mut left);
+        region.insert(&mut right);
+        region.relight(&[(15, 20, 8)]);
+        assert!(region.changed_sections.contains(&(1, 0, 1)));
+        assert_eq!(region.light(LightType::Block, 16, 20, 8), Some(14));
+        assert_eq!(region.light(LightType::Block, 20, 20, 8), Some(10));
+    }
+}
@@ -0,0 +1,235 @@
+//! Everything about a world that isn't chunks: the seed, spawn point, time and game rules.
+//!
+//! The metadata is stored as JSON so fields can be added later without a migration; anything
+//! missing from an older world just gets its default.
+
+use crate::errors::WorldError;
+use crate::World;
+use ferrumc_storage::backend::StorageBackend;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// The table the world metadata is stored in. There's only ever one entry.
+const METADATA_TABLE: &str = "metadata";
+const METADATA_KEY: u128 = 0;
+
+/// How long a Minecraft day is, in ticks.
+pub const DAY_LENGTH: i64 = 24000;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Difficulty {
+    Peaceful,
+    #[default]
+    Easy,
+    Normal,
+    Hard,
+}
+
+/// Where players spawn when they first join or respawn without a bed.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SpawnPosition {
+    pub x: i32,
+    pub y: i32,
+    pub z: i32,
+    pub angle: f32,
+}
+
+impl Default for SpawnPosition {
+    fn default() -> Self {
+        Self {
+            x: 0,
+            y: 100,
+            z: 0,
+            angle: 0.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum GameRuleValue {
+    Bool(bool),
+    Int(i32),
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WorldMetadata {
+    pub seed: u64,
+    pub spawn: SpawnPosition,
+    /// How many ticks the world has been running for.
+    pub world_age: i64,
+    /// The time of day in ticks. This keeps counting up past [`DAY_LENGTH`] like vanilla's, so
+    /// it also says how many days have passed.
+    pub day_time: i64,
+    pub difficulty: Difficulty,
+    pub hardcore: bool,
+    /// Game rules by their vanilla names, e.g. `doDaylightCycle`.
+    pub game_rules: BTreeMap<String, GameRuleValue>,
+}
+
+impl Default for WorldMetadata {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            spawn: SpawnPosition::default(),
+            world_age: 0,
+            day_time: 0,
+            difficulty: Difficulty::default(),
+            hardcore: false,
+            game_rules: default_game_rules(),
+        }
+    }
+}
+
+fn default_game_rules() -> BTreeMap<String, GameRuleValue> {
+    use GameRuleValue::{Bool, Int};
+    [
+        ("doDaylightCycle", Bool(true)),
+        ("doWeatherCycle", Bool(true)),
+        ("doMobSpawning", Bool(true)),
+        ("keepInventory", Bool(false)),
+        ("naturalRegeneration", Bool(true)),
+        ("playersSleepingPercentage", Int(100)),
+        ("randomTickSpeed", Int(3)),
+        ("spawnRadius", Int(10)),
+    ]
+    .into_iter()
+    .map(|(name, value)| (name.to_string(), value))
+    .collect()
+}
+
+impl WorldMetadata {
+    /// Creates the metadata for a brand new world.
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            ..Default::default()
+        }
+    }
+
+    /// Gets a boolean game rule, or `None` if it isn't set or isn't a boolean.
+    pub fn bool_rule(&self, name: &str) -> Option<bool> {
+        match self.game_rules.get(name) {
+            Some(GameRuleValue::Bool(value)) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// Gets an integer game rule, or `None` if it isn't set or isn't an integer.
+    pub fn int_rule(&self, name: &str) -> Option<i32> {
+        match self.game_rules.get(name) {
+            Some(GameRuleValue::Int(value)) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// Moves the world on by one tick.
+    pub fn tick(&mut self) {
+        self.world_age += 1;
+        if self.bool_rule("doDaylightCycle").unwrap_or(true) {
+            self.day_time += 1;
+        }
+    }
+}
+
+/// Creates the metadata table if the world doesn't have one yet, so the metadata can be saved.
+pub(crate) fn create_metadata_table(
+    storage_backend: &dyn StorageBackend,
+) -> Result<(), WorldError> {
+    if !storage_backend.table_exists(METADATA_TABLE.to_string())? {
+        storage_backend.create_table(METADATA_TABLE.to_string())?;
+    }
+    Ok(())
+}
+
+/// Turns a seed as typed into the config into a numeric seed, the same way vanilla does.
+///
+/// Numbers are used as they are, and anything else is hashed with Java's `String.hashCode`.
+/// Returns `None` for an empty seed, in which case a random one should be picked.
+pub fn parse_seed(seed: &str) -> Option<u64> {
+    let seed = seed.trim();
+    if seed.is_empty() {
+        return None;
+    }
+    if let Ok(seed) = seed.parse::<i64>() {
+        return Some(seed as u64);
+    }
+    let hash = seed
+        .encode_utf16()
+        .fold(0i32, |hash, c| hash.wrapping_mul(31).wrapping_add(c as i32));
+    Some(hash as i64 as u64)
+}
+
+impl World {
+    /// Loads the world metadata, or `None` if the world doesn't have any yet.
+    pub fn load_metadata(&self) -> Result<Option<WorldMetadata>, WorldError> {
+        if !self
+            .storage_backend
+            .table_exists(METADATA_TABLE.to_string())?
+        {
+            return Ok(None);
+        }
+        let Some(data) = self
+            .storage_backend
+            .get(METADATA_TABLE.to_string(), METADATA_KEY)?
+        else {
+            return Ok(None);
+        };
+        serde_json::from_slice(&data)
+            .map(Some)
+            .map_err(|e| WorldError::GenericIOError(format!("Invalid world metadata: {e}")))
+    }
+
+    /// Writes the world metadata to the storage backend.
+    pub fn save_metadata(&self, metadata: &WorldMetadata) -> Result<(), WorldError> {
+        let data =
+            serde_json::to_vec(metadata).map_err(|e| WorldError::GenericIOError(e.to_string()))?;
+        self.storage_backend
+            .upsert(METADATA_TABLE.to_string(), METADATA_KEY, data)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_seed() {
+        assert_eq!(parse_seed(""), None);
+        assert_eq!(parse_seed("  "), None);
+        assert_eq!(parse_seed("12345"), Some(12345));
+        assert_eq!(parse_seed("-1"), Some(u64::MAX));
+        // "hello".hashCode() in Java
+        assert_eq!(parse_seed("hello"), Some(99162322));
+        // Negative hashes are sign extended, like vanilla's (long) cast
+        assert_eq!(
+            parse_seed("polygenelubricants"),
+            Some(i32::MIN as i64 as u64)
+        );
+    }
+
+    #[test]
+    fn test_missing_fields_get_defaults() {
+        let metadata: WorldMetadata =
+            serde_json::from_str(r#"{"seed": 42, "hardcore": true}"#).unwrap();
+        assert_eq!(metadata.seed, 42);
+        assert!(metadata.hardcore);
+        assert_eq!(metadata.spawn, SpawnPosition::default());
+        assert_eq!(metadata.bool_rule("doDaylightCycle"), Some(true));
+    }
+
+    #[test]
+    fn test_tick() {
+        let mut metadata = WorldMetadata::new(0);
+        metadata.tick();
+        assert_eq!((metadata.world_age, metadata.day_time), (1, 1));
+        metadata
+            .game_rules
+            .insert("doDaylightCycle".to_string(), GameRuleValue::Bool(false));
+        metadata.tick();
+        assert_eq!((metadata.world_age, metadata.day_time), (2, 1));
+    }
+}
@@ -0,0 +1,299 @@
+//! Versioning and migrations for the on-disk chunk format.
+//!
+//! Every stored chunk starts with the version of the format it was encoded with, followed by the
+//! bitcode encoded chunk. Chunks saved before versions existed are version 0.
+//!
+//! When [`Chunk`] (or anything inside it) changes in a way that changes its bitcode encoding:
+//! 1. Copy the old definitions into a new `vN` module below, keeping only what's needed to
+//!    decode them.
+//! 2. Add a migration to [`MIGRATIONS`] that decodes the old version and encodes the new one.
+//! 3. Bump [`CHUNK_FORMAT_VERSION`].
+//!
+//! Old chunks are then upgraded when they're loaded, or all at once with [`World::migrate`].
+//!
+//! A migration can also fix up data whose meaning changed without the encoding changing, in
+//! which case it decodes the current [`Chunk`] directly.
+
+use crate::chunk_format::{Chunk, Heightmaps, Section};
+use crate::db_functions::{rewrite_stored_chunks, StoredChunk, REWRITE_BATCH_SIZE};
+use crate::errors::WorldError;
+use crate::spatial::{chunk_key, CHUNK_TABLE, LEGACY_CHUNK_TABLE};
+use crate::World;
+use bitcode_derive::Decode;
+use tracing::{info, warn};
+
+/// The version of the chunk format this server writes.
+pub const CHUNK_FORMAT_VERSION: u32 = 2;
+
+/// Upgrades an encoded chunk from one version to the next.
+type Migration = fn(&[u8]) -> Result<Vec<u8>, WorldError>;
+
+/// The migration at index `n` upgrades chunks from version `n` to version `n + 1`.
+const MIGRATIONS: [Migration; CHUNK_FORMAT_VERSION as usize] = [v0::migrate, v1::migrate];
+
+/// Encodes a chunk with the current version header.
+pub(crate) fn encode_chunk(chunk: &Chunk) -> Vec<u8> {
+    let mut data = CHUNK_FORMAT_VERSION.to_le_bytes().to_vec();
+    data.extend_from_slice(&bitcode::encode(chunk));
+    data
+}
+
+/// Splits stored chunk data into its format version and the encoded chunk.
+pub(crate) fn split_version(data: &[u8]) -> Result<(u32, &[u8]), WorldError> {
+    if data.len() < 4 {
+        return Err(WorldError::BitcodeDecodeError(
+            "Chunk data is missing its format version".to_string(),
+        ));
+    }
+    let (version, data) = data.split_at(4);
+    Ok((u32::from_le_bytes(version.try_into().unwrap()), data))
+}
+
+/// Decodes a chunk encoded with the given format version, migrating it if it's out of date.
+pub(crate) fn decode_chunk(version: u32, data: &[u8]) -> Result<Chunk, WorldError> {
+    if version > CHUNK_FORMAT_VERSION {
+        return Err(WorldError::UnsupportedChunkVersion(version));
+    }
+    let migrated;
+    let mut data = data;
+    if version < CHUNK_FORMAT_VERSION {
+        migrated = migrate_encoded(version, data)?;
+        data = &migrated;
+    }
+    bitcode::decode(data).map_err(|e| WorldError::BitcodeDecodeError(e.to_string()))
+}
+
+/// Runs every migration needed to bring encoded chunk data up to the current version.
+fn migrate_encoded(version: u32, data: &[u8]) -> Result<Vec<u8>, WorldError> {
+    let mut data = data.to_vec();
+    for migration in &MIGRATIONS[version as usize..] {
+        data = migration(&data)?;
+    }
+    Ok(data)
+}
+
+fn decode_error(version: u32, error: bitcode::Error) -> WorldError {
+    WorldError::BitcodeDecodeError(format!("Chunk format version {version}: {error}"))
+}
+
+/// The original format, which only stored the heightmaps that were sent to the client.
+mod v0 {
+    use super::*;
+
+    #[derive(Decode)]
+    struct Chunk {
+        x: i32,
+        z: i32,
+        dimension: String,
+        sections: Vec<Section>,
+        #[allow(dead_code)]
+        heightmaps: Heightmaps,
+    }
+
+    #[derive(Decode)]
+    struct Heightmaps {
+        #[allow(dead_code)]
+        motion_blocking: Vec<i64>,
+        #[allow(dead_code)]
+        world_surface: Vec<i64>,
+    }
+
+    pub(super) fn migrate(data: &[u8]) -> Result<Vec<u8>, WorldError> {
+        let old: Chunk = bitcode::decode(data).map_err(|e| decode_error(0, e))?;
+        let mut chunk = super::Chunk {
+            x: old.x,
+            z: old.z,
+            dimension: old.dimension,
+            sections: old.sections,
+            heightmaps: super::Heightmaps::new(),
+        };
+        // Rather than trusting the two old heightmaps, work all of them out from the blocks
+        chunk.compute_heightmaps();
+        Ok(bitcode::encode(&chunk))
+    }
+}
+
+/// Biomes weren't handled yet, so every section has a placeholder biome palette that doesn't
+/// point at a real biome.
+mod v1 {
+    use super::*;
+    use crate::biomes::BiomeId;
+    use crate::chunk_format::BiomeStates;
+
+    pub(super) fn migrate(data: &[u8]) -> Result<Vec<u8>, WorldError> {
+        let mut chunk: Chunk = bitcode::decode(data).map_err(|e| decode_error(1, e))?;
+        for section in &mut chunk.sections {
+            section.biome_states = BiomeStates::single(BiomeId::default());
+        }
+        Ok(bitcode::encode(&chunk))
+    }
+}
+
+impl World {
+    /// Upgrades every stored chunk to the current chunk format.
+    ///
+    /// Chunks are also upgraded as they're loaded, so this is only needed to avoid paying for
+    /// the migration at runtime. Returns the number of chunks that were upgraded.
+    pub fn migrate(&self) -> Result<u64, WorldError> {
+        let migrated = rewrite_stored_chunks(self, "Migrating chunks...", |stored| {
+            let StoredChunk { version, data } = stored;
+            if version == CHUNK_FORMAT_VERSION {
+                return Ok(None);
+            }
+            let chunk = decode_chunk(version, &data)?;
+            Ok(Some(encode_chunk(&chunk)))
+        })?;
+        info!(
+            "Migrated {} chunks to chunk format version {}",
+            migrated, CHUNK_FORMAT_VERSION
+        );
+        Ok(migrated)
+    }
+
+    /// Moves chunks out of the table they were kept in before chunks had spatial keys.
+    ///
+    /// The old keys were a hash of the dimension, so each chunk has to be decoded to find out
+    /// where it belongs. This only does anything the first time a world is opened after
+    /// upgrading.
+    pub(crate) fn migrate_legacy_chunk_keys(&self) -> Result<u64, WorldError> {
+        if !self
+            .storage_backend
+            .table_exists(LEGACY_CHUNK_TABLE.to_string())?
+        {
+            return Ok(0);
+        }
+        let keys = self.storage_backend.keys(LEGACY_CHUNK_TABLE.to_string())?;
+        if keys.is_empty() {
+            return Ok(0);
+        }
+        warn!(
+            "Moving {} chunks to spatial keys, this only happens once...",
+            keys.len()
+        );
+
+        let mut moved_count = 0;
+        for batch in keys.chunks(REWRITE_BATCH_SIZE) {
+            let values = self
+                .storage_backend
+                .batch_get(LEGACY_CHUNK_TABLE.to_string(), batch.to_vec())?;
+            let mut moved = Vec::with_capacity(batch.len());
+            for compressed in values.into_iter().flatten() {
+                // The compressed data is copied over as-is, the format is upgraded on load
+                let stored = StoredChunk::read(self, &compressed)?;
+                let chunk = decode_chunk(stored.version, &stored.data)?;
+                let dimension_id = self
+                    .dimensions
+                    .get_or_register(self.storage_backend.as_ref(), &chunk.dimension)?;
+                moved.push((chunk_key(dimension_id, chunk.x, chunk.z), compressed));
+            }
+            moved_count += moved.len() as u64;
+            self.storage_backend
+                .batch_upsert(CHUNK_TABLE.to_string(), moved)?;
+            for key in batch {
+                self.storage_backend
+                    .delete(LEGACY_CHUNK_TABLE.to_string(), *key)?;
+            }
+        }
+        self.storage_backend.flush()?;
+        info!("Moved {} chunks to spatial keys", moved_count);
+        Ok(moved_count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::biomes::BiomeId;
+    use crate::block_id::BlockId;
+    use crate::chunk_format::{BiomeStates, BlockStates, PaletteType};
+    use crate::heightmaps::HeightmapType;
+    use bitcode_derive::Encode;
+    use ferrumc_net_codec::net_types::var_int::VarInt;
+    use std::collections::HashMap;
+
+    #[derive(Encode)]
+    struct ChunkV0 {
+        x: i32,
+        z: i32,
+        dimension: String,
+        sections: Vec<Section>,
+        heightmaps: HeightmapsV0,
+    }
+
+    #[derive(Encode)]
+    struct HeightmapsV0 {
+        motion_blocking: Vec<i64>,
+        world_surface: Vec<i64>,
+    }
+
+    fn stone_section(y: i8) -> Section {
+        Section {
+            y,
+            block_states: BlockStates {
+                non_air_blocks: 4096,
+                block_data: PaletteType::Single(VarInt::from(1)),
+                block_counts: HashMap::from([(BlockId(1), 4096)]),
+            },
+            biome_states: BiomeStates {
+                bits_per_biome: 0,
+                data: vec![],
+                palette: vec![VarInt::from(0)],
+            },
+            block_light: vec![0; 2048],
+            sky_light: vec![0; 2048],
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let chunk = Chunk::new(3, -7, "overworld".to_string());
+        let encoded = encode_chunk(&chunk);
+        let (version, data) = split_version(&encoded).unwrap();
+        assert_eq!(version, CHUNK_FORMAT_VERSION);
+        assert_eq!(decode_chunk(version, data).unwrap(), chunk);
+    }
+
+    #[test]
+    fn test_migrate_v0() {
+        let old = ChunkV0 {
+            x: 1,
+            z: 2,
+            dimension: "overworld".to_string(),
+            sections: vec![stone_section(-4), stone_section(-3)],
+            heightmaps: HeightmapsV0 {
+                motion_blocking: vec![],
+                world_surface: vec![],
+            },
+        };
+        let chunk = decode_chunk(0, &bitcode::encode(&old)).unwrap();
+        assert_eq!((chunk.x, chunk.z), (1, 2));
+        assert_eq!(chunk.sections.len(), 2);
+        assert!(!chunk.heightmaps.is_incomplete());
+        // The top of the stone is at y = -32
+        assert_eq!(chunk.get_height(HeightmapType::OceanFloor, 0, 0), Some(-32));
+    }
+
+    #[test]
+    fn test_migrate_v1_biomes() {
+        let mut chunk = Chunk::new(0, 0, "overworld".to_string());
+        // What imported chunks used to store
+        chunk.sections[0].biome_states = BiomeStates {
+            bits_per_biome: 4,
+            data: vec![],
+            palette: vec![VarInt::from(0)],
+        };
+        let chunk = decode_chunk(1, &bitcode::encode(&chunk)).unwrap();
+        assert_eq!(chunk.get_biome(0, -64, 0).unwrap(), BiomeId::default());
+        assert_eq!(chunk.sections[0].biome_states.bits_per_biome, 0);
+    }
+
+    #[test]
+    fn test_newer_version_is_rejected() {
+        let chunk = Chunk::new(0, 0, "overworld".to_string());
+        let data = bitcode::encode(&chunk);
+        assert!(matches!(
+            decode_chunk(CHUNK_FORMAT_VERSION + 1, &data),
+            Err(WorldError::UnsupportedChunkVersion(_))
+        ));
+    }
+}
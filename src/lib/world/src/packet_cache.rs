@@ -0,0 +1,87 @@
+//! Chunk packets that have already been encoded, so a chunk sent to lots of players is only
+//! encoded and compressed once.
+//!
+//! The world doesn't know how packets are encoded, it only keeps the bytes. Each packet remembers
+//! the revision of the chunk it was made from (see [`World::chunk_revision`]), so a packet for a
+//! chunk that's been saved since is never handed out. Saving or deleting a chunk also drops its
+//! packets straight away, so they don't take up space until they're evicted.
+
+use crate::World;
+use ferrumc_config::server_config::get_global_config;
+use moka::sync::Cache;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// A chunk's coordinates and dimension, and whether the packet is compressed. Connections that
+/// haven't turned on compression need the uncompressed packet.
+pub(crate) type PacketCacheKey = (i32, i32, String, bool);
+
+#[derive(Clone)]
+pub(crate) struct CachedPacket {
+    revision: u64,
+    data: Arc<Vec<u8>>,
+}
+
+/// Creates the packet cache. Its entries expire like the chunk cache's, but it has its own
+/// capacity, so packets don't push chunks out of memory.
+pub(crate) fn new_packet_cache() -> Cache<PacketCacheKey, CachedPacket> {
+    let config = &get_global_config().database;
+    Cache::builder()
+        .weigher(packet_weight)
+        .time_to_live(Duration::from_secs(config.cache_ttl))
+        .max_capacity(config.packet_cache_capacity * 1024)
+        .build()
+}
+
+/// How much of the packet cache's capacity a packet takes up, in bytes. The key is counted too,
+/// since the dimension is its own allocation.
+fn packet_weight(key: &PacketCacheKey, packet: &CachedPacket) -> u32 {
+    let size = size_of::<PacketCacheKey>()
+        + key.2.len()
+        + size_of::<CachedPacket>()
+        + size_of::<Vec<u8>>()
+        + packet.data.capacity();
+    size.try_into().unwrap_or(u32::MAX)
+}
+
+impl World {
+    /// Gets the encoded packet for a chunk, calling `encode` to make it if there isn't one for the
+    /// chunk's current revision yet.
+    ///
+    /// `encode` should load the chunk itself, so the packet is never older than the revision it's
+    /// stored under. Packets that hide blocks based on the chunks around them, like anti-xray does,
+    /// aren't remade when only those chunks change.
+    pub fn cached_chunk_packet<E>(
+        &self,
+        x: i32,
+        z: i32,
+        dimension: &str,
+        compressed: bool,
+        encode: impl FnOnce() -> Result<Vec<u8>, E>,
+    ) -> Result<Arc<Vec<u8>>, E> {
+        let key = (x, z, dimension.to_string(), compressed);
+        let revision = self.chunk_revision(x, z, dimension);
+        if let Some(packet) = self.packet_cache.get(&key) {
+            if packet.revision == revision {
+                return Ok(packet.data);
+            }
+        }
+        let data = Arc::new(encode()?);
+        self.packet_cache.insert(
+            key,
+            CachedPacket {
+                revision,
+                data: data.clone(),
+            },
+        );
+        Ok(data)
+    }
+
+    /// Drops the encoded packets for a chunk, after it's changed.
+    pub(crate) fn invalidate_chunk_packets(&self, x: i32, z: i32, dimension: &str) {
+        for compressed in [false, true] {
+            self.packet_cache
+                .invalidate(&(x, z, dimension.to_string(), compressed));
+        }
+    }
+}
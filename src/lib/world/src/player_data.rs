@@ -0,0 +1,102 @@
+//! Saved player state: where players are, what they're holding and their game mode.
+//!
+//! Like the world metadata, this is stored as JSON so new fields can be added without a
+//! migration.
+
+use crate::errors::WorldError;
+use crate::World;
+use ferrumc_storage::backend::StorageBackend;
+use serde_derive::{Deserialize, Serialize};
+
+/// The table player data is stored in, keyed by player UUID.
+const PLAYER_TABLE: &str = "players";
+
+/// An item in a player's inventory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SavedItem {
+    /// The inventory slot the item is in, using the player inventory window's slot numbers.
+    pub slot: u16,
+    /// The item's protocol ID.
+    pub item_id: i32,
+    pub count: i32,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PlayerData {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+    pub dimension: String,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub inventory: Vec<SavedItem>,
+    /// The selected hotbar slot, from 0 to 8.
+    pub selected_slot: u8,
+    /// The game mode ID, as sent to the client.
+    pub gamemode: u8,
+}
+
+impl Default for PlayerData {
+    fn default() -> Self {
+        Self {
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+            dimension: "overworld".to_string(),
+            yaw: 0.0,
+            pitch: 0.0,
+            inventory: Vec::new(),
+            selected_slot: 0,
+            gamemode: 0,
+        }
+    }
+}
+
+/// Creates the player data table if the world doesn't have one yet, so players can be saved.
+pub(crate) fn create_player_table(storage_backend: &dyn StorageBackend) -> Result<(), WorldError> {
+    if !storage_backend.table_exists(PLAYER_TABLE.to_string())? {
+        storage_backend.create_table(PLAYER_TABLE.to_string())?;
+    }
+    Ok(())
+}
+
+impl World {
+    /// Loads a player's saved data, or `None` if they've never played in this world.
+    pub fn load_player_data(&self, uuid: u128) -> Result<Option<PlayerData>, WorldError> {
+        if !self
+            .storage_backend
+            .table_exists(PLAYER_TABLE.to_string())?
+        {
+            return Ok(None);
+        }
+        let Some(data) = self.storage_backend.get(PLAYER_TABLE.to_string(), uuid)? else {
+            return Ok(None);
+        };
+        serde_json::from_slice(&data).map(Some).map_err(|e| {
+            WorldError::GenericIOError(format!("Invalid player data for {uuid:032x}: {e}"))
+        })
+    }
+
+    /// Writes a player's data to the storage backend.
+    pub fn save_player_data(&self, uuid: u128, data: &PlayerData) -> Result<(), WorldError> {
+        let data =
+            serde_json::to_vec(data).map_err(|e| WorldError::GenericIOError(e.to_string()))?;
+        self.storage_backend
+            .upsert(PLAYER_TABLE.to_string(), uuid, data)?;
+        Ok(())
+    }
+
+    /// Writes several players' data at once.
+    pub fn save_player_data_batch(&self, players: &[(u128, PlayerData)]) -> Result<(), WorldError> {
+        let mut batch = Vec::with_capacity(players.len());
+        for (uuid, data) in players {
+            let data =
+                serde_json::to_vec(data).map_err(|e| WorldError::GenericIOError(e.to_string()))?;
+            batch.push((*uuid, data));
+        }
+        self.storage_backend
+            .batch_upsert(PLAYER_TABLE.to_string(), batch)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,317 @@
+//! Protected regions, which stop players from changing blocks in areas they don't own.
+//!
+//! Each region is a cuboid in one dimension with a unique name, a set of owners who can manage
+//! it, members who can do anything in it, and flags saying what everyone else can do. Where
+//! regions overlap, a player has to be allowed by all of them.
+//!
+//! Like the world metadata, every region is stored together as JSON under a single key. There are
+//! rarely more than a few hundred, so they're kept in memory and written back on every change.
+
+use crate::errors::WorldError;
+use crate::regions::Region;
+use crate::World;
+use ferrumc_storage::backend::StorageBackend;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::RwLock;
+
+/// The table protected regions are stored in. There's only ever one entry.
+const PROTECTION_TABLE: &str = "protected_regions";
+const PROTECTION_KEY: u128 = 0;
+
+/// Things a player can do to the blocks in a region.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ProtectionAction {
+    /// Placing blocks.
+    Build,
+    /// Breaking blocks.
+    Break,
+    /// Using blocks without changing them, like opening doors. The server doesn't handle using
+    /// blocks yet, so nothing checks this so far.
+    Interact,
+}
+
+impl ProtectionAction {
+    /// The action a flag name refers to, as used by commands.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "build" => Some(ProtectionAction::Build),
+            "break" => Some(ProtectionAction::Break),
+            "interact" => Some(ProtectionAction::Interact),
+            _ => None,
+        }
+    }
+}
+
+/// What players who aren't owners or members can do in a region. Everything is denied by default.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProtectionFlags {
+    pub build: bool,
+    #[serde(rename = "break")]
+    pub break_blocks: bool,
+    pub interact: bool,
+}
+
+impl ProtectionFlags {
+    pub fn allows(&self, action: ProtectionAction) -> bool {
+        match action {
+            ProtectionAction::Build => self.build,
+            ProtectionAction::Break => self.break_blocks,
+            ProtectionAction::Interact => self.interact,
+        }
+    }
+
+    pub fn set(&mut self, action: ProtectionAction, allowed: bool) {
+        match action {
+            ProtectionAction::Build => self.build = allowed,
+            ProtectionAction::Break => self.break_blocks = allowed,
+            ProtectionAction::Interact => self.interact = allowed,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProtectedRegion {
+    pub name: String,
+    pub dimension: String,
+    pub area: Region,
+    /// Players who can do anything in the region, including changing who else can.
+    pub owners: BTreeSet<u128>,
+    /// Players who can do anything in the region, but can't manage it.
+    pub members: BTreeSet<u128>,
+    #[serde(default)]
+    pub flags: ProtectionFlags,
+}
+
+impl ProtectedRegion {
+    /// Creates a region that only its owner can change.
+    pub fn new(
+        name: impl Into<String>,
+        dimension: impl Into<String>,
+        area: Region,
+        owner: u128,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            dimension: dimension.into(),
+            area,
+            owners: BTreeSet::from([owner]),
+            members: BTreeSet::new(),
+            flags: ProtectionFlags::default(),
+        }
+    }
+
+    pub fn is_owner(&self, player: u128) -> bool {
+        self.owners.contains(&player)
+    }
+
+    pub fn is_member(&self, player: u128) -> bool {
+        self.owners.contains(&player) || self.members.contains(&player)
+    }
+
+    pub fn contains(&self, dimension: &str, x: i32, y: i32, z: i32) -> bool {
+        self.dimension == dimension && self.area.contains(x, y, z)
+    }
+
+    /// Whether a player can do something in the region.
+    pub fn allows(&self, player: u128, action: ProtectionAction) -> bool {
+        self.is_member(player) || self.flags.allows(action)
+    }
+}
+
+/// Every protected region, by name.
+pub(crate) struct ProtectedRegions {
+    regions: RwLock<BTreeMap<String, ProtectedRegion>>,
+}
+
+impl ProtectedRegions {
+    pub(crate) fn load(storage_backend: &dyn StorageBackend) -> Result<Self, WorldError> {
+        let mut regions = BTreeMap::new();
+        if storage_backend.table_exists(PROTECTION_TABLE.to_string())? {
+            if let Some(data) = storage_backend.get(PROTECTION_TABLE.to_string(), PROTECTION_KEY)? {
+                let stored: Vec<ProtectedRegion> = serde_json::from_slice(&data).map_err(|e| {
+                    WorldError::GenericIOError(format!("Invalid protected regions: {e}"))
+                })?;
+                regions.extend(
+                    stored
+                        .into_iter()
+                        .map(|region| (region.name.clone(), region)),
+                );
+            }
+        } else {
+            storage_backend.create_table(PROTECTION_TABLE.to_string())?;
+        }
+        Ok(Self {
+            regions: RwLock::new(regions),
+        })
+    }
+
+    fn save(
+        storage_backend: &dyn StorageBackend,
+        regions: &BTreeMap<String, ProtectedRegion>,
+    ) -> Result<(), WorldError> {
+        let regions: Vec<&ProtectedRegion> = regions.values().collect();
+        let data =
+            serde_json::to_vec(&regions).map_err(|e| WorldError::GenericIOError(e.to_string()))?;
+        storage_backend.upsert(PROTECTION_TABLE.to_string(), PROTECTION_KEY, data)?;
+        Ok(())
+    }
+}
+
+impl World {
+    /// Changes the protected regions and saves them. If `change` fails or the regions can't be
+    /// saved, they're left as they were.
+    fn modify_protected_regions<R>(
+        &self,
+        change: impl FnOnce(&mut BTreeMap<String, ProtectedRegion>) -> Result<R, WorldError>,
+    ) -> Result<R, WorldError> {
+        let mut regions = self.protection.regions.write().unwrap();
+        let mut changed = regions.clone();
+        let result = change(&mut changed)?;
+        ProtectedRegions::save(self.storage_backend.as_ref(), &changed)?;
+        *regions = changed;
+        Ok(result)
+    }
+
+    /// Adds a protected region, failing if there's already one with the same name.
+    pub fn add_protected_region(&self, region: ProtectedRegion) -> Result<(), WorldError> {
+        self.modify_protected_regions(|regions| {
+            if regions.contains_key(&region.name) {
+                return Err(WorldError::InvalidProtectedRegion(format!(
+                    "A region named {} already exists",
+                    region.name
+                )));
+            }
+            regions.insert(region.name.clone(), region);
+            Ok(())
+        })
+    }
+
+    /// Removes a protected region, returning it if it existed.
+    pub fn remove_protected_region(
+        &self,
+        name: &str,
+    ) -> Result<Option<ProtectedRegion>, WorldError> {
+        if !self.protection.regions.read().unwrap().contains_key(name) {
+            return Ok(None);
+        }
+        self.modify_protected_regions(|regions| Ok(regions.remove(name)))
+    }
+
+    /// Changes a protected region, failing if it doesn't exist. The region's name can't be
+    /// changed this way.
+    pub fn update_protected_region<R>(
+        &self,
+        name: &str,
+        update: impl FnOnce(&mut ProtectedRegion) -> R,
+    ) -> Result<R, WorldError> {
+        self.modify_protected_regions(|regions| {
+            let region = regions.get_mut(name).ok_or_else(|| {
+                WorldError::InvalidProtectedRegion(format!("There's no region named {name}"))
+            })?;
+            let result = update(region);
+            region.name = name.to_string();
+            Ok(result)
+        })
+    }
+
+    pub fn protected_region(&self, name: &str) -> Option<ProtectedRegion> {
+        self.protection.regions.read().unwrap().get(name).cloned()
+    }
+
+    /// Every protected region, sorted by name.
+    pub fn protected_regions(&self) -> Vec<ProtectedRegion> {
+        self.protection
+            .regions
+            .read()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    /// The protected regions that include a block.
+    pub fn protected_regions_at(
+        &self,
+        dimension: &str,
+        x: i32,
+        y: i32,
+        z: i32,
+    ) -> Vec<ProtectedRegion> {
+        self.protection
+            .regions
+            .read()
+            .unwrap()
+            .values()
+            .filter(|region| region.contains(dimension, x, y, z))
+            .cloned()
+            .collect()
+    }
+
+    /// Whether a player can do something to a block. Blocks outside every protected region can
+    /// be changed by anyone.
+    pub fn can_modify_block(
+        &self,
+        player: u128,
+        dimension: &str,
+        (x, y, z): (i32, i32, i32),
+        action: ProtectionAction,
+    ) -> bool {
+        self.protection
+            .regions
+            .read()
+            .unwrap()
+            .values()
+            .filter(|region| region.contains(dimension, x, y, z))
+            .all(|region| region.allows(player, action))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_region_permissions() {
+        let mut region = ProtectedRegion::new(
+            "spawn",
+            "overworld",
+            Region::new((-10, 0, -10), (10, 100, 10)),
+            1,
+        );
+        region.members.insert(2);
+        assert!(region.allows(1, ProtectionAction::Build));
+        assert!(region.allows(2, ProtectionAction::Break));
+        assert!(!region.allows(3, ProtectionAction::Build));
+        assert!(region.is_owner(1));
+        assert!(!region.is_owner(2));
+
+        region.flags.set(ProtectionAction::Interact, true);
+        assert!(region.allows(3, ProtectionAction::Interact));
+        assert!(!region.allows(3, ProtectionAction::Break));
+
+        assert!(region.contains("overworld", 0, 50, 0));
+        assert!(!region.contains("the_nether", 0, 50, 0));
+        assert!(!region.contains("overworld", 11, 50, 0));
+    }
+
+    #[test]
+    fn test_flags_json() {
+        let flags = ProtectionFlags {
+            build: false,
+            break_blocks: true,
+            interact: false,
+        };
+        let json = serde_json::to_string(&flags).unwrap();
+        assert_eq!(json, r#"{"build":false,"break":true,"interact":false}"#);
+        // Missing flags are denied
+        let parsed: ProtectionFlags = serde_json::from_str(r#"{"break":true}"#).unwrap();
+        assert_eq!(parsed, flags);
+        assert_eq!(
+            ProtectionAction::from_name("Break"),
+            Some(ProtectionAction::Break)
+        );
+        assert_eq!(ProtectionAction::from_name("fly"), None);
+    }
+}
@@ -0,0 +1,148 @@
+//! Deleting chunks nobody cares about, so the world doesn't grow forever.
+
+use crate::activity::ChunkActivity;
+use crate::errors::WorldError;
+use crate::spatial::ChunkBounds;
+use crate::World;
+use tracing::{debug, info};
+
+/// Which chunks to prune. A chunk is pruned if it matches any of the criteria that are set.
+#[derive(Clone, Debug, Default)]
+pub struct PruneOptions {
+    /// Prune chunks more than this many chunks away from `center`, in either direction.
+    pub radius: Option<u32>,
+    pub center: (i32, i32),
+    /// Prune chunks players have spent less than this many ticks near.
+    pub min_inhabited_time: Option<u64>,
+    /// Chunks in these areas are never pruned, such as the areas around online players.
+    pub keep: Vec<ChunkBounds>,
+    /// Work out what would be pruned without deleting anything.
+    pub dry_run: bool,
+}
+
+impl PruneOptions {
+    fn should_prune(&self, x: i32, z: i32, activity: ChunkActivity) -> bool {
+        if self.keep.iter().any(|bounds| bounds.contains(x, z)) {
+            return false;
+        }
+        let outside_radius = self.radius.is_some_and(|radius| {
+            x.abs_diff(self.center.0) > radius || z.abs_diff(self.center.1) > radius
+        });
+        let uninhabited = self
+            .min_inhabited_time
+            .is_some_and(|min| activity.inhabited_time < min);
+        outside_radius || uninhabited
+    }
+}
+
+/// What a prune did, or would have done for a dry run.
+#[derive(Clone, Debug, Default)]
+pub struct PruneReport {
+    /// How many chunks were looked at.
+    pub checked: usize,
+    /// The chunks that were pruned.
+    pub pruned: Vec<(i32, i32)>,
+    pub dry_run: bool,
+}
+
+impl World {
+    /// Deletes the chunks in a dimension that match the prune options.
+    ///
+    /// Pruned chunks are deleted with [`World::delete_chunk`], so they'll be generated again if a
+    /// player goes near them. This reads the whole dimension's chunk list, so it shouldn't be
+    /// called from the tick thread.
+    pub fn prune(
+        &self,
+        dimension: &str,
+        options: &PruneOptions,
+    ) -> Result<PruneReport, WorldError> {
+        let activity = self.dimension_activity(dimension)?;
+        let coords = self.chunk_coords(dimension, None)?;
+
+        let mut report = PruneReport {
+            checked: coords.len(),
+            pruned: Vec::new(),
+            dry_run: options.dry_run,
+        };
+        for (x, z) in coords {
+            let chunk_activity = activity.get(&(x, z)).copied().unwrap_or_default();
+            if !options.should_prune(x, z, chunk_activity) {
+                continue;
+            }
+            if !options.dry_run {
+                debug!("Pruning chunk ({}, {}) in {}", x, z, dimension);
+                self.delete_chunk(x, z, dimension)?;
+                self.delete_activity(x, z, dimension)?;
+            }
+            report.pruned.push((x, z));
+        }
+
+        if !options.dry_run && !report.pruned.is_empty() {
+            self.sync()?;
+        }
+        info!(
+            "{} {} of {} chunks in {}",
+            if options.dry_run {
+                "Would prune"
+            } else {
+                "Pruned"
+            },
+            report.pruned.len(),
+            report.checked,
+            dimension
+        );
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inhabited(ticks: u64) -> ChunkActivity {
+        ChunkActivity {
+            inhabited_time: ticks,
+            last_visited: 0,
+        }
+    }
+
+    #[test]
+    fn test_radius() {
+        let options = PruneOptions {
+            radius: Some(2),
+            center: (10, 0),
+            ..Default::default()
+        };
+        assert!(!options.should_prune(12, -2, inhabited(0)));
+        assert!(options.should_prune(13, 0, inhabited(0)));
+        assert!(options.should_prune(10, -3, inhabited(1000)));
+    }
+
+    #[test]
+    fn test_inhabited_time() {
+        let options = PruneOptions {
+            min_inhabited_time: Some(100),
+            ..Default::default()
+        };
+        assert!(options.should_prune(500, 500, inhabited(99)));
+        assert!(!options.should_prune(500, 500, inhabited(100)));
+    }
+
+    #[test]
+    fn test_keep_areas_win() {
+        let options = PruneOptions {
+            radius: Some(0),
+            min_inhabited_time: Some(100),
+            keep: vec![ChunkBounds::around(50, 50, 1)],
+            ..Default::default()
+        };
+        assert!(!options.should_prune(51, 49, inhabited(0)));
+        assert!(options.should_prune(53, 50, inhabited(0)));
+    }
+
+    #[test]
+    fn test_no_criteria_prunes_nothing() {
+        let options = PruneOptions::default();
+        assert!(!options.should_prune(1_000_000, -1_000_000, inhabited(0)));
+    }
+}
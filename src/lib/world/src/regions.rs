@@ -0,0 +1,1037 @@
+//! Edits to cuboid regions of the world, which can span any number of chunks.
+//!
+//! Every chunk in a region is edited as its own task on the thread pool, holding only that
+//! chunk's lock, so big edits use every core without blocking edits elsewhere. Changed blocks and
+//! light are recorded with [`World::notify_block_changes`] and [`World::notify_light_changes`] so
+//! players see the edit, and every operation returns a [`RegionEdit`] that can be undone with an
+//! [`EditHistory`].
+//!
+//! None of the operations can be called from the thread pool itself, since they wait on it.
+//! Work that's already running there can use [`World::set_blocks_on_current_thread`] instead.
+
+use crate::block_changes::BlockChange;
+use crate::block_id::{BlockId, BLOCK2ID};
+use crate::chunk_format::Chunk;
+use crate::edit_batch::EditBatch;
+use crate::errors::WorldError;
+use crate::vanilla_chunk_format::BlockData;
+use crate::World;
+use ahash::AHashMap;
+use ferrumc_threadpool::ThreadPool;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+/// How many edits an [`EditHistory`] keeps by default.
+pub const DEFAULT_HISTORY_LIMIT: usize = 16;
+
+/// The most blocks a [`Clipboard`] can hold, which is 64 MB of block IDs.
+pub const MAX_CLIPBOARD_VOLUME: u64 = 1 << 24;
+
+/// A cuboid of blocks, with both corners included.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Region {
+    pub min: (i32, i32, i32),
+    pub max: (i32, i32, i32),
+}
+
+impl Region {
+    /// Creates the region between two corners, which can be any two opposite corners.
+    pub fn new(a: (i32, i32, i32), b: (i32, i32, i32)) -> Self {
+        Self {
+            min: (a.0.min(b.0), a.1.min(b.1), a.2.min(b.2)),
+            max: (a.0.max(b.0), a.1.max(b.1), a.2.max(b.2)),
+        }
+    }
+
+    /// The number of blocks along each axis.
+    pub fn size(&self) -> (i32, i32, i32) {
+        (
+            self.max.0 - self.min.0 + 1,
+            self.max.1 - self.min.1 + 1,
+            self.max.2 - self.min.2 + 1,
+        )
+    }
+
+    /// The number of blocks in the region, or `u64::MAX` if there are more than that.
+    pub fn volume(&self) -> u64 {
+        let length = |min: i32, max: i32| (max as i64 - min as i64 + 1) as u64;
+        length(self.min.0, self.max.0)
+            .saturating_mul(length(self.min.1, self.max.1))
+            .saturating_mul(length(self.min.2, self.max.2))
+    }
+
+    pub fn contains(&self, x: i32, y: i32, z: i32) -> bool {
+        (self.min.0..=self.max.0).contains(&x)
+            && (self.min.1..=self.max.1).contains(&y)
+            && (self.min.2..=self.max.2).contains(&z)
+    }
+
+    /// Whether the two regions share any blocks.
+    pub fn intersects(&self, other: &Region) -> bool {
+        self.min.0 <= other.max.0
+            && other.min.0 <= self.max.0
+            && self.min.1 <= other.max.1
+            && other.min.1 <= self.max.1
+            && self.min.2 <= other.max.2
+            && other.min.2 <= self.max.2
+    }
+
+    /// The coordinates of every chunk the region touches.
+    pub fn chunks(&self) -> Vec<(i32, i32)> {
+        let mut chunks = Vec::new();
+        for chunk_x in self.min.0 >> 4..=self.max.0 >> 4 {
+            for chunk_z in self.min.2 >> 4..=self.max.2 >> 4 {
+                chunks.push((chunk_x, chunk_z));
+            }
+        }
+        chunks
+    }
+
+    /// The part of the region inside a chunk, limited to the sections the chunk has.
+    fn within_chunk(&self, chunk: &Chunk) -> Option<Region> {
+        let min_y = chunk.sections.iter().map(|section| section.y).min()? as i32 * 16;
+        let max_y = chunk.sections.iter().map(|section| section.y).max()? as i32 * 16 + 15;
+        let min = (
+            self.min.0.max(chunk.x * 16),
+            self.min.1.max(min_y),
+            self.min.2.max(chunk.z * 16),
+        );
+        let max = (
+            self.max.0.min(chunk.x * 16 + 15),
+            self.max.1.min(max_y),
+            self.max.2.min(chunk.z * 16 + 15),
+        );
+        (min.0 <= max.0 && min.1 <= max.1 && min.2 <= max.2).then_some(Region { min, max })
+    }
+
+    /// Every block position in the region.
+    pub fn positions(&self) -> impl Iterator<Item = (i32, i32, i32)> + '_ {
+        (self.min.1..=self.max.1).flat_map(move |y| {
+            (self.min.2..=self.max.2)
+                .flat_map(move |z| (self.min.0..=self.max.0).map(move |x| (x, y, z)))
+        })
+    }
+}
+
+/// What a region operation changed. Only blocks that actually changed are included.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RegionEdit {
+    pub dimension: String,
+    /// The changed blocks as they were before the edit.
+    pub before: Vec<BlockChange>,
+    /// The changed blocks as they are after the edit, in the same order as `before`.
+    pub after: Vec<BlockChange>,
+    /// Chunks in the region that haven't been generated yet, and so were left alone.
+    pub missing_chunks: Vec<(i32, i32)>,
+}
+
+impl RegionEdit {
+    /// The number of blocks that were changed.
+    pub fn len(&self) -> usize {
+        self.after.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.after.is_empty()
+    }
+}
+
+/// A quarter turn clockwise around the Y axis, seen from above.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Rotation {
+    #[default]
+    None,
+    Clockwise90,
+    Clockwise180,
+    Clockwise270,
+}
+
+impl Rotation {
+    /// The number of clockwise quarter turns.
+    pub fn quarter_turns(&self) -> i32 {
+        match self {
+            Rotation::None => 0,
+            Rotation::Clockwise90 => 1,
+            Rotation::Clockwise180 => 2,
+            Rotation::Clockwise270 => 3,
+        }
+    }
+
+    /// The rotation for a number of clockwise quarter turns, which can be negative.
+    pub fn from_quarter_turns(turns: i32) -> Self {
+        match turns.rem_euclid(4) {
+            0 => Rotation::None,
+            1 => Rotation::Clockwise90,
+            2 => Rotation::Clockwise180,
+            _ => Rotation::Clockwise270,
+        }
+    }
+
+    /// The rotation for an angle in degrees, if it's a multiple of 90.
+    pub fn from_degrees(degrees: i32) -> Option<Self> {
+        (degrees % 90 == 0).then(|| Self::from_quarter_turns(degrees / 90))
+    }
+}
+
+/// Which axis to flip along.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Mirror {
+    #[default]
+    None,
+    /// Flips east and west.
+    X,
+    /// Flips north and south.
+    Z,
+}
+
+/// How to turn a clipboard before pasting it. The mirror is applied before the rotation.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Transform {
+    pub rotation: Rotation,
+    pub mirror: Mirror,
+}
+
+impl Transform {
+    /// Moves a position relative to the origin.
+    fn apply(&self, (mut x, y, mut z): (i32, i32, i32)) -> (i32, i32, i32) {
+        match self.mirror {
+            Mirror::None => {}
+            Mirror::X => x = -x,
+            Mirror::Z => z = -z,
+        }
+        for _ in 0..self.rotation.quarter_turns() {
+            (x, z) = (-z, x);
+        }
+        (x, y, z)
+    }
+
+    /// Turns a horizontal direction, where 0 is north and each step is a quarter turn clockwise.
+    fn apply_direction(&self, mut direction: i32) -> i32 {
+        match self.mirror {
+            Mirror::None => {}
+            Mirror::X if direction % 2 == 1 => direction += 2,
+            Mirror::Z if direction % 2 == 0 => direction += 2,
+            _ => {}
+        }
+        (direction + self.rotation.quarter_turns()).rem_euclid(4)
+    }
+
+    /// Turns a block's state to match, e.g. so stairs still face the same way relative to the rest
+    /// of the build. States that don't exist, which shouldn't happen, leave the block as it was.
+    pub fn apply_to_block(&self, block: BlockId) -> BlockId {
+        if *self == Transform::default() {
+            return block;
+        }
+        let Some(BlockData {
+            name,
+            properties: Some(properties),
+        }) = block.to_block_data()
+        else {
+            return block;
+        };
+        let mirrored = self.mirror != Mirror::None;
+        let properties = properties
+            .into_iter()
+            .map(|(key, value)| {
+                // Fences, walls, panes and the like have a property per side
+                let key = match direction_index(&key) {
+                    Some(direction) => DIRECTIONS[self.apply_direction(direction) as usize],
+                    None => key.as_str(),
+                }
+                .to_string();
+                let value = match (key.as_str(), value.as_str()) {
+                    (_, direction) if direction_index(direction).is_some() => {
+                        let direction = direction_index(direction).expect("checked above");
+                        DIRECTIONS[self.apply_direction(direction) as usize].to_string()
+                    }
+                    ("shape", shape) if rail_shape(shape).is_some() => self.apply_rail_shape(shape),
+                    ("axis", "x") if self.rotation.quarter_turns() % 2 == 1 => "z".to_string(),
+                    ("axis", "z") if self.rotation.quarter_turns() % 2 == 1 => "x".to_string(),
+                    ("rotation", rotation) => match rotation.parse::<i32>() {
+                        Ok(rotation) => self.apply_rotation_property(rotation).to_string(),
+                        Err(_) => value,
+                    },
+                    // Mirroring swaps left and right handed blocks
+                    ("hinge" | "type" | "shape", _) if mirrored => swap_handedness(&value),
+                    _ => value,
+                };
+                (key, value)
+            })
+            .collect();
+        let data = BlockData {
+            name,
+            properties: Some(properties),
+        };
+        BLOCK2ID
+            .get(&data)
+            .map(|id| BlockId(*id as u32))
+            .unwrap_or(block)
+    }
+
+    /// Turns a rail shape like `north_east` or `ascending_south`.
+    fn apply_rail_shape(&self, shape: &str) -> String {
+        let (ascending, directions) = rail_shape(shape).expect("checked by the caller");
+        let mut directions: Vec<i32> = directions
+            .into_iter()
+            .map(|direction| self.apply_direction(direction))
+            .collect();
+        // Shapes name north or south first, except for east_west
+        directions.sort_by_key(|direction| (direction % 2, *direction));
+        let directions: Vec<&str> = directions
+            .into_iter()
+            .map(|direction| DIRECTIONS[direction as usize])
+            .collect();
+        let shape = directions.join("_");
+        if ascending {
+            format!("ascending_{shape}")
+        } else {
+            shape
+        }
+    }
+
+    /// Turns the 16 step rotation used by signs, banners and heads, where 0 is south and each
+    /// step is a sixteenth turn clockwise.
+    fn apply_rotation_property(&self, mut rotation: i32) -> i32 {
+        match self.mirror {
+            Mirror::None => {}
+            Mirror::X => rotation = 16 - rotation,
+            Mirror::Z => rotation = 8 - rotation,
+        }
+        (rotation + self.rotation.quarter_turns() * 4).rem_euclid(16)
+    }
+}
+
+/// Horizontal directions, clockwise from north.
+const DIRECTIONS: [&str; 4] = ["north", "east", "south", "west"];
+
+fn direction_index(name: &str) -> Option<i32> {
+    DIRECTIONS
+        .iter()
+        .position(|direction| *direction == name)
+        .map(|index| index as i32)
+}
+
+/// Splits a rail shape into whether it's ascending and the directions it connects.
+fn rail_shape(shape: &str) -> Option<(bool, Vec<i32>)> {
+    let (ascending, directions) = match shape.strip_prefix("ascending_") {
+        Some(direction) => (true, direction),
+        None => (false, shape),
+    };
+    let directions = directions
+        .split('_')
+        .map(direction_index)
+        .collect::<Option<Vec<_>>>()?;
+    Some((ascending, directions))
+}
+
+fn swap_handedness(value: &str) -> String {
+    if let Some(prefix) = value.strip_suffix("left") {
+        format!("{prefix}right")
+    } else if let Some(prefix) = value.strip_suffix("right") {
+        format!("{prefix}left")
+    } else {
+        value.to_string()
+    }
+}
+
+fn too_big_for_clipboard(volume: u64) -> WorldError {
+    WorldError::InvalidClipboard(format!(
+        "{volume} blocks is more than the {MAX_CLIPBOARD_VOLUME} a clipboard can hold"
+    ))
+}
+
+/// The number of blocks in a clipboard of the given size, checking the size isn't negative or more
+/// than a clipboard can hold. Check this before allocating blocks for a size read from a file.
+pub(crate) fn clipboard_volume(size: (i32, i32, i32)) -> Result<usize, WorldError> {
+    if size.0 < 0 || size.1 < 0 || size.2 < 0 {
+        return Err(WorldError::InvalidClipboard(format!(
+            "Negative size {size:?}"
+        )));
+    }
+    let volume = (size.0 as u64)
+        .checked_mul(size.1 as u64)
+        .and_then(|volume| volume.checked_mul(size.2 as u64))
+        .unwrap_or(u64::MAX);
+    if volume > MAX_CLIPBOARD_VOLUME {
+        return Err(too_big_for_clipboard(volume));
+    }
+    Ok(volume as usize)
+}
+
+/// Blocks copied out of the world, ready to be pasted somewhere else.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Clipboard {
+    /// Where the clipboard's lowest corner is, relative to the origin it was copied from. Pasting
+    /// puts this corner at the same offset from where it's pasted.
+    pub offset: (i32, i32, i32),
+    size: (i32, i32, i32),
+    /// Every block, indexed by `(y * size.z + z) * size.x + x`.
+    blocks: Vec<BlockId>,
+}
+
+impl Clipboard {
+    /// Creates a clipboard from its blocks, indexed by `(y * size.z + z) * size.x + x`.
+    pub fn new(
+        offset: (i32, i32, i32),
+        size: (i32, i32, i32),
+        blocks: Vec<BlockId>,
+    ) -> Result<Self, WorldError> {
+        let volume = clipboard_volume(size)?;
+        if blocks.len() != volume {
+            return Err(WorldError::InvalidClipboard(format!(
+                "Expected {volume} blocks, but got {}",
+                blocks.len()
+            )));
+        }
+        Ok(Self {
+            offset,
+            size,
+            blocks,
+        })
+    }
+
+    /// The number of blocks along each axis.
+    pub fn size(&self) -> (i32, i32, i32) {
+        self.size
+    }
+
+    /// Where a block is in `blocks`, or `None` if the position is outside the clipboard.
+    fn index(&self, x: i32, y: i32, z: i32) -> Option<usize> {
+        if !(0..self.size.0).contains(&x)
+            || !(0..self.size.1).contains(&y)
+            || !(0..self.size.2).contains(&z)
+        {
+            return None;
+        }
+        let (size_x, size_z) = (self.size.0 as usize, self.size.2 as usize);
+        (y as usize)
+            .checked_mul(size_z)?
+            .checked_add(z as usize)?
+            .checked_mul(size_x)?
+            .checked_add(x as usize)
+    }
+
+    /// Gets a block by its position in the clipboard, from the lowest corner.
+    pub fn get_block(&self, x: i32, y: i32, z: i32) -> Option<BlockId> {
+        self.blocks.get(self.index(x, y, z)?).copied()
+    }
+
+    /// Every block in the clipboard, in index order.
+    pub fn blocks(&self) -> &[BlockId] {
+        &self.blocks
+    }
+
+    /// Every block with its position relative to the origin.
+    pub fn iter(&self) -> impl Iterator<Item = ((i32, i32, i32), BlockId)> + '_ {
+        let (size_x, size_z) = (self.size.0 as usize, self.size.2 as usize);
+        self.blocks.iter().enumerate().map(move |(index, block)| {
+            // Each of these is smaller than the clipboard's size, so they fit back in an i32
+            let x = (index % size_x) as i32;
+            let z = (index / size_x % size_z) as i32;
+            let y = (index / (size_x * size_z)) as i32;
+            (
+                (self.offset.0 + x, self.offset.1 + y, self.offset.2 + z),
+                *block,
+            )
+        })
+    }
+
+    /// Returns a copy of the clipboard mirrored and rotated around its origin, with the block
+    /// states turned to match.
+    pub fn transformed(&self, transform: Transform) -> Clipboard {
+        if transform == Transform::default() || self.blocks.is_empty() {
+            return self.clone();
+        }
+        let far_corner = (
+            self.offset.0 + self.size.0 - 1,
+            self.offset.1 + self.size.1 - 1,
+            self.offset.2 + self.size.2 - 1,
+        );
+        let (a, b) = (transform.apply(self.offset), transform.apply(far_corner));
+        let region = Region::new(a, b);
+        let (size_x, size_y, size_z) = region.size();
+        let mut transformed = Clipboard {
+            offset: region.min,
+            size: (size_x, size_y, size_z),
+            blocks: vec![BlockId::default(); self.blocks.len()],
+        };
+        let mut turned_blocks: AHashMap<BlockId, BlockId> = AHashMap::new();
+        for (position, block) in self.iter() {
+            let (x, y, z) = transform.apply(position);
+            let Some(index) =
+                transformed.index(x - region.min.0, y - region.min.1, z - region.min.2)
+            else {
+                continue;
+            };
+            transformed.blocks[index] = *turned_blocks
+                .entry(block)
+                .or_insert_with(|| transform.apply_to_block(block));
+        }
+        transformed
+    }
+}
+
+/// Region edits that can be undone and redone, newest last.
+pub struct EditHistory {
+    undo: VecDeque<RegionEdit>,
+    redo: Vec<RegionEdit>,
+    limit: usize,
+}
+
+impl Default for EditHistory {
+    fn default() -> Self {
+        Self::new(DEFAULT_HISTORY_LIMIT)
+    }
+}
+
+impl EditHistory {
+    /// Creates a history that keeps the last `limit` edits.
+    pub fn new(limit: usize) -> Self {
+        Self {
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            limit,
+        }
+    }
+
+    /// Adds an edit to the history, forgetting the oldest one if it's full. Anything that was
+    /// undone can't be redone after this.
+    pub fn record(&mut self, edit: RegionEdit) {
+        if edit.is_empty() {
+            return;
+        }
+        self.redo.clear();
+        self.undo.push_back(edit);
+        while self.undo.len() > self.limit {
+            self.undo.pop_front();
+        }
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    /// Puts back the blocks changed by the newest edit. Returns how many blocks were changed, or
+    /// `None` if there was nothing to undo.
+    ///
+    /// Blocks are put back as they were, even if something else has changed them since.
+    pub fn undo(&mut self, world: &World, pool: &ThreadPool) -> Result<Option<usize>, WorldError> {
+        let Some(edit) = self.undo.pop_back() else {
+            return Ok(None);
+        };
+        match world.set_blocks(pool, &edit.dimension, edit.before.clone()) {
+            Ok(undone) => {
+                self.redo.push(edit);
+                Ok(Some(undone.len()))
+            }
+            Err(e) => {
+                self.undo.push_back(edit);
+                Err(e)
+            }
+        }
+    }
+
+    /// Makes the newest undone edit again. Returns how many blocks were changed, or `None` if
+    /// there was nothing to redo.
+    pub fn redo(&mut self, world: &World, pool: &ThreadPool) -> Result<Option<usize>, WorldError> {
+        let Some(edit) = self.redo.pop() else {
+            return Ok(None);
+        };
+        match world.set_blocks(pool, &edit.dimension, edit.after.clone()) {
+            Ok(redone) => {
+                self.undo.push_back(edit);
+                Ok(Some(redone.len()))
+            }
+            Err(e) => {
+                self.redo.push(edit);
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Works out which blocks to set in a chunk. The blocks it returns have to be in that chunk.
+type ChunkEdit = dyn Fn(&Chunk) -> Vec<BlockChange> + Send + Sync;
+/// The blocks a chunk edit changed, before and after, or `None` if the chunk doesn't exist.
+type ChunkEditResult = Result<Option<(Vec<BlockChange>, Vec<BlockChange>)>, WorldError>;
+
+/// Splits block changes up by the chunk they're in.
+fn group_by_chunk(blocks: Vec<BlockChange>) -> AHashMap<(i32, i32), Vec<BlockChange>> {
+    let mut by_chunk: AHashMap<(i32, i32), Vec<BlockChange>> = AHashMap::new();
+    for block in blocks {
+        by_chunk
+            .entry((block.x >> 4, block.z >> 4))
+            .or_default()
+            .push(block);
+    }
+    by_chunk
+}
+
+/// Merges what every chunk in an edit changed. `results` are in the same order as `chunks`.
+///
+/// If a chunk failed the first error is returned, but the other chunks have still been edited.
+fn merge_chunk_edits(
+    dimension: &str,
+    chunks: Vec<(i32, i32)>,
+    results: Vec<ChunkEditResult>,
+) -> Result<RegionEdit, WorldError> {
+    let mut merged = RegionEdit {
+        dimension: dimension.to_string(),
+        ..Default::default()
+    };
+    let mut error = None;
+    for (coords, result) in chunks.into_iter().zip(results) {
+        match result {
+            Ok(Some((before, after))) => {
+                merged.before.extend(before);
+                merged.after.extend(after);
+            }
+            Ok(None) => merged.missing_chunks.push(coords),
+            Err(e) => {
+                error.get_or_insert(e);
+            }
+        }
+    }
+    match error {
+        Some(e) => Err(e),
+        None => Ok(merged),
+    }
+}
+
+impl World {
+    /// Sets every block in a region.
+    pub fn fill_region(
+        &self,
+        pool: &ThreadPool,
+        dimension: &str,
+        region: Region,
+        block: impl Into<BlockId>,
+    ) -> Result<RegionEdit, WorldError> {
+        let block = block.into();
+        self.edit_region_chunks(
+            pool,
+            dimension,
+            region.chunks(),
+            Arc::new(move |chunk: &Chunk| {
+                let Some(within) = region.within_chunk(chunk) else {
+                    return Vec::new();
+                };
+                within
+                    .positions()
+                    .map(|(x, y, z)| BlockChange { x, y, z, block })
+                    .collect()
+            }),
+        )
+    }
+
+    /// Sets every block in a region that `matches` accepts.
+    pub fn replace_region(
+        &self,
+        pool: &ThreadPool,
+        dimension: &str,
+        region: Region,
+        matches: impl Fn(BlockId) -> bool + Send + Sync + 'static,
+        block: impl Into<BlockId>,
+    ) -> Result<RegionEdit, WorldError> {
+        let block = block.into();
+        self.edit_region_chunks(
+            pool,
+            dimension,
+            region.chunks(),
+            Arc::new(move |chunk: &Chunk| {
+                let Some(within) = region.within_chunk(chunk) else {
+                    return Vec::new();
+                };
+                within
+                    .positions()
+                    .filter(|&(x, y, z)| chunk.get_block(x, y, z).is_ok_and(&matches))
+                    .map(|(x, y, z)| BlockChange { x, y, z, block })
+                    .collect()
+            }),
+        )
+    }
+
+    /// Sets any number of blocks, anywhere in a dimension.
+    pub fn set_blocks(
+        &self,
+        pool: &ThreadPool,
+        dimension: &str,
+        blocks: Vec<BlockChange>,
+    ) -> Result<RegionEdit, WorldError> {
+        let by_chunk = Arc::new(group_by_chunk(blocks));
+        let chunks: Vec<(i32, i32)> = by_chunk.keys().copied().collect();
+        self.edit_region_chunks(
+            pool,
+            dimension,
+            chunks,
+            Arc::new(move |chunk: &Chunk| {
+                by_chunk
+                    .get(&(chunk.x, chunk.z))
+                    .cloned()
+                    .unwrap_or_default()
+            }),
+        )
+    }
+
+    /// Like [`World::set_blocks`], but the chunks are edited one after another on the current
+    /// thread. This is for work that's already running on the thread pool, which can't wait on it.
+    pub fn set_blocks_on_current_thread(
+        &self,
+        dimension: &str,
+        blocks: Vec<BlockChange>,
+    ) -> Result<RegionEdit, WorldError> {
+        let by_chunk = group_by_chunk(blocks);
+        let chunks: Vec<(i32, i32)> = by_chunk.keys().copied().collect();
+        let edit = |chunk: &Chunk| {
+            by_chunk
+                .get(&(chunk.x, chunk.z))
+                .cloned()
+                .unwrap_or_default()
+        };
+        let results = chunks
+            .iter()
+            .map(|&(chunk_x, chunk_z)| self.edit_region_chunk(dimension, chunk_x, chunk_z, &edit))
+            .collect();
+        merge_chunk_edits(dimension, chunks, results)
+    }
+
+    /// Copies a region into a clipboard, with its positions relative to `origin`. Chunks that
+    /// haven't been generated yet are copied as air.
+    pub fn copy_region(
+        &self,
+        pool: &ThreadPool,
+        dimension: &str,
+        region: Region,
+        origin: (i32, i32, i32),
+    ) -> Result<Clipboard, WorldError> {
+        let volume = region.volume();
+        if volume > MAX_CLIPBOARD_VOLUME {
+            return Err(too_big_for_clipboard(volume));
+        }
+        let mut batch = pool.batch();
+        for (chunk_x, chunk_z) in region.chunks() {
+            let world = self.clone();
+            let dimension = dimension.to_string();
+            batch.execute(move || {
+                let chunk = match world.load_chunk(chunk_x, chunk_z, &dimension) {
+                    Ok(chunk) => chunk,
+                    Err(WorldError::ChunkNotFound) => return Ok(Vec::new()),
+                    Err(e) => return Err(e),
+                };
+                let Some(within) = region.within_chunk(&chunk) else {
+                    return Ok(Vec::new());
+                };
+                within
+                    .positions()
+                    .map(|(x, y, z)| Ok(((x, y, z), chunk.get_block(x, y, z)?)))
+                    .collect::<Result<Vec<_>, WorldError>>()
+            });
+        }
+        let (size_x, size_y, size_z) = region.size();
+        let mut clipboard = Clipboard {
+            offset: (
+                region.min.0 - origin.0,
+                region.min.1 - origin.1,
+                region.min.2 - origin.2,
+            ),
+            size: (size_x, size_y, size_z),
+            blocks: vec![BlockId::default(); volume as usize],
+        };
+        for blocks in batch.wait() {
+            for ((x, y, z), block) in blocks? {
+                if let Some(index) =
+                    clipboard.index(x - region.min.0, y - region.min.1, z - region.min.2)
+                {
+                    clipboard.blocks[index] = block;
+                }
+            }
+        }
+        Ok(clipboard)
+    }
+
+    /// Pastes a clipboard with its origin at `origin`. Air in the clipboard is skipped unless
+    /// `paste_air` is set, so pasted builds don't carve out whatever is around them.
+    pub fn paste_clipboard(
+        &self,
+        pool: &ThreadPool,
+        dimension: &str,
+        clipboard: &Clipboard,
+        origin: (i32, i32, i32),
+        paste_air: bool,
+    ) -> Result<RegionEdit, WorldError> {
+        let blocks = clipboard
+            .iter()
+            .filter(|(_, block)| paste_air || *block != BlockId::default())
+            .map(|((x, y, z), block)| BlockChange {
+                x: origin.0 + x,
+                y: origin.1 + y,
+                z: origin.2 + z,
+                block,
+            })
+            .collect();
+        self.set_blocks(pool, dimension, blocks)
+    }
+
+    /// Runs `edit` on each chunk in parallel, then merges what every chunk changed.
+    ///
+    /// If a chunk fails the first error is returned, but the other chunks are still edited.
+    fn edit_region_chunks(
+        &self,
+        pool: &ThreadPool,
+        dimension: &str,
+        chunks: Vec<(i32, i32)>,
+        edit: Arc<ChunkEdit>,
+    ) -> Result<RegionEdit, WorldError> {
+        let mut batch = pool.batch();
+        for &(chunk_x, chunk_z) in &chunks {
+            let world = self.clone();
+            let dimension = dimension.to_string();
+            let edit = edit.clone();
+            batch.execute(move || world.edit_region_chunk(&dimension, chunk_x, chunk_z, &*edit));
+        }
+        // Results come back in the order the chunks were queued
+        merge_chunk_edits(dimension, chunks, batch.wait())
+    }
+
+    /// Edits a single chunk for [`World::edit_region_chunks`], returning the blocks before and
+    /// after, or `None` if the chunk doesn't exist.
+    fn edit_region_chunk(
+        &self,
+        dimension: &str,
+        chunk_x: i32,
+        chunk_z: i32,
+        edit: &(impl Fn(&Chunk) -> Vec<BlockChange> + ?Sized),
+    ) -> ChunkEditResult {
+        let result = self.edit(chunk_x, chunk_z, dimension, |chunk| {
+            let mut before = Vec::new();
+            let mut after = Vec::new();
+            for change in edit(chunk) {
+                let old = chunk.get_block(change.x, change.y, change.z)?;
+                if old != change.block {
+                    before.push(BlockChange {
+                        block: old,
+                        ..change
+                    });
+                    after.push(change);
+                }
+            }
+            if !after.is_empty() {
+                let mut batch = EditBatch::new(chunk);
+                for change in &after {
+                    batch.set_block(change.x, change.y, change.z, change.block);
+                }
+                batch.apply()?;
+            }
+            Ok((before, after))
+        });
+        let (before, after) = match result {
+            Ok(changes) => changes,
+            Err(WorldError::ChunkNotFound) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        if after.is_empty() {
+            return Ok(Some((before, after)));
+        }
+        self.notify_block_changes(dimension, after.iter().copied());
+        let positions: Vec<(i32, i32, i32)> = after
+            .iter()
+            .map(|change| (change.x, change.y, change.z))
+            .collect();
+        let lit_sections = self.relight(dimension, &positions)?;
+        self.notify_light_changes(dimension, &lit_sections);
+        Ok(Some((before, after)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(name: &str, properties: &[(&str, &str)]) -> BlockId {
+        BlockData {
+            name: name.to_string(),
+            properties: (!properties.is_empty()).then(|| {
+                properties
+                    .iter()
+                    .map(|(key, value)| (key.to_string(), value.to_string()))
+                    .collect()
+            }),
+        }
+        .to_block_id()
+    }
+
+    #[test]
+    fn test_region_chunks() {
+        let region = Region::new((17, 80, -1), (-3, 60, 15));
+        assert_eq!(region.min, (-3, 60, -1));
+        assert_eq!(region.size(), (21, 21, 17));
+        assert_eq!(
+            region.chunks(),
+            vec![(-1, -1), (-1, 0), (0, -1), (0, 0), (1, -1), (1, 0)]
+        );
+
+        let chunk = Chunk::new(1, 0, "overworld".to_string());
+        let within = region.within_chunk(&chunk).unwrap();
+        assert_eq!(within, Region::new((16, 60, 0), (17, 80, 15)));
+    }
+
+    #[test]
+    fn test_transform_positions() {
+        let clockwise = Transform {
+            rotation: Rotation::Clockwise90,
+            ..Default::default()
+        };
+        // North of the origin ends up east of it
+        assert_eq!(clockwise.apply((0, 5, -2)), (2, 5, 0));
+        let mirrored = Transform {
+            mirror: Mirror::X,
+            rotation: Rotation::Clockwise180,
+        };
+        assert_eq!(mirrored.apply((3, 0, 1)), (3, 0, -1));
+        assert_eq!(Rotation::from_degrees(-90), Some(Rotation::Clockwise270));
+        assert_eq!(Rotation::from_degrees(45), None);
+    }
+
+    #[test]
+    fn test_transform_block_states() {
+        let clockwise = Transform {
+            rotation: Rotation::Clockwise90,
+            ..Default::default()
+        };
+        let stairs = |facing, shape| {
+            block(
+                "minecraft:oak_stairs",
+                &[
+                    ("facing", facing),
+                    ("half", "bottom"),
+                    ("shape", shape),
+                    ("waterlogged", "false"),
+                ],
+            )
+        };
+        assert_eq!(
+            clockwise.apply_to_block(stairs("north", "inner_left")),
+            stairs("east", "inner_left")
+        );
+        let mirror = Transform {
+            mirror: Mirror::Z,
+            ..Default::default()
+        };
+        assert_eq!(
+            mirror.apply_to_block(stairs("north", "inner_left")),
+            stairs("south", "inner_right")
+        );
+        let log = |axis| block("minecraft:oak_log", &[("axis", axis)]);
+        assert_eq!(clockwise.apply_to_block(log("x")), log("z"));
+        assert_eq!(clockwise.apply_to_block(log("y")), log("y"));
+        let rail = |shape| {
+            block(
+                "minecraft:rail",
+                &[("shape", shape), ("waterlogged", "false")],
+            )
+        };
+        assert_eq!(
+            clockwise.apply_to_block(rail("east_west")),
+            rail("north_south")
+        );
+        assert_eq!(
+            clockwise.apply_to_block(rail("north_east")),
+            rail("south_east")
+        );
+        assert_eq!(
+            clockwise.apply_to_block(rail("ascending_west")),
+            rail("ascending_north")
+        );
+    }
+
+    #[test]
+    fn test_rotation_property() {
+        let mirror_x = Transform {
+            mirror: Mirror::X,
+            ..Default::default()
+        };
+        // West and east swap, south stays put
+        assert_eq!(mirror_x.apply_rotation_property(4), 12);
+        assert_eq!(mirror_x.apply_rotation_property(0), 0);
+        let clockwise = Transform {
+            rotation: Rotation::Clockwise90,
+            ..Default::default()
+        };
+        assert_eq!(clockwise.apply_rotation_property(14), 2);
+    }
+
+    #[test]
+    fn test_clipboard_transformed() {
+        let stone = block("minecraft:stone", &[]);
+        let air = BlockId::default();
+        // Two blocks in a line going east from the origin, stone at the origin
+        let clipboard = Clipboard::new((0, 0, 0), (2, 1, 1), vec![stone, air]).unwrap();
+        let turned = clipboard.transformed(Transform {
+            rotation: Rotation::Clockwise90,
+            ..Default::default()
+        });
+        // The line now goes south
+        assert_eq!(turned.offset, (0, 0, 0));
+        assert_eq!(turned.size(), (1, 1, 2));
+        assert_eq!(turned.get_block(0, 0, 0), Some(stone));
+        assert_eq!(turned.get_block(0, 0, 1), Some(air));
+
+        let mirrored = clipboard.transformed(Transform {
+            mirror: Mirror::X,
+            ..Default::default()
+        });
+        assert_eq!(mirrored.offset, (-1, 0, 0));
+        assert_eq!(mirrored.get_block(1, 0, 0), Some(stone));
+
+        assert!(Clipboard::new((0, 0, 0), (2, 2, 2), vec![stone]).is_err());
+    }
+
+    #[test]
+    fn test_clipboard_size_limit() {
+        let everything = Region::new(
+            (i32::MIN, i32::MIN, i32::MIN),
+            (i32::MAX, i32::MAX, i32::MAX),
+        );
+        assert_eq!(everything.volume(), u64::MAX);
+        assert!(Clipboard::new((0, 0, 0), (i32::MAX, i32::MAX, i32::MAX), vec![]).is_err());
+
+        let air = BlockId::default();
+        let clipboard = Clipboard::new((0, 0, 0), (2, 1, 2), vec![air; 4]).unwrap();
+        assert_eq!(clipboard.get_block(1, 0, 1), Some(air));
+        assert_eq!(clipboard.get_block(2, 0, 0), None);
+        assert_eq!(clipboard.get_block(0, -1, 0), None);
+    }
+
+    #[test]
+    fn test_history_limit() {
+        let edit = |x| RegionEdit {
+            dimension: "overworld".to_string(),
+            before: vec![BlockChange {
+                x,
+                y: 0,
+                z: 0,
+                block: BlockId::default(),
+            }],
+            after: vec![BlockChange {
+                x,
+                y: 0,
+                z: 0,
+                block: BlockId(1),
+            }],
+            missing_chunks: vec![],
+        };
+        let mut history = EditHistory::new(2);
+        history.record(RegionEdit::default());
+        assert!(!history.can_undo());
+        for x in 0..3 {
+            history.record(edit(x));
+        }
+        assert_eq!(history.undo.len(), 2);
+        assert_eq!(history.undo[0], edit(1));
+    }
+}
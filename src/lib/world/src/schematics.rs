@@ -0,0 +1,502 @@
+//! Reading and writing builds in the formats other tools use, so they can be moved between
+//! servers: Sponge schematics (`.schem`, version 3), which WorldEdit uses, and vanilla structure
+//! files (`.nbt`), which structure blocks save.
+//!
+//! Both are loaded into a [`Clipboard`], so they can be pasted like anything copied in game.
+//! Block entities and entities aren't supported, so only the blocks themselves are kept.
+
+use crate::block_id::{BlockId, BLOCK2ID};
+use crate::errors::WorldError;
+use crate::regions::{clipboard_volume, Clipboard, Region, RegionEdit, Transform};
+use crate::vanilla_chunk_format::BlockData;
+use crate::World;
+use ahash::AHashMap;
+use ferrumc_macros::{NBTDeserialize, NBTSerialize};
+use ferrumc_nbt::{read_nbt_file, write_nbt_file, FromNbt, NBTSerializable, NBTSerializeOptions};
+use ferrumc_net_codec::net_types::var_int::VarInt;
+use ferrumc_threadpool::ThreadPool;
+use std::collections::BTreeMap;
+use std::io::Cursor;
+use std::path::Path;
+use tracing::warn;
+
+/// The data version of the Minecraft version the server runs, 1.21.8.
+pub const DATA_VERSION: i32 = 4440;
+
+/// The only Sponge schematic version that's supported.
+const SPONGE_VERSION: i32 = 3;
+
+/// The file formats a clipboard can be saved as.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SchematicFormat {
+    /// A Sponge schematic, version 3.
+    Sponge,
+    /// A vanilla structure file.
+    Structure,
+}
+
+impl SchematicFormat {
+    /// Picks the format from a file's extension, `.schem` or `.nbt`.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "schem" => Some(SchematicFormat::Sponge),
+            "nbt" => Some(SchematicFormat::Structure),
+            _ => None,
+        }
+    }
+}
+
+#[derive(NBTSerialize, NBTDeserialize, Debug, Clone)]
+struct SpongeFile {
+    #[nbt(rename = "Schematic")]
+    schematic: SpongeSchematic,
+}
+
+#[derive(NBTSerialize, NBTDeserialize, Debug, Clone)]
+struct SpongeSchematic {
+    #[nbt(rename = "Version")]
+    version: i32,
+    #[nbt(rename = "DataVersion")]
+    data_version: i32,
+    /// The size along X. Sizes are unsigned shorts.
+    #[nbt(rename = "Width")]
+    width: i16,
+    #[nbt(rename = "Height")]
+    height: i16,
+    /// The size along Z.
+    #[nbt(rename = "Length")]
+    length: i16,
+    /// Where the lowest corner is relative to the origin.
+    #[nbt(rename = "Offset")]
+    offset: Option<Vec<i32>>,
+    #[nbt(rename = "Blocks")]
+    blocks: Option<SpongeBlocks>,
+}
+
+#[derive(NBTSerialize, NBTDeserialize, Debug, Clone)]
+struct SpongeBlocks {
+    /// Block states, as strings, to their index in the palette.
+    #[nbt(rename = "Palette")]
+    palette: BTreeMap<String, i32>,
+    /// Palette indexes as VarInts, in the same order as a [`Clipboard`]'s blocks.
+    #[nbt(rename = "Data")]
+    data: Vec<i8>,
+}
+
+#[derive(NBTSerialize, NBTDeserialize, Debug, Clone)]
+struct StructureFile {
+    #[nbt(rename = "DataVersion")]
+    data_version: Option<i32>,
+    size: IntList,
+    palette: Option<Vec<BlockData>>,
+    /// Used instead of `palette` by structures with random variants, like shipwrecks. Only the
+    /// first variant is used.
+    palettes: Option<Vec<Vec<BlockData>>>,
+    blocks: Vec<StructureBlock>,
+}
+
+#[derive(NBTSerialize, NBTDeserialize, Debug, Clone)]
+struct StructureBlock {
+    state: i32,
+    pos: IntList,
+}
+
+/// A list of ints. Vanilla structure files use these rather than int arrays, and won't load if
+/// they're int arrays.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct IntList(Vec<i32>);
+
+const TAG_INT: u8 = 3;
+const TAG_LIST: u8 = 9;
+
+impl NBTSerializable for IntList {
+    fn serialize(&self, buf: &mut Vec<u8>, options: &NBTSerializeOptions<'_>) {
+        match options {
+            NBTSerializeOptions::None => {}
+            NBTSerializeOptions::WithHeader(name) => {
+                TAG_LIST.serialize(buf, &NBTSerializeOptions::None);
+                name.serialize(buf, &NBTSerializeOptions::None);
+            }
+            NBTSerializeOptions::Network | NBTSerializeOptions::Flatten => {
+                TAG_LIST.serialize(buf, &NBTSerializeOptions::None);
+            }
+        }
+        TAG_INT.serialize(buf, &NBTSerializeOptions::None);
+        (self.0.len() as i32).serialize(buf, &NBTSerializeOptions::None);
+        for value in &self.0 {
+            value.serialize(buf, &NBTSerializeOptions::None);
+        }
+    }
+
+    fn id() -> u8 {
+        TAG_LIST
+    }
+}
+
+impl<'a> FromNbt<'a> for IntList {
+    fn from_nbt(
+        tapes: &ferrumc_nbt::NbtTape<'a>,
+        element: &ferrumc_nbt::NbtTapeElement<'a>,
+    ) -> ferrumc_nbt::Result<Self> {
+        Vec::<i32>::from_nbt(tapes, element).map(IntList)
+    }
+}
+
+/// Finds the ID of a block from another server. Blocks this version doesn't have are replaced
+/// with air, since they'd otherwise stop the whole build from loading.
+fn resolve_block(block: &BlockData) -> BlockId {
+    match BLOCK2ID.get(block) {
+        Some(id) => BlockId(*id as u32),
+        None => {
+            warn!(
+                "Replacing unknown block {} with air",
+                block.to_state_string()
+            );
+            BlockId::default()
+        }
+    }
+}
+
+fn block_data(block: BlockId) -> Result<BlockData, WorldError> {
+    block
+        .to_block_data()
+        .ok_or(WorldError::InvalidBlockId(block.0))
+}
+
+fn invalid(message: impl Into<String>) -> WorldError {
+    WorldError::InvalidSchematic(message.into())
+}
+
+fn xyz(values: &[i32], name: &str) -> Result<(i32, i32, i32), WorldError> {
+    match values {
+        [x, y, z] => Ok((*x, *y, *z)),
+        _ => Err(invalid(format!("{name} should have 3 values"))),
+    }
+}
+
+/// Checks a clipboard is small enough for formats that store sizes as shorts.
+fn short_size(clipboard: &Clipboard) -> Result<(i16, i16, i16), WorldError> {
+    let (x, y, z) = clipboard.size();
+    let short = |size: i32| {
+        u16::try_from(size)
+            .map(|size| size as i16)
+            .map_err(|_| invalid(format!("{x}x{y}x{z} is too big to save")))
+    };
+    Ok((short(x)?, short(y)?, short(z)?))
+}
+
+impl Clipboard {
+    /// Reads a Sponge schematic, which should already be decompressed.
+    pub fn from_sponge(data: &[u8]) -> Result<Self, WorldError> {
+        let schematic = SpongeFile::from_bytes(data)?.schematic;
+        if schematic.version != SPONGE_VERSION {
+            return Err(invalid(format!(
+                "Sponge schematic version {} isn't supported, only version {SPONGE_VERSION}",
+                schematic.version
+            )));
+        }
+        let size = (
+            schematic.width as u16 as i32,
+            schematic.height as u16 as i32,
+            schematic.length as u16 as i32,
+        );
+        let offset = match &schematic.offset {
+            Some(offset) => xyz(offset, "Offset")?,
+            None => (0, 0, 0),
+        };
+        let volume = clipboard_volume(size)?;
+        let Some(blocks) = schematic.blocks else {
+            return Clipboard::new(offset, size, vec![BlockId::default(); volume]);
+        };
+
+        let mut palette = AHashMap::new();
+        for (state, index) in &blocks.palette {
+            let data = BlockData::from_state_string(state)
+                .ok_or_else(|| invalid(format!("Invalid block state {state}")))?;
+            palette.insert(*index, resolve_block(&data));
+        }
+        let bytes: Vec<u8> = blocks.data.iter().map(|byte| *byte as u8).collect();
+        let mut cursor = Cursor::new(bytes.as_slice());
+        let mut ids = Vec::with_capacity(volume);
+        for _ in 0..volume {
+            let index = VarInt::read(&mut cursor)
+                .map_err(|e| invalid(format!("Invalid block data: {e}")))?;
+            let block = palette
+                .get(&index.0)
+                .ok_or_else(|| invalid(format!("Palette index {} out of range", index.0)))?;
+            ids.push(*block);
+        }
+        Clipboard::new(offset, size, ids)
+    }
+
+    /// Writes the clipboard as a Sponge schematic. The data isn't compressed yet.
+    pub fn to_sponge(&self) -> Result<Vec<u8>, WorldError> {
+        let (width, height, length) = short_size(self)?;
+        let mut palette: BTreeMap<String, i32> = BTreeMap::new();
+        let mut indexes: AHashMap<BlockId, i32> = AHashMap::new();
+        let mut data = Vec::with_capacity(self.blocks().len());
+        for block in self.blocks() {
+            let index = match indexes.get(block) {
+                Some(index) => *index,
+                None => {
+                    let index = indexes.len() as i32;
+                    palette.insert(block_data(*block)?.to_state_string(), index);
+                    indexes.insert(*block, index);
+                    index
+                }
+            };
+            VarInt::new(index)
+                .write(&mut data)
+                .map_err(|e| invalid(e.to_string()))?;
+        }
+        let file = SpongeFile {
+            schematic: SpongeSchematic {
+                version: SPONGE_VERSION,
+                data_version: DATA_VERSION,
+                width,
+                height,
+                length,
+                offset: Some(vec![self.offset.0, self.offset.1, self.offset.2]),
+                blocks: Some(SpongeBlocks {
+                    palette,
+                    data: data.into_iter().map(|byte| byte as i8).collect(),
+                }),
+            },
+        };
+        let mut buf = Vec::new();
+        file.serialize(&mut buf, &NBTSerializeOptions::WithHeader(""));
+        Ok(buf)
+    }
+
+    /// Reads a vanilla structure file, which should already be decompressed. Structures don't
+    /// have an origin, so their lowest corner is pasted at the paste position like a structure
+    /// block would. Structure voids, which aren't saved, are read as air.
+    pub fn from_structure(data: &[u8]) -> Result<Self, WorldError> {
+        let structure = StructureFile::from_bytes(data)?;
+        let size = xyz(&structure.size.0, "size")?;
+        let volume = clipboard_volume(size)?;
+        let palette = structure
+            .palette
+            .or_else(|| {
+                structure
+                    .palettes
+                    .and_then(|palettes| palettes.into_iter().next())
+            })
+            .unwrap_or_default();
+        let palette: Vec<BlockId> = palette.iter().map(resolve_block).collect();
+
+        let mut blocks = vec![BlockId::default(); volume];
+        for block in &structure.blocks {
+            let (x, y, z) = xyz(&block.pos.0, "pos")?;
+            if !(0..size.0).contains(&x) || !(0..size.1).contains(&y) || !(0..size.2).contains(&z) {
+                return Err(invalid(format!(
+                    "Block at {x}, {y}, {z} is outside the structure"
+                )));
+            }
+            let id = palette
+                .get(block.state as usize)
+                .ok_or_else(|| invalid(format!("Palette index {} out of range", block.state)))?;
+            // Inside the size checked above, so this can't overflow
+            let (x, y, z) = (x as usize, y as usize, z as usize);
+            blocks[(y * size.2 as usize + z) * size.0 as usize + x] = *id;
+        }
+        Clipboard::new((0, 0, 0), size, blocks)
+    }
+
+    /// Writes the clipboard as a vanilla structure file. The data isn't compressed yet.
+    ///
+    /// Structure files don't have an origin, so the clipboard's offset is lost.
+    pub fn to_structure(&self) -> Result<Vec<u8>, WorldError> {
+        let (size_x, size_y, size_z) = self.size();
+        let mut palette = Vec::new();
+        let mut indexes: AHashMap<BlockId, i32> = AHashMap::new();
+        let mut blocks = Vec::with_capacity(self.blocks().len());
+        for y in 0..size_y {
+            for z in 0..size_z {
+                for x in 0..size_x {
+                    let block = self
+                        .get_block(x, y, z)
+                        .expect("Position is inside the clipboard");
+                    let state = match indexes.get(&block) {
+                        Some(index) => *index,
+                        None => {
+                            let index = palette.len() as i32;
+                            palette.push(block_data(block)?);
+                            indexes.insert(block, index);
+                            index
+                        }
+                    };
+                    blocks.push(StructureBlock {
+                        state,
+                        pos: IntList(vec![x, y, z]),
+                    });
+                }
+            }
+        }
+        let file = StructureFile {
+            data_version: Some(DATA_VERSION),
+            size: IntList(vec![size_x, size_y, size_z]),
+            palette: Some(palette),
+            palettes: None,
+            blocks,
+        };
+        let mut buf = Vec::new();
+        file.serialize(&mut buf, &NBTSerializeOptions::WithHeader(""));
+        Ok(buf)
+    }
+
+    /// Loads a schematic file, picking the format from its extension.
+    pub fn load(path: &Path) -> Result<Self, WorldError> {
+        let format = SchematicFormat::from_path(path).ok_or_else(|| {
+            invalid(format!(
+                "Can't tell the format of {}, it should end in .schem or .nbt",
+                path.display()
+            ))
+        })?;
+        let data = read_nbt_file(path)?;
+        match format {
+            SchematicFormat::Sponge => Clipboard::from_sponge(&data),
+            SchematicFormat::Structure => Clipboard::from_structure(&data),
+        }
+    }
+
+    /// Saves the clipboard to a file, gzip compressed like other tools expect.
+    pub fn save(&self, path: &Path, format: SchematicFormat) -> Result<(), WorldError> {
+        let data = match format {
+            SchematicFormat::Sponge => self.to_sponge()?,
+            SchematicFormat::Structure => self.to_structure()?,
+        };
+        write_nbt_file(path, &data)?;
+        Ok(())
+    }
+}
+
+impl World {
+    /// Loads a schematic file and pastes it with its origin at `origin`. See
+    /// [`World::paste_clipboard`].
+    pub fn paste_schematic(
+        &self,
+        pool: &ThreadPool,
+        dimension: &str,
+        path: &Path,
+        origin: (i32, i32, i32),
+        transform: Transform,
+        paste_air: bool,
+    ) -> Result<RegionEdit, WorldError> {
+        let clipboard = Clipboard::load(path)?.transformed(transform);
+        self.paste_clipboard(pool, dimension, &clipboard, origin, paste_air)
+    }
+
+    /// Copies a region and saves it as a schematic file, in the format its extension asks for.
+    /// Positions in the schematic are relative to `origin`.
+    pub fn export_schematic(
+        &self,
+        pool: &ThreadPool,
+        dimension: &str,
+        region: Region,
+        origin: (i32, i32, i32),
+        path: &Path,
+    ) -> Result<(), WorldError> {
+        let format = SchematicFormat::from_path(path).ok_or_else(|| {
+            invalid(format!(
+                "Can't tell the format of {}, it should end in .schem or .nbt",
+                path.display()
+            ))
+        })?;
+        let clipboard = self.copy_region(pool, dimension, region, origin)?;
+        clipboard.save(path, format)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(state: &str) -> BlockId {
+        BlockData::from_state_string(state).unwrap().to_block_id()
+    }
+
+    fn test_clipboard() -> Clipboard {
+        let stairs = block("oak_stairs[facing=east,half=bottom,shape=straight,waterlogged=false]");
+        let stone = block("stone");
+        let air = BlockId::default();
+        Clipboard::new((-1, 0, 2), (2, 2, 1), vec![stone, stairs, air, stone]).unwrap()
+    }
+
+    #[test]
+    fn test_block_state_strings() {
+        let data = BlockData::from_state_string("oak_stairs[facing=east, half=bottom]").unwrap();
+        assert_eq!(data.name, "minecraft:oak_stairs");
+        assert_eq!(
+            data.to_state_string(),
+            "minecraft:oak_stairs[facing=east,half=bottom]"
+        );
+        assert_eq!(
+            BlockData::from_state_string("minecraft:stone")
+                .unwrap()
+                .to_state_string(),
+            "minecraft:stone"
+        );
+        assert!(BlockData::from_state_string("stone[facing]").is_none());
+        assert!(BlockData::from_state_string("stone[facing=east").is_none());
+    }
+
+    #[test]
+    fn test_sponge_round_trip() {
+        let clipboard = test_clipboard();
+        let data = clipboard.to_sponge().unwrap();
+        assert_eq!(Clipboard::from_sponge(&data).unwrap(), clipboard);
+    }
+
+    #[test]
+    fn test_structure_round_trip() {
+        let clipboard = test_clipboard();
+        let data = clipboard.to_structure().unwrap();
+        let loaded = Clipboard::from_structure(&data).unwrap();
+        // Structures don't keep the offset
+        assert_eq!(loaded.offset, (0, 0, 0));
+        assert_eq!(loaded.blocks(), clipboard.blocks());
+    }
+
+    #[test]
+    fn test_huge_sponge_rejected() {
+        let file = SpongeFile {
+            schematic: SpongeSchematic {
+                version: SPONGE_VERSION,
+                data_version: DATA_VERSION,
+                // 65535 along every axis
+                width: -1,
+                height: -1,
+                length: -1,
+                offset: None,
+                blocks: None,
+            },
+        };
+        let mut data = Vec::new();
+        file.serialize(&mut data, &NBTSerializeOptions::WithHeader(""));
+        assert!(Clipboard::from_sponge(&data).is_err());
+    }
+
+    #[test]
+    fn test_int_list() {
+        let mut buf = Vec::new();
+        IntList(vec![1, 2]).serialize(&mut buf, &NBTSerializeOptions::WithHeader("pos"));
+        assert_eq!(
+            buf,
+            vec![9, 0, 3, b'p', b'o', b's', 3, 0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 2]
+        );
+    }
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(
+            SchematicFormat::from_path(Path::new("house.schem")),
+            Some(SchematicFormat::Sponge)
+        );
+        assert_eq!(
+            SchematicFormat::from_path(Path::new("structures/house.nbt")),
+            Some(SchematicFormat::Structure)
+        );
+        assert_eq!(SchematicFormat::from_path(Path::new("house.txt")), None);
+    }
+}
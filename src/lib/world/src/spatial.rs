@@ -0,0 +1,304 @@
+//! Spatial chunk keys, and looking up which chunks are stored in an area.
+//!
+//! Chunks are stored under keys that sort by dimension, then region, then position in the region,
+//! so all the chunks in a dimension or a range of regions sit next to each other in the storage
+//! backend and can be listed without knowing their coordinates up front.
+//!
+//! | Bits    | Contents                              |
+//! |---------|---------------------------------------|
+//! | 96..128 | Dimension ID                          |
+//! | 64..96  | Region X, with the sign bit flipped   |
+//! | 32..64  | Region Z, with the sign bit flipped   |
+//! | 16..32  | Chunk X within the region             |
+//! | 0..16   | Chunk Z within the region             |
+//!
+//! Dimension IDs are handed out as dimensions are first saved to, and kept in their own table so
+//! they can be mapped back to names.
+
+use crate::chunk_format::Chunk;
+use crate::errors::WorldError;
+use crate::World;
+use ferrumc_storage::backend::StorageBackend;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, RwLock};
+
+/// The table chunks are stored in.
+pub(crate) const CHUNK_TABLE: &str = "spatial_chunks";
+/// The table chunks were stored in before they had spatial keys, keyed by a hash of the
+/// dimension and the coordinates.
+pub(crate) const LEGACY_CHUNK_TABLE: &str = "chunks";
+/// Maps dimension IDs to their names.
+const DIMENSION_TABLE: &str = "dimensions";
+
+/// The width of a region in chunks.
+pub const REGION_SIZE: i32 = 32;
+const REGION_SHIFT: u32 = REGION_SIZE.trailing_zeros();
+
+/// Maps a signed number to an unsigned one with the same ordering.
+fn ordered(value: i32) -> u32 {
+    (value as u32) ^ 0x8000_0000
+}
+
+fn unordered(value: u32) -> i32 {
+    (value ^ 0x8000_0000) as i32
+}
+
+/// Builds the storage key for a chunk.
+pub(crate) fn chunk_key(dimension_id: u32, x: i32, z: i32) -> u128 {
+    let local_x = (x & (REGION_SIZE - 1)) as u128;
+    let local_z = (z & (REGION_SIZE - 1)) as u128;
+    region_key(dimension_id, x >> REGION_SHIFT, z >> REGION_SHIFT) | (local_x << 16) | local_z
+}
+
+/// The first key of a region.
+fn region_key(dimension_id: u32, region_x: i32, region_z: i32) -> u128 {
+    ((dimension_id as u128) << 96)
+        | ((ordered(region_x) as u128) << 64)
+        | ((ordered(region_z) as u128) << 32)
+}
+
+/// Splits a storage key back into the dimension ID and chunk coordinates.
+pub(crate) fn decode_chunk_key(key: u128) -> (u32, i32, i32) {
+    let dimension_id = (key >> 96) as u32;
+    let region_x = unordered((key >> 64) as u32);
+    let region_z = unordered((key >> 32) as u32);
+    let local_x = ((key >> 16) & 0xFFFF) as i32;
+    let local_z = (key & 0xFFFF) as i32;
+    (
+        dimension_id,
+        (region_x << REGION_SHIFT) | local_x,
+        (region_z << REGION_SHIFT) | local_z,
+    )
+}
+
+/// The dimensions that have chunks stored in them, and the IDs their chunk keys use.
+pub(crate) struct DimensionRegistry {
+    ids: RwLock<HashMap<String, u32>>,
+}
+
+impl DimensionRegistry {
+    pub(crate) fn load(storage_backend: &dyn StorageBackend) -> Result<Self, WorldError> {
+        let mut ids = HashMap::new();
+        if storage_backend.table_exists(DIMENSION_TABLE.to_string())? {
+            for id in storage_backend.keys(DIMENSION_TABLE.to_string())? {
+                if let Some(name) = storage_backend.get(DIMENSION_TABLE.to_string(), id)? {
+                    let name = String::from_utf8(name).map_err(|_| {
+                        WorldError::InvalidDimension(format!("Dimension {id} has an invalid name"))
+                    })?;
+                    ids.insert(name, id as u32);
+                }
+            }
+        } else {
+            storage_backend.create_table(DIMENSION_TABLE.to_string())?;
+        }
+        Ok(Self {
+            ids: RwLock::new(ids),
+        })
+    }
+
+    /// The ID of a dimension, or `None` if nothing has been saved in it yet.
+    pub(crate) fn id(&self, dimension: &str) -> Option<u32> {
+        self.ids.read().unwrap().get(dimension).copied()
+    }
+
+    /// The ID of a dimension, registering it if it hasn't been seen before.
+    pub(crate) fn get_or_register(
+        &self,
+        storage_backend: &dyn StorageBackend,
+        dimension: &str,
+    ) -> Result<u32, WorldError> {
+        if let Some(id) = self.id(dimension) {
+            return Ok(id);
+        }
+        let mut ids = self.ids.write().unwrap();
+        // Someone else might have registered it while we were waiting for the lock
+        if let Some(id) = ids.get(dimension) {
+            return Ok(*id);
+        }
+        let id = ids.values().max().map_or(0, |max| max + 1);
+        storage_backend.upsert(
+            DIMENSION_TABLE.to_string(),
+            id as u128,
+            dimension.as_bytes().to_vec(),
+        )?;
+        ids.insert(dimension.to_string(), id);
+        Ok(id)
+    }
+
+    pub(crate) fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.ids.read().unwrap().keys().cloned().collect();
+        names.sort();
+        names
+    }
+}
+
+/// An inclusive rectangle of chunk coordinates.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChunkBounds {
+    pub min_x: i32,
+    pub min_z: i32,
+    pub max_x: i32,
+    pub max_z: i32,
+}
+
+impl ChunkBounds {
+    /// Creates bounds covering everything between two corners, in any order.
+    pub fn new(corner_a: (i32, i32), corner_b: (i32, i32)) -> Self {
+        Self {
+            min_x: corner_a.0.min(corner_b.0),
+            min_z: corner_a.1.min(corner_b.1),
+            max_x: corner_a.0.max(corner_b.0),
+            max_z: corner_a.1.max(corner_b.1),
+        }
+    }
+
+    /// Creates bounds covering a square of chunks around a centre chunk.
+    pub fn around(x: i32, z: i32, radius: i32) -> Self {
+        Self::new((x - radius, z - radius), (x + radius, z + radius))
+    }
+
+    pub fn contains(&self, x: i32, z: i32) -> bool {
+        (self.min_x..=self.max_x).contains(&x) && (self.min_z..=self.max_z).contains(&z)
+    }
+}
+
+impl World {
+    /// Lists every dimension that has had chunks saved in it, in alphabetical order.
+    pub fn list_dimensions(&self) -> Vec<String> {
+        let mut dimensions: BTreeSet<String> = self.dimensions.names().into_iter().collect();
+        // Chunks in a new dimension aren't registered until they're first written
+        dimensions.extend(self.dirty_chunks.iter().map(|entry| entry.key().2.clone()));
+        dimensions.into_iter().collect()
+    }
+
+    /// Whether any chunks have been saved in the world. Dimensions are only registered once a
+    /// chunk is saved in them, so this doesn't need to look through the chunks.
+    pub fn has_chunks(&self) -> bool {
+        !self.list_dimensions().is_empty()
+    }
+
+    /// Lists the coordinates of every chunk saved in a dimension, optionally limited to an area.
+    ///
+    /// The chunks are ordered by region, which keeps chunks that are near each other together.
+    pub fn chunk_coords(
+        &self,
+        dimension: &str,
+        bounds: Option<ChunkBounds>,
+    ) -> Result<Vec<(i32, i32)>, WorldError> {
+        let in_bounds = |x: i32, z: i32| bounds.is_none_or(|bounds| bounds.contains(x, z));
+        let mut coords = BTreeSet::new();
+
+        if let Some(id) = self.dimensions.id(dimension) {
+            if self.storage_backend.table_exists(CHUNK_TABLE.to_string())? {
+                let (start, end) = match bounds {
+                    Some(bounds) => (
+                        region_key(
+                            id,
+                            bounds.min_x >> REGION_SHIFT,
+                            bounds.min_z >> REGION_SHIFT,
+                        ),
+                        region_key(id, bounds.max_x >> REGION_SHIFT, i32::MAX) | u32::MAX as u128,
+                    ),
+                    None => {
+                        let start = (id as u128) << 96;
+                        (start, start | ((1 << 96) - 1))
+                    }
+                };
+                for key in
+                    self.storage_backend
+                        .keys_in_range(CHUNK_TABLE.to_string(), start, end)?
+                {
+                    let (_, x, z) = decode_chunk_key(key);
+                    if in_bounds(x, z) {
+                        coords.insert((key, x, z));
+                    }
+                }
+            }
+        }
+
+        // Include chunks that haven't been written to the storage backend yet
+        let id = self.dimensions.id(dimension).unwrap_or(u32::MAX);
+        for entry in self.dirty_chunks.iter() {
+            let (x, z, chunk_dimension) = entry.key();
+            if chunk_dimension == dimension && in_bounds(*x, *z) {
+                coords.insert((chunk_key(id, *x, *z), *x, *z));
+            }
+        }
+
+        Ok(coords.into_iter().map(|(_, x, z)| (x, z)).collect())
+    }
+
+    /// Iterates over the chunks saved in a dimension, optionally limited to an area.
+    ///
+    /// The chunks are loaded lazily as the iterator advances, in the same order as
+    /// [`World::chunk_coords`].
+    pub fn iter_chunks<'a>(
+        &'a self,
+        dimension: &'a str,
+        bounds: Option<ChunkBounds>,
+    ) -> Result<impl Iterator<Item = Result<Arc<Chunk>, WorldError>> + 'a, WorldError> {
+        Ok(self
+            .chunk_coords(dimension, bounds)?
+            .into_iter()
+            .map(move |(x, z)| self.load_chunk(x, z, dimension)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_round_trip() {
+        for (x, z) in [
+            (0, 0),
+            (-1, -1),
+            (31, 32),
+            (-33, 17),
+            (1_875_000, -1_875_000),
+            (i32::MAX, i32::MIN),
+        ] {
+            assert_eq!(decode_chunk_key(chunk_key(3, x, z)), (3, x, z));
+        }
+    }
+
+    #[test]
+    fn test_keys_sort_by_dimension_then_region() {
+        let mut coords = vec![
+            (1, 40, 0),
+            (0, 0, 0),
+            (0, -1, 5),
+            (0, 31, 31),
+            (0, 32, 0),
+            (0, 0, 32),
+            (0, -32, -32),
+        ];
+        coords.sort_by_key(|&(dimension, x, z)| chunk_key(dimension, x, z));
+        assert_eq!(
+            coords,
+            vec![
+                (0, -32, -32),
+                (0, -1, 5),
+                (0, 0, 0),
+                (0, 31, 31),
+                (0, 0, 32),
+                (0, 32, 0),
+                (1, 40, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bounds() {
+        let bounds = ChunkBounds::new((5, -3), (-2, 4));
+        assert_eq!((bounds.min_x, bounds.min_z), (-2, -3));
+        assert_eq!((bounds.max_x, bounds.max_z), (5, 4));
+        assert_eq!(
+            ChunkBounds::around(1, 0, 2),
+            ChunkBounds::new((-1, -2), (3, 2))
+        );
+        assert!(bounds.contains(-2, -3));
+        assert!(bounds.contains(5, 4));
+        assert!(!bounds.contains(6, 0));
+    }
+}
@@ -0,0 +1,444 @@
+//! Chunk tickets, which decide which chunks are kept loaded and which are simulated.
+//!
+//! Like vanilla, every ticket has a level, which spreads to the chunks around it going up by one
+//! for each chunk away. A chunk's level is the lowest any ticket gives it:
+//!
+//! - [`ENTITY_TICKING_LEVEL`] and below: entities and blocks in it are simulated.
+//! - [`TICKING_LEVEL`]: blocks in it are simulated.
+//! - [`LOADED_LEVEL`]: it's kept loaded, but nothing in it is simulated.
+//! - Anything higher: it's only in memory if the chunk cache still has it.
+//!
+//! Loaded chunks are kept in memory once they've been loaded, however full the chunk cache is or
+//! how long it's been since they were used, so the spawn radius and the number of forced chunks
+//! are kept small. Forced tickets are saved like the protected regions, as JSON under a single
+//! key. The other tickets are made again when the server starts and players join.
+
+use crate::chunk_format::Chunk;
+use crate::errors::WorldError;
+use crate::{ChunkKey, World};
+use dashmap::DashMap;
+use ferrumc_storage::backend::StorageBackend;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+/// The table forced chunks are stored in. There's only ever one entry.
+const TICKETS_TABLE: &str = "chunk_tickets";
+const TICKETS_KEY: u128 = 0;
+
+pub const ENTITY_TICKING_LEVEL: u8 = 31;
+pub const TICKING_LEVEL: u8 = 32;
+pub const LOADED_LEVEL: u8 = 33;
+
+/// The most chunks that can be forced at once. Each one keeps the 5x5 chunks around it loaded.
+pub const MAX_FORCED_CHUNKS: usize = 64;
+
+/// How long temporary tickets last.
+pub const TEMPORARY_TICKET_LIFETIME: Duration = Duration::from_secs(15);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TicketType {
+    /// The chunk a player is in. Every player has one.
+    Player,
+    /// The world's spawn chunk, which keeps the chunks up to `radius` away from it loaded.
+    Spawn { radius: u8 },
+    /// A chunk an admin has force loaded. These are saved, and a chunk can only be forced once.
+    Forced,
+    /// A chunk that's needed for a short while, like one an entity is teleported to. These go
+    /// away after [`TEMPORARY_TICKET_LIFETIME`].
+    Temporary,
+}
+
+impl TicketType {
+    /// The level tickets of this type give their own chunk.
+    pub fn level(self) -> u8 {
+        match self {
+            TicketType::Player | TicketType::Forced => ENTITY_TICKING_LEVEL,
+            // Entities are simulated in all but the outer two chunks, like vanilla
+            TicketType::Spawn { radius } => LOADED_LEVEL - radius.min(LOADED_LEVEL),
+            TicketType::Temporary => LOADED_LEVEL,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ChunkStatus {
+    Unloaded,
+    Loaded,
+    Ticking,
+    EntityTicking,
+}
+
+impl ChunkStatus {
+    pub fn from_level(level: Option<u8>) -> Self {
+        match level {
+            Some(level) if level <= ENTITY_TICKING_LEVEL => ChunkStatus::EntityTicking,
+            Some(TICKING_LEVEL) => ChunkStatus::Ticking,
+            Some(LOADED_LEVEL) => ChunkStatus::Loaded,
+            _ => ChunkStatus::Unloaded,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Ticket {
+    kind: TicketType,
+    expires: Option<Instant>,
+}
+
+#[derive(Default)]
+struct TicketState {
+    tickets: HashMap<ChunkKey, Vec<Ticket>>,
+    /// The level of every loaded chunk.
+    levels: HashMap<ChunkKey, u8>,
+}
+
+impl TicketState {
+    /// Works out the level of every chunk again. There are few enough tickets that this is quicker
+    /// than keeping track of which ticket gave each chunk its level.
+    fn update_levels(&mut self) {
+        self.levels.clear();
+        for ((ticket_x, ticket_z, dimension), tickets) in &self.tickets {
+            let Some(level) = tickets.iter().map(|ticket| ticket.kind.level()).min() else {
+                continue;
+            };
+            let radius = (LOADED_LEVEL - level) as i32;
+            for x in ticket_x - radius..=ticket_x + radius {
+                for z in ticket_z - radius..=ticket_z + radius {
+                    let distance = (x - ticket_x).abs().max((z - ticket_z).abs()) as u8;
+                    self.levels
+                        .entry((x, z, dimension.clone()))
+                        .and_modify(|existing| *existing = (*existing).min(level + distance))
+                        .or_insert(level + distance);
+                }
+            }
+        }
+    }
+}
+
+/// Every chunk ticket, and the loaded chunks they keep in memory.
+pub(crate) struct ChunkTickets {
+    state: RwLock<TicketState>,
+    /// Loaded chunks that have been loaded since they got a ticket.
+    resident: DashMap<ChunkKey, Arc<Chunk>>,
+}
+
+impl ChunkTickets {
+    pub(crate) fn load(storage_backend: &dyn StorageBackend) -> Result<Self, WorldError> {
+        let mut state = TicketState::default();
+        if storage_backend.table_exists(TICKETS_TABLE.to_string())? {
+            if let Some(data) = storage_backend.get(TICKETS_TABLE.to_string(), TICKETS_KEY)? {
+                let forced: Vec<ChunkKey> = serde_json::from_slice(&data).map_err(|e| {
+                    WorldError::GenericIOError(format!("Invalid forced chunks: {e}"))
+                })?;
+                for chunk in forced {
+                    state.tickets.entry(chunk).or_default().push(Ticket {
+                        kind: TicketType::Forced,
+                        expires: None,
+                    });
+                }
+            }
+        } else {
+            storage_backend.create_table(TICKETS_TABLE.to_string())?;
+        }
+        state.update_levels();
+        Ok(Self {
+            state: RwLock::new(state),
+            resident: DashMap::new(),
+        })
+    }
+
+    fn save(
+        storage_backend: &dyn StorageBackend,
+        tickets: &HashMap<ChunkKey, Vec<Ticket>>,
+    ) -> Result<(), WorldError> {
+        let forced: Vec<&ChunkKey> = tickets
+            .iter()
+            .filter(|(_, tickets)| {
+                tickets
+                    .iter()
+                    .any(|ticket| ticket.kind == TicketType::Forced)
+            })
+            .map(|(chunk, _)| chunk)
+            .collect();
+        let data =
+            serde_json::to_vec(&forced).map_err(|e| WorldError::GenericIOError(e.to_string()))?;
+        storage_backend.upsert(TICKETS_TABLE.to_string(), TICKETS_KEY, data)?;
+        Ok(())
+    }
+
+    pub(crate) fn resident(&self, key: &ChunkKey) -> Option<Arc<Chunk>> {
+        self.resident.get(key).map(|chunk| chunk.clone())
+    }
+
+    /// Keeps a chunk in memory if it's loaded.
+    pub(crate) fn keep_if_loaded(&self, key: ChunkKey, chunk: Arc<Chunk>) {
+        // Holding the lock stops the chunk being unloaded before it's kept
+        let state = self.state.read().unwrap();
+        if state.levels.contains_key(&key) {
+            self.resident.insert(key, chunk);
+        }
+    }
+
+    pub(crate) fn forget(&self, key: &ChunkKey) {
+        self.resident.remove(key);
+    }
+
+    /// Works out the levels again, and lets go of the chunks that aren't loaded anymore.
+    fn update_levels(&self, state: &mut TicketState) {
+        state.update_levels();
+        self.resident
+            .retain(|key, _| state.levels.contains_key(key));
+    }
+}
+
+impl World {
+    /// Adds a ticket to a chunk. Returns `false` if the chunk was already forced and this was
+    /// another forced ticket, since a chunk can only be forced once.
+    pub fn add_chunk_ticket(
+        &self,
+        kind: TicketType,
+        x: i32,
+        z: i32,
+        dimension: &str,
+    ) -> Result<bool, WorldError> {
+        let mut state = self.tickets.state.write().unwrap();
+        let key = (x, z, dimension.to_string());
+        if kind == TicketType::Forced {
+            let is_forced =
+                |tickets: &Vec<Ticket>| tickets.iter().any(|ticket| ticket.kind == kind);
+            if state.tickets.get(&key).is_some_and(is_forced) {
+                return Ok(false);
+            }
+            let forced = state
+                .tickets
+                .values()
+                .filter(|tickets| is_forced(tickets))
+                .count();
+            if forced >= MAX_FORCED_CHUNKS {
+                return Err(WorldError::TooManyForcedChunks(MAX_FORCED_CHUNKS));
+            }
+        }
+        let tickets = state.tickets.entry(key.clone()).or_default();
+        tickets.push(Ticket {
+            kind,
+            expires: (kind == TicketType::Temporary)
+                .then(|| Instant::now() + TEMPORARY_TICKET_LIFETIME),
+        });
+        if kind == TicketType::Forced {
+            if let Err(e) = ChunkTickets::save(self.storage_backend.as_ref(), &state.tickets) {
+                remove_ticket(&mut state.tickets, &key, kind);
+                return Err(e);
+            }
+        }
+        self.tickets.update_levels(&mut state);
+        Ok(true)
+    }
+
+    /// Removes one ticket of a type from a chunk, returning whether there was one.
+    pub fn remove_chunk_ticket(
+        &self,
+        kind: TicketType,
+        x: i32,
+        z: i32,
+        dimension: &str,
+    ) -> Result<bool, WorldError> {
+        let mut state = self.tickets.state.write().unwrap();
+        let key = (x, z, dimension.to_string());
+        if !remove_ticket(&mut state.tickets, &key, kind) {
+            return Ok(false);
+        }
+        if kind == TicketType::Forced {
+            if let Err(e) = ChunkTickets::save(self.storage_backend.as_ref(), &state.tickets) {
+                state.tickets.entry(key).or_default().push(Ticket {
+                    kind,
+                    expires: None,
+                });
+                return Err(e);
+            }
+        }
+        self.tickets.update_levels(&mut state);
+        Ok(true)
+    }
+
+    /// Removes temporary tickets that have run out, returning how many there were.
+    pub fn expire_chunk_tickets(&self) -> usize {
+        let now = Instant::now();
+        let expired = |ticket: &Ticket| ticket.expires.is_some_and(|expires| expires <= now);
+        // Most of the time nothing has run out, so there's no need to wait for the write lock
+        let any_expired = self
+            .tickets
+            .state
+            .read()
+            .unwrap()
+            .tickets
+            .values()
+            .flatten()
+            .any(expired);
+        if !any_expired {
+            return 0;
+        }
+        let mut state = self.tickets.state.write().unwrap();
+        let mut removed = 0;
+        state.tickets.retain(|_, tickets| {
+            let before = tickets.len();
+            tickets.retain(|ticket| !expired(ticket));
+            removed += before - tickets.len();
+            !tickets.is_empty()
+        });
+        self.tickets.update_levels(&mut state);
+        removed
+    }
+
+    /// The level tickets give a chunk, or `None` if it isn't loaded.
+    pub fn chunk_level(&self, x: i32, z: i32, dimension: &str) -> Option<u8> {
+        self.tickets
+            .state
+            .read()
+            .unwrap()
+            .levels
+            .get(&(x, z, dimension.to_string()))
+            .copied()
+    }
+
+    pub fn chunk_status(&self, x: i32, z: i32, dimension: &str) -> ChunkStatus {
+        ChunkStatus::from_level(self.chunk_level(x, z, dimension))
+    }
+
+    /// Every chunk in a dimension that's at least as loaded as `status`.
+    pub fn chunks_with_status(&self, dimension: &str, status: ChunkStatus) -> Vec<(i32, i32)> {
+        self.tickets
+            .state
+            .read()
+            .unwrap()
+            .levels
+            .iter()
+            .filter(|((_, _, dim), level)| {
+                dim == dimension && ChunkStatus::from_level(Some(**level)) >= status
+            })
+            .map(|((x, z, _), _)| (*x, *z))
+            .collect()
+    }
+
+    /// Every forced chunk, as its coordinates and dimension.
+    pub fn forced_chunks(&self) -> Vec<(i32, i32, String)> {
+        let mut forced: Vec<_> = self
+            .tickets
+            .state
+            .read()
+            .unwrap()
+            .tickets
+            .iter()
+            .filter(|(_, tickets)| {
+                tickets
+                    .iter()
+                    .any(|ticket| ticket.kind == TicketType::Forced)
+            })
+            .map(|(chunk, _)| chunk.clone())
+            .collect();
+        forced.sort();
+        forced
+    }
+
+    /// Loads every loaded chunk that exists, so they're in memory before they're needed, like the
+    /// spawn chunks when the server starts. Returns how many were loaded.
+    pub fn load_ticketed_chunks(&self) -> Result<usize, WorldError> {
+        let chunks: Vec<ChunkKey> = self
+            .tickets
+            .state
+            .read()
+            .unwrap()
+            .levels
+            .keys()
+            .cloned()
+            .collect();
+        let mut loaded = 0;
+        for (x, z, dimension) in chunks {
+            match self.load_chunk(x, z, &dimension) {
+                Ok(_) => loaded += 1,
+                Err(WorldError::ChunkNotFound) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(loaded)
+    }
+}
+
+/// Removes one ticket of a type from a chunk, returning whether there was one.
+fn remove_ticket(
+    tickets: &mut HashMap<ChunkKey, Vec<Ticket>>,
+    key: &ChunkKey,
+    kind: TicketType,
+) -> bool {
+    let Some(chunk_tickets) = tickets.get_mut(key) else {
+        return false;
+    };
+    let Some(index) = chunk_tickets.iter().position(|ticket| ticket.kind == kind) else {
+        return false;
+    };
+    chunk_tickets.swap_remove(index);
+    if chunk_tickets.is_empty() {
+        tickets.remove(key);
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state_with(tickets: &[(TicketType, i32, i32)]) -> TicketState {
+        let mut state = TicketState::default();
+        for (kind, x, z) in tickets {
+            state
+                .tickets
+                .entry((*x, *z, "overworld".to_string()))
+                .or_default()
+                .push(Ticket {
+                    kind: *kind,
+                    expires: None,
+                });
+        }
+        state.update_levels();
+        state
+    }
+
+    fn status(state: &TicketState, x: i32, z: i32) -> ChunkStatus {
+        ChunkStatus::from_level(state.levels.get(&(x, z, "overworld".to_string())).copied())
+    }
+
+    #[test]
+    fn test_levels_spread_from_tickets() {
+        let state = state_with(&[(TicketType::Player, 0, 0)]);
+        assert_eq!(status(&state, 0, 0), ChunkStatus::EntityTicking);
+        assert_eq!(status(&state, 1, -1), ChunkStatus::Ticking);
+        assert_eq!(status(&state, 2, 0), ChunkStatus::Loaded);
+        assert_eq!(status(&state, 3, 0), ChunkStatus::Unloaded);
+
+        let state = state_with(&[(TicketType::Spawn { radius: 11 }, 0, 0)]);
+        assert_eq!(status(&state, 9, 9), ChunkStatus::EntityTicking);
+        assert_eq!(status(&state, 11, 0), ChunkStatus::Loaded);
+        assert_eq!(status(&state, 12, 0), ChunkStatus::Unloaded);
+
+        let state = state_with(&[(TicketType::Spawn { radius: 2 }, 0, 0)]);
+        assert_eq!(status(&state, 0, 0), ChunkStatus::EntityTicking);
+        assert_eq!(status(&state, 2, 2), ChunkStatus::Loaded);
+        assert_eq!(status(&state, 3, 0), ChunkStatus::Unloaded);
+    }
+
+    #[test]
+    fn test_closest_ticket_wins() {
+        let state = state_with(&[(TicketType::Temporary, 0, 0), (TicketType::Forced, 2, 0)]);
+        // The temporary ticket only loads its own chunk, but the forced one is close enough to
+        // make it ticking
+        assert_eq!(status(&state, 0, 0), ChunkStatus::Loaded);
+        assert_eq!(status(&state, 1, 0), ChunkStatus::Ticking);
+        assert_eq!(status(&state, -1, 0), ChunkStatus::Unloaded);
+
+        let mut tickets = state.tickets;
+        let key = (2, 0, "overworld".to_string());
+        assert!(remove_ticket(&mut tickets, &key, TicketType::Forced));
+        assert!(!remove_ticket(&mut tickets, &key, TicketType::Forced));
+        assert!(!tickets.contains_key(&key));
+    }
+}
@@ -0,0 +1,350 @@
+//! Chunk edits that can't lose each other's changes.
+//!
+//! Loading a chunk, changing it and saving it back isn't atomic, so two systems editing the same
+//! chunk at once would each save over the other's changes. [`World::edit`] and
+//! [`World::transaction`] hold a lock on each chunk from when it's loaded until it's saved, so
+//! edits to the same chunk happen one after the other.
+//!
+//! Every save also moves the chunk on to a new revision. Code that can't hold a lock while it
+//! works, like something that loads a chunk on one thread and saves it later, can use
+//! [`World::save_chunk_if_unchanged`] to only save if nothing else changed the chunk in between.
+
+use crate::block_id::BlockId;
+use crate::chunk_format::Chunk;
+use crate::errors::WorldError;
+use crate::{ChunkKey, World};
+use dashmap::DashMap;
+use parking_lot::{ArcMutexGuard, Mutex, RawMutex};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+/// A lock for every chunk that's currently being edited.
+#[derive(Default)]
+pub(crate) struct ChunkLocks {
+    locks: DashMap<ChunkKey, Arc<Mutex<()>>>,
+}
+
+/// Holds a chunk's lock until it's dropped.
+pub(crate) struct ChunkLockGuard {
+    guard: Option<ArcMutexGuard<RawMutex, ()>>,
+    key: ChunkKey,
+    locks: Arc<ChunkLocks>,
+}
+
+impl ChunkLocks {
+    /// Waits until nothing else is editing the chunk, then locks it.
+    pub(crate) fn lock(self: &Arc<Self>, key: ChunkKey) -> ChunkLockGuard {
+        // Clone the mutex out so the map isn't held up while waiting for it
+        let lock = self.locks.entry(key.clone()).or_default().clone();
+        ChunkLockGuard {
+            guard: Some(lock.lock_arc()),
+            key,
+            locks: self.clone(),
+        }
+    }
+
+    /// The number of chunks with a lock, locked or not.
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.locks.len()
+    }
+}
+
+impl Drop for ChunkLockGuard {
+    fn drop(&mut self) {
+        self.guard.take();
+        // Clean up the lock unless someone else is holding or waiting on it. Taking a clone of
+        // the lock needs the same map shard, so nobody can grab it while this checks.
+        self.locks
+            .locks
+            .remove_if(&self.key, |_, lock| Arc::strong_count(lock) == 1);
+    }
+}
+
+/// Several chunks being edited at once. Created by [`World::transaction`].
+///
+/// Block coordinates are world coordinates, and only the chunks the transaction was started with
+/// can be changed.
+pub struct ChunkTransaction {
+    dimension: String,
+    chunks: BTreeMap<(i32, i32), Chunk>,
+    changed: BTreeSet<(i32, i32)>,
+}
+
+impl ChunkTransaction {
+    fn new(dimension: &str, chunks: impl IntoIterator<Item = Chunk>) -> Self {
+        Self {
+            dimension: dimension.to_string(),
+            chunks: chunks
+                .into_iter()
+                .map(|chunk| ((chunk.x, chunk.z), chunk))
+                .collect(),
+            changed: BTreeSet::new(),
+        }
+    }
+
+    pub fn dimension(&self) -> &str {
+        &self.dimension
+    }
+
+    /// The coordinates of every chunk in the transaction. Chunks that didn't exist when the
+    /// transaction started aren't included.
+    pub fn chunk_coords(&self) -> impl Iterator<Item = (i32, i32)> + '_ {
+        self.chunks.keys().copied()
+    }
+
+    pub fn chunk(&self, x: i32, z: i32) -> Option<&Chunk> {
+        self.chunks.get(&(x, z))
+    }
+
+    /// Gets a chunk to change. The chunk is saved when the transaction is committed, whether or
+    /// not it was actually changed.
+    pub fn chunk_mut(&mut self, x: i32, z: i32) -> Option<&mut Chunk> {
+        let chunk = self.chunks.get_mut(&(x, z))?;
+        self.changed.insert((x, z));
+        Some(chunk)
+    }
+
+    /// Gets every chunk to change. Like [`ChunkTransaction::chunk_mut`], they're all saved when
+    /// the transaction is committed unless they're [discarded](ChunkTransaction::discard).
+    pub fn chunks_mut(&mut self) -> impl Iterator<Item = &mut Chunk> {
+        self.changed.extend(self.chunks.keys().copied());
+        self.chunks.values_mut()
+    }
+
+    /// Stops a chunk from being saved when the transaction is committed, e.g. because nothing in
+    /// it actually changed. Any changes to it are thrown away.
+    pub fn discard(&mut self, x: i32, z: i32) {
+        self.changed.remove(&(x, z));
+    }
+
+    pub fn get_block(&self, x: i32, y: i32, z: i32) -> Result<BlockId, WorldError> {
+        self.chunk(x >> 4, z >> 4)
+            .ok_or(WorldError::ChunkNotInTransaction(x >> 4, z >> 4))?
+            .get_block(x, y, z)
+    }
+
+    pub fn set_block(
+        &mut self,
+        x: i32,
+        y: i32,
+        z: i32,
+        block: impl Into<BlockId>,
+    ) -> Result<(), WorldError> {
+        self.chunk_mut(x >> 4, z >> 4)
+            .ok_or(WorldError::ChunkNotInTransaction(x >> 4, z >> 4))?
+            .set_block(x, y, z, block)
+    }
+
+    /// The chunks that were changed, and so need saving.
+    fn into_changed(self) -> impl Iterator<Item = Chunk> {
+        let changed = self.changed;
+        self.chunks
+            .into_iter()
+            .filter(move |(coords, _)| changed.contains(coords))
+            .map(|(_, chunk)| chunk)
+    }
+}
+
+impl World {
+    /// Edits a chunk without racing anything else that's editing it.
+    ///
+    /// The chunk is locked, loaded, passed to `edit` and then saved if `edit` succeeded. If it
+    /// returns an error the chunk is left as it was. Don't edit the same chunk again from inside
+    /// `edit`, since it's still locked and would wait forever.
+    ///
+    /// # Errors
+    ///
+    /// * `WorldError::ChunkNotFound` - If the chunk doesn't exist.
+    /// * Any error returned by `edit`.
+    pub fn edit<R>(
+        &self,
+        x: i32,
+        z: i32,
+        dimension: &str,
+        edit: impl FnOnce(&mut Chunk) -> Result<R, WorldError>,
+    ) -> Result<R, WorldError> {
+        let _lock = self.chunk_locks.lock((x, z, dimension.to_string()));
+        let mut chunk = self.load_chunk_owned(x, z, dimension)?;
+        let result = edit(&mut chunk)?;
+        self.save_chunk(Arc::new(chunk))?;
+        Ok(result)
+    }
+
+    /// Like [`World::edit`], but chunks that don't exist yet are made by `create` first, e.g. by
+    /// generating them. The chunk is locked before it's created, so two edits to a new chunk can't
+    /// each create their own copy and save over each other.
+    pub fn edit_or_create<R>(
+        &self,
+        x: i32,
+        z: i32,
+        dimension: &str,
+        create: impl FnOnce() -> Result<Chunk, WorldError>,
+        edit: impl FnOnce(&mut Chunk) -> Result<R, WorldError>,
+    ) -> Result<R, WorldError> {
+        let _lock = self.chunk_locks.lock((x, z, dimension.to_string()));
+        let mut chunk = match self.load_chunk_owned(x, z, dimension) {
+            Ok(chunk) => chunk,
+            Err(WorldError::ChunkNotFound) => create()?,
+            Err(e) => return Err(e),
+        };
+        let result = edit(&mut chunk)?;
+        self.save_chunk(Arc::new(chunk))?;
+        Ok(result)
+    }
+
+    /// Edits several chunks at once, either saving all the changes or none of them.
+    ///
+    /// Every chunk is locked before any of them are loaded. Locks are always taken in the same
+    /// order, so overlapping transactions can't deadlock. Chunks that don't exist are left out of
+    /// the transaction rather than failing it.
+    pub fn transaction<R>(
+        &self,
+        dimension: &str,
+        chunks: impl IntoIterator<Item = (i32, i32)>,
+        edit: impl FnOnce(&mut ChunkTransaction) -> Result<R, WorldError>,
+    ) -> Result<R, WorldError> {
+        let coords: BTreeSet<(i32, i32)> = chunks.into_iter().collect();
+        let _locks: Vec<_> = coords
+            .iter()
+            .map(|&(x, z)| self.chunk_locks.lock((x, z, dimension.to_string())))
+            .collect();
+        let mut loaded = Vec::with_capacity(coords.len());
+        for &(x, z) in &coords {
+            match self.load_chunk_owned(x, z, dimension) {
+                Ok(chunk) => loaded.push(chunk),
+                Err(WorldError::ChunkNotFound) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        let mut transaction = ChunkTransaction::new(dimension, loaded);
+        let result = edit(&mut transaction)?;
+        for chunk in transaction.into_changed() {
+            self.save_chunk(Arc::new(chunk))?;
+        }
+        Ok(result)
+    }
+
+    /// The chunk's current revision, which changes every time it's saved or deleted. Chunks that
+    /// haven't changed since they were loaded are at revision 0.
+    pub fn chunk_revision(&self, x: i32, z: i32, dimension: &str) -> u64 {
+        self.revisions
+            .get(&(x, z, dimension.to_string()))
+            .map(|revision| *revision)
+            .unwrap_or(0)
+    }
+
+    /// Moves a chunk on to a new revision, after it's been saved or deleted.
+    pub(crate) fn bump_revision(&self, key: ChunkKey) {
+        let revision = self.next_revision.fetch_add(1, Ordering::Relaxed) + 1;
+        self.revisions.insert(key, revision);
+    }
+
+    /// Forgets the revisions of chunks that aren't in memory anymore, because they've been
+    /// unloaded or deleted. Revisions are never reused, so anything still holding an old revision
+    /// of one of them won't match it again.
+    pub(crate) fn prune_revisions(&self) {
+        self.revisions.retain(|key, _| {
+            self.cache.contains_key(key)
+                || self.dirty_chunks.contains_key(key)
+                || self.tickets.resident(key).is_some()
+        });
+    }
+
+    /// Saves a chunk, but only if it's still at the given revision, i.e. nothing else has saved
+    /// it since it was loaded. Returns whether the chunk was saved.
+    pub fn save_chunk_if_unchanged(
+        &self,
+        chunk: Arc<Chunk>,
+        revision: u64,
+    ) -> Result<bool, WorldError> {
+        let _lock = self
+            .chunk_locks
+            .lock((chunk.x, chunk.z, chunk.dimension.clone()));
+        if self.chunk_revision(chunk.x, chunk.z, &chunk.dimension) != revision {
+            return Ok(false);
+        }
+        self.save_chunk(chunk)?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vanilla_chunk_format::BlockData;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    fn key(x: i32) -> ChunkKey {
+        (x, 0, "overworld".to_string())
+    }
+
+    #[test]
+    fn test_locks_are_exclusive() {
+        let locks = Arc::new(ChunkLocks::default());
+        let in_edit = Arc::new(AtomicBool::new(false));
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let locks = locks.clone();
+                let in_edit = in_edit.clone();
+                thread::spawn(move || {
+                    for _ in 0..10 {
+                        let _lock = locks.lock(key(0));
+                        assert!(!in_edit.swap(true, Ordering::SeqCst));
+                        thread::sleep(Duration::from_micros(100));
+                        in_edit.store(false, Ordering::SeqCst);
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        // Nothing's locked any more, so the locks should all be cleaned up
+        assert_eq!(locks.len(), 0);
+    }
+
+    #[test]
+    fn test_different_chunks_dont_block() {
+        let locks = Arc::new(ChunkLocks::default());
+        let _first = locks.lock(key(0));
+        let _second = locks.lock(key(1));
+        assert_eq!(locks.len(), 2);
+    }
+
+    #[test]
+    fn test_transaction_set_block() {
+        let stone = BlockData {
+            name: "minecraft:stone".to_string(),
+            properties: None,
+        }
+        .to_block_id();
+        let chunks =
+            [(0, 0), (-1, 0), (5, 5)].map(|(x, z)| Chunk::new(x, z, "overworld".to_string()));
+        let mut transaction = ChunkTransaction::new("overworld", chunks);
+        transaction.set_block(-1, 64, 3, stone).unwrap();
+        transaction
+            .set_block(16 * 5 + 2, 64, 16 * 5, stone)
+            .unwrap();
+        assert_eq!(
+            transaction
+                .chunk(-1, 0)
+                .unwrap()
+                .get_block(15, 64, 3)
+                .unwrap(),
+            stone
+        );
+        assert!(matches!(
+            transaction.set_block(16, 64, 0, stone),
+            Err(WorldError::ChunkNotInTransaction(1, 0))
+        ));
+        let changed: Vec<_> = transaction
+            .into_changed()
+            .map(|chunk| (chunk.x, chunk.z))
+            .collect();
+        assert_eq!(changed, vec![(-1, 0), (5, 5)]);
+    }
+}
@@ -51,12 +51,12 @@ pub(crate) struct VanillaChunk {
 #[derive(deepsize::DeepSizeOf)]
 #[nbt(net_encode)]
 pub(crate) struct VanillaHeightmaps {
-    // #[nbt(rename = "MOTION_BLOCKING_NO_LEAVES")]
-    // pub motion_blocking_no_leaves: Option<Vec<i64>>,
+    #[nbt(rename = "MOTION_BLOCKING_NO_LEAVES")]
+    pub motion_blocking_no_leaves: Option<Vec<i64>>,
     #[nbt(rename = "MOTION_BLOCKING")]
     pub motion_blocking: Option<Vec<i64>>,
-    // #[nbt(rename = "OCEAN_FLOOR")]
-    // pub ocean_floor: Option<Vec<i64>>,
+    #[nbt(rename = "OCEAN_FLOOR")]
+    pub ocean_floor: Option<Vec<i64>>,
     #[nbt(rename = "WORLD_SURFACE")]
     pub world_surface: Option<Vec<i64>>,
 }
@@ -113,6 +113,54 @@ impl Display for BlockData {
     }
 }
 
+impl BlockData {
+    /// Formats the block the way commands and schematics write block states, e.g.
+    /// `minecraft:oak_stairs[facing=north,half=bottom]`.
+    pub fn to_state_string(&self) -> String {
+        match &self.properties {
+            Some(properties) if !properties.is_empty() => {
+                let properties: Vec<String> = properties
+                    .iter()
+                    .map(|(key, value)| format!("{key}={value}"))
+                    .collect();
+                format!("{}[{}]", self.name, properties.join(","))
+            }
+            _ => self.name.clone(),
+        }
+    }
+
+    /// Parses a block state string like `minecraft:oak_stairs[facing=north,half=bottom]`. The
+    /// `minecraft:` namespace can be left out. Returns `None` if the string isn't formatted like a
+    /// block state, but doesn't check the block exists.
+    pub fn from_state_string(state: &str) -> Option<Self> {
+        let (name, properties) = match state.split_once('[') {
+            Some((name, properties)) => (name, Some(properties.strip_suffix(']')?)),
+            None => (state, None),
+        };
+        if name.is_empty() {
+            return None;
+        }
+        let name = if name.contains(':') {
+            name.to_string()
+        } else {
+            format!("minecraft:{name}")
+        };
+        let properties = match properties {
+            Some(properties) if !properties.is_empty() => Some(
+                properties
+                    .split(',')
+                    .map(|property| {
+                        let (key, value) = property.split_once('=')?;
+                        Some((key.trim().to_string(), value.trim().to_string()))
+                    })
+                    .collect::<Option<BTreeMap<_, _>>>()?,
+            ),
+            _ => None,
+        };
+        Some(BlockData { name, properties })
+    }
+}
+
 impl Default for BlockData {
     fn default() -> Self {
         BlockData {
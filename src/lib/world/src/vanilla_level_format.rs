@@ -0,0 +1,275 @@
+//! The parts of vanilla's `level.dat` and `playerdata/*.dat` files that FerrumC keeps, and how
+//! they map onto [`WorldMetadata`] and [`PlayerData`].
+
+use crate::metadata::{Difficulty, GameRuleValue, SpawnPosition, WorldMetadata};
+use crate::player_data::{PlayerData, SavedItem};
+use ferrumc_inventories::item::ItemID;
+use ferrumc_macros::NBTDeserialize;
+use std::collections::BTreeMap;
+use tracing::warn;
+
+#[derive(NBTDeserialize, Debug, Clone)]
+pub(crate) struct VanillaLevel {
+    #[nbt(rename = "Data")]
+    pub data: VanillaLevelData,
+}
+
+#[derive(NBTDeserialize, Debug, Clone, Default)]
+pub(crate) struct VanillaLevelData {
+    #[nbt(rename = "WorldGenSettings")]
+    pub world_gen_settings: Option<WorldGenSettings>,
+    /// Where the seed was kept before 1.16.
+    #[nbt(rename = "RandomSeed")]
+    pub random_seed: Option<i64>,
+    #[nbt(rename = "SpawnX")]
+    pub spawn_x: Option<i32>,
+    #[nbt(rename = "SpawnY")]
+    pub spawn_y: Option<i32>,
+    #[nbt(rename = "SpawnZ")]
+    pub spawn_z: Option<i32>,
+    #[nbt(rename = "SpawnAngle")]
+    pub spawn_angle: Option<f32>,
+    #[nbt(rename = "Time")]
+    pub time: Option<i64>,
+    #[nbt(rename = "DayTime")]
+    pub day_time: Option<i64>,
+    #[nbt(rename = "Difficulty")]
+    pub difficulty: Option<i8>,
+    pub hardcore: Option<bool>,
+    /// Vanilla stores every game rule as a string, numbers included.
+    #[nbt(rename = "GameRules")]
+    pub game_rules: Option<BTreeMap<String, String>>,
+}
+
+#[derive(NBTDeserialize, Debug, Clone, Default)]
+pub(crate) struct WorldGenSettings {
+    pub seed: Option<i64>,
+}
+
+#[derive(NBTDeserialize, Debug, Clone, Default)]
+pub(crate) struct VanillaPlayer {
+    #[nbt(rename = "Pos")]
+    pub pos: Option<Vec<f64>>,
+    #[nbt(rename = "Rotation")]
+    pub rotation: Option<Vec<f32>>,
+    #[nbt(rename = "Dimension")]
+    pub dimension: Option<String>,
+    #[nbt(rename = "Inventory")]
+    pub inventory: Option<Vec<VanillaItem>>,
+    /// Armour and the offhand, which moved out of the inventory list in 1.21.5.
+    pub equipment: Option<VanillaEquipment>,
+    #[nbt(rename = "SelectedItemSlot")]
+    pub selected_item_slot: Option<i32>,
+    #[nbt(rename = "playerGameType")]
+    pub game_type: Option<i32>,
+}
+
+#[derive(NBTDeserialize, Debug, Clone, Default)]
+pub(crate) struct VanillaItem {
+    #[nbt(rename = "Slot")]
+    pub slot: Option<i8>,
+    pub id: Option<String>,
+    pub count: Option<i32>,
+    /// The item count before 1.20.5.
+    #[nbt(rename = "Count")]
+    pub legacy_count: Option<i8>,
+}
+
+#[derive(NBTDeserialize, Debug, Clone, Default)]
+pub(crate) struct VanillaEquipment {
+    pub head: Option<VanillaItem>,
+    pub chest: Option<VanillaItem>,
+    pub legs: Option<VanillaItem>,
+    pub feet: Option<VanillaItem>,
+    pub offhand: Option<VanillaItem>,
+}
+
+impl VanillaLevelData {
+    /// Builds FerrumC's metadata from the level data. Anything vanilla didn't save keeps
+    /// FerrumC's default.
+    pub(crate) fn to_metadata(&self) -> WorldMetadata {
+        let mut metadata = WorldMetadata::default();
+        if let Some(seed) = self
+            .world_gen_settings
+            .as_ref()
+            .and_then(|settings| settings.seed)
+            .or(self.random_seed)
+        {
+            metadata.seed = seed as u64;
+        }
+        if let (Some(x), Some(y), Some(z)) = (self.spawn_x, self.spawn_y, self.spawn_z) {
+            metadata.spawn = SpawnPosition {
+                x,
+                y,
+                z,
+                angle: self.spawn_angle.unwrap_or_default(),
+            };
+        }
+        metadata.world_age = self.time.unwrap_or_default();
+        metadata.day_time = self.day_time.unwrap_or_default();
+        if let Some(difficulty) = self.difficulty {
+            metadata.difficulty = match difficulty {
+                0 => Difficulty::Peaceful,
+                1 => Difficulty::Easy,
+                2 => Difficulty::Normal,
+                _ => Difficulty::Hard,
+            };
+        }
+        metadata.hardcore = self.hardcore.unwrap_or_default();
+        for (name, value) in self.game_rules.iter().flatten() {
+            match parse_game_rule(value) {
+                Some(value) => {
+                    metadata.game_rules.insert(name.clone(), value);
+                }
+                None => warn!("Skipping game rule {} with unknown value {}", name, value),
+            }
+        }
+        metadata
+    }
+}
+
+fn parse_game_rule(value: &str) -> Option<GameRuleValue> {
+    match value {
+        "true" => Some(GameRuleValue::Bool(true)),
+        "false" => Some(GameRuleValue::Bool(false)),
+        _ => value.parse().ok().map(GameRuleValue::Int),
+    }
+}
+
+/// Converts a slot number from a vanilla player file to the player inventory window's
+/// numbering, which is what FerrumC uses.
+fn window_slot(vanilla_slot: i8) -> Option<u16> {
+    match vanilla_slot {
+        // Hotbar
+        0..=8 => Some(vanilla_slot as u16 + 36),
+        // Main inventory
+        9..=35 => Some(vanilla_slot as u16),
+        // Armour, from feet up to head
+        100..=103 => Some(108 - vanilla_slot as u16),
+        // Offhand
+        -106 => Some(45),
+        _ => None,
+    }
+}
+
+impl VanillaItem {
+    fn to_saved(&self, slot: u16) -> Option<SavedItem> {
+        let id = self.id.as_deref()?;
+        let Some(item_id) = ItemID::from_name(id) else {
+            warn!("Skipping unknown item {}", id);
+            return None;
+        };
+        let count = self.count.or(self.legacy_count.map(i32::from)).unwrap_or(1);
+        Some(SavedItem {
+            slot,
+            item_id: item_id.0 .0,
+            count,
+        })
+    }
+}
+
+impl VanillaPlayer {
+    pub(crate) fn to_player_data(&self) -> PlayerData {
+        let mut data = PlayerData::default();
+        if let Some([x, y, z]) = self.pos.as_deref() {
+            (data.x, data.y, data.z) = (*x, *y, *z);
+        }
+        if let Some([yaw, pitch]) = self.rotation.as_deref() {
+            (data.yaw, data.pitch) = (*yaw, *pitch);
+        }
+        if let Some(dimension) = &self.dimension {
+            data.dimension = dimension
+                .strip_prefix("minecraft:")
+                .unwrap_or(dimension)
+                .to_string();
+        }
+        for item in self.inventory.iter().flatten() {
+            if let Some(slot) = item.slot.and_then(window_slot) {
+                data.inventory.extend(item.to_saved(slot));
+            }
+        }
+        if let Some(equipment) = &self.equipment {
+            let equipped = [
+                (&equipment.head, 5),
+                (&equipment.chest, 6),
+                (&equipment.legs, 7),
+                (&equipment.feet, 8),
+                (&equipment.offhand, 45),
+            ];
+            for (item, slot) in equipped {
+                data.inventory
+                    .extend(item.as_ref().and_then(|item| item.to_saved(slot)));
+            }
+        }
+        data.selected_slot = self.selected_item_slot.unwrap_or_default().clamp(0, 8) as u8;
+        data.gamemode = self.game_type.unwrap_or_default().clamp(0, 3) as u8;
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_window_slot() {
+        assert_eq!(window_slot(0), Some(36));
+        assert_eq!(window_slot(8), Some(44));
+        assert_eq!(window_slot(9), Some(9));
+        assert_eq!(window_slot(35), Some(35));
+        // Boots go in the last armour slot and the helmet in the first
+        assert_eq!(window_slot(100), Some(8));
+        assert_eq!(window_slot(103), Some(5));
+        assert_eq!(window_slot(-106), Some(45));
+        assert_eq!(window_slot(50), None);
+    }
+
+    #[test]
+    fn test_level_to_metadata() {
+        let level = VanillaLevelData {
+            world_gen_settings: Some(WorldGenSettings { seed: Some(-5) }),
+            random_seed: Some(7),
+            spawn_x: Some(10),
+            spawn_y: Some(64),
+            spawn_z: Some(-3),
+            time: Some(1000),
+            day_time: Some(30000),
+            difficulty: Some(2),
+            game_rules: Some(BTreeMap::from([
+                ("keepInventory".to_string(), "true".to_string()),
+                ("randomTickSpeed".to_string(), "10".to_string()),
+            ])),
+            ..Default::default()
+        };
+        let metadata = level.to_metadata();
+        assert_eq!(metadata.seed, -5i64 as u64);
+        assert_eq!(
+            (metadata.spawn.x, metadata.spawn.y, metadata.spawn.z),
+            (10, 64, -3)
+        );
+        assert_eq!((metadata.world_age, metadata.day_time), (1000, 30000));
+        assert_eq!(metadata.difficulty, Difficulty::Normal);
+        assert_eq!(metadata.bool_rule("keepInventory"), Some(true));
+        assert_eq!(metadata.int_rule("randomTickSpeed"), Some(10));
+        // Rules the level didn't mention keep their defaults
+        assert_eq!(metadata.bool_rule("doDaylightCycle"), Some(true));
+    }
+
+    #[test]
+    fn test_player_position() {
+        let player = VanillaPlayer {
+            pos: Some(vec![1.5, 70.0, -2.5]),
+            rotation: Some(vec![90.0, 10.0]),
+            dimension: Some("minecraft:the_nether".to_string()),
+            selected_item_slot: Some(4),
+            game_type: Some(3),
+            ..Default::default()
+        };
+        let data = player.to_player_data();
+        assert_eq!((data.x, data.y, data.z), (1.5, 70.0, -2.5));
+        assert_eq!((data.yaw, data.pitch), (90.0, 10.0));
+        assert_eq!(data.dimension, "the_nether");
+        assert_eq!(data.selected_slot, 4);
+        assert_eq!(data.gamemode, 3);
+    }
+}
@@ -8,12 +8,8 @@ use std::collections::BTreeMap;
 pub(crate) struct PlainsBiome;
 
 impl BiomeGenerator for PlainsBiome {
-    fn _biome_id(&self) -> u8 {
-        0
-    }
-
-    fn _biome_name(&self) -> String {
-        "plains".to_string()
+    fn biome_name(&self) -> &'static str {
+        "minecraft:plains"
     }
 
     fn generate_chunk(
@@ -45,8 +41,7 @@ impl BiomeGenerator for PlainsBiome {
             for chunk_z in 0..16i64 {
                 let global_x = i64::from(x) * 16 + chunk_x;
                 let global_z = i64::from(z) * 16 + chunk_z;
-                let height = noise.get_noise(global_x as f64, global_z as f64);
-                let height = (height * 64.0) as i32 + 64;
+                let height = noise.get_height(global_x, global_z);
                 heights.push((global_x, global_z, height));
             }
         }
@@ -93,6 +88,7 @@ impl BiomeGenerator for PlainsBiome {
         }
 
         batch.apply()?;
+        chunk.compute_light();
 
         Ok(chunk)
     }
@@ -2,6 +2,7 @@ mod biomes;
 pub mod errors;
 
 use crate::errors::WorldGenError;
+use ferrumc_world::biomes::BiomeId;
 use ferrumc_world::chunk_format::Chunk;
 use noise::{Clamp, NoiseFn, OpenSimplex};
 
@@ -9,8 +10,12 @@ use noise::{Clamp, NoiseFn, OpenSimplex};
 ///
 /// Should be implemented for each biome's generator
 pub(crate) trait BiomeGenerator {
-    fn _biome_id(&self) -> u8;
-    fn _biome_name(&self) -> String;
+    /// The biome's name in the biome registry.
+    fn biome_name(&self) -> &'static str;
+    /// The biome's ID in the biome registry.
+    fn biome_id(&self) -> BiomeId {
+        BiomeId::from_name(self.biome_name()).unwrap_or_default()
+    }
     fn generate_chunk(
         &self,
         x: i32,
@@ -19,6 +24,12 @@ pub(crate) trait BiomeGenerator {
     ) -> Result<Chunk, WorldGenError>;
 }
 
+/// Terrain lower than this is under the water the generator fills the world with.
+const SEA_LEVEL: i32 = 64;
+
+/// Terrain up to this height is topped with sand rather than grass.
+const BEACH_HEIGHT: i32 = 65;
+
 pub(crate) struct NoiseGenerator {
     pub(crate) layers: Vec<Clamp<f64, OpenSimplex, 2>>,
 }
@@ -47,6 +58,11 @@ impl NoiseGenerator {
         }
         noise / (self.layers.len() as f64 / 2.0)
     }
+
+    /// The height of the terrain at some block coordinates.
+    pub fn get_height(&self, x: i64, z: i64) -> i32 {
+        (self.get_noise(x as f64, z as f64) * 64.0) as i32 + 64
+    }
 }
 
 impl WorldGenerator {
@@ -62,8 +78,29 @@ impl WorldGenerator {
         Box::new(biomes::plains::PlainsBiome)
     }
 
+    /// Picks the biome for a column from the terrain height, so the water and sand the
+    /// generator places are oceans and beaches.
+    fn column_biome(&self, generator: &dyn BiomeGenerator, x: i64, z: i64) -> BiomeId {
+        let name = match self.noise_generator.get_height(x, z) {
+            ..SEA_LEVEL => "ocean",
+            SEA_LEVEL..=BEACH_HEIGHT => "beach",
+            _ => return generator.biome_id(),
+        };
+        BiomeId::from_name(name).unwrap_or_default()
+    }
+
     pub fn generate_chunk(&self, x: i32, z: i32) -> Result<Chunk, WorldGenError> {
         let biome = self.get_biome(x, z);
-        biome.generate_chunk(x, z, &self.noise_generator)
+        let mut chunk = biome.generate_chunk(x, z, &self.noise_generator)?;
+        // Biomes are stored per 4x4 column, so sample the middle of each one
+        for column_x in (0..16).step_by(4) {
+            for column_z in (0..16).step_by(4) {
+                let global_x = i64::from(x) * 16 + i64::from(column_x) + 2;
+                let global_z = i64::from(z) * 16 + i64::from(column_z) + 2;
+                let column_biome = self.column_biome(biome.as_ref(), global_x, global_z);
+                chunk.set_column_biome(column_x, column_z, column_biome)?;
+            }
+        }
+        Ok(chunk)
     }
 }